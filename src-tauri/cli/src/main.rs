@@ -0,0 +1,416 @@
+//! Command line player built on the same player core as the app, for servers, SSH sessions and
+//! terminals.
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::{cursor, terminal, QueueableCommand};
+use plain_music_player::library::{self, Library};
+use plain_music_player::player::export::{
+    ExportEvent, ExportFormat, ExportJob, ExportOptions, ExportResult,
+};
+use plain_music_player::player::render::{self, WavFormat};
+use plain_music_player::player::source::Source;
+use plain_music_player::player::{
+    chapters, Player, RepeatMode, Symphonia, MAX_VOLUME, VOLUME_STEP,
+};
+use plain_music_player::track::Track;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage:
+    plain-music-player-cli play <files or folders>...
+    plain-music-player-cli info <file>
+    plain-music-player-cli scan <folder>
+    plain-music-player-cli render <output.wav> <files or folders>...
+    plain-music-player-cli export [options] <folder> <files or folders>...
+
+Export options:
+    --format <format>      flac (default), mp3[:kbps], vorbis[:quality] or opus[:kbps]
+    --template <template>  names of the files, such as {albumartist}/{album}/{track} {title}
+    --jobs <count>         tracks exported at once
+    --overwrite            replace existing files";
+
+const KEYS: &str = "\
+space pause   n/p next/previous   left/right seek   up/down volume   m mute   r repeat   q quit";
+
+/// How often the keyboard and the end of the track are checked, and the status line redrawn.
+const TICK: Duration = Duration::from_millis(100);
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, paths)) if command == "play" && !paths.is_empty() => play(paths),
+        Some((command, [path])) if command == "info" => info(Path::new(path)),
+        Some((command, [folder])) if command == "scan" => scan(Path::new(folder)),
+        Some((command, [output, paths @ ..])) if command == "render" && !paths.is_empty() => {
+            render(Path::new(output), paths)
+        }
+        Some((command, args)) if command == "export" => export(args),
+        Some((command, _)) if command == "-h" || command == "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Plays the files, and the audio files in the folders, in order.
+fn play(paths: &[String]) -> Result<(), String> {
+    let tracks = find_tracks(paths)?;
+    let mut player = Player::new();
+    player.set_playlist(tracks, Some(0));
+    if !player.has_output_device() {
+        return Err("no audio output device".to_string());
+    }
+
+    println!("{}", KEYS);
+    terminal::enable_raw_mode().map_err(|err| err.to_string())?;
+    let result = run(&mut player);
+    // The terminal must be restored even if drawing failed.
+    let _ = terminal::disable_raw_mode();
+    println!();
+    player.stop();
+    result.map_err(|err| err.to_string())
+}
+
+/// Returns the files, and the audio files in the folders, in order.
+fn find_tracks(paths: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut tracks = Vec::new();
+    for path in paths.iter().map(PathBuf::from) {
+        if path.is_dir() {
+            tracks.extend(library::find_audio_files(&[path]));
+        } else if path.is_file() {
+            tracks.push(path);
+        } else {
+            return Err(format!("{}: no such file or folder", path.display()));
+        }
+    }
+    if tracks.is_empty() {
+        return Err("no audio files to play".to_string());
+    }
+    Ok(tracks)
+}
+
+/// Handles the keys and redraws the status line until the playlist ends or the user quits.
+fn run(player: &mut Player) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    let mut current = None;
+    let mut title = String::new();
+    loop {
+        if player.is_stopped() {
+            return Ok(());
+        }
+        if current != player.playlist().current_index() {
+            current = player.playlist().current_index();
+            title = player.current_path().map(track_title).unwrap_or_default();
+        }
+        draw_status(&mut stdout, player, &title)?;
+
+        if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                if !handle_key(player, key) {
+                    return Ok(());
+                }
+            }
+        }
+        player.check_output();
+        player.poll_track_end();
+    }
+}
+
+/// Applies `key` to the player. Returns `false` if it asks to quit.
+fn handle_key(player: &mut Player, key: KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => return false,
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+        KeyCode::Char(' ') => {
+            if player.is_paused() {
+                player.resume();
+            } else {
+                player.pause();
+            }
+        }
+        KeyCode::Char('n') => {
+            player.next();
+        }
+        KeyCode::Char('p') => {
+            player.previous();
+        }
+        KeyCode::Right => player.seek_fw(),
+        KeyCode::Left => player.seek_bw(),
+        KeyCode::Up | KeyCode::Char('+') => {
+            player.volume_up(VOLUME_STEP);
+        }
+        KeyCode::Down | KeyCode::Char('-') => {
+            player.volume_down(VOLUME_STEP);
+        }
+        KeyCode::Char('m') => {
+            if player.is_muted() {
+                player.unmute();
+            } else {
+                player.mute();
+            }
+        }
+        KeyCode::Char('r') => {
+            let repeat = match player.repeat() {
+                RepeatMode::Off => RepeatMode::All,
+                RepeatMode::All => RepeatMode::One,
+                RepeatMode::One => RepeatMode::Off,
+            };
+            player.set_repeat(repeat);
+        }
+        _ => {}
+    }
+    true
+}
+
+fn draw_status(stdout: &mut io::Stdout, player: &Player, title: &str) -> io::Result<()> {
+    let state = if player.is_paused() {
+        "paused"
+    } else {
+        "playing"
+    };
+    let duration = player
+        .duration()
+        .map(|secs| format_time(Duration::from_secs_f64(secs.max(0.0))))
+        .unwrap_or_else(|| "--:--".to_string());
+    let volume = if player.is_muted() {
+        "muted".to_string()
+    } else {
+        format!("vol {}", player.volume)
+    };
+    let repeat = match player.repeat() {
+        RepeatMode::Off => "",
+        RepeatMode::All => "  repeat",
+        RepeatMode::One => "  repeat one",
+    };
+    let position = player
+        .playlist()
+        .current_index()
+        .map_or(0, |index| index + 1);
+
+    stdout.queue(cursor::MoveToColumn(0))?;
+    stdout.queue(terminal::Clear(terminal::ClearType::CurrentLine))?;
+    write!(
+        stdout,
+        "[{}/{}] {} {} / {}  {}  {}{}",
+        position,
+        player.playlist().len(),
+        state,
+        format_time(player.elapsed()),
+        duration,
+        title,
+        volume,
+        repeat
+    )?;
+    stdout.flush()
+}
+
+/// Returns `Artist - Title` from the tags of `path`, or its file name.
+fn track_title(path: &Path) -> String {
+    let track = Track::read_from_path(path).ok();
+    let tag = |get: fn(&Track) -> Option<&str>| track.as_ref().and_then(get).map(str::to_string);
+    match (tag(Track::artist), tag(Track::title)) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title,
+        _ => path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    }
+}
+
+/// Prints the tags, the format and the chapters of `path`.
+fn info(path: &Path) -> Result<(), String> {
+    let track =
+        Track::read_from_path(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    println!("File:     {}", path.display());
+    for (name, value) in [
+        ("Title", track.title()),
+        ("Artist", track.artist()),
+        ("Album", track.album()),
+    ] {
+        if let Some(value) = value {
+            println!("{:<9} {}", format!("{}:", name), value);
+        }
+    }
+
+    let decoder = File::open(path)
+        .ok()
+        .and_then(|file| Symphonia::new(file, false).ok());
+    if let Some(decoder) = decoder {
+        if let Some(duration) = decoder.total_duration() {
+            println!("Duration: {}", format_time(duration));
+        }
+        println!("Channels: {}", decoder.channels());
+        println!("Rate:     {} Hz", decoder.sample_rate());
+        if let Some(bits) = decoder.bits_per_sample() {
+            println!("Bits:     {}", bits);
+        }
+    }
+
+    let chapters = chapters::read(path);
+    if !chapters.is_empty() {
+        println!("Chapters:");
+        for chapter in chapters {
+            println!(
+                "  {}  {}",
+                format_time(chapter.start),
+                chapter.title.unwrap_or_default()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Prints the audio files in `folder` and their tags, one per line separated by tabs.
+fn scan(folder: &Path) -> Result<(), String> {
+    if !folder.is_dir() {
+        return Err(format!("{}: not a folder", folder.display()));
+    }
+    let library = Library::scan(&[folder.to_path_buf()]);
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for track in library.tracks() {
+        let field = |value: &Option<String>| value.clone().unwrap_or_default();
+        // Fails if the output was closed, e.g. by `head`.
+        if writeln!(
+            stdout,
+            "{}\t{}\t{}\t{}",
+            track.path.display(),
+            field(&track.artist),
+            field(&track.album),
+            field(&track.title)
+        )
+        .is_err()
+        {
+            break;
+        }
+    }
+    eprintln!("{} tracks", library.tracks().len());
+    Ok(())
+}
+
+/// Renders the files, and the audio files in the folders, one after the other into a 16-bit WAV
+/// file, at the sample rate and channels of the first one.
+fn render(output: &Path, paths: &[String]) -> Result<(), String> {
+    let tracks = find_tracks(paths)?;
+    let first = &tracks[0];
+    let decoder = File::open(first)
+        .ok()
+        .and_then(|file| Symphonia::new(file, true).ok())
+        .ok_or_else(|| format!("{}: not a supported audio file", first.display()))?;
+
+    let mut player = Player::new_offline(decoder.channels(), decoder.sample_rate());
+    player.set_volume(MAX_VOLUME);
+    player.set_playlist(tracks, Some(0));
+    let file = File::create(output).map_err(|err| format!("{}: {}", output.display(), err))?;
+    let (_, duration) =
+        render::render_to_wav(&mut player, BufWriter::new(file), WavFormat::Int16, None)
+            .map_err(|err| format!("{}: {}", output.display(), err))?;
+    eprintln!("{} rendered to {}", format_time(duration), output.display());
+    Ok(())
+}
+
+/// Exports the files, and the audio files in the folders, to a folder with the options given
+/// before it.
+fn export(args: &[String]) -> Result<(), String> {
+    let mut format = ExportFormat::Flac;
+    let mut template = None;
+    let mut workers = None;
+    let mut overwrite = false;
+    let mut args = args.iter();
+    let folder = loop {
+        let arg = args.next().ok_or(USAGE)?;
+        let mut value = || args.next().ok_or_else(|| format!("{}: missing value", arg));
+        match arg.as_str() {
+            "--format" => format = parse_format(value()?)?,
+            "--template" => template = Some(value()?.clone()),
+            "--jobs" => {
+                let jobs = value()?;
+                workers = Some(
+                    jobs.parse()
+                        .map_err(|_| format!("{}: not a number of tracks", jobs))?,
+                );
+            }
+            "--overwrite" => overwrite = true,
+            _ => break PathBuf::from(arg),
+        }
+    };
+    let paths: Vec<String> = args.cloned().collect();
+    if paths.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    let tracks = find_tracks(&paths)?;
+
+    let mut options = ExportOptions::new(folder, format);
+    options.template = template.unwrap_or(options.template);
+    options.workers = workers.unwrap_or(options.workers);
+    options.overwrite = overwrite;
+    let on_event = Box::new(|event: ExportEvent| match event {
+        ExportEvent::Track {
+            source,
+            result,
+            done,
+            total,
+            ..
+        } => {
+            let status = match result {
+                ExportResult::Exported(path) => format!("-> {}", path.display()),
+                ExportResult::Skipped(path) => format!("skipped, {} exists", path.display()),
+                ExportResult::Failed(err) => format!("failed: {}", err),
+            };
+            eprintln!("[{}/{}] {} {}", done, total, source.display(), status);
+        }
+        ExportEvent::Finished { .. } => {}
+    });
+    ExportJob::start(tracks, options, on_event)
+        .map_err(|err| err.to_string())?
+        .wait();
+    Ok(())
+}
+
+/// Parses a format such as `mp3:256`, with the bitrate or quality after a colon.
+fn parse_format(format: &str) -> Result<ExportFormat, String> {
+    let (name, setting) = match format.split_once(':') {
+        Some((name, setting)) => (name, Some(setting)),
+        None => (format, None),
+    };
+    let invalid = || format!("{}: unknown format", format);
+    let number = |default| {
+        setting.map_or(Ok(default), |setting| {
+            setting.parse().map_err(|_| invalid())
+        })
+    };
+    match name {
+        "flac" if setting.is_none() => Ok(ExportFormat::Flac),
+        "mp3" => Ok(ExportFormat::Mp3(number(256)?)),
+        "vorbis" | "ogg" => {
+            let quality =
+                setting.map_or(Ok(6.0), |setting| setting.parse().map_err(|_| invalid()))?;
+            Ok(ExportFormat::Vorbis(quality))
+        }
+        "opus" => Ok(ExportFormat::Opus(number(128)?)),
+        _ => Err(invalid()),
+    }
+}
+
+fn format_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
@@ -0,0 +1,9 @@
+//! Player core of Plain Music Player: decoding, output and playlists, the tags of tracks and the
+//! music library. The app and the command line player are built on it.
+
+/// The music library: the tracks of the library folders and their tags.
+pub mod library;
+/// Playback: decoding, output, playlists and the sleep timer.
+pub mod player;
+/// Reading and writing the tags of tracks.
+pub mod track;
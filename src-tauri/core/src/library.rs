@@ -0,0 +1,142 @@
+use crate::track::Track;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extensions of the files the decoder can play.
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "aac", "aif", "aiff", "flac", "m4a", "m4b", "mp3", "mp4", "oga", "ogg", "wav",
+];
+
+/// A track found in the music folders.
+#[derive(Clone, Debug, Serialize)]
+pub struct LibraryTrack {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+impl LibraryTrack {
+    /// Reads the tags of the file at `path`. Files whose tags can't be read are still listed.
+    pub fn read(path: PathBuf) -> Self {
+        let track = Track::read_from_path(&path).ok();
+        let tag =
+            |get: fn(&Track) -> Option<&str>| track.as_ref().and_then(get).map(str::to_string);
+        Self {
+            title: tag(Track::title),
+            artist: tag(Track::artist),
+            album: tag(Track::album),
+            path,
+        }
+    }
+
+    /// Returns `true` if every word of `words`, in lowercase, appears in the tags or the file
+    /// name.
+    fn matches(&self, words: &[String]) -> bool {
+        let file_name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase());
+        let fields: Vec<String> = [&self.title, &self.artist, &self.album]
+            .iter()
+            .filter_map(|field| field.as_ref().map(|field| field.to_lowercase()))
+            .chain(file_name)
+            .collect();
+        words
+            .iter()
+            .all(|word| fields.iter().any(|field| field.contains(word.as_str())))
+    }
+}
+
+/// Tracks of the music folders, sorted by path.
+#[derive(Clone, Debug, Default)]
+pub struct Library {
+    folders: Vec<PathBuf>,
+    tracks: Vec<LibraryTrack>,
+}
+
+impl Library {
+    /// Indexes the audio files in `folders` and their subfolders.
+    ///
+    /// Folders that can't be read are skipped, and symbolic links to folders aren't followed so
+    /// that a link to a parent doesn't loop forever.
+    pub fn scan(folders: &[PathBuf]) -> Self {
+        Self {
+            folders: folders.to_vec(),
+            tracks: find_audio_files(folders)
+                .into_iter()
+                .map(LibraryTrack::read)
+                .collect(),
+        }
+    }
+
+    /// Creates a library of `tracks` that were already read from `folders`.
+    pub fn from_tracks(folders: Vec<PathBuf>, mut tracks: Vec<LibraryTrack>) -> Self {
+        tracks.sort_by(|a, b| a.path.cmp(&b.path));
+        tracks.dedup_by(|a, b| a.path == b.path);
+        Self { folders, tracks }
+    }
+
+    /// Returns the folders that were indexed.
+    pub fn folders(&self) -> &[PathBuf] {
+        &self.folders
+    }
+
+    pub fn tracks(&self) -> &[LibraryTrack] {
+        &self.tracks
+    }
+
+    /// Returns the track at `path`, if it is in the library.
+    pub fn track(&self, path: &Path) -> Option<&LibraryTrack> {
+        self.tracks
+            .binary_search_by(|track| track.path.as_path().cmp(path))
+            .ok()
+            .map(|index| &self.tracks[index])
+    }
+
+    /// Returns the tracks whose tags or file name contain every word of `query`, ignoring case.
+    pub fn search(&self, query: &str) -> Vec<&LibraryTrack> {
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        self.tracks
+            .iter()
+            .filter(|track| track.matches(&words))
+            .collect()
+    }
+}
+
+/// Returns `true` if `path` has the extension of a file the decoder can play.
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| {
+            AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+        })
+}
+
+/// Returns the audio files in `folders` and their subfolders, sorted by path, without reading
+/// their tags. Symbolic links to folders aren't followed.
+pub fn find_audio_files(folders: &[PathBuf]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for folder in folders {
+        collect_audio_files(folder, &mut paths);
+    }
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+fn collect_audio_files(folder: &Path, paths: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => collect_audio_files(&path, paths),
+            Ok(_) if is_audio_file(&path) => paths.push(path),
+            _ => {}
+        }
+    }
+}
@@ -5,7 +5,7 @@
 //! # Example
 //!
 //! ```
-//! use rodio::buffer::SamplesBuffer;
+//! use plain_music_player::player::buffer::SamplesBuffer;
 //! let _ = SamplesBuffer::new(1, 44100, vec![1i16, 2, 3, 4, 5, 6]);
 //! ```
 //!
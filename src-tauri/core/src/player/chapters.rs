@@ -0,0 +1,543 @@
+//! Chapters of audiobooks and podcasts.
+//!
+//! Symphonia only exposes the cues of a few containers, so the chapter metadata of the two
+//! formats spoken-word files come in is read here: ID3v2 `CHAP`/`CTOC` frames at the start of
+//! MP3 files, and in MP4/M4B files either the Nero `chpl` atom or a QuickTime chapter track.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Serialize, Serializer};
+
+/// Size above which the `moov` atom isn't read, as it can't belong to a sane file.
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+/// Number of samples of a chapter track above which the track is considered broken.
+const MAX_CHAPTERS: usize = 10_000;
+
+/// A chapter of a track.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Chapter {
+    /// Title of the chapter, if it has one.
+    pub title: Option<String>,
+    /// Position of the start of the chapter in the track, serialized in milliseconds.
+    #[serde(rename = "start_ms", serialize_with = "serialize_millis")]
+    pub start: Duration,
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+/// Reads the chapters of the file at `path`, sorted by start. Files without chapters, or whose
+/// chapters can't be read, have none.
+pub fn read(path: &Path) -> Vec<Chapter> {
+    let mut chapters = File::open(path)
+        .and_then(|mut file| read_from(&mut file))
+        .unwrap_or_default();
+    chapters.sort_by_key(|chapter| chapter.start);
+    chapters
+}
+
+fn read_from(file: &mut File) -> io::Result<Vec<Chapter>> {
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    if &magic[..3] == b"ID3" {
+        read_id3(file)
+    } else if &magic[4..8] == b"ftyp" {
+        read_mp4(file)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+// ID3v2
+
+/// Reads the `CHAP` frames of the ID3v2 tag at the start of `file`.
+///
+/// When the tag has a top-level `CTOC` frame, only the chapters it lists, directly or through
+/// nested tables of contents, are kept.
+fn read_id3(file: &mut File) -> io::Result<Vec<Chapter>> {
+    let mut header = [0; 10];
+    file.read_exact(&mut header)?;
+    let version = header[3];
+    let flags = header[5];
+    // Chapters were added in ID3v2.3, and tags unsynchronised as a whole are too rare to bother.
+    if !(version == 3 || version == 4) || (version == 3 && flags & 0x80 != 0) {
+        return Ok(Vec::new());
+    }
+
+    let mut tag = vec![0; syncsafe(&header[6..10]) as usize];
+    file.read_exact(&mut tag)?;
+    let mut frames = tag.as_slice();
+    if flags & 0x40 != 0 {
+        // Skips the extended header, whose size includes itself only in ID3v2.4.
+        let size = match (version, frames.get(..4)) {
+            (3, Some(size)) => u32_be(size) as usize + 4,
+            (_, Some(size)) => syncsafe(size) as usize,
+            _ => return Ok(Vec::new()),
+        };
+        frames = frames.get(size..).unwrap_or_default();
+    }
+
+    let mut chapters: HashMap<String, Chapter> = HashMap::new();
+    let mut tables: HashMap<String, (bool, Vec<String>)> = HashMap::new();
+    for (id, body) in Id3Frames::new(frames, version) {
+        match id {
+            b"CHAP" => {
+                if let Some((element, chapter)) = parse_chap(body, version) {
+                    chapters.insert(element, chapter);
+                }
+            }
+            b"CTOC" => {
+                if let Some((element, table)) = parse_ctoc(body) {
+                    tables.insert(element, table);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let top_level = tables.values().find(|(top_level, _)| *top_level);
+    match top_level {
+        Some((_, children)) => {
+            let mut listed = Vec::new();
+            collect_chapters(children, &tables, &mut chapters, &mut listed, 0);
+            Ok(listed)
+        }
+        None => Ok(chapters.into_values().collect()),
+    }
+}
+
+/// Moves the chapters listed by `children` into `listed`, expanding nested tables of contents.
+fn collect_chapters(
+    children: &[String],
+    tables: &HashMap<String, (bool, Vec<String>)>,
+    chapters: &mut HashMap<String, Chapter>,
+    listed: &mut Vec<Chapter>,
+    depth: usize,
+) {
+    // Tables of contents that list themselves would never end.
+    if depth > 8 {
+        return;
+    }
+    for child in children {
+        if let Some(chapter) = chapters.remove(child) {
+            listed.push(chapter);
+        } else if let Some((_, nested)) = tables.get(child) {
+            collect_chapters(nested, tables, chapters, listed, depth + 1);
+        }
+    }
+}
+
+/// Parses a `CHAP` frame into its element ID and chapter.
+fn parse_chap(body: &[u8], version: u8) -> Option<(String, Chapter)> {
+    let (element, rest) = split_terminated(body)?;
+    let start_ms = u32_be(rest.get(..4)?);
+    // The end time and the byte offsets aren't needed.
+    let sub_frames = rest.get(16..)?;
+
+    let title = Id3Frames::new(sub_frames, version)
+        .find(|(id, _)| *id == b"TIT2")
+        .and_then(|(_, body)| parse_text(body));
+    let chapter = Chapter {
+        title,
+        start: Duration::from_millis(u64::from(start_ms)),
+    };
+    Some((latin1(element), chapter))
+}
+
+/// Parses a `CTOC` frame into its element ID, whether it is the top-level table of contents,
+/// and the element IDs of its children.
+fn parse_ctoc(body: &[u8]) -> Option<(String, (bool, Vec<String>))> {
+    let (element, rest) = split_terminated(body)?;
+    let flags = *rest.first()?;
+    let count = *rest.get(1)?;
+    let mut rest = rest.get(2..)?;
+
+    let mut children = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        let (child, after) = split_terminated(rest)?;
+        children.push(latin1(child));
+        rest = after;
+    }
+    Some((latin1(element), (flags & 0x02 != 0, children)))
+}
+
+/// Decodes the body of a text frame, made of an encoding byte and the text.
+fn parse_text(body: &[u8]) -> Option<String> {
+    let (&encoding, text) = body.split_first()?;
+    let text = match encoding {
+        0 => latin1(text),
+        1 => utf16_with_bom(text),
+        2 => utf16(text, u16::from_be_bytes),
+        3 => String::from_utf8_lossy(text).into_owned(),
+        _ => return None,
+    };
+    let text = text.trim_end_matches('\0').trim().to_string();
+    Some(text).filter(|text| !text.is_empty())
+}
+
+/// Iterator over the ID and body of the frames of an ID3v2 tag.
+struct Id3Frames<'a> {
+    data: &'a [u8],
+    version: u8,
+}
+
+impl<'a> Id3Frames<'a> {
+    fn new(data: &'a [u8], version: u8) -> Self {
+        Self { data, version }
+    }
+}
+
+impl<'a> Iterator for Id3Frames<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.data.get(..10)?;
+        let id = &header[..4];
+        // The padding after the last frame is made of zeros.
+        if id[0] == 0 {
+            return None;
+        }
+        let size = if self.version == 4 {
+            syncsafe(&header[4..8])
+        } else {
+            u32_be(&header[4..8])
+        } as usize;
+        let body = self.data.get(10..10 + size)?;
+        self.data = &self.data[10 + size..];
+
+        // Compressed, encrypted, grouped or unsynchronised frames are skipped.
+        let format_flags = header[9];
+        let unreadable = if self.version == 4 {
+            format_flags & 0x4F != 0
+        } else {
+            format_flags & 0xE0 != 0
+        };
+        if unreadable {
+            return self.next();
+        }
+        Some((id, body))
+    }
+}
+
+// MP4
+
+/// Reads the chapters of the `moov` atom of `file`, preferring the QuickTime chapter track to
+/// the Nero `chpl` atom when a file has both.
+fn read_mp4(file: &mut File) -> io::Result<Vec<Chapter>> {
+    let moov = match find_top_level_atom(file, b"moov")? {
+        Some(moov) => moov,
+        None => return Ok(Vec::new()),
+    };
+
+    let quicktime = read_chapter_track(file, &moov)?;
+    if !quicktime.is_empty() {
+        return Ok(quicktime);
+    }
+    Ok(Atoms::new(&moov)
+        .find(|(kind, _)| *kind == b"udta")
+        .and_then(|(_, udta)| Atoms::new(udta).find(|(kind, _)| *kind == b"chpl"))
+        .and_then(|(_, chpl)| parse_chpl(chpl))
+        .unwrap_or_default())
+}
+
+/// Returns the body of the top-level atom of type `kind`.
+fn find_top_level_atom(file: &mut File, kind: &[u8; 4]) -> io::Result<Option<Vec<u8>>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let mut position = 0;
+    while position + 8 <= file_len {
+        file.seek(SeekFrom::Start(position))?;
+        let mut header = [0; 8];
+        file.read_exact(&mut header)?;
+        let (header_len, size) = match u32_be(&header[..4]) {
+            0 => (8, file_len - position),
+            1 => {
+                let mut large = [0; 8];
+                file.read_exact(&mut large)?;
+                (16, u64::from_be_bytes(large))
+            }
+            size => (8, u64::from(size)),
+        };
+        if size < header_len {
+            break;
+        }
+
+        if &header[4..8] == kind {
+            if size > MAX_MOOV_SIZE {
+                break;
+            }
+            let mut body = vec![0; (size - header_len) as usize];
+            file.read_exact(&mut body)?;
+            return Ok(Some(body));
+        }
+        position += size;
+    }
+    Ok(None)
+}
+
+/// Parses a Nero `chpl` atom, whose start times are in units of 100 ns.
+fn parse_chpl(body: &[u8]) -> Option<Vec<Chapter>> {
+    let version = *body.first()?;
+    // Version 1 has 4 more reserved bytes after the flags.
+    let mut rest = body.get(if version == 0 { 4 } else { 8 }..)?;
+    let count = *rest.first()?;
+    rest = &rest[1..];
+
+    let mut chapters = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        let start = u64::from_be_bytes(rest.get(..8)?.try_into().ok()?);
+        let len = usize::from(*rest.get(8)?);
+        let title = String::from_utf8_lossy(rest.get(9..9 + len)?).into_owned();
+        rest = &rest[9 + len..];
+        // Starts past what a duration holds are corrupt.
+        if let Some(start) = start.checked_mul(100) {
+            chapters.push(Chapter {
+                title: Some(title).filter(|title| !title.is_empty()),
+                start: Duration::from_nanos(start),
+            });
+        }
+    }
+    Some(chapters)
+}
+
+/// Reads the chapters from the text track that the `chap` reference of an audio track points
+/// to.
+fn read_chapter_track(file: &mut File, moov: &[u8]) -> io::Result<Vec<Chapter>> {
+    let traks: Vec<&[u8]> = Atoms::new(moov)
+        .filter(|(kind, _)| *kind == b"trak")
+        .map(|(_, trak)| trak)
+        .collect();
+
+    let chapter_ids: Vec<u32> = traks
+        .iter()
+        .filter_map(|trak| child(trak, &[b"tref", b"chap"]))
+        .flat_map(|chap| chap.chunks_exact(4).map(u32_be))
+        .collect();
+    let trak = traks
+        .iter()
+        .find(|trak| track_id(trak).map_or(false, |id| chapter_ids.contains(&id)));
+    let samples = match trak.and_then(|trak| chapter_samples(trak)) {
+        Some(samples) => samples,
+        None => return Ok(Vec::new()),
+    };
+
+    let file_len = file.metadata()?.len();
+    let mut chapters = Vec::with_capacity(samples.len());
+    for (start, offset, size) in samples {
+        // Samples that don't fit in the file are corrupt, and aren't allocated.
+        let end = offset.checked_add(u64::from(size));
+        if end.map_or(true, |end| end > file_len) {
+            continue;
+        }
+        let mut sample = vec![0; size as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut sample)?;
+
+        // A text sample starts with the length of the text, which may be followed by
+        // extensions.
+        let title = sample.get(..2).and_then(|len| {
+            let len = usize::from(u16::from_be_bytes([len[0], len[1]]));
+            let text = sample.get(2..2 + len)?;
+            let title = if text.starts_with(&[0xFE, 0xFF]) || text.starts_with(&[0xFF, 0xFE]) {
+                utf16_with_bom(text)
+            } else {
+                String::from_utf8_lossy(text).into_owned()
+            };
+            Some(title).filter(|title| !title.is_empty())
+        });
+        chapters.push(Chapter { title, start });
+    }
+    Ok(chapters)
+}
+
+/// Returns the ID of the track from its `tkhd` atom.
+fn track_id(trak: &[u8]) -> Option<u32> {
+    let tkhd = child(trak, &[b"tkhd"])?;
+    let offset = if *tkhd.first()? == 1 { 20 } else { 12 };
+    tkhd.get(offset..offset + 4).map(u32_be)
+}
+
+/// Returns the start time, file offset and size of every sample of a track.
+fn chapter_samples(trak: &[u8]) -> Option<Vec<(Duration, u64, u32)>> {
+    let mdhd = child(trak, &[b"mdia", b"mdhd"])?;
+    let timescale_offset = if *mdhd.first()? == 1 { 20 } else { 12 };
+    let timescale = u64::from(u32_be(mdhd.get(timescale_offset..timescale_offset + 4)?));
+    if timescale == 0 {
+        return None;
+    }
+
+    let stbl = child(trak, &[b"mdia", b"minf", b"stbl"])?;
+    let table = |kind: &[u8; 4]| child(stbl, &[kind]).and_then(|atom| atom.get(8..));
+
+    // Time to sample: runs of samples with the same duration.
+    let mut starts = Vec::new();
+    let mut time: u64 = 0;
+    for entry in table(b"stts")?.chunks_exact(8) {
+        let (count, delta) = (u32_be(&entry[..4]), u64::from(u32_be(&entry[4..])));
+        for _ in 0..count {
+            if starts.len() == MAX_CHAPTERS {
+                return None;
+            }
+            // Split so that it can't overflow, whatever the time and the timescale.
+            let nanos = time % timescale * 1_000_000_000 / timescale;
+            starts.push(Duration::from_secs(time / timescale) + Duration::from_nanos(nanos));
+            time = time.saturating_add(delta);
+        }
+    }
+
+    // Sample sizes, either all the same or one per sample.
+    let stsz = child(stbl, &[b"stsz"])?;
+    let uniform_size = u32_be(stsz.get(4..8)?);
+    let sizes: Vec<u32> = if uniform_size == 0 {
+        stsz.get(12..)?.chunks_exact(4).map(u32_be).collect()
+    } else {
+        vec![uniform_size; starts.len()]
+    };
+
+    let offsets: Vec<u64> = match table(b"stco") {
+        Some(stco) => stco.chunks_exact(4).map(|o| u64::from(u32_be(o))).collect(),
+        None => table(b"co64")?
+            .chunks_exact(8)
+            .map(|o| u64::from_be_bytes([o[0], o[1], o[2], o[3], o[4], o[5], o[6], o[7]]))
+            .collect(),
+    };
+
+    // Sample to chunk: runs of chunks with the same number of samples, by first chunk.
+    let runs: Vec<(usize, u32)> = table(b"stsc")?
+        .chunks_exact(12)
+        .map(|entry| (u32_be(&entry[..4]) as usize, u32_be(&entry[4..8])))
+        .collect();
+
+    let mut samples = Vec::with_capacity(starts.len());
+    let mut sample = 0;
+    for (chunk, &chunk_offset) in offsets.iter().enumerate() {
+        let per_chunk = runs
+            .iter()
+            .rev()
+            .find(|(first, _)| *first <= chunk + 1)
+            .map_or(0, |&(_, count)| count);
+        let mut offset = chunk_offset;
+        for _ in 0..per_chunk {
+            let (start, size) = match (starts.get(sample), sizes.get(sample)) {
+                (Some(&start), Some(&size)) => (start, size),
+                _ => return Some(samples),
+            };
+            samples.push((start, offset, size));
+            offset += u64::from(size);
+            sample += 1;
+        }
+    }
+    Some(samples)
+}
+
+/// Returns the body of the atom found by following `path` from `atom`.
+fn child<'a>(atom: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().try_fold(atom, |atom, kind| {
+        Atoms::new(atom)
+            .find(|(found, _)| found == *kind)
+            .map(|(_, body)| body)
+    })
+}
+
+/// Iterator over the type and body of the atoms in the body of an MP4 atom.
+struct Atoms<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Atoms<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for Atoms<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.data.get(..8)?;
+        let (header_len, size) = match u32_be(&header[..4]) {
+            0 => (8, self.data.len()),
+            1 => {
+                let large = self.data.get(8..16)?;
+                (16, u64::from_be_bytes(large.try_into().ok()?) as usize)
+            }
+            size => (8, size as usize),
+        };
+        let body = self.data.get(header_len..size)?;
+        let kind = &header[4..8];
+        self.data = &self.data[size..];
+        Some((kind, body))
+    }
+}
+
+// Encodings
+
+fn u32_be(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Decodes a 28-bit integer stored in the low 7 bits of 4 bytes.
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .take(4)
+        .fold(0, |value, &byte| (value << 7) | u32::from(byte & 0x7F))
+}
+
+/// Splits `data` at the first zero byte, dropping it.
+fn split_terminated(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = data.iter().position(|&byte| byte == 0)?;
+    Some((&data[..end], &data[end + 1..]))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| char::from(byte)).collect()
+}
+
+fn utf16_with_bom(bytes: &[u8]) -> String {
+    match bytes {
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        _ => utf16(bytes, u16::from_be_bytes),
+    }
+}
+
+fn utf16(bytes: &[u8], decode: fn([u8; 2]) -> u16) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| decode([pair[0], pair[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the body of a version 0 `chpl` atom with `chapters`.
+    fn chpl(chapters: &[(u64, &str)]) -> Vec<u8> {
+        let mut body = vec![0, 0, 0, 0, chapters.len() as u8];
+        for (start, title) in chapters {
+            body.extend_from_slice(&start.to_be_bytes());
+            body.push(title.len() as u8);
+            body.extend_from_slice(title.as_bytes());
+        }
+        body
+    }
+
+    #[test]
+    fn skips_nero_chapters_starting_out_of_range() {
+        let chapters =
+            parse_chpl(&chpl(&[(0, "One"), (u64::MAX, "Two"), (20_000_000, "")])).unwrap();
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].title.as_deref(), Some("One"));
+        assert_eq!(chapters[1].title, None);
+        assert_eq!(chapters[1].start, Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,490 @@
+use super::Sample;
+
+use std::collections::VecDeque;
+use std::f32::consts::FRAC_1_SQRT_2;
+
+/// Speaker positions of the channels of a stream.
+///
+/// Each bit is a speaker, with the same values as Symphonia's `Channels`. Channels are
+/// interleaved in the order of their bits, from the lowest to the highest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChannelLayout(u32);
+
+// Bits of the speakers, named after their positions.
+#[allow(missing_docs)]
+impl ChannelLayout {
+    pub const FRONT_LEFT: u32 = 1 << 0;
+    pub const FRONT_RIGHT: u32 = 1 << 1;
+    pub const FRONT_CENTER: u32 = 1 << 2;
+    pub const LFE: u32 = 1 << 3;
+    pub const REAR_LEFT: u32 = 1 << 4;
+    pub const REAR_RIGHT: u32 = 1 << 5;
+    pub const FRONT_LEFT_CENTER: u32 = 1 << 6;
+    pub const FRONT_RIGHT_CENTER: u32 = 1 << 7;
+    pub const REAR_CENTER: u32 = 1 << 8;
+    pub const SIDE_LEFT: u32 = 1 << 9;
+    pub const SIDE_RIGHT: u32 = 1 << 10;
+    pub const TOP_CENTER: u32 = 1 << 11;
+    pub const TOP_FRONT_LEFT: u32 = 1 << 12;
+    pub const TOP_FRONT_CENTER: u32 = 1 << 13;
+    pub const TOP_FRONT_RIGHT: u32 = 1 << 14;
+    pub const TOP_REAR_LEFT: u32 = 1 << 15;
+    pub const TOP_REAR_CENTER: u32 = 1 << 16;
+    pub const TOP_REAR_RIGHT: u32 = 1 << 17;
+    pub const REAR_LEFT_CENTER: u32 = 1 << 18;
+    pub const REAR_RIGHT_CENTER: u32 = 1 << 19;
+    pub const FRONT_LEFT_WIDE: u32 = 1 << 20;
+    pub const FRONT_RIGHT_WIDE: u32 = 1 << 21;
+    pub const FRONT_LEFT_HIGH: u32 = 1 << 22;
+    pub const FRONT_CENTER_HIGH: u32 = 1 << 23;
+    pub const FRONT_RIGHT_HIGH: u32 = 1 << 24;
+    pub const LFE2: u32 = 1 << 25;
+}
+
+impl ChannelLayout {
+    /// Pairs of speakers exchanged by `ChannelOptions::swap_left_right`.
+    const LEFT_RIGHT_PAIRS: [(u32, u32); 9] = [
+        (Self::FRONT_LEFT, Self::FRONT_RIGHT),
+        (Self::REAR_LEFT, Self::REAR_RIGHT),
+        (Self::FRONT_LEFT_CENTER, Self::FRONT_RIGHT_CENTER),
+        (Self::SIDE_LEFT, Self::SIDE_RIGHT),
+        (Self::TOP_FRONT_LEFT, Self::TOP_FRONT_RIGHT),
+        (Self::TOP_REAR_LEFT, Self::TOP_REAR_RIGHT),
+        (Self::REAR_LEFT_CENTER, Self::REAR_RIGHT_CENTER),
+        (Self::FRONT_LEFT_WIDE, Self::FRONT_RIGHT_WIDE),
+        (Self::FRONT_LEFT_HIGH, Self::FRONT_RIGHT_HIGH),
+    ];
+
+    /// Builds a layout from a mask of speaker bits.
+    #[inline]
+    pub const fn from_mask(mask: u32) -> Self {
+        Self(mask)
+    }
+
+    /// Returns the usual layout for the given number of channels, as used by WAVE files and
+    /// by most audio devices.
+    pub const fn default_for(channels: cpal::ChannelCount) -> Self {
+        let front = Self::FRONT_LEFT | Self::FRONT_RIGHT;
+        let rear = Self::REAR_LEFT | Self::REAR_RIGHT;
+        let side = Self::SIDE_LEFT | Self::SIDE_RIGHT;
+        Self(match channels {
+            1 => Self::FRONT_CENTER,
+            2 => front,
+            3 => front | Self::FRONT_CENTER,
+            4 => front | rear,
+            5 => front | Self::FRONT_CENTER | rear,
+            6 => front | Self::FRONT_CENTER | Self::LFE | rear,
+            7 => front | Self::FRONT_CENTER | Self::LFE | Self::REAR_CENTER | side,
+            8 => front | Self::FRONT_CENTER | Self::LFE | rear | side,
+            n if n >= 32 => u32::MAX,
+            n => (1 << n) - 1,
+        })
+    }
+
+    /// Returns the mask of speaker bits.
+    #[inline]
+    pub const fn mask(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the number of channels.
+    #[inline]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn channels(self) -> cpal::ChannelCount {
+        self.0.count_ones() as cpal::ChannelCount
+    }
+
+    /// Returns `true` if the layout has all the given speakers.
+    #[inline]
+    const fn contains(self, speakers: u32) -> bool {
+        self.0 & speakers == speakers
+    }
+
+    /// Returns the position of the channel of `speaker` in a frame.
+    #[inline]
+    const fn index_of(self, speaker: u32) -> Option<usize> {
+        if self.contains(speaker) {
+            Some((self.0 & (speaker - 1)).count_ones() as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the speakers in the order of their channels.
+    fn speakers(self) -> impl Iterator<Item = u32> {
+        (0..32)
+            .map(|bit| 1 << bit)
+            .filter(move |speaker| self.0 & speaker != 0)
+    }
+}
+
+/// How the channels of the sources are adapted to the output, beyond matching the layouts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelOptions {
+    /// Plays a mono downmix of every source on the front speakers, for listeners who can only
+    /// hear from one side.
+    pub mono: bool,
+    /// Exchanges the left and right channels.
+    pub swap_left_right: bool,
+}
+
+/// Iterator that converts from a certain channel count to another.
+///
+/// Each output frame is computed from the input frame with a mixing matrix. Downmixes follow
+/// ITU-R BS.775: the center and surround channels are folded into the front channels at -3 dB.
+/// The LFE channel, which ITU drops, is folded into the center at -3 dB as well, so that 5.1
+/// material doesn't lose its bass. When the sum of the gains of an output channel is above 1,
+/// the whole matrix is scaled down to avoid clipping.
+///
+/// Upmixes only route each channel to the same speaker, except mono which is played at full
+/// level on both front speakers. No surround channel is synthesized.
+#[derive(Clone, Debug)]
+pub struct ChannelCountConverter<I>
+where
+    I: Iterator,
+{
+    input: I,
+    from: cpal::ChannelCount,
+    to: cpal::ChannelCount,
+    /// `to * from` gains, row by row, or `None` to pass frames through unchanged.
+    matrix: Option<Vec<f32>>,
+    /// The samples of the current input frame.
+    input_frame: Vec<f32>,
+    /// The samples of the current output frame waiting to be output.
+    output_frame: VecDeque<I::Item>,
+}
+
+impl<I> ChannelCountConverter<I>
+where
+    I: Iterator,
+{
+    /// Initializes the iterator, assuming the usual layouts for `from` and `to` channels.
+    ///
+    /// # Panic
+    ///
+    /// Panicks if `from` or `to` are equal to 0.
+    ///
+    #[inline]
+    pub fn new(input: I, from: cpal::ChannelCount, to: cpal::ChannelCount) -> Self {
+        Self::with_layouts(
+            input,
+            ChannelLayout::default_for(from),
+            ChannelLayout::default_for(to),
+            ChannelOptions::default(),
+        )
+    }
+
+    /// Initializes the iterator with the speaker positions of the input and the output.
+    ///
+    /// # Panic
+    ///
+    /// Panicks if either layout has no channel.
+    ///
+    pub fn with_layouts(
+        input: I,
+        from: ChannelLayout,
+        to: ChannelLayout,
+        options: ChannelOptions,
+    ) -> Self {
+        assert!(from.channels() >= 1);
+        assert!(to.channels() >= 1);
+
+        let matrix = if from == to && options == ChannelOptions::default() {
+            None
+        } else {
+            Some(mixing_matrix(from, to, options))
+        };
+
+        Self {
+            input,
+            from: from.channels(),
+            to: to.channels(),
+            matrix,
+            input_frame: Vec::with_capacity(usize::from(from.channels())),
+            output_frame: VecDeque::with_capacity(usize::from(to.channels())),
+        }
+    }
+
+    /// Destroys this iterator and returns the underlying iterator.
+    #[inline]
+    pub fn into_inner(self) -> I {
+        self.input
+    }
+}
+
+impl<I> Iterator for ChannelCountConverter<I>
+where
+    I: Iterator,
+    I::Item: Sample,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let matrix = match &self.matrix {
+            Some(matrix) => matrix,
+            None => return self.input.next(),
+        };
+
+        if let Some(sample) = self.output_frame.pop_front() {
+            return Some(sample);
+        }
+
+        self.input_frame.clear();
+        for _ in 0..self.from {
+            // A partial frame at the end of the input is dropped.
+            let sample = self.input.next()?;
+            self.input_frame.push(cpal::Sample::to_f32(&sample));
+        }
+
+        for row in matrix.chunks(usize::from(self.from)) {
+            let value: f32 = row
+                .iter()
+                .zip(&self.input_frame)
+                .map(|(gain, sample)| gain * sample)
+                .sum();
+            self.output_frame
+                .push_back(<I::Item as cpal::Sample>::from(&value.clamp(-1.0, 1.0)));
+        }
+
+        self.output_frame.pop_front()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.matrix.is_none() {
+            return self.input.size_hint();
+        }
+
+        let apply = |samples: usize| {
+            samples / usize::from(self.from) * usize::from(self.to) + self.output_frame.len()
+        };
+
+        let (min, max) = self.input.size_hint();
+        (apply(min), max.map(apply))
+    }
+}
+
+/// Computes the `to * from` gains applied to an input frame to get an output frame.
+fn mixing_matrix(from: ChannelLayout, to: ChannelLayout, options: ChannelOptions) -> Vec<f32> {
+    let from_count = usize::from(from.channels());
+    let to_count = usize::from(to.channels());
+
+    let mut matrix = if options.mono {
+        // Every front speaker plays the same mono downmix.
+        let mono = downmix_matrix(from, ChannelLayout::default_for(1));
+        let mut matrix = vec![0.0; to_count * from_count];
+        let fronts: Vec<usize> = [ChannelLayout::FRONT_LEFT, ChannelLayout::FRONT_RIGHT]
+            .iter()
+            .filter_map(|speaker| to.index_of(*speaker))
+            .collect();
+        let targets = if fronts.is_empty() {
+            to.index_of(ChannelLayout::FRONT_CENTER)
+                .map_or_else(|| vec![0], |index| vec![index])
+        } else {
+            fronts
+        };
+        for target in targets {
+            matrix[target * from_count..(target + 1) * from_count].copy_from_slice(&mono);
+        }
+        matrix
+    } else {
+        downmix_matrix(from, to)
+    };
+
+    if options.swap_left_right {
+        for &(left, right) in &ChannelLayout::LEFT_RIGHT_PAIRS {
+            if let (Some(left), Some(right)) = (to.index_of(left), to.index_of(right)) {
+                for column in 0..from_count {
+                    matrix.swap(left * from_count + column, right * from_count + column);
+                }
+            }
+        }
+    }
+
+    matrix
+}
+
+/// Computes the matrix that routes each input speaker to the closest output speakers, scaled
+/// down if any output channel could clip.
+fn downmix_matrix(from: ChannelLayout, to: ChannelLayout) -> Vec<f32> {
+    let from_count = usize::from(from.channels());
+    let mut matrix = vec![0.0; usize::from(to.channels()) * from_count];
+
+    let mono_to_front =
+        from.channels() == 1 && to.contains(ChannelLayout::FRONT_LEFT | ChannelLayout::FRONT_RIGHT);
+    for (column, speaker) in from.speakers().enumerate() {
+        let mut routes = Vec::new();
+        if mono_to_front && to.index_of(speaker).is_none() {
+            // Mono recordings keep their level on both front speakers.
+            routes.push((ChannelLayout::FRONT_LEFT, 1.0));
+            routes.push((ChannelLayout::FRONT_RIGHT, 1.0));
+        } else {
+            route(speaker, 1.0, to, 0, &mut routes);
+        }
+        for (target, gain) in routes {
+            if let Some(row) = to.index_of(target) {
+                matrix[row * from_count + column] += gain;
+            }
+        }
+    }
+
+    let max_sum = matrix
+        .chunks(from_count)
+        .map(|row| row.iter().sum::<f32>())
+        .fold(0.0, f32::max);
+    if max_sum > 1.0 {
+        for gain in &mut matrix {
+            *gain /= max_sum;
+        }
+    }
+    matrix
+}
+
+/// Appends to `routes` the output speakers that play `speaker`, and their gains.
+///
+/// Speakers missing from `to` are folded into neighbouring ones, recursively. `depth` stops
+/// the folding when no neighbour is available.
+fn route(speaker: u32, gain: f32, to: ChannelLayout, depth: u32, routes: &mut Vec<(u32, f32)>) {
+    type L = ChannelLayout;
+
+    if to.contains(speaker) {
+        routes.push((speaker, gain));
+        return;
+    }
+    if depth >= 3 {
+        return;
+    }
+
+    let mut fold = |target: u32, factor: f32| route(target, gain * factor, to, depth + 1, routes);
+    let either = |first: u32, second: u32| if to.contains(first) { first } else { second };
+
+    match speaker {
+        L::FRONT_CENTER | L::TOP_CENTER | L::TOP_FRONT_CENTER | L::FRONT_CENTER_HIGH => {
+            if speaker != L::FRONT_CENTER && to.contains(L::FRONT_CENTER) {
+                fold(L::FRONT_CENTER, FRAC_1_SQRT_2);
+            } else {
+                fold(L::FRONT_LEFT, FRAC_1_SQRT_2);
+                fold(L::FRONT_RIGHT, FRAC_1_SQRT_2);
+            }
+        }
+        L::FRONT_LEFT | L::FRONT_RIGHT => fold(L::FRONT_CENTER, FRAC_1_SQRT_2),
+        L::LFE | L::LFE2 => fold(L::FRONT_CENTER, FRAC_1_SQRT_2),
+        L::REAR_LEFT => fold(either(L::SIDE_LEFT, L::FRONT_LEFT), FRAC_1_SQRT_2),
+        L::REAR_RIGHT => fold(either(L::SIDE_RIGHT, L::FRONT_RIGHT), FRAC_1_SQRT_2),
+        L::SIDE_LEFT => fold(either(L::REAR_LEFT, L::FRONT_LEFT), FRAC_1_SQRT_2),
+        L::SIDE_RIGHT => fold(either(L::REAR_RIGHT, L::FRONT_RIGHT), FRAC_1_SQRT_2),
+        L::REAR_CENTER | L::TOP_REAR_CENTER => {
+            let (left, right) = if to.contains(L::REAR_LEFT | L::REAR_RIGHT) {
+                (L::REAR_LEFT, L::REAR_RIGHT)
+            } else if to.contains(L::SIDE_LEFT | L::SIDE_RIGHT) {
+                (L::SIDE_LEFT, L::SIDE_RIGHT)
+            } else {
+                (L::FRONT_LEFT, L::FRONT_RIGHT)
+            };
+            fold(left, FRAC_1_SQRT_2);
+            fold(right, FRAC_1_SQRT_2);
+        }
+        L::FRONT_LEFT_CENTER | L::FRONT_LEFT_WIDE => fold(L::FRONT_LEFT, 1.0),
+        L::FRONT_RIGHT_CENTER | L::FRONT_RIGHT_WIDE => fold(L::FRONT_RIGHT, 1.0),
+        L::TOP_FRONT_LEFT | L::FRONT_LEFT_HIGH => fold(L::FRONT_LEFT, FRAC_1_SQRT_2),
+        L::TOP_FRONT_RIGHT | L::FRONT_RIGHT_HIGH => fold(L::FRONT_RIGHT, FRAC_1_SQRT_2),
+        L::TOP_REAR_LEFT | L::REAR_LEFT_CENTER => fold(L::REAR_LEFT, FRAC_1_SQRT_2),
+        L::TOP_REAR_RIGHT | L::REAR_RIGHT_CENTER => fold(L::REAR_RIGHT, FRAC_1_SQRT_2),
+        // Unknown positions are dropped.
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::fixtures::sine;
+
+    fn convert(input: Vec<f32>, from: u16, to: u16, options: ChannelOptions) -> Vec<f32> {
+        ChannelCountConverter::with_layouts(
+            input.into_iter(),
+            ChannelLayout::default_for(from),
+            ChannelLayout::default_for(to),
+            options,
+        )
+        .collect()
+    }
+
+    fn assert_close(converted: &[f32], expected: &[f32]) {
+        assert_eq!(converted.len(), expected.len());
+        for (converted, expected) in converted.iter().zip(expected) {
+            assert!(
+                (converted - expected).abs() < 1e-6,
+                "{:?} instead of {:?}",
+                converted,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn passes_frames_through_when_layouts_match() {
+        let input = sine(2, 44_100, 100, 440.0, 0.5);
+        let converted: Vec<f32> =
+            ChannelCountConverter::new(input.clone().into_iter(), 2, 2).collect();
+        assert_eq!(converted, input);
+    }
+
+    #[test]
+    fn plays_mono_on_both_front_speakers() {
+        let converted = convert(
+            sine(1, 44_100, 100, 440.0, 0.5),
+            1,
+            2,
+            ChannelOptions::default(),
+        );
+        assert_eq!(converted, sine(2, 44_100, 100, 440.0, 0.5));
+    }
+
+    #[test]
+    fn downmixes_stereo_to_mono() {
+        let converted = convert(vec![0.2, 0.6, -0.4, 0.0], 2, 1, ChannelOptions::default());
+        assert_close(&converted, &[0.4, -0.2]);
+    }
+
+    #[test]
+    fn downmixes_surround_to_stereo_without_clipping() {
+        let frame = |values: [f32; 6]| convert(values.to_vec(), 6, 2, ChannelOptions::default());
+
+        let left = frame([1.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert!(left[0] > 0.0);
+        assert_eq!(left[1], 0.0);
+
+        let center = frame([0.0, 0.0, 1.0, 0.0, 0.0, 0.0]);
+        assert_close(&center, &[center[0], center[0]]);
+        assert_close(&[center[0]], &[left[0] * FRAC_1_SQRT_2]);
+
+        let surround_right = frame([0.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(surround_right[0], 0.0);
+        assert_close(&[surround_right[1]], &[left[0] * FRAC_1_SQRT_2]);
+
+        let full = frame([1.0; 6]);
+        assert!(full.iter().all(|&sample| sample > 0.0 && sample <= 1.0));
+    }
+
+    #[test]
+    fn applies_the_options() {
+        let swapped = ChannelOptions {
+            swap_left_right: true,
+            ..ChannelOptions::default()
+        };
+        assert_eq!(convert(vec![0.25, 0.5], 2, 2, swapped), vec![0.5, 0.25]);
+
+        let mono = ChannelOptions {
+            mono: true,
+            ..ChannelOptions::default()
+        };
+        assert_close(&convert(vec![0.2, 0.6], 2, 2, mono), &[0.4, 0.4]);
+    }
+
+    #[test]
+    fn drops_a_partial_frame_at_the_end() {
+        let converted = convert(vec![0.5, 0.5, 0.5], 2, 1, ChannelOptions::default());
+        assert_close(&converted, &[0.5]);
+    }
+}
@@ -2,10 +2,10 @@
 //!
 //! This includes conversion between sample formats, channels or sample rates.
 
-pub use self::channels::ChannelCountConverter;
+pub use self::channels::{ChannelCountConverter, ChannelLayout, ChannelOptions};
 pub use self::sample::DataConverter;
 pub use self::sample::Sample;
-pub use self::sample_rate::SampleRateConverter;
+pub use self::sample_rate::{ResamplerQuality, SampleRateConverter};
 
 mod channels;
 mod sample;
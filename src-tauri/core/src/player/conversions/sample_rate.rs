@@ -0,0 +1,609 @@
+use super::Sample;
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// Quality of the band-limited interpolation done by `SampleRateConverter`.
+///
+/// Higher qualities use longer filters: they keep more of the high frequencies and reject more
+/// of the aliases, at the cost of more computation per sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResamplerQuality {
+    /// 32 taps. Passband up to about 15 kHz for 44.1 kHz material.
+    Low,
+    /// 64 taps. Passband up to about 17.5 kHz for 44.1 kHz material.
+    Medium,
+    /// 128 taps. Passband up to about 19.5 kHz for 44.1 kHz material.
+    High,
+}
+
+impl Default for ResamplerQuality {
+    fn default() -> Self {
+        Self::Medium
+    }
+}
+
+impl ResamplerQuality {
+    /// Number of taps on each side of the interpolated point, at unity ratio.
+    const fn half_taps(self) -> usize {
+        match self {
+            Self::Low => 16,
+            Self::Medium => 32,
+            Self::High => 64,
+        }
+    }
+
+    /// Cutoff of the low-pass filter, relative to the Nyquist frequency of the lower rate.
+    const fn cutoff(self) -> f64 {
+        match self {
+            Self::Low => 0.80,
+            Self::Medium => 0.88,
+            Self::High => 0.94,
+        }
+    }
+
+    /// Beta parameter of the Kaiser window, which sets the stopband attenuation.
+    const fn kaiser_beta(self) -> f64 {
+        match self {
+            Self::Low => 6.0,
+            Self::Medium => 8.0,
+            Self::High => 10.0,
+        }
+    }
+}
+
+/// Above this number of phases, filter coefficients are computed for each output frame instead
+/// of being stored in a table.
+const MAX_TABLE_PHASES: u32 = 1024;
+
+/// Iterator that converts from a certain sample rate to another.
+///
+/// Each output frame is interpolated with a Kaiser-windowed sinc filter, which removes the
+/// frequencies that can't be represented at the lower of the two rates instead of letting them
+/// alias. The filter is stored as a polyphase table: one set of coefficients per position of an
+/// output frame between two input frames.
+#[derive(Clone, Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SampleRateConverter<I>
+where
+    I: Iterator,
+{
+    /// The iterator that gives us samples.
+    input: I,
+    /// We convert chunks of `from` samples into chunks of `to` samples.
+    from: u32,
+    /// We convert chunks of `from` samples into chunks of `to` samples.
+    to: u32,
+    /// Number of channels in the stream
+    channels: cpal::ChannelCount,
+    /// The filter applied to the input frames.
+    filter: PolyphaseFilter,
+    /// The input frames covered by the filter, interleaved. The output frame being computed lies
+    /// between frames `half_taps - 1` and `half_taps`.
+    window: VecDeque<f32>,
+    /// Position of the next output frame between the two middle frames of `window`, in
+    /// `1 / to` of an input frame.
+    phase: u32,
+    /// Number of input frames left from the middle of `window` on, once `input` is exhausted,
+    /// or `None` if it isn't yet.
+    remaining_frames: Option<usize>,
+    /// The samples of the current output frame waiting to be output.
+    output_buffer: VecDeque<I::Item>,
+}
+
+impl<I> SampleRateConverter<I>
+where
+    I: Iterator,
+    I::Item: Sample,
+{
+    ///
+    ///
+    /// # Panic
+    ///
+    /// Panics if `from` or `to` are equal to 0.
+    ///
+    #[inline]
+    pub fn new(
+        input: I,
+        from: cpal::SampleRate,
+        to: cpal::SampleRate,
+        num_channels: cpal::ChannelCount,
+        quality: ResamplerQuality,
+    ) -> Self {
+        let from = from.0;
+        let to = to.0;
+
+        assert!(from >= 1);
+        assert!(to >= 1);
+
+        // finding greatest common divisor
+        let gcd = {
+            #[inline]
+            fn gcd(a: u32, b: u32) -> u32 {
+                if b == 0 {
+                    a
+                } else {
+                    gcd(b, a % b)
+                }
+            }
+
+            gcd(from, to)
+        };
+
+        let filter = PolyphaseFilter::new(from / gcd, to / gcd, quality);
+
+        let mut converter = Self {
+            input,
+            from: from / gcd,
+            to: to / gcd,
+            channels: num_channels,
+            window: VecDeque::with_capacity(filter.taps() * num_channels as usize),
+            filter,
+            phase: 0,
+            remaining_frames: None,
+            output_buffer: VecDeque::with_capacity(num_channels as usize),
+        };
+
+        if converter.from != converter.to {
+            // Samples before the start of the input are silent.
+            let channels = usize::from(num_channels);
+            let half_taps = converter.filter.half_taps;
+            converter.window.resize((half_taps - 1) * channels, 0.0);
+            for _ in 0..=half_taps {
+                converter.push_input_frame();
+            }
+        }
+
+        converter
+    }
+
+    /// Destroys this iterator and returns the underlying iterator.
+    #[inline]
+    pub fn into_inner(self) -> I {
+        self.input
+    }
+
+    /// Appends the next input frame to the window, or a silent one if the input is exhausted.
+    fn push_input_frame(&mut self) {
+        let channels = usize::from(self.channels);
+
+        if self.remaining_frames.is_none() {
+            let start = self.window.len();
+            for _ in 0..channels {
+                match self.input.next() {
+                    Some(sample) => self.window.push_back(cpal::Sample::to_f32(&sample)),
+                    None => break,
+                }
+            }
+
+            if self.window.len() - start == channels {
+                return;
+            }
+
+            // A partial frame is dropped.
+            self.window.truncate(start);
+            self.remaining_frames = Some(start / channels + 1 - self.filter.half_taps);
+        }
+
+        self.window.resize(self.window.len() + channels, 0.0);
+    }
+
+    /// Moves the window one input frame forward.
+    fn next_input_frame(&mut self) {
+        self.window.drain(..usize::from(self.channels));
+        if let Some(remaining) = &mut self.remaining_frames {
+            *remaining = remaining.saturating_sub(1);
+        }
+        self.push_input_frame();
+    }
+}
+
+impl<I> Iterator for SampleRateConverter<I>
+where
+    I: Iterator,
+    I::Item: Sample + Clone,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        // the algorithm below doesn't work if `self.from == self.to`
+        if self.from == self.to {
+            debug_assert_eq!(self.from, 1);
+            return self.input.next();
+        }
+
+        // Short circuit if there are some samples waiting.
+        if let Some(sample) = self.output_buffer.pop_front() {
+            return Some(sample);
+        }
+
+        if self.remaining_frames == Some(0) {
+            return None;
+        }
+
+        // Filtering the window into `self.output_buffer`.
+        let channels = usize::from(self.channels);
+        let coefficients = self.filter.coefficients(self.phase);
+        for channel in 0..channels {
+            let value: f32 = coefficients
+                .iter()
+                .zip(self.window.iter().skip(channel).step_by(channels))
+                .map(|(coefficient, sample)| coefficient * sample)
+                .sum();
+            let value = value.clamp(-1.0, 1.0);
+            self.output_buffer
+                .push_back(<I::Item as cpal::Sample>::from(&value));
+        }
+
+        // Advancing to the position of the next output frame.
+        self.phase += self.from;
+        while self.phase >= self.to {
+            self.phase -= self.to;
+            self.next_input_frame();
+            if self.remaining_frames == Some(0) {
+                break;
+            }
+        }
+
+        self.output_buffer.pop_front()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.from == self.to {
+            return self.input.size_hint();
+        }
+
+        let channels = usize::from(self.channels);
+        let apply = |samples: usize| {
+            let input_frames = self.remaining_frames.unwrap_or_else(|| {
+                self.window.len() / channels + 1 - self.filter.half_taps + samples / channels
+            });
+            // Output frames are `from / to` input frames apart, starting `phase / to` after the
+            // middle of the window.
+            let positions =
+                (input_frames as u64 * u64::from(self.to)).saturating_sub(u64::from(self.phase));
+            let from = u64::from(self.from);
+            let frames = positions / from + u64::from(positions % from != 0);
+            frames as usize * channels + self.output_buffer.len()
+        };
+
+        let (min, max) = self.input.size_hint();
+        (apply(min), max.map(apply))
+    }
+}
+
+/// Coefficients of a windowed sinc low-pass filter, for each phase of a rational resampler.
+#[derive(Clone, Debug)]
+struct PolyphaseFilter {
+    /// Number of input frames on each side of an output frame.
+    half_taps: usize,
+    /// Number of phases, i.e. the `to` of the reduced ratio.
+    phases: u32,
+    /// Cutoff frequency, relative to the input Nyquist frequency.
+    cutoff: f64,
+    kaiser_beta: f64,
+    /// `phases * taps` coefficients, or empty if there are too many phases.
+    table: Vec<f32>,
+    /// Coefficients of the last phase, when they aren't in `table`.
+    scratch: Vec<f32>,
+}
+
+impl PolyphaseFilter {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn new(from: u32, to: u32, quality: ResamplerQuality) -> Self {
+        // When downsampling, the filter is stretched to cut at the output Nyquist frequency,
+        // which takes proportionally more input frames for the same quality.
+        let ratio = f64::from(to) / f64::from(from);
+        let stretch = (1.0 / ratio).max(1.0);
+        let half_taps = (quality.half_taps() as f64 * stretch).ceil() as usize;
+
+        let mut filter = Self {
+            half_taps,
+            phases: to,
+            cutoff: quality.cutoff() * ratio.min(1.0),
+            kaiser_beta: quality.kaiser_beta(),
+            table: Vec::new(),
+            scratch: Vec::new(),
+        };
+
+        if from != to && to <= MAX_TABLE_PHASES {
+            filter.table = (0..to).flat_map(|phase| filter.compute(phase)).collect();
+        }
+        filter
+    }
+
+    #[inline]
+    const fn taps(&self) -> usize {
+        self.half_taps * 2
+    }
+
+    /// Returns the coefficients to apply to the window when the output frame is `phase /
+    /// phases` of an input frame after the middle of the window.
+    #[inline]
+    fn coefficients(&mut self, phase: u32) -> &[f32] {
+        let taps = self.taps();
+        if self.table.is_empty() {
+            self.scratch = self.compute(phase);
+            &self.scratch
+        } else {
+            let start = phase as usize * taps;
+            &self.table[start..start + taps]
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn compute(&self, phase: u32) -> Vec<f32> {
+        let offset = f64::from(phase) / f64::from(self.phases);
+        let half_taps = self.half_taps as f64;
+        let i0_beta = bessel_i0(self.kaiser_beta);
+
+        let coefficients: Vec<f64> = (0..self.taps())
+            .map(|tap| {
+                // Distance between the input frame and the output frame, in input frames.
+                let x = tap as f64 - (half_taps - 1.0) - offset;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * self.cutoff * x).sin() / (PI * self.cutoff * x)
+                };
+                let w = x / half_taps;
+                let window = if w.abs() >= 1.0 {
+                    0.0
+                } else {
+                    bessel_i0(self.kaiser_beta * (1.0 - w * w).sqrt()) / i0_beta
+                };
+                sinc * window
+            })
+            .collect();
+
+        // Normalized so that a constant signal keeps its level for every phase.
+        let sum: f64 = coefficients.iter().sum();
+        coefficients.iter().map(|c| (c / sum) as f32).collect()
+    }
+}
+
+/// Zeroth-order modified Bessel function of the first kind.
+#[allow(clippy::cast_precision_loss)]
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x = x / 2.0;
+    for k in 1..50 {
+        term *= half_x / k as f64;
+        let squared = term * term;
+        sum += squared;
+        if squared < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResamplerQuality, SampleRateConverter};
+    use crate::player::fixtures::impulse;
+    use std::f64::consts::PI;
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn sine(frequency: f64, rate: u32, frames: usize, amplitude: f64) -> Vec<f32> {
+        (0..frames)
+            .map(|n| (amplitude * (2.0 * PI * frequency * n as f64 / f64::from(rate)).sin()) as f32)
+            .collect()
+    }
+
+    /// Linear sweep from `start` to `end` Hz.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn sweep(start: f64, end: f64, rate: u32, frames: usize, amplitude: f64) -> Vec<f32> {
+        let duration = frames as f64 / f64::from(rate);
+        (0..frames)
+            .map(|n| {
+                let t = n as f64 / f64::from(rate);
+                let phase = 2.0 * PI * (start * t + (end - start) * t * t / (2.0 * duration));
+                (amplitude * phase.sin()) as f32
+            })
+            .collect()
+    }
+
+    fn convert(input: Vec<f32>, from: u32, to: u32, quality: ResamplerQuality) -> Vec<f32> {
+        SampleRateConverter::new(
+            input.into_iter(),
+            cpal::SampleRate(from),
+            cpal::SampleRate(to),
+            1,
+            quality,
+        )
+        .collect()
+    }
+
+    /// Least-squares amplitude of a sine of the given frequency in `samples`.
+    #[allow(clippy::cast_precision_loss)]
+    fn amplitude(samples: &[f32], frequency: f64, rate: u32) -> f64 {
+        let (mut ss, mut cc, mut sc, mut ys, mut yc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (n, y) in samples.iter().enumerate() {
+            let phase = 2.0 * PI * frequency * n as f64 / f64::from(rate);
+            let (s, c) = phase.sin_cos();
+            let y = f64::from(*y);
+            ss += s * s;
+            cc += c * c;
+            sc += s * c;
+            ys += y * s;
+            yc += y * c;
+        }
+        let det = ss * cc - sc * sc;
+        let a = (ys * cc - yc * sc) / det;
+        let b = (yc * ss - ys * sc) / det;
+        (a * a + b * b).sqrt()
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn rms(samples: &[f32]) -> f64 {
+        let sum: f64 = samples.iter().map(|s| f64::from(*s).powi(2)).sum();
+        (sum / samples.len() as f64).sqrt()
+    }
+
+    fn db(ratio: f64) -> f64 {
+        20.0 * ratio.log10()
+    }
+
+    #[test]
+    fn passthrough_when_rates_match() {
+        let input = sine(1000.0, 44_100, 1000, 0.5);
+        let output = convert(input.clone(), 44_100, 44_100, ResamplerQuality::High);
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn output_length_follows_ratio() {
+        for &(from, to) in &[(44_100, 48_000), (48_000, 44_100), (22_050, 96_000)] {
+            let converter = SampleRateConverter::new(
+                vec![0.0_f32; 44_100].into_iter(),
+                cpal::SampleRate(from),
+                cpal::SampleRate(to),
+                1,
+                ResamplerQuality::Medium,
+            );
+            // One frame for each output position before the end of the input.
+            let expected = (44_100 * to as usize - 1) / from as usize + 1;
+            assert_eq!(converter.size_hint(), (expected, Some(expected)));
+            assert_eq!(converter.count(), expected, "{} -> {}", from, to);
+        }
+    }
+
+    #[test]
+    fn keeps_multichannel_frames_apart() {
+        let left = sine(440.0, 44_100, 4410, 0.5);
+        let input: Vec<f32> = left.iter().flat_map(|s| vec![*s, 0.0]).collect();
+        let output: Vec<f32> = SampleRateConverter::new(
+            input.into_iter(),
+            cpal::SampleRate(44_100),
+            cpal::SampleRate(48_000),
+            2,
+            ResamplerQuality::Medium,
+        )
+        .collect();
+
+        let right: Vec<f32> = output.iter().skip(1).step_by(2).copied().collect();
+        assert!(right.iter().all(|s| *s == 0.0));
+        let left: Vec<f32> = output.iter().step_by(2).copied().collect();
+        assert!((amplitude(&left[500..4000], 440.0, 48_000) - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn passband_ripple_on_stepped_sweep() {
+        let cases = [
+            (ResamplerQuality::Low, 14_000.0),
+            (ResamplerQuality::Medium, 17_000.0),
+            (ResamplerQuality::High, 19_000.0),
+        ];
+        for &(quality, passband_end) in &cases {
+            let mut frequency = 50.0;
+            while frequency <= passband_end {
+                let output = convert(
+                    sine(frequency, 44_100, 44_100, 0.5),
+                    44_100,
+                    48_000,
+                    quality,
+                );
+                // Skips the filter transients at both ends.
+                let gain = amplitude(&output[2000..output.len() - 2000], frequency, 48_000) / 0.5;
+                assert!(
+                    db(gain).abs() < 0.1,
+                    "{:?}: {:.3} dB at {} Hz",
+                    quality,
+                    db(gain),
+                    frequency
+                );
+                frequency *= 1.5;
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_aliases_when_downsampling() {
+        let cases = [
+            (ResamplerQuality::Low, -50.0),
+            (ResamplerQuality::Medium, -70.0),
+            (ResamplerQuality::High, -85.0),
+        ];
+        for &(quality, max_db) in &cases {
+            // Everything in this sweep is above the output Nyquist frequency, so anything left
+            // in the output is an alias.
+            let input = sweep(23_000.0, 44_000.0, 96_000, 96_000, 0.5);
+            let output = convert(input, 96_000, 44_100, quality);
+            let alias = db(rms(&output[2000..output.len() - 2000]) / (0.5 / 2.0_f64.sqrt()));
+            assert!(alias < max_db, "{:?}: aliases at {:.1} dB", quality, alias);
+        }
+    }
+
+    #[test]
+    fn rejects_images_when_upsampling() {
+        // Linear interpolation leaves images of a 15 kHz tone at 44.1 - 15 = 29.1 kHz.
+        let output = convert(
+            sine(15_000.0, 44_100, 44_100, 0.5),
+            44_100,
+            96_000,
+            ResamplerQuality::Medium,
+        );
+        let output = &output[4000..output.len() - 4000];
+        let image = amplitude(output, 29_100.0, 96_000) / 0.5;
+        assert!(db(image) < -70.0, "image at {:.1} dB", db(image));
+    }
+
+    #[test]
+    fn keeps_dc_level() {
+        for &(from, to) in &[(44_100, 48_000), (48_000, 44_100), (44_100, 96_000)] {
+            let output = convert(vec![0.5; 20_000], from, to, ResamplerQuality::High);
+            let middle = &output[output.len() / 4..output.len() * 3 / 4];
+            for sample in middle {
+                assert!(
+                    (sample - 0.5).abs() < 1e-3,
+                    "{} -> {}: {}",
+                    from,
+                    to,
+                    sample
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[allow(clippy::cast_precision_loss)]
+    fn keeps_impulses_in_time() {
+        for &(from, to) in &[(44_100, 48_000), (48_000, 44_100), (22_050, 96_000)] {
+            let output = convert(
+                impulse(1, 4_000, 1_000, 0.8),
+                from,
+                to,
+                ResamplerQuality::High,
+            );
+            let peak = output
+                .iter()
+                .enumerate()
+                .fold((0, 0.0_f32), |peak, (index, sample)| {
+                    if sample.abs() > peak.1 {
+                        (index, sample.abs())
+                    } else {
+                        peak
+                    }
+                })
+                .0;
+            let expected = 1_000.0 * f64::from(to) / f64::from(from);
+            assert!(
+                (peak as f64 - expected).abs() <= 1.0,
+                "{} -> {}: peak at {} instead of {}",
+                from,
+                to,
+                peak,
+                expected
+            );
+        }
+    }
+}
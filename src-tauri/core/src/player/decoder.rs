@@ -0,0 +1,631 @@
+use super::Source;
+use crate::player::source::{
+    Amplify, FadeIn, Pausable, PeriodicAccess, SamplesConverter, Speed, Stoppable, TakeDuration,
+};
+use crate::player::{ChannelLayout, Sample};
+use std::cmp::max;
+use std::{fmt, fs::File, time::Duration};
+use symphonia::{
+    core::{
+        audio::{AudioBufferRef, SampleBuffer, SignalSpec},
+        codecs::{self, CodecParameters},
+        errors::Error,
+        formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+        io::{MediaSourceStream, MediaSourceStreamOptions},
+        meta::MetadataOptions,
+        probe::Hint,
+        units::{Time, TimeBase},
+    },
+    default::get_probe,
+};
+
+// Decoder errors are not considered fatal.
+// The correct action is to just get a new packet and try again.
+// But a decode error in more than 3 consecutive packets is fatal.
+const MAX_DECODE_ERRORS: usize = 3;
+/// Duration in milliseconds of the fades around the jump back of an A-B loop.
+const LOOP_FADE_MILLIS: u64 = 5;
+
+/// Decodes a file with Symphonia into interleaved `i16` samples.
+pub struct Symphonia {
+    decoder: Box<dyn codecs::Decoder>,
+    current_frame_offset: usize,
+    format: Box<dyn FormatReader>,
+    buffer: SampleBuffer<i16>,
+    spec: SignalSpec,
+    bits_per_sample: Option<u32>,
+    duration: Duration,
+    elapsed: Duration,
+    fade_in_remaining_ns: f32,
+    fade_in_total_ns: f32,
+    fade_out_remaining_ns: f32,
+    fade_out_total_ns: f32,
+    is_seeking_soon: bool,
+    seek_to_time: Duration,
+    /// Index of the first frame of `buffer`, counted from the start of the stream.
+    packet_frame: u64,
+    /// Start and end of the A-B loop.
+    loop_region: Option<(Duration, Duration)>,
+    /// Start and end of the A-B loop, in frames.
+    loop_frames: Option<(u64, u64)>,
+    /// Frames before this one are skipped after jumping back to the start of the loop.
+    loop_skip_to: Option<u64>,
+    /// End of the fade in after jumping back to the start of the loop.
+    loop_fade_in_until: u64,
+}
+
+impl Symphonia {
+    /// Opens `file`. With `gapless`, the encoder delay and padding are trimmed.
+    pub fn new(file: File, gapless: bool) -> Result<Self, SymphoniaDecoderError> {
+        let source = Box::new(file);
+
+        let mss = MediaSourceStream::new(source, MediaSourceStreamOptions::default());
+        match Self::init(mss, gapless) {
+            Err(e) => match e {
+                Error::IoError(e) => Err(SymphoniaDecoderError::IoError(e.to_string())),
+                Error::DecodeError(e) => Err(SymphoniaDecoderError::DecodeError(e)),
+                Error::SeekError(_) => {
+                    unreachable!("Seek errors should not occur during initialization")
+                }
+                Error::Unsupported(_) => Err(SymphoniaDecoderError::UnrecognizedFormat),
+                Error::LimitError(e) => Err(SymphoniaDecoderError::LimitError(e)),
+                Error::ResetRequired => Err(SymphoniaDecoderError::ResetRequired),
+            },
+            Ok(Some(decoder)) => Ok(decoder),
+            Ok(None) => Err(SymphoniaDecoderError::NoStreams),
+        }
+    }
+
+    fn init(
+        mss: MediaSourceStream,
+        gapless: bool,
+    ) -> symphonia::core::errors::Result<Option<Self>> {
+        let mut probed = get_probe().format(
+            &Hint::default(),
+            mss,
+            &FormatOptions {
+                prebuild_seek_index: true,
+                seek_index_fill_rate: 10,
+                enable_gapless: gapless,
+                // enable_gapless: false,
+            },
+            &MetadataOptions::default(),
+        )?;
+
+        let track = match probed.format.default_track() {
+            Some(stream) => stream,
+            None => return Ok(None),
+        };
+
+        let mut decoder = symphonia::default::get_codecs().make(
+            &track.codec_params,
+            &codecs::DecoderOptions { verify: true },
+        )?;
+
+        let duration = Self::get_duration(&track.codec_params);
+        let bits_per_sample = track.codec_params.bits_per_sample;
+
+        let mut decode_errors: usize = 0;
+        let decode_result = loop {
+            let current_frame = probed.format.next_packet()?;
+            match decoder.decode(&current_frame) {
+                Ok(result) => break result,
+                Err(e) => match e {
+                    Error::DecodeError(_) => {
+                        decode_errors += 1;
+                        if decode_errors > MAX_DECODE_ERRORS {
+                            return Err(e);
+                        }
+                    }
+                    _ => return Err(e),
+                },
+            }
+        };
+        let spec = *decode_result.spec();
+        let buffer = Self::get_buffer(decode_result, spec);
+
+        Ok(Some(Self {
+            decoder,
+            current_frame_offset: 0,
+            format: probed.format,
+            buffer,
+            spec,
+            bits_per_sample,
+            duration,
+            elapsed: Duration::from_secs(0),
+            fade_in_remaining_ns: 0.0,
+            fade_in_total_ns: 0.0,
+            fade_out_remaining_ns: 0.0,
+            fade_out_total_ns: 0.0,
+            is_seeking_soon: false,
+            seek_to_time: Duration::from_secs(0),
+            packet_frame: 0,
+            loop_region: None,
+            loop_frames: None,
+            loop_skip_to: None,
+            loop_fade_in_until: 0,
+        }))
+    }
+
+    fn get_duration(params: &CodecParameters) -> Duration {
+        // if let Some(n_frames) = params.n_frames {
+        //     if let Some(tb) = params.time_base {
+        //         let time = tb.calc_time(n_frames);
+        //         Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac)
+        //     } else {
+        //         panic!("no time base?");
+        //     }
+        // } else {
+        //     panic!("no n_frames");
+        // }
+
+        params.n_frames.map_or_else(
+            || {
+                // panic!("no n_frames");
+                Duration::from_secs(99)
+            },
+            |n_frames| {
+                params.time_base.map_or_else(
+                    || {
+                        // panic!("no time base?");
+                        Duration::from_secs(199)
+                    },
+                    |tb| {
+                        let time = tb.calc_time(n_frames);
+                        Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac)
+                    },
+                )
+            },
+        )
+    }
+
+    /// Returns the resolution of the encoded samples, if the codec has one.
+    ///
+    /// Lossy codecs such as MP3 or AAC don't.
+    pub fn bits_per_sample(&self) -> Option<u32> {
+        self.bits_per_sample
+    }
+
+    /// Returns the start of each chapter, from the cues of the container.
+    pub fn chapter_starts(&self) -> Vec<Duration> {
+        let time_base = self
+            .format
+            .default_track()
+            .and_then(|track| track.codec_params.time_base);
+        let mut starts: Vec<Duration> = time_base.map_or_else(Vec::new, |time_base| {
+            self.format
+                .cues()
+                .iter()
+                .map(|cue| {
+                    let time = time_base.calc_time(cue.start_ts);
+                    Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac)
+                })
+                .collect()
+        });
+        starts.sort_unstable();
+        starts
+    }
+
+    /// Seeks right away, without the fade out used by `Source::seek`.
+    ///
+    /// Meant to be used before the decoder starts playing, e.g. to resume a track at a given
+    /// position. Returns the time actually seeked to.
+    pub fn seek_immediately(&mut self, time: Duration) -> Option<Duration> {
+        self.seek_to_time = time;
+        let seeked_to = self.seek_format();
+        if seeked_to.is_some() {
+            // Discards the samples decoded before the seek.
+            self.current_frame_offset = self.buffer.len();
+        }
+        seeked_to
+    }
+
+    /// Seeks the format reader to `seek_to_time` and updates `elapsed`.
+    fn seek_format(&mut self) -> Option<Duration> {
+        let seeked_to = self
+            .format
+            .seek(
+                SeekMode::Coarse,
+                // Symphonia seems to seek about 0.05s before the specified time (not earlier than 0.05s).
+                // Then, at the moment of seek, the time 1 second earlier is displayed on the UI.
+                // To solve this problem, seek to the time that adds 0.05s.
+                SeekTo::Time {
+                    time: Time::from(self.seek_to_time.as_secs_f64() + 0.05),
+                    track_id: None,
+                },
+            )
+            .ok()?;
+
+        let base = TimeBase::new(1, self.sample_rate());
+        let time = base.calc_time(seeked_to.actual_ts);
+        let duration = Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac);
+        self.elapsed = duration;
+        Some(duration)
+    }
+
+    /// Decodes the next packet into `buffer`. Returns `false` at the end of the stream.
+    fn load_packet(&mut self) -> bool {
+        let mut decode_errors: usize = 0;
+        let decoded = loop {
+            match self.format.next_packet() {
+                Ok(packet) => match self.decoder.decode(&packet) {
+                    Ok(decoded) => {
+                        let ts = packet.ts();
+                        if let Some(track) = self.format.default_track() {
+                            if let Some(tb) = track.codec_params.time_base {
+                                let t = tb.calc_time(ts);
+                                self.elapsed = Duration::from_secs(t.seconds)
+                                    + Duration::from_secs_f64(t.frac);
+                            }
+                        }
+                        break decoded;
+                    }
+                    Err(e) => match e {
+                        Error::DecodeError(_) => {
+                            decode_errors += 1;
+                            if decode_errors > MAX_DECODE_ERRORS {
+                                return false;
+                            }
+                        }
+                        _ => return false,
+                    },
+                },
+                Err(_) => return false,
+            }
+        };
+        self.spec = *decoded.spec();
+        self.buffer = Self::get_buffer(decoded, self.spec);
+        self.current_frame_offset = 0;
+        self.packet_frame = self.frame_at(self.elapsed);
+        true
+    }
+
+    /// Returns the index of the frame of the next sample, counted from the start of the stream.
+    #[allow(clippy::cast_possible_truncation)]
+    fn current_frame(&self) -> u64 {
+        let channels = self.spec.channels.count().max(1);
+        self.packet_frame + (self.current_frame_offset / channels) as u64
+    }
+
+    /// Returns the index of the frame played at `time`.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn frame_at(&self, time: Duration) -> u64 {
+        (time.as_secs_f64() * f64::from(self.spec.rate)).round() as u64
+    }
+
+    /// Returns the number of frames of the fades at the ends of the loop.
+    fn loop_fade_frames(&self) -> u64 {
+        (u64::from(self.spec.rate) * LOOP_FADE_MILLIS / 1000).max(1)
+    }
+
+    /// Seeks back to the start of the loop. Returns `false`, and stops looping, if the stream
+    /// can't seek.
+    fn jump_to_loop_start(&mut self) -> bool {
+        let (start, start_frame) = match (self.loop_region, self.loop_frames) {
+            (Some((start, _)), Some((start_frame, _))) => (start, start_frame),
+            _ => return false,
+        };
+
+        let seeked = self.format.seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: Time::from(start.as_secs_f64()),
+                track_id: None,
+            },
+        );
+        if seeked.is_err() {
+            self.loop_frames = None;
+            return false;
+        }
+
+        self.decoder.reset();
+        // Discards the rest of the packet, the next one is decoded from the new position.
+        self.current_frame_offset = self.buffer.len();
+        self.loop_skip_to = Some(start_frame);
+        self.loop_fade_in_until = start_frame + self.loop_fade_frames();
+        true
+    }
+
+    /// Fades the sound out right before the end of the loop, and in right after jumping back to
+    /// its start, so that the jump doesn't click.
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    fn apply_loop_fade(&self, frame: u64, sample: i16) -> i16 {
+        let end = match self.loop_frames {
+            Some((_, end)) => end,
+            None => return sample,
+        };
+        let fade = self.loop_fade_frames();
+
+        let mut factor: f32 = 1.0;
+        let to_end = end.saturating_sub(frame);
+        if to_end < fade {
+            factor = to_end as f32 / fade as f32;
+        }
+        if frame < self.loop_fade_in_until {
+            let to_full = self.loop_fade_in_until - frame;
+            factor = factor.min(fade.saturating_sub(to_full) as f32 / fade as f32);
+        }
+        (f32::from(sample) * factor) as i16
+    }
+
+    #[inline]
+    fn get_buffer(decoded: AudioBufferRef, spec: SignalSpec) -> SampleBuffer<i16> {
+        let duration = decoded.capacity() as u64;
+        let mut buffer = SampleBuffer::<i16>::new(duration * 10, spec);
+        buffer.copy_interleaved_ref(decoded);
+        buffer
+    }
+}
+
+impl Source for Symphonia {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.buffer.samples().len())
+    }
+
+    #[inline]
+    #[allow(clippy::cast_possible_truncation)]
+    fn channels(&self) -> u16 {
+        self.spec.channels.count() as u16
+    }
+
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        Some(ChannelLayout::from_mask(self.spec.channels.bits()))
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.spec.rate
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        Some(self.duration)
+    }
+
+    #[inline]
+    fn elapsed(&mut self) -> Duration {
+        self.elapsed
+    }
+
+    #[inline]
+    fn fade_in_from_now(&mut self, duration: Duration) {
+        let duration = duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos());
+
+        self.fade_in_remaining_ns = duration as f32;
+        self.fade_in_total_ns = duration as f32;
+    }
+
+    #[inline]
+    fn fade_out_from_now(&mut self, duration: Duration) {
+        let duration = duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos());
+
+        self.fade_out_remaining_ns = duration as f32;
+        self.fade_out_total_ns = duration as f32;
+    }
+
+    fn set_loop_region(&mut self, region: Option<(Duration, Duration)>) {
+        if region == self.loop_region {
+            return;
+        }
+        self.loop_region = region;
+        self.loop_frames = region.map(|(start, end)| (self.frame_at(start), self.frame_at(end)));
+        self.loop_skip_to = None;
+        self.loop_fade_in_until = 0;
+    }
+
+    #[inline]
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    fn seek(&mut self, time: Duration) -> Option<Duration> {
+        // Suppresses noise at the moment of seek.
+        self.fade_out_from_now(Duration::from_millis(10));
+        self.is_seeking_soon = true;
+        self.seek_to_time = time;
+        Some(Duration::from_secs(0))
+    }
+}
+
+impl Iterator for Symphonia {
+    type Item = i16;
+
+    #[inline]
+    fn next(&mut self) -> Option<i16> {
+        let (frame, mut sample) = loop {
+            if self.current_frame_offset == self.buffer.len() && !self.load_packet() {
+                return None;
+            }
+
+            let frame = self.current_frame();
+            if self.loop_frames.map_or(false, |(_, end)| frame >= end) && self.jump_to_loop_start()
+            {
+                continue;
+            }
+
+            let sample = self.buffer.samples()[self.current_frame_offset];
+            self.current_frame_offset += 1;
+
+            // After jumping back, the packet containing the start of the loop is played from
+            // the start of the loop only.
+            match self.loop_skip_to {
+                Some(start) if frame < start => continue,
+                Some(_) => self.loop_skip_to = None,
+                None => {}
+            }
+            break (frame, sample);
+        };
+        sample = self.apply_loop_fade(frame, sample);
+
+        if self.fade_out_remaining_ns > 0.0 {
+            let fade_out_factor =
+                (self.fade_out_remaining_ns / self.fade_out_total_ns * 2.0 - 1.0).max(0.0);
+            self.fade_out_remaining_ns -=
+                1_000_000_000.0 / (self.sample_rate() as f32 * f32::from(self.channels()));
+
+            sample = (sample as f32 * fade_out_factor) as i16;
+        } else if self.is_seeking_soon {
+            if self.seek_format().is_some() {
+                // Suppresses noise at the moment of seek.
+                self.fade_in_from_now(Duration::from_millis(100));
+            }
+
+            self.is_seeking_soon = false;
+        }
+
+        if self.fade_in_remaining_ns > 0.0 {
+            let fade_in_factor =
+                ((1.0 - self.fade_in_remaining_ns / self.fade_in_total_ns) * 2.0 - 1.0).max(0.0);
+            self.fade_in_remaining_ns -=
+                1_000_000_000.0 / (self.sample_rate() as f32 * f32::from(self.channels()));
+
+            sample = (sample as f32 * fade_in_factor) as i16;
+        }
+
+        Some(sample)
+    }
+}
+
+/// Error that can happen when creating a decoder.
+#[derive(Debug, Clone)]
+pub enum SymphoniaDecoderError {
+    /// The format of the data has not been recognized.
+    UnrecognizedFormat,
+
+    /// An IO error occured while reading, writing, or seeking the stream.
+    IoError(String),
+
+    /// The stream contained malformed data and could not be decoded or demuxed.
+    DecodeError(&'static str),
+
+    /// A default or user-defined limit was reached while decoding or demuxing the stream. Limits
+    /// are used to prevent denial-of-service attacks from malicious streams.
+    LimitError(&'static str),
+
+    /// The demuxer or decoder needs to be reset before continuing.
+    ResetRequired,
+
+    /// No streams were found by the decoder
+    NoStreams,
+}
+
+impl fmt::Display for SymphoniaDecoderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Self::UnrecognizedFormat => "Unrecognized format",
+            Self::IoError(msg) => &msg[..],
+            Self::DecodeError(msg) | Self::LimitError(msg) => msg,
+            Self::ResetRequired => "Reset required",
+            Self::NoStreams => "No streams",
+        };
+        write!(f, "{}", text)
+    }
+}
+impl std::error::Error for SymphoniaDecoderError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::fixtures::{impulse, quantize, silence, sine, FlacFixture, WavFixture};
+    use crate::player::render::WavFormat;
+
+    fn assert_close(decoded: &[i16], expected: &[i16], tolerance: i16) {
+        assert_eq!(decoded.len(), expected.len());
+        for (index, (decoded, expected)) in decoded.iter().zip(expected).enumerate() {
+            assert!(
+                (decoded - expected).abs() <= tolerance,
+                "sample {}: {} instead of {}",
+                index,
+                decoded,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_16_bit_samples_exactly() {
+        let samples = sine(2, 44_100, 4_410, 440.0, 0.5);
+        let fixture = WavFixture::new(&samples, 2, 44_100, WavFormat::Int16);
+        let decoder = fixture.decode();
+        assert_eq!(decoder.channels(), 2);
+        assert_eq!(decoder.sample_rate(), 44_100);
+        assert_eq!(decoder.bits_per_sample(), Some(16));
+        assert_eq!(decoder.total_duration(), Some(Duration::from_millis(100)));
+
+        assert_eq!(decoder.collect::<Vec<_>>(), quantize(&samples));
+    }
+
+    #[test]
+    fn decodes_24_bit_and_float_samples() {
+        let samples = sine(1, 48_000, 4_800, 1_000.0, 0.9);
+        for format in [WavFormat::Int24, WavFormat::Float32] {
+            let fixture = WavFixture::new(&samples, 1, 48_000, format);
+            let decoder = fixture.decode();
+            assert_eq!(decoder.channels(), 1);
+            assert_eq!(decoder.sample_rate(), 48_000);
+            if format == WavFormat::Int24 {
+                assert_eq!(decoder.bits_per_sample(), Some(24));
+            }
+
+            assert_close(&decoder.collect::<Vec<_>>(), &quantize(&samples), 1);
+        }
+    }
+
+    #[test]
+    fn keeps_silence_and_impulses_in_place() {
+        let mut samples = silence(2, 1_000);
+        samples.extend(impulse(2, 1_000, 600, -0.75));
+        let fixture = WavFixture::new(&samples, 2, 44_100, WavFormat::Int16);
+
+        let decoded: Vec<i16> = fixture.decode().collect();
+        assert_eq!(decoded, quantize(&samples));
+        let peak = decoded.iter().position(|&sample| sample != 0);
+        assert_eq!(peak, Some(1_600 * 2));
+    }
+
+    #[test]
+    fn seeks_to_the_frame_it_reports() {
+        let samples = sine(2, 44_100, 44_100, 220.0, 0.5);
+        let fixture = WavFixture::new(&samples, 2, 44_100, WavFormat::Int16);
+        let mut decoder = fixture.decode();
+
+        let seeked_to = decoder
+            .seek_immediately(Duration::from_millis(500))
+            .unwrap();
+        assert!(seeked_to >= Duration::from_millis(500));
+        assert!(seeked_to < Duration::from_millis(600));
+        let frame = (seeked_to.as_secs_f64() * 44_100.0).round() as usize;
+        let decoded: Vec<i16> = decoder.take(1_000).collect();
+        assert_eq!(decoded, quantize(&samples)[frame * 2..frame * 2 + 1_000]);
+    }
+
+    #[test]
+    fn decodes_flac_samples_exactly() {
+        let mut samples = sine(2, 48_000, 9_000, 440.0, 0.8);
+        samples.extend(silence(2, 2_000));
+        samples.extend(impulse(2, 1_000, 300, 0.5));
+        let fixture = FlacFixture::new(&samples, 2, 48_000);
+        let decoder = fixture.decode();
+        assert_eq!(decoder.channels(), 2);
+        assert_eq!(decoder.sample_rate(), 48_000);
+        assert_eq!(decoder.bits_per_sample(), Some(16));
+        assert_eq!(decoder.total_duration(), Some(Duration::from_millis(250)));
+
+        assert_eq!(decoder.collect::<Vec<_>>(), quantize(&samples));
+    }
+
+    #[test]
+    fn seeks_in_flac_to_the_frame_it_reports() {
+        let samples = sine(1, 44_100, 88_200, 330.0, 0.5);
+        let fixture = FlacFixture::new(&samples, 1, 44_100);
+        let mut decoder = fixture.decode();
+
+        let seeked_to = decoder
+            .seek_immediately(Duration::from_millis(1_250))
+            .unwrap();
+        assert!(seeked_to >= Duration::from_millis(1_150));
+        assert!(seeked_to <= Duration::from_millis(1_250));
+        let frame = (seeked_to.as_secs_f64() * 44_100.0).round() as usize;
+        let decoded: Vec<i16> = decoder.take(1_000).collect();
+        assert_eq!(decoded, quantize(&samples)[frame..frame + 1_000]);
+    }
+}
@@ -0,0 +1,142 @@
+//! Enumeration of the output devices of the default host.
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use serde::Serialize;
+
+use super::{Device, StreamError, SupportedStreamConfig};
+
+/// An output device and the stream configurations it supports.
+#[derive(Clone, Debug, Serialize)]
+pub struct OutputDeviceInfo {
+    /// Name of the device.
+    pub name: String,
+    /// Whether it is the default output device.
+    pub is_default: bool,
+    /// Configuration the device uses by default, if it reports one.
+    pub default_config: Option<OutputConfigInfo>,
+    /// Configurations the device supports.
+    pub supported_configs: Vec<OutputConfigRangeInfo>,
+}
+
+/// A single stream configuration, as returned by `default_output_config`.
+#[derive(Clone, Debug, Serialize)]
+pub struct OutputConfigInfo {
+    /// Number of channels.
+    pub channels: u16,
+    /// Sample rate, in Hz.
+    pub sample_rate: u32,
+    /// Sample format, such as `i16` or `f32`.
+    pub sample_format: String,
+}
+
+/// A range of stream configurations supported by a device.
+#[derive(Clone, Debug, Serialize)]
+pub struct OutputConfigRangeInfo {
+    /// Number of channels.
+    pub channels: u16,
+    /// Lowest supported sample rate, in Hz.
+    pub min_sample_rate: u32,
+    /// Highest supported sample rate, in Hz.
+    pub max_sample_rate: u32,
+    /// Sample format, such as `i16` or `f32`.
+    pub sample_format: String,
+}
+
+impl From<&SupportedStreamConfig> for OutputConfigInfo {
+    fn from(config: &SupportedStreamConfig) -> Self {
+        Self {
+            channels: config.channels(),
+            sample_rate: config.sample_rate().0,
+            sample_format: format!("{:?}", config.sample_format()),
+        }
+    }
+}
+
+/// Lists the output devices of the default host.
+///
+/// Devices whose name can't be read are skipped, as they couldn't be selected again later.
+pub fn list_output_devices() -> Result<Vec<OutputDeviceInfo>, StreamError> {
+    let default_name = default_output_device_name();
+
+    Ok(cpal::default_host()
+        .output_devices()?
+        .filter_map(|device| {
+            let name = device.name().ok()?;
+            let supported_configs = device
+                .supported_output_configs()
+                .map(|configs| {
+                    configs
+                        .map(|range| OutputConfigRangeInfo {
+                            channels: range.channels(),
+                            min_sample_rate: range.min_sample_rate().0,
+                            max_sample_rate: range.max_sample_rate().0,
+                            sample_format: format!("{:?}", range.sample_format()),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            Some(OutputDeviceInfo {
+                is_default: default_name.as_deref() == Some(name.as_str()),
+                default_config: device
+                    .default_output_config()
+                    .ok()
+                    .as_ref()
+                    .map(OutputConfigInfo::from),
+                supported_configs,
+                name,
+            })
+        })
+        .collect())
+}
+
+/// Returns a configuration of `device` that plays `channels` channels at `sample_rate` without
+/// any conversion, if there is one.
+///
+/// 16-bit integer formats are preferred since the decoder produces 16-bit samples, then 32-bit
+/// floats which can represent them exactly.
+pub fn native_output_config(
+    device: &Device,
+    channels: u16,
+    sample_rate: u32,
+) -> Option<SupportedStreamConfig> {
+    let rank = |format: cpal::SampleFormat| match format {
+        cpal::SampleFormat::I16 => 0,
+        cpal::SampleFormat::F32 => 1,
+        cpal::SampleFormat::U16 => 2,
+    };
+
+    device
+        .supported_output_configs()
+        .ok()?
+        .filter(|range| {
+            range.channels() == channels
+                && range.min_sample_rate().0 <= sample_rate
+                && sample_rate <= range.max_sample_rate().0
+        })
+        .min_by_key(|range| rank(range.sample_format()))
+        .map(|range| range.with_sample_rate(cpal::SampleRate(sample_rate)))
+}
+
+/// Returns `true` if samples of the given format reach the device without being altered.
+pub fn is_lossless_format(format: cpal::SampleFormat) -> bool {
+    matches!(format, cpal::SampleFormat::I16 | cpal::SampleFormat::F32)
+}
+
+/// Returns the current default output device.
+pub fn default_output_device() -> Option<Device> {
+    cpal::default_host().default_output_device()
+}
+
+/// Returns the name of the current default output device.
+pub fn default_output_device_name() -> Option<String> {
+    default_output_device().and_then(|device| device.name().ok())
+}
+
+/// Returns the output device with the given name, if it is currently available.
+pub fn find_output_device(name: &str) -> Result<Device, StreamError> {
+    cpal::default_host()
+        .output_devices()?
+        .find(|device| device.name().map_or(false, |n| n == name))
+        .ok_or(StreamError::NoDevice)
+}
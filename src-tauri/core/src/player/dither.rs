@@ -0,0 +1,133 @@
+//! Dither and noise shaping for the conversion of the mixer output to 16-bit devices.
+//!
+//! Quantizing to 16 bits without dither turns the rounding error into distortion that follows
+//! the signal, which becomes audible on quiet passages once the volume has been lowered. TPDF
+//! dither makes that error a constant, signal-independent hiss one bit deep, and noise shaping
+//! moves most of this hiss to high frequencies where the ear is less sensitive.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+/// When dither is applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DitherMode {
+    /// Samples are only rounded.
+    Off,
+    /// Dither is applied when the samples were altered by the player, e.g. by the volume or a
+    /// sample rate conversion, and not when they reach the device unchanged.
+    Auto,
+    /// Dither is always applied.
+    On,
+}
+
+impl Default for DitherMode {
+    fn default() -> Self {
+        Self::Auto
+    }
+}
+
+/// Dither settings shared between the player and the audio thread.
+#[derive(Debug, Default)]
+pub struct DitherConfig {
+    enabled: AtomicBool,
+    noise_shaping: AtomicBool,
+}
+
+impl DitherConfig {
+    /// Sets whether dither is applied, and whether it is noise shaped.
+    #[inline]
+    pub fn set(&self, enabled: bool, noise_shaping: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        self.noise_shaping.store(noise_shaping, Ordering::Relaxed);
+    }
+
+    /// Returns whether dither is applied.
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the dither is noise shaped.
+    #[inline]
+    pub fn noise_shaping(&self) -> bool {
+        self.noise_shaping.load(Ordering::Relaxed)
+    }
+}
+
+/// Quantizes interleaved `f32` samples to `i16`, with the state of the dither of each channel.
+pub struct Ditherer {
+    /// Quantization errors of the previous two samples of each channel, latest first.
+    errors: Vec<[f32; 2]>,
+    /// Channel of the next sample.
+    channel: usize,
+    /// State of the xorshift generator.
+    seed: u32,
+}
+
+impl Ditherer {
+    /// Returns a ditherer for interleaved samples of `channels` channels.
+    pub fn new(channels: u16) -> Self {
+        Self {
+            errors: vec![[0.0; 2]; usize::from(channels.max(1))],
+            channel: 0,
+            seed: 0x9E37_79B9,
+        }
+    }
+
+    /// Converts the next sample, with the same scale as `cpal::Sample::to_i16`.
+    ///
+    /// Without dither, the sample is rounded to the nearest value, so that 16-bit samples that
+    /// went through `f32` come out unchanged.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn convert(&mut self, sample: f32, dither: bool, noise_shaping: bool) -> i16 {
+        const MAX: f32 = 32767.0;
+        const MIN: f32 = -32768.0;
+
+        let scaled = if sample >= 0.0 {
+            sample * MAX
+        } else {
+            sample * -MIN
+        };
+
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.errors.len();
+
+        if !dither {
+            return scaled.round().clamp(MIN, MAX) as i16;
+        }
+
+        // The difference of two uniform values has a triangular distribution of +/- 1 LSB.
+        let noise = self.uniform() - self.uniform();
+
+        // Second order error feedback: the spectrum of the error is shaped by (1 - z^-1)^2,
+        // which removes it from low frequencies at the cost of more of it near Nyquist.
+        let errors = &mut self.errors[channel];
+        let wanted = if noise_shaping {
+            scaled - (2.0 * errors[0] - errors[1])
+        } else {
+            scaled
+        };
+        let quantized = (wanted + noise).round();
+
+        // The error is measured before clipping so that the feedback loop stays stable.
+        errors[1] = errors[0];
+        errors[0] = if noise_shaping {
+            quantized - wanted
+        } else {
+            0.0
+        };
+
+        quantized.clamp(MIN, MAX) as i16
+    }
+
+    /// Returns a uniform random value in `[0, 1)`.
+    #[allow(clippy::cast_precision_loss)]
+    fn uniform(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed >> 8) as f32 / (1 << 24) as f32
+    }
+}
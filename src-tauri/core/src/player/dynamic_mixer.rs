@@ -4,6 +4,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use super::conversions::{ChannelOptions, ResamplerQuality};
 use super::source::{Source, UniformSourceIterator};
 use super::Sample;
 
@@ -25,26 +26,32 @@ where
         pending_sources: Mutex::new(Vec::new()),
         channels,
         sample_rate,
+        resampler_quality: Mutex::new(ResamplerQuality::default()),
+        channel_options: Mutex::new(ChannelOptions::default()),
     });
 
     let output = DynamicMixer {
         current_sources: Vec::with_capacity(16),
         input: input.clone(),
         sample_count: 0,
-        still_pending: vec![],
         still_current: vec![],
     };
 
     (input, output)
 }
 
+/// A source added to the mixer, converted to its format once it starts.
+type PendingSource<S> = Box<dyn FnOnce() -> Box<dyn Source<Item = S> + Send> + Send>;
+
 /// The input of the mixer.
 #[allow(clippy::module_name_repetitions)]
 pub struct DynamicMixerController<S> {
     has_pending: AtomicBool,
-    pending_sources: Mutex<Vec<Box<dyn Source<Item = S> + Send>>>,
+    pending_sources: Mutex<Vec<PendingSource<S>>>,
     channels: u16,
     sample_rate: u32,
+    resampler_quality: Mutex<ResamplerQuality>,
+    channel_options: Mutex<ChannelOptions>,
 }
 
 impl<S> DynamicMixerController<S>
@@ -57,13 +64,35 @@ where
     where
         T: Source<Item = S> + Send + 'static,
     {
-        let uniform_source = UniformSourceIterator::new(source, self.channels, self.sample_rate);
-        self.pending_sources
-            .lock()
-            .unwrap()
-            .push(Box::new(uniform_source) as Box<_>);
+        let quality = *self.resampler_quality.lock().unwrap();
+        let channel_options = *self.channel_options.lock().unwrap();
+        let (channels, sample_rate) = (self.channels, self.sample_rate);
+        // The conversion is set up from the format the source has when it starts, as sounds may
+        // only be queued on it after it was added.
+        let start = move || {
+            Box::new(UniformSourceIterator::new(
+                source,
+                channels,
+                sample_rate,
+                quality,
+                channel_options,
+            )) as Box<_>
+        };
+        self.pending_sources.lock().unwrap().push(Box::new(start));
         self.has_pending.store(true, Ordering::SeqCst); // TODO: can we relax this ordering?
     }
+
+    /// Sets the quality used to convert the sample rate of the sources added from now on.
+    #[inline]
+    pub fn set_resampler_quality(&self, quality: ResamplerQuality) {
+        *self.resampler_quality.lock().unwrap() = quality;
+    }
+
+    /// Sets how the channels of the sources added from now on are mapped to the output.
+    #[inline]
+    pub fn set_channel_options(&self, options: ChannelOptions) {
+        *self.channel_options.lock().unwrap() = options;
+    }
 }
 
 /// The output of the mixer. Implements `Source`.
@@ -77,9 +106,6 @@ pub struct DynamicMixer<S> {
     // The number of samples produced so far.
     sample_count: usize,
 
-    // A temporary vec used in start_pending_sources.
-    still_pending: Vec<Box<dyn Source<Item = S> + Send>>,
-
     // A temporary vec used in sum_current_sources.
     still_current: Vec<Box<dyn Source<Item = S> + Send>>,
 }
@@ -156,21 +182,16 @@ where
     // in-step with the modulo of the samples produced so far. Otherwise, the
     // sound will play on the wrong channels, e.g. left / right will be reversed.
     fn start_pending_sources(&mut self) {
-        let mut pending = self.input.pending_sources.lock().unwrap(); // TODO: relax ordering?
-
-        for source in pending.drain(..) {
-            let in_step = self.sample_count % source.channels() as usize == 0;
-
-            if in_step {
-                self.current_sources.push(source);
-            } else {
-                self.still_pending.push(source);
-            }
+        // All the sources are converted to the channels of the mixer.
+        let in_step = self.sample_count % self.input.channels as usize == 0;
+        if !in_step {
+            return;
         }
-        std::mem::swap(&mut self.still_pending, &mut pending);
 
-        let has_pending = !pending.is_empty();
-        self.input.has_pending.store(has_pending, Ordering::SeqCst); // TODO: relax ordering?
+        let mut pending = self.input.pending_sources.lock().unwrap(); // TODO: relax ordering?
+        self.current_sources
+            .extend(pending.drain(..).map(|start| start()));
+        self.input.has_pending.store(false, Ordering::SeqCst); // TODO: relax ordering?
     }
 
     fn sum_current_sources(&mut self) -> S {
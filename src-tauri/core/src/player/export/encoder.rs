@@ -0,0 +1,208 @@
+//! Encoders of the export formats.
+//!
+//! FLAC is encoded by `FlacWriter`. The lossy formats are encoded by the reference encoders,
+//! `lame`, `oggenc` and `opusenc`, which are given raw 16-bit samples on their input.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+use super::flac::FlacWriter;
+use super::{ExportError, ExportFormat};
+
+/// Encodes interleaved 16-bit samples to a file.
+pub trait Encoder {
+    /// Appends interleaved samples.
+    fn write(&mut self, samples: &[i16]) -> Result<(), ExportError>;
+
+    /// Ends the file.
+    fn finish(self: Box<Self>) -> Result<(), ExportError>;
+}
+
+/// Creates the file at `path` and returns an encoder of `format` to it.
+pub fn open(
+    format: ExportFormat,
+    path: &Path,
+    channels: u16,
+    sample_rate: u32,
+) -> Result<Box<dyn Encoder>, ExportError> {
+    let raw_channels = channels.to_string();
+    let raw_rate = sample_rate.to_string();
+    let (program, args): (_, Vec<OsString>) = match format {
+        ExportFormat::Flac => {
+            let file = BufWriter::new(File::create(path)?);
+            return Ok(Box::new(FlacWriter::new(file, channels, sample_rate)?));
+        }
+        ExportFormat::Mp3(bitrate) => (
+            "lame",
+            vec![
+                "--quiet".into(),
+                "-r".into(),
+                "-s".into(),
+                (f64::from(sample_rate) / 1000.0).to_string().into(),
+                "--bitwidth".into(),
+                "16".into(),
+                "--signed".into(),
+                "--little-endian".into(),
+                "-m".into(),
+                if channels == 1 { "m" } else { "j" }.into(),
+                "-b".into(),
+                bitrate.to_string().into(),
+                "-".into(),
+                path.into(),
+            ],
+        ),
+        ExportFormat::Vorbis(quality) => (
+            "oggenc",
+            vec![
+                "-Q".into(),
+                "-r".into(),
+                "-B".into(),
+                "16".into(),
+                "-C".into(),
+                raw_channels.into(),
+                "-R".into(),
+                raw_rate.into(),
+                "--raw-endianness".into(),
+                "0".into(),
+                "-q".into(),
+                quality.to_string().into(),
+                "-o".into(),
+                path.into(),
+                "-".into(),
+            ],
+        ),
+        ExportFormat::Opus(bitrate) => (
+            "opusenc",
+            vec![
+                "--quiet".into(),
+                "--raw".into(),
+                "--raw-bits".into(),
+                "16".into(),
+                "--raw-rate".into(),
+                raw_rate.into(),
+                "--raw-chan".into(),
+                raw_channels.into(),
+                "--raw-endianness".into(),
+                "0".into(),
+                "--bitrate".into(),
+                bitrate.to_string().into(),
+                "-".into(),
+                path.into(),
+            ],
+        ),
+    };
+    Ok(Box::new(ProcessEncoder::start(program, &args)?))
+}
+
+impl<W: Write + Seek> Encoder for FlacWriter<W> {
+    fn write(&mut self, samples: &[i16]) -> Result<(), ExportError> {
+        Ok(FlacWriter::write(self, samples)?)
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), ExportError> {
+        FlacWriter::finish(*self)?;
+        Ok(())
+    }
+}
+
+/// An encoder program, which reads raw little-endian samples on its input.
+struct ProcessEncoder {
+    program: &'static str,
+    child: Child,
+    /// Input of the program, closed to end the file.
+    stdin: Option<ChildStdin>,
+    /// Thread reading the error output of the program, so that the program never blocks on a
+    /// full pipe. Returns what it read.
+    stderr: Option<JoinHandle<String>>,
+    bytes: Vec<u8>,
+    finished: bool,
+}
+
+impl ProcessEncoder {
+    fn start(program: &'static str, args: &[OsString]) -> Result<Self, ExportError> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound => ExportError::EncoderNotFound(program.to_string()),
+                _ => err.into(),
+            })?;
+        let stdin = child.stdin.take();
+        let stderr = child.stderr.take().map(|mut stderr| {
+            thread::spawn(move || {
+                let mut message = String::new();
+                let _ = stderr.read_to_string(&mut message);
+                message
+            })
+        });
+        Ok(Self {
+            program,
+            child,
+            stdin,
+            stderr,
+            bytes: Vec::new(),
+            finished: false,
+        })
+    }
+
+    /// Waits for the program to exit, and returns an error with what it wrote if it failed.
+    fn wait(&mut self) -> Result<(), ExportError> {
+        self.stdin = None;
+        let status = self.child.wait()?;
+        let message = self
+            .stderr
+            .take()
+            .and_then(|stderr| stderr.join().ok())
+            .unwrap_or_default();
+        self.finished = true;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(ExportError::EncoderError(format!(
+                "{} failed ({}): {}",
+                self.program,
+                status,
+                message.trim()
+            )))
+        }
+    }
+}
+
+impl Encoder for ProcessEncoder {
+    fn write(&mut self, samples: &[i16]) -> Result<(), ExportError> {
+        self.bytes.clear();
+        for sample in samples {
+            self.bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        let written = match &mut self.stdin {
+            Some(stdin) => stdin.write_all(&self.bytes),
+            None => Ok(()),
+        };
+        match written {
+            Ok(()) => Ok(()),
+            // The program exited, most likely with an error.
+            Err(err) => Err(self.wait().err().unwrap_or_else(|| err.into())),
+        }
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), ExportError> {
+        self.wait()
+    }
+}
+
+impl Drop for ProcessEncoder {
+    fn drop(&mut self) {
+        // An export that was cancelled or failed leaves no program behind.
+        if !self.finished {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
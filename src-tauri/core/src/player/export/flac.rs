@@ -0,0 +1,436 @@
+//! Encoder of 16-bit FLAC files.
+//!
+//! Blocks have a fixed size, and each channel is coded on its own with the best of the fixed
+//! predictors of order 0 to 4 and partitioned Rice codes for the residual. This compresses a
+//! little less than the reference encoder, but every decoder plays it.
+
+use std::convert::TryFrom;
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Number of frames of each block, but the last one.
+const BLOCK_FRAMES: usize = 4096;
+/// Highest order of the fixed predictors.
+const MAX_ORDER: usize = 4;
+/// Highest number of partitions of the residual is 2 to the power of this.
+const MAX_PARTITION_ORDER: u32 = 8;
+/// Highest Rice parameter, as 15 is the escape code.
+const MAX_RICE_PARAMETER: u32 = 14;
+/// Offset of the `STREAMINFO` fields that `finish` fills in.
+const STREAMINFO_OFFSET: u64 = 8;
+
+/// Writes interleaved 16-bit samples to a FLAC file. The stream information is filled in by
+/// `finish`.
+pub struct FlacWriter<W: Write + Seek> {
+    writer: W,
+    channels: u16,
+    sample_rate: u32,
+    /// Samples of the block being filled.
+    pending: Vec<i16>,
+    frame_number: u64,
+    total_frames: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    /// Writes the header of a FLAC file with `channels` channels, from 1 to 8, at
+    /// `sample_rate` to `writer`.
+    pub fn new(writer: W, channels: u16, sample_rate: u32) -> io::Result<Self> {
+        if !(1..=8).contains(&channels) || sample_rate == 0 || sample_rate >= 1 << 20 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "FLAC supports 1 to 8 channels at up to 1 MHz",
+            ));
+        }
+        let mut flac = Self {
+            writer,
+            channels,
+            sample_rate,
+            pending: Vec::with_capacity(BLOCK_FRAMES * usize::from(channels)),
+            frame_number: 0,
+            total_frames: 0,
+            min_frame_size: 0,
+            max_frame_size: 0,
+        };
+        let stream_info = flac.stream_info();
+        flac.writer.write_all(b"fLaC")?;
+        // The only metadata block, of 34 bytes.
+        flac.writer.write_all(&[0x80, 0, 0, 34])?;
+        flac.writer.write_all(&stream_info)?;
+        Ok(flac)
+    }
+
+    /// Appends interleaved samples.
+    pub fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        let block_len = BLOCK_FRAMES * usize::from(self.channels);
+        for &sample in samples {
+            self.pending.push(sample);
+            if self.pending.len() == block_len {
+                self.write_block()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the last block, fills in the stream information and returns the writer.
+    ///
+    /// A partial frame at the end of the samples is dropped.
+    pub fn finish(mut self) -> io::Result<W> {
+        let channels = usize::from(self.channels);
+        self.pending
+            .truncate(self.pending.len() / channels * channels);
+        if !self.pending.is_empty() {
+            self.write_block()?;
+        }
+        let stream_info = self.stream_info();
+        self.writer.seek(SeekFrom::Start(STREAMINFO_OFFSET))?;
+        self.writer.write_all(&stream_info)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Returns the `STREAMINFO` metadata block, without its header. The signature of the
+    /// samples is left out, which decoders take as unknown.
+    fn stream_info(&self) -> [u8; 34] {
+        let mut bits = BitWriter::default();
+        bits.write(BLOCK_FRAMES as u64, 16);
+        bits.write(BLOCK_FRAMES as u64, 16);
+        bits.write(u64::from(self.min_frame_size), 24);
+        bits.write(u64::from(self.max_frame_size), 24);
+        bits.write(u64::from(self.sample_rate), 20);
+        bits.write(u64::from(self.channels - 1), 3);
+        bits.write(15, 5);
+        bits.write(self.total_frames, 36);
+        let mut stream_info = [0; 34];
+        stream_info[..18].copy_from_slice(&bits.bytes);
+        stream_info
+    }
+
+    /// Encodes the pending samples as one frame.
+    fn write_block(&mut self) -> io::Result<()> {
+        let channels = usize::from(self.channels);
+        let frames = self.pending.len() / channels;
+
+        let mut bits = BitWriter::default();
+        bits.write(0b1111_1111_1111_1000, 16);
+        // Block size in 16 bits at the end of the header, and sample rate of the stream info.
+        bits.write(0b0111, 4);
+        bits.write(0, 4);
+        // Independent channels of 16 bits.
+        bits.write((channels - 1) as u64, 4);
+        bits.write(0b100, 3);
+        bits.write(0, 1);
+        write_utf8_number(&mut bits, self.frame_number);
+        bits.write((frames - 1) as u64, 16);
+        let crc = crc8(&bits.bytes);
+        bits.write(u64::from(crc), 8);
+
+        let mut channel = Vec::with_capacity(frames);
+        for index in 0..channels {
+            channel.clear();
+            channel.extend(
+                self.pending
+                    .iter()
+                    .skip(index)
+                    .step_by(channels)
+                    .map(|&sample| i32::from(sample)),
+            );
+            write_subframe(&mut bits, &channel);
+        }
+        bits.align();
+        let crc = crc16(&bits.bytes);
+        bits.write(u64::from(crc), 16);
+
+        self.writer.write_all(&bits.bytes)?;
+        let size = u32::try_from(bits.bytes.len()).unwrap_or(u32::MAX);
+        self.min_frame_size = if self.frame_number == 0 {
+            size
+        } else {
+            self.min_frame_size.min(size)
+        };
+        self.max_frame_size = self.max_frame_size.max(size);
+        self.frame_number += 1;
+        self.total_frames += frames as u64;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// Writes the subframe of one channel with whichever coding is the smallest.
+fn write_subframe(bits: &mut BitWriter, samples: &[i32]) {
+    if samples.iter().all(|&sample| sample == samples[0]) {
+        bits.write(0, 8);
+        bits.write_signed(samples[0], 16);
+        return;
+    }
+
+    let verbatim_bits = samples.len() as u64 * 16;
+    let best = (0..=MAX_ORDER.min(samples.len() - 1))
+        .map(|order| {
+            let residual = fixed_residual(samples, order);
+            let (partition_order, parameters, residual_bits) =
+                best_partitions(&residual, samples.len(), order);
+            let bits = (order as u64 * 16 + 6).saturating_add(residual_bits);
+            (bits, order, residual, partition_order, parameters)
+        })
+        .min_by_key(|(bits, ..)| *bits);
+
+    match best {
+        Some((bits_used, order, residual, partition_order, parameters))
+            if bits_used < verbatim_bits =>
+        {
+            bits.write(0b0001_0000 | ((order as u64) << 1), 8);
+            for &sample in &samples[..order] {
+                bits.write_signed(sample, 16);
+            }
+            // Rice codes with 4-bit parameters.
+            bits.write(0, 2);
+            bits.write(u64::from(partition_order), 4);
+            let partition_len = samples.len() >> partition_order;
+            let mut start = 0;
+            for (index, &parameter) in parameters.iter().enumerate() {
+                let end = (index + 1) * partition_len - order;
+                bits.write(u64::from(parameter), 4);
+                for &value in &residual[start..end] {
+                    bits.write_rice(value, parameter);
+                }
+                start = end;
+            }
+        }
+        _ => {
+            bits.write(0b0000_0010, 8);
+            for &sample in samples {
+                bits.write_signed(sample, 16);
+            }
+        }
+    }
+}
+
+/// Returns the residual of the fixed predictor of `order`, from the sample after the warm-up
+/// samples.
+fn fixed_residual(samples: &[i32], order: usize) -> Vec<i32> {
+    samples
+        .windows(order + 1)
+        .map(|window| {
+            let s = |back: usize| window[order - back];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Returns the partition order, the Rice parameter of each partition and the number of bits
+/// that code `residual` with the fewest bits.
+fn best_partitions(residual: &[i32], block_len: usize, order: usize) -> (u32, Vec<u32>, u64) {
+    let mut best: Option<(u32, Vec<u32>, u64)> = None;
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1 << partition_order;
+        // Every partition must be whole, and the first one must be longer than the warm up.
+        if block_len % partitions != 0 || block_len / partitions <= order {
+            break;
+        }
+        let partition_len = block_len / partitions;
+        let mut parameters = Vec::with_capacity(partitions);
+        let mut total = 0;
+        let mut start = 0;
+        for index in 0..partitions {
+            let end = (index + 1) * partition_len - order;
+            let (parameter, bits) = best_parameter(&residual[start..end]);
+            parameters.push(parameter);
+            total += 4 + bits;
+            start = end;
+        }
+        if best.as_ref().map_or(true, |(_, _, bits)| total < *bits) {
+            best = Some((partition_order, parameters, total));
+        }
+    }
+    best.unwrap_or((0, vec![0], u64::MAX))
+}
+
+/// Returns the Rice parameter that codes `values` with the fewest bits, and that number.
+fn best_parameter(values: &[i32]) -> (u32, u64) {
+    let folded: Vec<u64> = values.iter().map(|&value| fold(value)).collect();
+    (0..=MAX_RICE_PARAMETER)
+        .map(|parameter| {
+            let bits = folded
+                .iter()
+                .map(|value| (value >> parameter) + 1 + u64::from(parameter))
+                .sum();
+            (parameter, bits)
+        })
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap_or((0, 0))
+}
+
+/// Maps signed values to unsigned ones, alternating: 0, -1, 1, -2, 2…
+fn fold(value: i32) -> u64 {
+    ((i64::from(value) << 1) ^ (i64::from(value) >> 63)) as u64
+}
+
+/// Writes the frame number with the variable length code of FLAC, which extends UTF-8.
+fn write_utf8_number(bits: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        bits.write(value, 8);
+        return;
+    }
+    // Each continuation byte holds 6 bits, and the lead byte 6 bits less than their number.
+    let mut continuation = 1;
+    while value >> (6 + 5 * continuation) != 0 {
+        continuation += 1;
+    }
+    // The lead byte has one bit set per byte, then a zero, then the highest bits.
+    let lead = (0xff_u64 << (7 - continuation)) & 0xff;
+    bits.write(lead | (value >> (6 * continuation)), 8);
+    for index in (0..continuation).rev() {
+        bits.write(0x80 | ((value >> (6 * index)) & 0x3f), 8);
+    }
+}
+
+/// CRC-8 of frame headers, with the polynomial x^8 + x^2 + x + 1.
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x07
+            }
+        })
+    })
+}
+
+/// CRC-16 of whole frames, with the polynomial x^16 + x^15 + x^2 + 1.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte) << 8, |crc, _| {
+            if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x8005
+            }
+        })
+    })
+}
+
+/// Bits written from the most significant one.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits not yet in `bytes`, in the low `pending_len` bits.
+    pending: u64,
+    pending_len: u32,
+}
+
+impl BitWriter {
+    /// Writes the low `len` bits of `value`, up to 32 at a time.
+    fn write(&mut self, value: u64, len: u32) {
+        if len > 32 {
+            self.write(value >> 32, len - 32);
+            self.write(value & 0xffff_ffff, 32);
+            return;
+        }
+        let mask = if len == 0 { 0 } else { u64::MAX >> (64 - len) };
+        self.pending = (self.pending << len) | (value & mask);
+        self.pending_len += len;
+        while self.pending_len >= 8 {
+            self.pending_len -= 8;
+            self.bytes.push((self.pending >> self.pending_len) as u8);
+        }
+    }
+
+    /// Writes `value` in two's complement on `len` bits.
+    fn write_signed(&mut self, value: i32, len: u32) {
+        self.write(value as u64, len);
+    }
+
+    /// Writes `value` folded to unsigned, as a unary quotient and `parameter` low bits.
+    fn write_rice(&mut self, value: i32, parameter: u32) {
+        let folded = fold(value);
+        let mut quotient = folded >> parameter;
+        while quotient >= 32 {
+            self.write(0, 32);
+            quotient -= 32;
+        }
+        self.write(1, quotient as u32 + 1);
+        self.write(folded, parameter);
+    }
+
+    /// Pads with zeros to a whole byte.
+    fn align(&mut self) {
+        if self.pending_len > 0 {
+            self.write(0, 8 - self.pending_len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::decoder::Symphonia;
+    use crate::player::fixtures::{impulse, quantize, silence, sine, TempFile};
+    use crate::player::Source;
+    use std::fs::{self, File};
+    use std::io::Cursor;
+
+    fn encode(samples: &[i16], channels: u16, sample_rate: u32) -> Vec<u8> {
+        let mut flac = FlacWriter::new(Cursor::new(Vec::new()), channels, sample_rate).unwrap();
+        flac.write(samples).unwrap();
+        flac.finish().unwrap().into_inner()
+    }
+
+    /// Decodes `bytes`, and returns the channels, the sample rate and the samples.
+    fn decode(bytes: &[u8]) -> (u16, u32, Vec<i16>) {
+        let file = TempFile::new("flac");
+        fs::write(file.path(), bytes).unwrap();
+        let decoder = Symphonia::new(File::open(file.path()).unwrap(), false).unwrap();
+        assert_eq!(decoder.bits_per_sample(), Some(16));
+        (decoder.channels(), decoder.sample_rate(), decoder.collect())
+    }
+
+    #[test]
+    fn decodes_to_the_same_samples() {
+        let mut signal = sine(2, 44_100, 10_000, 440.0, 0.7);
+        signal.extend(silence(2, 5_000));
+        signal.extend(impulse(2, 3_001, 100, -1.0));
+        let mut samples = quantize(&signal);
+        // Noise, which the predictors can't follow.
+        let mut state: u32 = 1;
+        samples.extend((0..2_000).map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 16) as i16
+        }));
+
+        let bytes = encode(&samples, 2, 44_100);
+        assert!(bytes.len() < samples.len() * 2);
+        assert_eq!(decode(&bytes), (2, 44_100, samples));
+    }
+
+    #[test]
+    fn encodes_short_and_multichannel_streams() {
+        for &(channels, frames) in &[(1, 1), (1, 4), (3, 5), (6, 4_097)] {
+            let samples = quantize(&sine(channels, 48_000, frames, 1_000.0, 0.5));
+            let bytes = encode(&samples, channels, 48_000);
+            assert_eq!(decode(&bytes), (channels, 48_000, samples));
+        }
+    }
+
+    #[test]
+    fn codes_frame_numbers_like_utf8() {
+        for &(value, expected) in &[
+            (0x41, &[0x41][..]),
+            (0x7ff, &[0xdf, 0xbf][..]),
+            (0x800, &[0xe0, 0xa0, 0x80][..]),
+            (0x1_0000, &[0xf0, 0x90, 0x80, 0x80][..]),
+        ] {
+            let mut bits = BitWriter::default();
+            write_utf8_number(&mut bits, value);
+            assert_eq!(bits.bytes, expected);
+        }
+    }
+}
@@ -0,0 +1,522 @@
+//! Export of tracks to a folder, such as the one of a phone or a portable player that can't play
+//! every file of the library.
+//!
+//! Each track is decoded, converted to at most two channels at a sample rate the target format
+//! supports, encoded, and tagged like the original with its cover art. The files are named by
+//! a `Template`, and several tracks are exported at once by a pool of worker threads.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::{error, fmt, io};
+
+use serde::{Deserialize, Serialize};
+
+use super::decoder::{Symphonia, SymphoniaDecoderError};
+use super::source::{Source, UniformSourceIterator};
+use super::{ChannelOptions, ResamplerQuality};
+use crate::track;
+
+mod encoder;
+pub mod flac;
+mod template;
+
+pub use template::Template;
+
+/// Template of the paths of exported files used by default.
+pub const DEFAULT_TEMPLATE: &str = "{albumartist}/{album}/{track} {title}";
+/// Sample rate above which tracks are resampled by default, which portable players support.
+pub const DEFAULT_MAX_SAMPLE_RATE: u32 = 48_000;
+/// Number of tracks exported at once by default.
+pub const DEFAULT_WORKERS: usize = 2;
+/// Number of frames encoded at a time, between checks for cancellation.
+const BLOCK_FRAMES: usize = 4096;
+
+/// Format of the exported files.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// 16-bit FLAC, lossless for CD-quality tracks.
+    Flac,
+    /// MP3 at the given bitrate in kbit/s, encoded by `lame`.
+    Mp3(u32),
+    /// Ogg Vorbis at the given quality, from -1 to 10, encoded by `oggenc`.
+    Vorbis(f32),
+    /// Opus at the given bitrate in kbit/s, encoded by `opusenc`.
+    Opus(u32),
+}
+
+impl ExportFormat {
+    /// Returns the extension of the files of this format.
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Flac => "flac",
+            Self::Mp3(_) => "mp3",
+            Self::Vorbis(_) => "ogg",
+            Self::Opus(_) => "opus",
+        }
+    }
+
+    /// Returns the channels and the sample rate that a track with `channels` channels at
+    /// `sample_rate` is exported at.
+    fn output_format(self, channels: u16, sample_rate: u32, max_sample_rate: u32) -> (u16, u32) {
+        let sample_rate = match self {
+            // Opus always codes at 48 kHz.
+            Self::Opus(_) => 48_000,
+            // MP3 has no rate above 48 kHz.
+            Self::Mp3(_) => supported_rate(sample_rate, max_sample_rate.min(48_000)),
+            Self::Flac | Self::Vorbis(_) => supported_rate(sample_rate, max_sample_rate),
+        };
+        (channels.min(2), sample_rate)
+    }
+}
+
+/// Returns `sample_rate` if it is at most `max`. Otherwise, returns it halved until it is, as
+/// 44.1 kHz for 88.2 kHz, or `max` if that is too low.
+fn supported_rate(sample_rate: u32, max: u32) -> u32 {
+    let max = max.max(8_000);
+    let mut rate = sample_rate;
+    while rate > max && rate % 2 == 0 {
+        rate /= 2;
+    }
+    if rate <= max && (rate >= 44_100 || rate == sample_rate) {
+        rate
+    } else {
+        max
+    }
+}
+
+/// Settings of an export.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportOptions {
+    /// Folder the files are written to.
+    pub folder: PathBuf,
+    /// Format of the files.
+    pub format: ExportFormat,
+    /// Template of the paths of the files in `folder`, see `Template`.
+    pub template: String,
+    /// Tracks at a higher sample rate are resampled.
+    pub max_sample_rate: u32,
+    /// Number of tracks exported at once.
+    pub workers: usize,
+    /// Whether existing files are replaced, rather than skipped.
+    pub overwrite: bool,
+}
+
+impl ExportOptions {
+    /// Returns the default options for an export to `folder` in `format`.
+    pub fn new(folder: PathBuf, format: ExportFormat) -> Self {
+        Self {
+            folder,
+            format,
+            template: DEFAULT_TEMPLATE.to_string(),
+            max_sample_rate: DEFAULT_MAX_SAMPLE_RATE,
+            workers: DEFAULT_WORKERS,
+            overwrite: false,
+        }
+    }
+}
+
+/// What became of a track.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportResult {
+    /// The track was exported to the given file.
+    Exported(PathBuf),
+    /// The given file already exists.
+    Skipped(PathBuf),
+    /// The track could not be exported.
+    Failed(String),
+}
+
+/// Progress of an export, reported from the worker threads.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportEvent {
+    /// A track is done.
+    Track {
+        /// Index of the track in the export.
+        index: usize,
+        /// The exported file.
+        source: PathBuf,
+        /// What became of it.
+        result: ExportResult,
+        /// Number of tracks done so far, this one included.
+        done: usize,
+        /// Number of tracks of the export.
+        total: usize,
+    },
+    /// Every track is done, or the export was cancelled.
+    Finished {
+        /// Number of tracks done.
+        done: usize,
+        /// Number of tracks of the export.
+        total: usize,
+        /// Whether the export was cancelled.
+        cancelled: bool,
+    },
+}
+
+/// State shared by the workers of an export.
+struct Shared {
+    tracks: Vec<PathBuf>,
+    options: ExportOptions,
+    template: Template,
+    /// Index of the next track to export.
+    next: AtomicUsize,
+    done: AtomicUsize,
+    /// Number of workers still running. The last one reports the end of the export.
+    running: AtomicUsize,
+    cancelled: AtomicBool,
+    on_event: Box<dyn Fn(ExportEvent) + Send + Sync>,
+}
+
+/// An export running in the background.
+pub struct ExportJob {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ExportJob {
+    /// Starts exporting `tracks` with `options`. `on_event` is called from the worker threads
+    /// as each track is done, and once at the end.
+    ///
+    /// Fails right away if the template is invalid or the folder can't be created.
+    pub fn start(
+        tracks: Vec<PathBuf>,
+        options: ExportOptions,
+        on_event: Box<dyn Fn(ExportEvent) + Send + Sync>,
+    ) -> Result<Self, ExportError> {
+        let template = Template::parse(&options.template)?;
+        fs::create_dir_all(&options.folder)?;
+
+        let workers = options.workers.clamp(1, tracks.len().max(1));
+        let shared = Arc::new(Shared {
+            tracks,
+            options,
+            template,
+            next: AtomicUsize::new(0),
+            done: AtomicUsize::new(0),
+            running: AtomicUsize::new(workers),
+            cancelled: AtomicBool::new(false),
+            on_event,
+        });
+        let workers = (0..workers)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || run_worker(&shared))
+            })
+            .collect();
+        Ok(Self { shared, workers })
+    }
+
+    /// Stops the export. The tracks being encoded are abandoned, and their files removed.
+    pub fn cancel(&self) {
+        self.shared.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` once every worker has stopped.
+    pub fn is_finished(&self) -> bool {
+        self.shared.running.load(Ordering::Acquire) == 0
+    }
+
+    /// Waits for the export to end.
+    pub fn wait(self) {
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Exports tracks until there are none left or the export is cancelled.
+fn run_worker(shared: &Shared) {
+    let total = shared.tracks.len();
+    while !shared.cancelled.load(Ordering::Relaxed) {
+        let index = shared.next.fetch_add(1, Ordering::Relaxed);
+        let source = match shared.tracks.get(index) {
+            Some(source) => source,
+            None => break,
+        };
+        let result = match export_track(shared, source, index) {
+            Ok(result) => result,
+            Err(ExportError::Cancelled) => break,
+            Err(err) => ExportResult::Failed(err.to_string()),
+        };
+        let done = shared.done.fetch_add(1, Ordering::AcqRel) + 1;
+        (shared.on_event)(ExportEvent::Track {
+            index,
+            source: source.clone(),
+            result,
+            done,
+            total,
+        });
+    }
+
+    if shared.running.fetch_sub(1, Ordering::AcqRel) == 1 {
+        (shared.on_event)(ExportEvent::Finished {
+            done: shared.done.load(Ordering::Acquire),
+            total,
+            cancelled: shared.cancelled.load(Ordering::Relaxed),
+        });
+    }
+}
+
+/// Exports the track at `source`, the `index`th of the export.
+fn export_track(shared: &Shared, source: &Path, index: usize) -> Result<ExportResult, ExportError> {
+    let options = &shared.options;
+    let fields = track::tag_fields(source);
+    let path = options.folder.join(shared.template.render(
+        &fields,
+        source,
+        options.format.extension(),
+    ));
+    if path.exists() && !options.overwrite {
+        return Ok(ExportResult::Skipped(path));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // The file is written under another name until it is complete, so that an interrupted
+    // export leaves no truncated file behind, even if tracks share a name.
+    let mut partial = path.clone().into_os_string();
+    partial.push(format!(".{}.part", index));
+    let partial = PathBuf::from(partial);
+    let encoded = encode(source, &partial, options, &shared.cancelled)
+        .and_then(|()| Ok(fs::rename(&partial, &path)?));
+    if let Err(err) = encoded {
+        let _ = fs::remove_file(&partial);
+        return Err(err);
+    }
+
+    if let Err(err) = track::copy_tags(source, &path) {
+        let _ = fs::remove_file(&path);
+        return Err(ExportError::TagError(err.to_string()));
+    }
+    Ok(ExportResult::Exported(path))
+}
+
+/// Decodes the file at `source`, and encodes it to `path` with `options`.
+fn encode(
+    source: &Path,
+    path: &Path,
+    options: &ExportOptions,
+    cancelled: &AtomicBool,
+) -> Result<(), ExportError> {
+    let decoder = Symphonia::new(File::open(source)?, true)?;
+    let (channels, sample_rate) = options.format.output_format(
+        decoder.channels(),
+        decoder.sample_rate(),
+        options.max_sample_rate,
+    );
+    let mut samples = UniformSourceIterator::<_, i16>::new(
+        decoder,
+        channels,
+        sample_rate,
+        ResamplerQuality::High,
+        ChannelOptions::default(),
+    );
+
+    let mut encoder = encoder::open(options.format, path, channels, sample_rate)?;
+    let mut block = Vec::with_capacity(BLOCK_FRAMES * usize::from(channels));
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(ExportError::Cancelled);
+        }
+        block.clear();
+        block.extend(samples.by_ref().take(BLOCK_FRAMES * usize::from(channels)));
+        if block.is_empty() {
+            break;
+        }
+        encoder.write(&block)?;
+    }
+    encoder.finish()
+}
+
+/// Error that can happen when exporting.
+#[derive(Debug, Serialize)]
+pub enum ExportError {
+    /// The template of the paths is invalid.
+    InvalidTemplate(String),
+    /// A file could not be read or written.
+    IoError(String),
+    /// A track could not be decoded.
+    DecoderError(String),
+    /// The given encoder program is not installed.
+    EncoderNotFound(String),
+    /// The encoder failed.
+    EncoderError(String),
+    /// The tags could not be copied.
+    TagError(String),
+    /// The export was cancelled.
+    Cancelled,
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> Self {
+        Self::IoError(err.to_string())
+    }
+}
+
+impl From<SymphoniaDecoderError> for ExportError {
+    fn from(err: SymphoniaDecoderError) -> Self {
+        Self::DecoderError(err.to_string())
+    }
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidTemplate(msg) => write!(f, "invalid template: {}", msg),
+            Self::EncoderNotFound(program) => write!(f, "{} is not installed", program),
+            Self::IoError(msg)
+            | Self::DecoderError(msg)
+            | Self::EncoderError(msg)
+            | Self::TagError(msg) => write!(f, "{}", msg),
+            Self::Cancelled => write!(f, "export cancelled"),
+        }
+    }
+}
+
+impl error::Error for ExportError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::fixtures::{quantize, sine, TempFile, WavFixture};
+    use crate::player::render::WavFormat;
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+
+    /// Exports `tracks` and returns the events, in the order they were sent.
+    fn export(tracks: Vec<PathBuf>, options: ExportOptions) -> Vec<ExportEvent> {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let on_event =
+            Box::new(move |event: ExportEvent| sender.lock().unwrap().send(event).unwrap());
+        ExportJob::start(tracks, options, on_event).unwrap().wait();
+        receiver.try_iter().collect()
+    }
+
+    fn results(events: &[ExportEvent]) -> Vec<(usize, ExportResult)> {
+        let mut results: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                ExportEvent::Track { index, result, .. } => Some((*index, result.clone())),
+                ExportEvent::Finished { .. } => None,
+            })
+            .collect();
+        results.sort_by_key(|(index, _)| *index);
+        results
+    }
+
+    #[test]
+    fn exports_tracks_losslessly_to_flac() {
+        let samples = sine(2, 44_100, 20_000, 440.0, 0.5);
+        let tracks = [
+            WavFixture::new(&samples, 2, 44_100, WavFormat::Int16),
+            WavFixture::new(&samples, 2, 44_100, WavFormat::Int16),
+            WavFixture::new(&samples, 2, 44_100, WavFormat::Int16),
+        ];
+        let folder = TempFile::new("export");
+        let mut options = ExportOptions::new(folder.path().to_path_buf(), ExportFormat::Flac);
+        options.template = "{artist}/{filename}".to_string();
+        let paths: Vec<PathBuf> = tracks.iter().map(|track| track.path().into()).collect();
+
+        let events = export(paths.clone(), options.clone());
+        assert_eq!(
+            events.last(),
+            Some(&ExportEvent::Finished {
+                done: 3,
+                total: 3,
+                cancelled: false
+            })
+        );
+        for (index, result) in results(&events) {
+            let name = paths[index].with_extension("flac");
+            let expected = folder
+                .path()
+                .join("Unknown Artist")
+                .join(name.file_name().unwrap());
+            assert_eq!(result, ExportResult::Exported(expected.clone()));
+            let decoder = Symphonia::new(File::open(&expected).unwrap(), true).unwrap();
+            assert_eq!(decoder.collect::<Vec<_>>(), quantize(&samples));
+        }
+
+        // The files are there now.
+        let events = export(paths, options);
+        assert!(results(&events)
+            .iter()
+            .all(|(_, result)| matches!(result, ExportResult::Skipped(_))));
+        let partial = fs::read_dir(folder.path().join("Unknown Artist"))
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .path()
+                    .to_string_lossy()
+                    .ends_with(".part")
+            })
+            .count();
+        assert_eq!(partial, 0);
+    }
+
+    #[test]
+    fn resamples_and_downmixes_for_portable_players() {
+        let track = WavFixture::new(
+            &sine(6, 96_000, 9_600, 1_000.0, 0.25),
+            6,
+            96_000,
+            WavFormat::Int24,
+        );
+        let folder = TempFile::new("export");
+        let options = ExportOptions::new(folder.path().to_path_buf(), ExportFormat::Flac);
+
+        let events = export(vec![track.path().into()], options);
+        let path = match &results(&events)[0].1 {
+            ExportResult::Exported(path) => path.clone(),
+            result => panic!("{:?}", result),
+        };
+        let decoder = Symphonia::new(File::open(path).unwrap(), true).unwrap();
+        assert_eq!(decoder.channels(), 2);
+        assert_eq!(decoder.sample_rate(), 48_000);
+        let frames = decoder.count() / 2;
+        assert!((4_790..=4_810).contains(&frames), "{} frames", frames);
+    }
+
+    #[test]
+    fn reports_tracks_that_fail() {
+        let folder = TempFile::new("export");
+        let options = ExportOptions::new(folder.path().to_path_buf(), ExportFormat::Flac);
+        let missing = folder.path().join("missing.wav");
+
+        let events = export(vec![missing.clone()], options);
+        assert!(matches!(
+            &events[..],
+            [
+                ExportEvent::Track {
+                    result: ExportResult::Failed(_),
+                    done: 1,
+                    ..
+                },
+                ExportEvent::Finished { done: 1, .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn picks_rates_portable_players_support() {
+        let format = |format: ExportFormat, rate| format.output_format(2, rate, 48_000).1;
+        assert_eq!(format(ExportFormat::Flac, 44_100), 44_100);
+        assert_eq!(format(ExportFormat::Flac, 88_200), 44_100);
+        assert_eq!(format(ExportFormat::Flac, 192_000), 48_000);
+        assert_eq!(format(ExportFormat::Vorbis(6.0), 22_050), 22_050);
+        assert_eq!(format(ExportFormat::Mp3(320), 96_000), 48_000);
+        assert_eq!(format(ExportFormat::Opus(128), 44_100), 48_000);
+        assert_eq!(supported_rate(96_000, 44_100), 44_100);
+        assert_eq!(ExportFormat::Flac.output_format(6, 44_100, 48_000).0, 2);
+    }
+}
@@ -0,0 +1,239 @@
+//! Paths of exported files, named after the tags of the tracks.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::ExportError;
+
+/// Fields a template can name.
+const FIELDS: &[&str] = &[
+    "artist",
+    "albumartist",
+    "album",
+    "title",
+    "track",
+    "disc",
+    "year",
+    "genre",
+    "filename",
+];
+
+/// Characters that FAT, the file system of most portable players, doesn't allow in names.
+const INVALID_CHARACTERS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Path of an exported file relative to the export folder, without its extension, such as
+/// `{albumartist}/{album}/{track} {title}`.
+///
+/// Fields are written between braces: `artist`, `albumartist`, `album`, `title`, `track`,
+/// `disc`, `year`, `genre`, and `filename` for the name of the original file. Slashes separate
+/// folders.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(&'static str),
+}
+
+impl Template {
+    /// Parses `template`. Fails on unknown fields and unbalanced braces.
+    pub fn parse(template: &str) -> Result<Self, ExportError> {
+        let invalid = |reason: String| ExportError::InvalidTemplate(reason);
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            let brace = rest.find(&['{', '}'][..]).unwrap_or(rest.len());
+            if brace > 0 {
+                parts.push(Part::Text(rest[..brace].to_string()));
+            }
+            rest = &rest[brace..];
+            if rest.starts_with('}') {
+                return Err(invalid("unopened '}'".to_string()));
+            }
+            if rest.starts_with('{') {
+                let end = rest
+                    .find('}')
+                    .ok_or_else(|| invalid("unclosed '{'".to_string()))?;
+                let name = &rest[1..end];
+                let field = FIELDS
+                    .iter()
+                    .find(|field| **field == name)
+                    .ok_or_else(|| invalid(format!("unknown field '{}'", name)))?;
+                parts.push(Part::Field(field));
+                rest = &rest[end + 1..];
+            }
+        }
+        if parts.is_empty() {
+            return Err(invalid("empty template".to_string()));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Returns the path of the export of `source`, whose tags are `fields`, with `extension`.
+    ///
+    /// Missing artists and albums are named `Unknown Artist` and `Unknown Album`, and missing
+    /// titles after the original file. Characters that portable players don't allow in names
+    /// are replaced with `_`, and folders left empty are skipped.
+    pub fn render(
+        &self,
+        fields: &HashMap<String, String>,
+        source: &Path,
+        extension: &str,
+    ) -> PathBuf {
+        let file_name = source
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let mut components = vec![String::new()];
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => {
+                    for (index, text) in text.split('/').enumerate() {
+                        if index > 0 {
+                            components.push(String::new());
+                        }
+                        components.last_mut().unwrap().push_str(&sanitize(text));
+                    }
+                }
+                Part::Field(name) => {
+                    let value = field(name, fields, &file_name);
+                    components.last_mut().unwrap().push_str(&sanitize(&value));
+                }
+            }
+        }
+
+        let mut components: Vec<&str> = components
+            .iter()
+            .map(|component| component.trim_matches(|c| c == ' ' || c == '.'))
+            .filter(|component| !component.is_empty())
+            .collect();
+        let fallback = sanitize(&file_name);
+        // The extension is appended, as the name may contain dots.
+        let name = format!("{}.{}", components.pop().unwrap_or(&fallback), extension);
+        components.iter().collect::<PathBuf>().join(name)
+    }
+}
+
+/// Returns the value of the field `name`.
+fn field(name: &str, fields: &HashMap<String, String>, file_name: &str) -> String {
+    let get = |name: &str| {
+        fields
+            .get(name)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+    };
+    // The number of a track or disc may be followed by the total, as in `3/12`.
+    let number = |name: &str| get(name).map(|value| value.split('/').next().unwrap_or(value));
+
+    match name {
+        "artist" => get("artist").unwrap_or("Unknown Artist").to_string(),
+        "albumartist" => get("albumartist")
+            .or_else(|| get("artist"))
+            .unwrap_or("Unknown Artist")
+            .to_string(),
+        "album" => get("album").unwrap_or("Unknown Album").to_string(),
+        "title" => get("title").unwrap_or(file_name).to_string(),
+        "track" => number("track").map_or_else(String::new, |track| {
+            track
+                .trim()
+                .parse::<u32>()
+                .map_or_else(|_| track.to_string(), |track| format!("{:02}", track))
+        }),
+        "disc" => number("disc").unwrap_or_default().trim().to_string(),
+        // Only the year of a full date.
+        "year" => get("year")
+            .map(|year| {
+                year.get(..4)
+                    .filter(|year| year.parse::<u32>().is_ok())
+                    .unwrap_or(year)
+            })
+            .unwrap_or_default()
+            .to_string(),
+        "genre" => get("genre").unwrap_or_default().to_string(),
+        _ => file_name.to_string(),
+    }
+}
+
+/// Replaces the characters that portable players don't allow in names with `_`.
+fn sanitize(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_control() || INVALID_CHARACTERS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn render(template: &str, tags: &[(&str, &str)]) -> PathBuf {
+        Template::parse(template).unwrap().render(
+            &fields(tags),
+            Path::new("/music/01 - song.flac"),
+            "mp3",
+        )
+    }
+
+    #[test]
+    fn names_files_after_the_tags() {
+        let tags = [
+            ("artist", "Artist"),
+            ("album", "Album"),
+            ("title", "Title"),
+            ("track", "3/12"),
+            ("year", "1999-04-01"),
+        ];
+        assert_eq!(
+            render("{albumartist}/{year} - {album}/{track} {title}", &tags),
+            Path::new("Artist/1999 - Album/03 Title.mp3")
+        );
+    }
+
+    #[test]
+    fn falls_back_on_missing_tags() {
+        assert_eq!(
+            render("{artist}/{album}/{disc}/{track} {title}", &[]),
+            Path::new("Unknown Artist/Unknown Album/01 - song.mp3")
+        );
+        assert_eq!(render("{genre}", &[]), Path::new("01 - song.mp3"));
+    }
+
+    #[test]
+    fn keeps_names_valid() {
+        let tags = [
+            ("artist", "AC/DC"),
+            ("album", ".."),
+            ("title", "Op. 3: \"Yes\"."),
+        ];
+        assert_eq!(
+            render("{artist}/{album}/{title}", &tags),
+            Path::new("AC_DC/Op. 3_ _Yes_.mp3")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        for template in ["", "{album", "album}", "{composer}/{title}"] {
+            assert!(matches!(
+                Template::parse(template),
+                Err(ExportError::InvalidTemplate(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Signals generated for the tests, and WAV and FLAC files of them.
+
+use std::f64::consts::PI;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::decoder::Symphonia;
+use super::export::flac::FlacWriter;
+use super::render::{to_i16, WavFormat, WavWriter};
+
+/// Number of the next fixture file, which keeps the files of tests running at once apart.
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns `frames` frames of a sine at `frequency` Hz and `amplitude`, the same on every
+/// channel.
+pub fn sine(
+    channels: u16,
+    sample_rate: u32,
+    frames: usize,
+    frequency: f64,
+    amplitude: f32,
+) -> Vec<f32> {
+    (0..frames)
+        .flat_map(|frame| {
+            let phase = 2.0 * PI * frequency * frame as f64 / f64::from(sample_rate);
+            let sample = amplitude * phase.sin() as f32;
+            vec![sample; usize::from(channels)]
+        })
+        .collect()
+}
+
+/// Returns `frames` frames of silence.
+pub fn silence(channels: u16, frames: usize) -> Vec<f32> {
+    vec![0.0; frames * usize::from(channels)]
+}
+
+/// Returns `frames` frames of silence, except the frame at `position` which is at `amplitude`.
+pub fn impulse(channels: u16, frames: usize, position: usize, amplitude: f32) -> Vec<f32> {
+    let mut samples = silence(channels, frames);
+    let channels = usize::from(channels);
+    for sample in &mut samples[position * channels..(position + 1) * channels] {
+        *sample = amplitude;
+    }
+    samples
+}
+
+/// Returns the samples the decoder gives for `samples` written as 16-bit integers.
+pub fn quantize(samples: &[f32]) -> Vec<i16> {
+    samples.iter().map(|&sample| to_i16(sample)).collect()
+}
+
+/// A file or folder in the temporary folder, removed when dropped.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Picks the path of a file with `extension` that no other test uses.
+    pub fn new(extension: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "plain-music-player-test-{}-{}.{}",
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed),
+            extension
+        ));
+        Self { path }
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if self.path.is_dir() {
+            let _ = fs::remove_dir_all(&self.path);
+        } else {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A WAV file in the temporary folder, removed when dropped.
+pub struct WavFixture {
+    file: TempFile,
+}
+
+impl WavFixture {
+    /// Writes the interleaved `samples` in `format`.
+    pub fn new(samples: &[f32], channels: u16, sample_rate: u32, format: WavFormat) -> Self {
+        let file = TempFile::new("wav");
+        let writer = BufWriter::new(File::create(file.path()).unwrap());
+        let mut wav = WavWriter::new(writer, channels, sample_rate, format).unwrap();
+        wav.write(samples).unwrap();
+        wav.finish().unwrap();
+        Self { file }
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    /// Opens the file with the decoder.
+    pub fn decode(&self) -> Symphonia {
+        Symphonia::new(File::open(self.path()).unwrap(), true).unwrap()
+    }
+}
+
+/// A FLAC file in the temporary folder, removed when dropped.
+pub struct FlacFixture {
+    file: TempFile,
+}
+
+impl FlacFixture {
+    /// Writes the interleaved `samples` as 16-bit integers.
+    pub fn new(samples: &[f32], channels: u16, sample_rate: u32) -> Self {
+        let file = TempFile::new("flac");
+        let writer = BufWriter::new(File::create(file.path()).unwrap());
+        let mut flac = FlacWriter::new(writer, channels, sample_rate).unwrap();
+        flac.write(&quantize(samples)).unwrap();
+        flac.finish().unwrap();
+        Self { file }
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    /// Opens the file with the decoder.
+    pub fn decode(&self) -> Symphonia {
+        Symphonia::new(File::open(self.path()).unwrap(), true).unwrap()
+    }
+}
@@ -0,0 +1,205 @@
+//! Listens to tracks, from when the player starts a track to when it leaves it, and whether they
+//! count as plays or skips.
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Fraction of a track past which leaving it counts as a play, by default.
+pub const DEFAULT_PLAY_THRESHOLD: f64 = 0.5;
+/// Moves of the position longer than this between two polls are seeks, and aren't counted as
+/// listened.
+const MAX_LISTEN_STEP: Duration = Duration::from_secs(1);
+
+/// How the listen to a track ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenEnd {
+    /// The track played to its end, or was left past the play threshold.
+    Played,
+    /// Another track was played before the play threshold.
+    Skipped,
+    /// Playback stopped, or the track was left while paused, before the play threshold.
+    Stopped,
+}
+
+/// A listen to a track, reported by `Player::poll_listens` once the player left the track.
+#[derive(Clone, Debug)]
+pub struct Listen {
+    /// Path of the track.
+    pub path: PathBuf,
+    /// When the player started the track.
+    pub started: SystemTime,
+    /// Position in the track when it was left.
+    pub position: Duration,
+    /// Time of the track that was heard, without the parts skipped by seeking.
+    pub listened: Duration,
+    /// Duration of the track, if known.
+    pub duration: Option<Duration>,
+    /// How the listen ended.
+    pub end: ListenEnd,
+}
+
+/// A listen to the current track, until the player leaves it.
+pub(crate) struct Progress {
+    pub started: SystemTime,
+    pub listened: Duration,
+    /// Position at the last poll.
+    position: Duration,
+}
+
+impl Progress {
+    /// Starts a listen at `position`.
+    pub fn new(position: Duration) -> Self {
+        Self {
+            started: SystemTime::now(),
+            listened: Duration::from_secs(0),
+            position,
+        }
+    }
+
+    /// Counts the time from the last position to `position` as listened, unless the position
+    /// jumped, as it does on seeks.
+    pub fn advance(&mut self, position: Duration) {
+        if position > self.position && position - self.position <= MAX_LISTEN_STEP {
+            self.listened += position - self.position;
+        }
+        self.position = position;
+    }
+}
+
+/// Returns how the listen to a track left at `position` ends, before the track played to its
+/// end.
+///
+/// `replaced` tells whether another track takes its place, and `paused` whether the track was
+/// paused. Tracks of unknown duration only count as played once they end.
+pub(crate) fn left_at(
+    position: Duration,
+    duration: Option<Duration>,
+    threshold: f64,
+    replaced: bool,
+    paused: bool,
+) -> ListenEnd {
+    let played = duration.map_or(false, |duration| {
+        position.as_secs_f64() >= duration.as_secs_f64() * threshold
+    });
+    if played {
+        ListenEnd::Played
+    } else if replaced && !paused {
+        ListenEnd::Skipped
+    } else {
+        ListenEnd::Stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::fixtures::{sine, WavFixture};
+    use crate::player::render::WavFormat;
+    use crate::player::Player;
+
+    const RATE: u32 = 44_100;
+
+    fn track(seconds: f64) -> WavFixture {
+        let frames = (seconds * f64::from(RATE)) as usize;
+        WavFixture::new(
+            &sine(2, RATE, frames, 440.0, 0.5),
+            2,
+            RATE,
+            WavFormat::Int16,
+        )
+    }
+
+    fn play(tracks: &[&WavFixture]) -> Player {
+        let mut player = Player::new_offline(2, RATE);
+        let paths = tracks
+            .iter()
+            .map(|track| track.path().to_path_buf())
+            .collect();
+        player.set_playlist(paths, Some(0));
+        player
+    }
+
+    /// Renders `seconds` of the output of `player`.
+    fn render(player: &mut Player, seconds: f64) {
+        let mut buffer = vec![0.0; (seconds * f64::from(RATE)) as usize * 2];
+        player.render(&mut buffer);
+    }
+
+    fn ends(listens: &[Listen]) -> Vec<ListenEnd> {
+        listens.iter().map(|listen| listen.end).collect()
+    }
+
+    #[test]
+    fn counts_tracks_played_to_the_end() {
+        let (first, second) = (track(0.5), track(0.5));
+        let mut player = play(&[&first, &second]);
+        render(&mut player, 2.0);
+        assert!(player.is_stopped());
+
+        let listens = player.poll_listens();
+        assert_eq!(ends(&listens), [ListenEnd::Played, ListenEnd::Played]);
+        assert_eq!(listens[0].path, first.path());
+        assert_eq!(listens[1].path, second.path());
+        assert!(listens[0].started <= listens[1].started);
+        assert!(player.poll_listens().is_empty());
+    }
+
+    #[test]
+    fn counts_early_changes_of_track_as_skips() {
+        let (first, second) = (track(2.0), track(2.0));
+        let mut player = play(&[&first, &second]);
+        render(&mut player, 0.5);
+        assert!(player.next());
+
+        let listens = player.poll_listens();
+        assert_eq!(ends(&listens), [ListenEnd::Skipped]);
+        let position = listens[0].position.as_secs_f64();
+        assert!((0.4..=0.5).contains(&position), "left at {}", position);
+        assert_eq!(listens[0].listened, listens[0].position);
+        assert_eq!(listens[0].duration.map(|d| d.as_secs()), Some(2));
+
+        // Past the threshold, leaving the track counts as a play.
+        render(&mut player, 1.5);
+        player.stop();
+        assert_eq!(ends(&player.poll_listens()), [ListenEnd::Played]);
+    }
+
+    #[test]
+    fn leaves_seeks_out_of_the_time_listened() {
+        let track = track(3.0);
+        let mut player = play(&[&track]);
+        render(&mut player, 0.5);
+        player.seek_to(Duration::from_secs(2));
+        render(&mut player, 0.5);
+        player.seek_to(Duration::from_secs(1));
+        render(&mut player, 0.5);
+        player.stop();
+
+        let listens = player.poll_listens();
+        assert_eq!(ends(&listens), [ListenEnd::Stopped]);
+        let listened = listens[0].listened.as_secs_f64();
+        assert!((1.3..=1.5).contains(&listened), "listened {}", listened);
+    }
+
+    #[test]
+    fn counts_stops_and_paused_tracks_as_neither() {
+        let (first, second) = (track(2.0), track(2.0));
+        let mut player = play(&[&first, &second]);
+        render(&mut player, 0.5);
+        player.pause();
+        assert!(player.next());
+        player.stop();
+        assert_eq!(
+            ends(&player.poll_listens()),
+            [ListenEnd::Stopped, ListenEnd::Stopped]
+        );
+
+        player.set_play_threshold(0.1);
+        player.set_playlist(vec![first.path().to_path_buf()], Some(0));
+        render(&mut player, 0.5);
+        player.stop();
+        assert_eq!(ends(&player.poll_listens()), [ListenEnd::Played]);
+    }
+}
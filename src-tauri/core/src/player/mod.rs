@@ -0,0 +1,1357 @@
+#![cfg_attr(test, deny(missing_docs))]
+
+mod conversions;
+#[cfg(test)]
+mod fixtures;
+mod sink;
+mod stream;
+
+pub mod buffer;
+pub mod chapters;
+/// Decoding of audio files.
+pub mod decoder;
+pub mod device;
+pub mod dither;
+pub mod dynamic_mixer;
+pub mod export;
+pub mod listen;
+pub mod playlist;
+pub mod queue;
+pub mod render;
+pub mod shuffle;
+pub mod sleep;
+pub mod source;
+pub mod visualizer;
+pub mod waveform;
+
+pub use chapters::Chapter;
+pub use conversions::{ChannelLayout, ChannelOptions, ResamplerQuality, Sample};
+pub use cpal::{
+    self, traits::DeviceTrait, Device, Devices, DevicesError, InputDevices, OutputDevices,
+    SupportedStreamConfig,
+};
+pub use decoder::Symphonia;
+pub use dither::DitherMode;
+pub use listen::{Listen, ListenEnd};
+pub use playlist::{Playlist, RepeatMode};
+pub use shuffle::ShuffleMode;
+pub use sink::Sink;
+pub use sleep::{SleepMode, SleepStatus};
+pub use source::{Source, TapBuffer};
+use std::fmt;
+pub use stream::{OutputStream, OutputStreamHandle, PlayError, StreamError};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Serialize;
+use shuffle::TrackKey;
+
+/// Change of the volume made by `volume_up` and `volume_down` by default.
+pub const VOLUME_STEP: u16 = 5;
+/// Jump made by `seek_fw` and `seek_bw` by default.
+pub const SEEK_STEP: Duration = Duration::from_secs(5);
+/// Relative seeks stop at the start of a chapter they would skip, unless it is this close to
+/// the position they start from.
+const CHAPTER_SNAP_MARGIN: Duration = Duration::from_secs(1);
+/// Relative seeks stop this far before the end, so that the end of the track is still heard.
+const SEEK_END_MARGIN: Duration = Duration::from_millis(100);
+/// Highest volume.
+pub const MAX_VOLUME: u16 = 100;
+/// Volume of a new player.
+pub const DEFAULT_VOLUME: u16 = 40;
+/// Playback speed of a new player.
+pub const DEFAULT_SPEED: f32 = 1.0;
+/// Past this position, going to the previous track restarts the current one instead. The same
+/// goes for chapters.
+const PREVIOUS_RESTART_THRESHOLD: Duration = Duration::from_secs(3);
+/// Tracks at least this long, such as audiobooks and podcasts, resume where they were left.
+/// Tracks with chapters always do.
+const RESUME_MIN_DURATION: Duration = Duration::from_secs(10 * 60);
+/// Positions this close to either end of a track aren't worth resuming from.
+const RESUME_MARGIN: Duration = Duration::from_secs(10);
+/// Duration of the fade out before the sleep timer stops playback by default.
+pub const SLEEP_FADE: Duration = Duration::from_secs(10);
+/// Number of samples kept for the visualizer, enough for a few FFT windows of 7.1 audio.
+const TAP_CAPACITY: usize = 65536;
+
+/// Plays a playlist on the output device.
+pub struct Player {
+    stream: Option<OutputStream>,
+    handle: Option<OutputStreamHandle>,
+    sink: Sink,
+    tap: Arc<TapBuffer>,
+    total_duration: Option<Duration>,
+    /// Chapters of the current track, sorted by start.
+    chapters: Vec<Chapter>,
+    /// Position to resume long tracks from, by path.
+    resume_positions: HashMap<PathBuf, Duration>,
+    sleep_mode: Option<SleepMode>,
+    /// When a sleep timer set in minutes stops playback.
+    sleep_deadline: Option<Instant>,
+    sleep_fade: Duration,
+    /// Whether the fade out of the sleep timer has started.
+    sleep_fading: bool,
+    /// Whether the sleep timer expired at the end of a track since the last poll.
+    sleep_expired: bool,
+    /// Start and end of the A-B loop in the current track.
+    loop_region: Option<(Duration, Duration)>,
+    /// Listen to the current track, until it is left.
+    listen: Option<listen::Progress>,
+    /// Listens that ended since the last poll.
+    listens: Vec<Listen>,
+    /// Fraction of a track past which leaving it counts as a play.
+    play_threshold: f64,
+    current_path: Option<PathBuf>,
+    playlist: Playlist,
+    device_name: Option<String>,
+    // Position to resume from once an output device is available again.
+    pending_position: Option<Duration>,
+    source_format: Option<SourceFormat>,
+    bit_perfect: bool,
+    resampler_quality: ResamplerQuality,
+    channel_options: ChannelOptions,
+    dither_mode: DitherMode,
+    noise_shaping: bool,
+    seek_step: Duration,
+    skip_silence: bool,
+    is_stopped: bool,
+    muted: bool,
+    /// Volume, from 0 to `MAX_VOLUME`.
+    pub volume: u16,
+    /// Playback speed, 1.0 being the normal speed.
+    pub speed: f32,
+    /// Whether the encoder delay and padding of tracks are trimmed.
+    pub gapless: bool,
+}
+
+unsafe impl Send for Player {}
+
+/// Format of the track being played.
+struct SourceFormat {
+    channels: u16,
+    sample_rate: u32,
+    bits_per_sample: Option<u32>,
+}
+
+/// Formats of the current track and of the output device, as reported to the UI.
+#[derive(Debug, Serialize)]
+pub struct OutputPath {
+    /// Whether bit-perfect playback was requested.
+    pub bit_perfect_mode: bool,
+    /// Whether the samples of the current track reach the device unaltered.
+    pub is_bit_perfect: bool,
+    /// Why the current track doesn't reach the device unaltered, although bit-perfect playback
+    /// was requested.
+    pub bit_perfect_limit: Option<BitPerfectLimit>,
+    /// Number of channels of the current track.
+    pub source_channels: Option<u16>,
+    /// Sample rate of the current track, in Hz.
+    pub source_sample_rate: Option<u32>,
+    /// Bit depth of the current track, if known.
+    pub source_bits_per_sample: Option<u32>,
+    /// Number of channels of the output stream.
+    pub output_channels: Option<u16>,
+    /// Sample rate of the output stream, in Hz.
+    pub output_sample_rate: Option<u32>,
+    /// Sample format of the output stream.
+    pub output_sample_format: Option<String>,
+    /// Whether dither is added when converting to the sample format of the device.
+    pub dither: bool,
+}
+
+/// What keeps a track from reaching the device unaltered in bit-perfect mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BitPerfectLimit {
+    /// The track has more than 16 bits per sample, or an unknown bit depth, and the decoder
+    /// produces 16-bit samples.
+    SourceBitDepth,
+    /// The device couldn't be opened at the channel count or sample rate of the track.
+    DeviceFormat,
+    /// The device only takes a sample format that doesn't hold 16-bit samples exactly.
+    SampleFormat,
+    /// The channels are downmixed to mono or swapped.
+    ChannelOptions,
+    /// Dither is added to the samples.
+    Dither,
+}
+
+/// Returns the gain applied for `volume`.
+///
+/// The cubic curve approximates the logarithmic perception of loudness: half the volume is
+/// about 18 dB quieter, while the gain still reaches zero at the bottom of the range.
+pub fn volume_gain(volume: u16) -> f32 {
+    let level = f32::from(volume.min(MAX_VOLUME)) / f32::from(MAX_VOLUME);
+    level * level * level
+}
+
+impl Player {
+    /// Returns a stopped player on the default output device.
+    pub fn new() -> Self {
+        let (stream, handle) = match OutputStream::try_default() {
+            Ok((stream, handle)) => (Some(stream), Some(handle)),
+            Err(err) => {
+                // Starts without a device. `check_output` picks one up once available.
+                eprintln!("no output device available: {}", err);
+                (None, None)
+            }
+        };
+        Self::with_output(stream, handle)
+    }
+
+    /// Returns a stopped player that doesn't play on a device. Its output, with `channels`
+    /// channels at `sample_rate`, is produced by `render` as fast as it is asked for, such as to
+    /// export it or to test it.
+    pub fn new_offline(channels: u16, sample_rate: u32) -> Self {
+        let (stream, handle) = OutputStream::offline(channels, sample_rate);
+        Self::with_output(Some(stream), Some(handle))
+    }
+
+    fn with_output(stream: Option<OutputStream>, handle: Option<OutputStreamHandle>) -> Self {
+        let gapless = true;
+        let mut sink = Self::new_sink(handle.as_ref(), gapless);
+        let tap = Arc::new(TapBuffer::new(TAP_CAPACITY));
+        sink.set_tap(tap.clone());
+        let volume = DEFAULT_VOLUME;
+        sink.set_volume(volume_gain(volume));
+        let speed = DEFAULT_SPEED;
+        sink.set_speed(speed);
+
+        Self {
+            stream,
+            handle,
+            sink,
+            tap,
+            total_duration: None,
+            chapters: Vec::new(),
+            resume_positions: HashMap::new(),
+            sleep_mode: None,
+            sleep_deadline: None,
+            sleep_fade: SLEEP_FADE,
+            sleep_fading: false,
+            sleep_expired: false,
+            loop_region: None,
+            listen: None,
+            listens: Vec::new(),
+            play_threshold: listen::DEFAULT_PLAY_THRESHOLD,
+            current_path: None,
+            playlist: Playlist::default(),
+            device_name: None,
+            pending_position: None,
+            source_format: None,
+            bit_perfect: false,
+            resampler_quality: ResamplerQuality::default(),
+            channel_options: ChannelOptions::default(),
+            dither_mode: DitherMode::default(),
+            noise_shaping: false,
+            seek_step: SEEK_STEP,
+            skip_silence: false,
+            is_stopped: true,
+            muted: false,
+            volume,
+            speed,
+            gapless,
+        }
+    }
+    /// Plays `path`, selecting it in the playlist. A track that isn't in the playlist replaces
+    /// it.
+    pub fn play(&mut self, path: &Path) {
+        match self.playlist.position(path) {
+            Some(index) => {
+                self.playlist.select(index);
+            }
+            None => self.playlist.set_tracks(vec![path.to_path_buf()], Some(0)),
+        }
+        self.play_from(path, self.resume_position(path), false);
+    }
+
+    /// Returns the playlist.
+    pub fn playlist(&self) -> &Playlist {
+        &self.playlist
+    }
+
+    /// Replaces the playlist with `tracks` and plays the one at `index`, if any.
+    pub fn set_playlist(&mut self, tracks: Vec<PathBuf>, index: Option<usize>) {
+        self.playlist.set_tracks(tracks, index);
+        self.reshuffle();
+        match self.playlist.current().map(Path::to_path_buf) {
+            Some(path) => self.play_from(&path, self.resume_position(&path), false),
+            None => self.stop(),
+        }
+    }
+
+    /// Restores a playlist saved from a previous run, paused at `position` of its current track.
+    pub fn restore_playlist(&mut self, playlist: Playlist, position: Duration) {
+        self.playlist = playlist;
+        if let Some(path) = self.playlist.current().map(Path::to_path_buf) {
+            self.play_from(&path, position, true);
+        }
+    }
+
+    /// Returns the repeat mode of the playlist.
+    pub fn repeat(&self) -> RepeatMode {
+        self.playlist.repeat()
+    }
+
+    /// Sets the repeat mode of the playlist.
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.playlist.set_repeat(repeat);
+    }
+
+    /// Returns the shuffle mode of the playlist.
+    pub fn shuffle_mode(&self) -> ShuffleMode {
+        self.playlist.shuffle_mode()
+    }
+
+    /// Shuffles the playlist with `mode`, in the order given by `seed`. The current track keeps
+    /// playing and comes first in the new order.
+    pub fn set_shuffle(&mut self, mode: ShuffleMode, seed: u64) {
+        let keys: Vec<TrackKey> = if mode.uses_tags() {
+            self.playlist
+                .tracks()
+                .iter()
+                .map(|path| TrackKey::read(path))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        self.playlist.shuffle(mode, seed, &keys);
+    }
+
+    /// Shuffles the playlist again after its tracks were replaced.
+    fn reshuffle(&mut self) {
+        let mode = self.playlist.shuffle_mode();
+        if mode != ShuffleMode::Off {
+            self.set_shuffle(mode, self.playlist.shuffle_seed());
+        }
+    }
+
+    /// Adds `path` at the end of the playlist.
+    pub fn enqueue(&mut self, path: PathBuf) {
+        self.playlist.push(path);
+    }
+
+    /// Removes the track at `index` from the playlist, stopping it if it is playing.
+    pub fn dequeue(&mut self, index: usize) {
+        let was_current = self.playlist.current_index() == Some(index);
+        if self.playlist.remove(index).is_some() && was_current {
+            self.stop();
+        }
+    }
+
+    /// Plays the track at `index` of the playlist. Returns `false` if there is no such track.
+    pub fn play_index(&mut self, index: usize) -> bool {
+        if !self.playlist.select(index) {
+            return false;
+        }
+        let path = self.playlist.tracks()[index].clone();
+        self.play_from(&path, self.resume_position(&path), false);
+        true
+    }
+
+    /// Selects the track at `index` of the playlist, paused at its start or resume position.
+    fn cue_index(&mut self, index: usize) -> bool {
+        if !self.playlist.select(index) {
+            return false;
+        }
+        let path = self.playlist.tracks()[index].clone();
+        self.play_from(&path, self.resume_position(&path), true);
+        true
+    }
+
+    /// Plays the next track of the playlist. Returns `false` if there is none.
+    pub fn next(&mut self) -> bool {
+        match self.playlist.next_index() {
+            Some(index) => self.play_index(index),
+            None => false,
+        }
+    }
+
+    /// Plays the previous track of the playlist, or restarts the current one if it has been
+    /// playing for a few seconds. Returns `false` if there is neither.
+    pub fn previous(&mut self) -> bool {
+        if !self.is_stopped && self.elapsed() > PREVIOUS_RESTART_THRESHOLD {
+            self.seek_to(Duration::from_secs(0));
+            return true;
+        }
+        match self.playlist.previous_index() {
+            Some(index) => self.play_index(index),
+            None => false,
+        }
+    }
+
+    /// Moves to the next track of the playlist once the current one has finished, following the
+    /// repeat mode, or stops at the end of the playlist. Returns `true` if the current track changed or playback stopped.
+    ///
+    /// Also counts the time listened to the current track, so it is best called often.
+    pub fn poll_track_end(&mut self) -> bool {
+        let position = self.elapsed();
+        if let Some(listen) = &mut self.listen {
+            listen.advance(position);
+        }
+        let finished = !self.is_stopped
+            && self.stream.is_some()
+            && self.pending_position.is_none()
+            && self.sink.is_empty();
+        if !finished {
+            return false;
+        }
+
+        self.end_listen(self.total_duration.unwrap_or(position), ListenEnd::Played);
+        // A finished track starts over next time.
+        if let Some(path) = self.current_path.take() {
+            self.resume_positions.remove(&path);
+        }
+        let sleep = self.sleep_ends_with_track();
+        if sleep {
+            self.sleep_mode = None;
+            self.sleep_fading = false;
+            self.sleep_expired = true;
+        }
+        let played = match self.playlist.following_index() {
+            // The sleep timer leaves the following track ready to play.
+            Some(index) if sleep => self.cue_index(index),
+            Some(index) => self.play_index(index),
+            None => false,
+        };
+        if !played {
+            self.stop();
+        }
+        true
+    }
+
+    /// Starts playing `path` at `position`, optionally paused.
+    pub fn play_from(&mut self, path: &Path, position: Duration, paused: bool) {
+        let decoder = File::open(path)
+            .ok()
+            .and_then(|file| Symphonia::new(file, self.gapless).ok());
+        if let Some(decoder) = &decoder {
+            if self.bit_perfect {
+                // Must happen before `stop`, which builds the sink on the current stream.
+                self.match_output_format(decoder.channels(), decoder.sample_rate());
+            }
+        }
+
+        // The A-B loop and the listen are kept when the same track is reopened, e.g. on another
+        // device.
+        let listen = if self.current_path.as_deref() == Some(path) {
+            self.listen.take()
+        } else {
+            self.leave_listen(true);
+            self.remember_position();
+            self.loop_region = None;
+            None
+        };
+
+        self.stop();
+        if let Some(mut decoder) = decoder {
+            if position > Duration::from_secs(0) {
+                decoder.seek_immediately(position);
+            }
+            decoder.set_loop_region(self.loop_region);
+            self.sink.set_loop_region(self.loop_region);
+            self.total_duration = decoder.total_duration();
+            self.chapters = chapters::read(path);
+            if self.chapters.is_empty() {
+                self.chapters = decoder
+                    .chapter_starts()
+                    .into_iter()
+                    .map(|start| Chapter { title: None, start })
+                    .collect();
+            }
+            self.source_format = Some(SourceFormat {
+                channels: decoder.channels(),
+                sample_rate: decoder.sample_rate(),
+                bits_per_sample: decoder.bits_per_sample(),
+            });
+            if paused {
+                self.sink.pause();
+            }
+            self.sink.append(decoder.trim_silence(self.skip_silence));
+            self.sink.set_speed(self.effective_speed());
+            // A fade out of the sleep timer is started again on the new sink.
+            self.sleep_fading = false;
+            self.current_path = Some(path.to_path_buf());
+            self.listen = listen.or_else(|| Some(listen::Progress::new(position)));
+            if self.stream.is_none() {
+                self.pending_position = Some(position);
+            }
+            self.is_stopped = false;
+        }
+        self.update_dither();
+    }
+
+    /// Loops the current track between `start` and `end`, jumping back to `start` without a gap
+    /// whenever `end` is reached. Returns `false` if nothing is playing or `end` doesn't come
+    /// after `start`.
+    pub fn set_loop(&mut self, start: Duration, end: Duration) -> bool {
+        if self.is_stopped || end <= start {
+            return false;
+        }
+        self.loop_region = Some((start, end));
+        self.sink.set_loop_region(self.loop_region);
+        true
+    }
+
+    /// Removes the A-B loop.
+    pub fn clear_loop(&mut self) {
+        self.loop_region = None;
+        self.sink.set_loop_region(None);
+    }
+
+    /// Returns the start and end of the A-B loop.
+    pub fn loop_region(&self) -> Option<(Duration, Duration)> {
+        self.loop_region
+    }
+
+    /// Returns the path of the current track.
+    pub fn current_path(&self) -> Option<&Path> {
+        self.current_path.as_deref()
+    }
+
+    /// Pauses playback.
+    pub fn pause(&mut self) {
+        self.sink.pause();
+    }
+
+    /// Resumes playback.
+    pub fn resume(&mut self) {
+        self.sink.play();
+    }
+
+    /// Returns whether playback is paused.
+    pub fn is_paused(&self) -> bool {
+        self.sink.is_paused()
+    }
+
+    /// Returns `true` if no track is loaded, or it was stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.is_stopped
+    }
+
+    /// Starts the sleep timer, replacing the previous one. Playback fades out over the duration
+    /// set with `set_sleep_fade`, then stops, leaving the track paused where it stopped.
+    pub fn set_sleep_timer(&mut self, mode: SleepMode) {
+        self.cancel_sleep_timer();
+        self.sleep_deadline = match mode {
+            SleepMode::Minutes(minutes) => {
+                Some(Instant::now() + Duration::from_secs(minutes.saturating_mul(60)))
+            }
+            SleepMode::EndOfTrack | SleepMode::EndOfQueue => None,
+        };
+        self.sleep_mode = Some(mode);
+    }
+
+    /// Cancels the sleep timer, bringing the volume back if it was fading out.
+    pub fn cancel_sleep_timer(&mut self) {
+        if self.sleep_fading {
+            self.sink.fade_out(Duration::from_secs(0));
+        }
+        self.sleep_mode = None;
+        self.sleep_deadline = None;
+        self.sleep_fading = false;
+    }
+
+    /// Returns the duration of the fade out before the sleep timer stops playback.
+    pub fn sleep_fade(&self) -> Duration {
+        self.sleep_fade
+    }
+
+    /// Sets the duration of the fade out before the sleep timer stops playback.
+    pub fn set_sleep_fade(&mut self, fade: Duration) {
+        self.sleep_fade = fade;
+    }
+
+    /// Returns the state of the sleep timer, or `None` if it isn't set.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn sleep_timer(&self) -> Option<SleepStatus> {
+        let mode = self.sleep_mode?;
+        Some(SleepStatus {
+            mode,
+            remaining_ms: self
+                .sleep_remaining()
+                .map(|remaining| remaining.as_millis() as u64),
+            tracks_left: match mode {
+                SleepMode::EndOfQueue => Some(self.playlist.tracks_after_current()),
+                SleepMode::Minutes(_) | SleepMode::EndOfTrack => None,
+            },
+        })
+    }
+
+    /// Returns the time left before the sleep timer stops playback.
+    fn sleep_remaining(&self) -> Option<Duration> {
+        match self.sleep_mode? {
+            SleepMode::Minutes(_) => self
+                .sleep_deadline
+                .map(|deadline| deadline.saturating_duration_since(Instant::now())),
+            SleepMode::EndOfTrack => self.track_remaining(),
+            SleepMode::EndOfQueue if self.playlist.tracks_after_current() == 0 => {
+                self.track_remaining()
+            }
+            SleepMode::EndOfQueue => None,
+        }
+    }
+
+    /// Returns the time left before the current track ends, or `None` if it doesn't end on its
+    /// own, such as while it loops.
+    fn track_remaining(&self) -> Option<Duration> {
+        if self.is_stopped || self.loop_region.is_some() {
+            return None;
+        }
+        let left = self.total_duration?.saturating_sub(self.elapsed());
+        Some(left.div_f32(self.effective_speed()))
+    }
+
+    /// Returns `true` if the sleep timer stops playback at the end of the current track.
+    fn sleep_ends_with_track(&self) -> bool {
+        match self.sleep_mode {
+            Some(SleepMode::EndOfTrack) => true,
+            Some(SleepMode::EndOfQueue) => self.playlist.tracks_after_current() == 0,
+            Some(SleepMode::Minutes(_)) | None => false,
+        }
+    }
+
+    /// Fades out and stops playback when the sleep timer is due. Returns `true` once when the
+    /// timer expires.
+    pub fn poll_sleep_timer(&mut self) -> bool {
+        // Timers ending with a track expire in `poll_track_end`.
+        if self.sleep_expired {
+            self.sleep_expired = false;
+            return true;
+        }
+        if let Some(deadline) = self.sleep_deadline {
+            if Instant::now() >= deadline {
+                self.sleep_mode = None;
+                self.sleep_deadline = None;
+                self.sleep_fading = false;
+                // Reopening the track resets the fade of the decoder, which would otherwise go
+                // back to full volume.
+                let position = self.elapsed();
+                let was_playing = !self.is_stopped;
+                self.resume_at(position, true, was_playing);
+                return true;
+            }
+        }
+
+        let remaining = match self.sleep_remaining() {
+            Some(remaining) => remaining,
+            None => return false,
+        };
+        if !self.sleep_fading
+            && !self.is_stopped
+            && !self.is_paused()
+            && remaining <= self.sleep_fade
+        {
+            // The fade out of the decoder reaches silence halfway through, and the decoder runs
+            // ahead of the clock when the speed is raised.
+            self.sink
+                .fade_out((remaining * 2).mul_f32(self.effective_speed()));
+            self.sleep_fading = true;
+        }
+        false
+    }
+
+    /// Returns the name of the output device chosen with `set_output_device`.
+    ///
+    /// `None` means the default device is used.
+    pub fn output_device(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
+
+    /// Moves playback to the output device named `name`, or to the default device if `None`.
+    ///
+    /// The current track keeps playing from the same position, and stays paused if it was.
+    pub fn set_output_device(&mut self, name: Option<&str>) -> Result<(), StreamError> {
+        self.open_output(name)?;
+        self.device_name = name.map(str::to_string);
+        Ok(())
+    }
+
+    /// Returns `true` if the player was made with `new_offline`.
+    pub fn is_offline(&self) -> bool {
+        self.stream.as_ref().map_or(false, OutputStream::is_offline)
+    }
+
+    /// Returns `true` if there is a working output stream.
+    pub fn has_output_device(&self) -> bool {
+        self.stream
+            .as_ref()
+            .map_or(false, |stream| !stream.has_failed())
+    }
+
+    /// Enables or disables bit-perfect playback.
+    ///
+    /// In bit-perfect mode the device is opened at the sample rate and channel count of each
+    /// track, reopening it when they change between tracks, and volume and speed are bypassed.
+    /// Tracks of more than 16 bits per sample still can't reach the device unaltered, which
+    /// `output_path` reports.
+    pub fn set_bit_perfect(&mut self, enabled: bool) -> Result<(), StreamError> {
+        if enabled == self.bit_perfect {
+            return Ok(());
+        }
+        self.bit_perfect = enabled;
+
+        if enabled {
+            let position = self.elapsed();
+            let paused = self.is_paused();
+            let was_playing = !self.is_stopped;
+            self.resume_at(position, paused, was_playing);
+            Ok(())
+        } else {
+            // Goes back to the device's default format.
+            let name = self.device_name.clone();
+            self.open_output(name.as_deref())
+        }
+    }
+
+    /// Returns whether bit-perfect playback was requested with `set_bit_perfect`.
+    pub fn bit_perfect(&self) -> bool {
+        self.bit_perfect
+    }
+
+    /// Sets the quality of the sample rate conversion, used when a track doesn't play at the
+    /// sample rate of the device.
+    ///
+    /// The current track is restarted from the same position to use it right away.
+    pub fn set_resampler_quality(&mut self, quality: ResamplerQuality) {
+        if quality == self.resampler_quality {
+            return;
+        }
+        self.resampler_quality = quality;
+
+        let position = self.elapsed();
+        let paused = self.is_paused();
+        let was_playing = !self.is_stopped;
+        self.resume_at(position, paused, was_playing);
+    }
+
+    /// Returns the quality set with `set_resampler_quality`.
+    pub fn resampler_quality(&self) -> ResamplerQuality {
+        self.resampler_quality
+    }
+
+    /// Sets how the channels of the tracks are mapped to the output, such as a mono downmix or
+    /// swapped left and right channels.
+    ///
+    /// The current track is restarted from the same position to use them right away.
+    pub fn set_channel_options(&mut self, options: ChannelOptions) {
+        if options == self.channel_options {
+            return;
+        }
+        self.channel_options = options;
+
+        let position = self.elapsed();
+        let paused = self.is_paused();
+        let was_playing = !self.is_stopped;
+        self.resume_at(position, paused, was_playing);
+    }
+
+    /// Returns the options set with `set_channel_options`.
+    pub fn channel_options(&self) -> ChannelOptions {
+        self.channel_options
+    }
+
+    /// Sets when dither is applied to 16-bit output, and whether it is noise shaped.
+    pub fn set_dither(&mut self, mode: DitherMode, noise_shaping: bool) {
+        self.dither_mode = mode;
+        self.noise_shaping = noise_shaping;
+        self.update_dither();
+    }
+
+    /// Returns the mode set with `set_dither`.
+    pub fn dither_mode(&self) -> DitherMode {
+        self.dither_mode
+    }
+
+    /// Returns whether noise shaping was enabled with `set_dither`.
+    pub fn noise_shaping(&self) -> bool {
+        self.noise_shaping
+    }
+
+    /// Returns `true` if dither is currently applied.
+    fn dither_active(&self) -> bool {
+        match self.dither_mode {
+            DitherMode::Off => false,
+            DitherMode::On => true,
+            DitherMode::Auto => self.is_signal_altered(),
+        }
+    }
+
+    /// Returns `true` if the samples of the current track are changed on their way to the
+    /// device, so that they no longer fit exactly in 16 bits.
+    #[allow(clippy::float_cmp)]
+    fn is_signal_altered(&self) -> bool {
+        let source = self.source_format.as_ref().filter(|_| !self.is_stopped);
+        match (&self.stream, source) {
+            (Some(stream), Some(source)) => {
+                self.effective_volume() != 1.0
+                    || self.effective_speed() != 1.0
+                    || self.channel_options != ChannelOptions::default()
+                    || stream.channels() != source.channels
+                    || stream.sample_rate() != source.sample_rate
+                    || source.bits_per_sample.map_or(true, |bits| bits > 16)
+            }
+            _ => false,
+        }
+    }
+
+    fn update_dither(&self) {
+        if let Some(handle) = &self.handle {
+            let _ = handle.set_dither(self.dither_active(), self.noise_shaping);
+        }
+    }
+
+    /// Describes how the current track reaches the device.
+    pub fn output_path(&self) -> OutputPath {
+        let stream = self.stream.as_ref();
+        let source = self.source_format.as_ref().filter(|_| !self.is_stopped);
+        // Dither only applies to integer formats.
+        let dither = self.dither_active()
+            && stream.map_or(false, |stream| {
+                stream.sample_format() != cpal::SampleFormat::F32
+            });
+
+        let limit = match (stream, source) {
+            (Some(stream), Some(source)) if self.bit_perfect => {
+                // The decoder produces 16-bit samples.
+                if source.bits_per_sample.map_or(true, |bits| bits > 16) {
+                    Some(BitPerfectLimit::SourceBitDepth)
+                } else if stream.channels() != source.channels
+                    || stream.sample_rate() != source.sample_rate
+                {
+                    Some(BitPerfectLimit::DeviceFormat)
+                } else if !device::is_lossless_format(stream.sample_format()) {
+                    Some(BitPerfectLimit::SampleFormat)
+                } else if self.channel_options != ChannelOptions::default() {
+                    Some(BitPerfectLimit::ChannelOptions)
+                } else if dither {
+                    Some(BitPerfectLimit::Dither)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        let is_bit_perfect =
+            self.bit_perfect && stream.is_some() && source.is_some() && limit.is_none();
+
+        OutputPath {
+            bit_perfect_mode: self.bit_perfect,
+            is_bit_perfect,
+            bit_perfect_limit: limit,
+            source_channels: source.map(|source| source.channels),
+            source_sample_rate: source.map(|source| source.sample_rate),
+            source_bits_per_sample: source.and_then(|source| source.bits_per_sample),
+            output_channels: stream.map(OutputStream::channels),
+            output_sample_rate: stream.map(OutputStream::sample_rate),
+            output_sample_format: stream.map(|stream| format!("{:?}", stream.sample_format())),
+            dither,
+        }
+    }
+
+    /// Reopens the output device at the given format, if it isn't already and the device
+    /// supports it. Otherwise the current stream is kept and samples get converted.
+    fn match_output_format(&mut self, channels: u16, sample_rate: u32) {
+        if let Some(stream) = &self.stream {
+            // An offline stream keeps the format it was created with.
+            if stream.is_offline()
+                || (stream.channels() == channels && stream.sample_rate() == sample_rate)
+            {
+                return;
+            }
+        }
+
+        let device = match &self.device_name {
+            Some(name) => device::find_output_device(name).ok(),
+            None => device::default_output_device(),
+        };
+        let device = match device {
+            Some(device) => device,
+            None => return,
+        };
+        let config = match device::native_output_config(&device, channels, sample_rate) {
+            Some(config) => config,
+            None => return,
+        };
+
+        // Some devices can only be opened once, so the current stream is closed first.
+        self.stream = None;
+        self.handle = None;
+        let opened = OutputStream::try_from_device_with_format(&device, config)
+            .or_else(|_| OutputStream::try_from_device(&device));
+        match opened {
+            Ok((stream, handle)) => {
+                self.stream = Some(stream);
+                self.handle = Some(handle);
+            }
+            Err(err) => eprintln!("failed to reopen output device: {}", err),
+        }
+    }
+
+    /// Rebuilds the output stream if it failed, if there was no device, or if the default device
+    /// changed while it is being followed.
+    ///
+    /// When the chosen device is gone, the default device is used instead. When no device at all
+    /// is available, the player keeps the current track and position until a later call
+    /// succeeds. Returns `true` if the output changed.
+    pub fn check_output(&mut self) -> bool {
+        let needs_reopen = match &self.stream {
+            None => true,
+            Some(stream) if stream.has_failed() => true,
+            Some(stream) => {
+                self.device_name.is_none()
+                    && stream.device_name().map_or(false, |name| {
+                        device::default_output_device_name().map_or(false, |d| d != name)
+                    })
+            }
+        };
+        if !needs_reopen {
+            return false;
+        }
+
+        let name = self.device_name.clone();
+        match self
+            .open_output(name.as_deref())
+            .or_else(|_| self.open_output(None))
+        {
+            Ok(()) => true,
+            Err(_) if self.stream.is_some() => {
+                self.lose_output();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Opens a new output stream and resumes the current track on it.
+    fn open_output(&mut self, name: Option<&str>) -> Result<(), StreamError> {
+        let (stream, handle) = match name {
+            Some(name) => OutputStream::try_from_device(&device::find_output_device(name)?)?,
+            None => OutputStream::try_default()?,
+        };
+
+        let position = self.elapsed();
+        let paused = self.is_paused();
+        let was_playing = !self.is_stopped;
+
+        self.stream = Some(stream);
+        self.handle = Some(handle);
+        self.resume_at(position, paused, was_playing);
+        Ok(())
+    }
+
+    /// Drops the output stream, keeping the current track ready to resume.
+    fn lose_output(&mut self) {
+        let position = self.elapsed();
+        let paused = self.is_paused();
+        let was_playing = !self.is_stopped;
+
+        self.stream = None;
+        self.handle = None;
+        self.resume_at(position, paused, was_playing);
+    }
+
+    fn resume_at(&mut self, position: Duration, paused: bool, was_playing: bool) {
+        match self.current_path.clone() {
+            Some(path) if was_playing => self.play_from(&path, position, paused),
+            _ => self.stop(),
+        }
+    }
+
+    fn new_sink(handle: Option<&OutputStreamHandle>, gapless: bool) -> Sink {
+        handle
+            .and_then(|handle| Sink::try_new(handle, gapless).ok())
+            // Without a device, the sink still accepts sounds but nothing plays them.
+            .unwrap_or_else(|| Sink::new_idle(gapless).0)
+    }
+
+    /// Stops playback.
+    pub fn stop(&mut self) {
+        self.leave_listen(false);
+        self.remember_position();
+        if let Some(handle) = &self.handle {
+            // Fails only if the stream is gone, in which case there is nothing to configure.
+            let _ = handle.set_resampler_quality(self.resampler_quality);
+            let _ = handle.set_channel_options(self.channel_options);
+        }
+        self.sink = Self::new_sink(self.handle.as_ref(), self.gapless);
+        self.sink.set_tap(self.tap.clone());
+        self.sink.set_volume(self.effective_volume());
+        self.pending_position = None;
+        self.chapters.clear();
+        self.is_stopped = true;
+        self.update_dither();
+    }
+    /// Renders the next interleaved samples of an offline player into `buffer`, moving through
+    /// the playlist as `poll_track_end` does. Returns the number of samples written, which is
+    /// less than the length of `buffer` once playback stopped, and 0 if the player plays on a
+    /// device.
+    pub fn render(&mut self, buffer: &mut [f32]) -> usize {
+        let channels = match &self.stream {
+            Some(stream) if stream.is_offline() => usize::from(stream.channels()),
+            _ => return 0,
+        };
+        let mut written = 0;
+        while written + channels <= buffer.len() && !self.is_stopped {
+            let frame = &mut buffer[written..written + channels];
+            if let Some(stream) = &mut self.stream {
+                stream.render(frame);
+            }
+            // The frame in which the track turned out to have ended only holds the silence that
+            // followed it, so it is rendered again from the next track, without a gap.
+            if !self.poll_track_end() {
+                written += channels;
+            }
+        }
+        written
+    }
+
+    /// Returns the buffer receiving a copy of everything played, for the visualizer.
+    pub fn tap_buffer(&self) -> Arc<TapBuffer> {
+        self.tap.clone()
+    }
+    /// Returns the position in the current track.
+    pub fn elapsed(&self) -> Duration {
+        self.pending_position.unwrap_or_else(|| self.sink.elapsed())
+    }
+    /// Returns the duration of the current track in seconds, if known.
+    pub fn duration(&self) -> Option<f64> {
+        self.total_duration
+            .map(|duration| duration.as_secs_f64() - 0.29)
+    }
+
+    /// Returns the jump made by `seek_fw` and `seek_bw`.
+    pub fn seek_step(&self) -> Duration {
+        self.seek_step
+    }
+
+    /// Sets the jump made by `seek_fw` and `seek_bw`.
+    pub fn set_seek_step(&mut self, step: Duration) {
+        self.seek_step = step;
+    }
+
+    /// Sets whether the digital silence at the start and end of tracks is skipped, from the next
+    /// track on.
+    pub fn set_skip_silence(&mut self, enabled: bool) {
+        self.skip_silence = enabled;
+    }
+
+    /// Returns whether the digital silence at the start and end of tracks is skipped.
+    pub fn skip_silence(&self) -> bool {
+        self.skip_silence
+    }
+
+    /// Seeks forward by the seek step.
+    #[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
+    pub fn seek_fw(&mut self) {
+        self.seek_relative(self.seek_step.as_millis() as i64);
+    }
+
+    /// Seeks backward by the seek step.
+    #[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
+    pub fn seek_bw(&mut self) {
+        self.seek_relative(-(self.seek_step.as_millis() as i64));
+    }
+
+    /// Seeks `delta_ms` milliseconds forward, or backward if negative, from the current
+    /// position.
+    ///
+    /// The jump stops at the start of the first chapter it would skip, and short of the end of
+    /// the track.
+    pub fn seek_relative(&mut self, delta_ms: i64) {
+        let elapsed = self.elapsed();
+        let delta = Duration::from_millis(delta_ms.unsigned_abs());
+        let mut target = if delta_ms >= 0 {
+            elapsed + delta
+        } else {
+            elapsed.saturating_sub(delta)
+        };
+        if let Some(duration) = self.total_duration {
+            target = target.min(duration.saturating_sub(SEEK_END_MARGIN));
+        }
+        if let Some(chapter) = self.chapter_between(elapsed, target) {
+            target = chapter;
+        }
+        self.seek_to(target);
+    }
+
+    /// Returns the start of the chapter closest to `from` between `from` and `to`.
+    fn chapter_between(&self, from: Duration, to: Duration) -> Option<Duration> {
+        if to > from {
+            let after = from + CHAPTER_SNAP_MARGIN;
+            self.chapters
+                .iter()
+                .map(|chapter| chapter.start)
+                .find(|&start| start > after && start < to)
+        } else {
+            let before = from.saturating_sub(CHAPTER_SNAP_MARGIN);
+            self.chapters
+                .iter()
+                .rev()
+                .map(|chapter| chapter.start)
+                .find(|&start| start < before && start > to)
+        }
+    }
+
+    /// Returns the chapters of the current track, sorted by start.
+    pub fn chapters(&self) -> &[Chapter] {
+        &self.chapters
+    }
+
+    /// Returns the index of the chapter being played.
+    pub fn current_chapter(&self) -> Option<usize> {
+        let elapsed = self.elapsed();
+        self.chapters
+            .iter()
+            .rposition(|chapter| chapter.start <= elapsed)
+    }
+
+    /// Jumps to the start of the next chapter. Returns `false` if there is none.
+    pub fn next_chapter(&mut self) -> bool {
+        let elapsed = self.elapsed();
+        match self.chapters.iter().find(|chapter| chapter.start > elapsed) {
+            Some(chapter) => {
+                let start = chapter.start;
+                self.seek_to(start);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Jumps to the start of the previous chapter, or of the current one if it has been playing
+    /// for a few seconds. Returns `false` if there is neither.
+    pub fn previous_chapter(&mut self) -> bool {
+        let index = match self.current_chapter() {
+            Some(index) => index,
+            None => return false,
+        };
+        let start = self.chapters[index].start;
+        if self.elapsed() > start + PREVIOUS_RESTART_THRESHOLD || index == 0 {
+            self.seek_to(start);
+        } else {
+            self.seek_to(self.chapters[index - 1].start);
+        }
+        true
+    }
+
+    /// Returns the fraction of a track past which leaving it counts as a play.
+    pub fn play_threshold(&self) -> f64 {
+        self.play_threshold
+    }
+
+    /// Sets the fraction of a track, from 0.0 to 1.0, past which leaving it counts as a play.
+    /// Tracks that play to their end always count.
+    pub fn set_play_threshold(&mut self, threshold: f64) {
+        self.play_threshold = threshold.clamp(0.0, 1.0);
+    }
+
+    /// Returns when the listen to the current track started, or `None` if there is no current
+    /// track. Changes whenever another listen starts, even to the same track.
+    pub fn listen_started(&self) -> Option<SystemTime> {
+        self.listen.as_ref().map(|listen| listen.started)
+    }
+
+    /// Returns the listens that ended since the last call, oldest first.
+    pub fn poll_listens(&mut self) -> Vec<Listen> {
+        std::mem::take(&mut self.listens)
+    }
+
+    /// Ends the listen to the current track, at `position`.
+    fn end_listen(&mut self, position: Duration, end: ListenEnd) {
+        if let (Some(mut listen), Some(path)) = (self.listen.take(), &self.current_path) {
+            listen.advance(position);
+            self.listens.push(Listen {
+                path: path.clone(),
+                started: listen.started,
+                position,
+                listened: listen.listened,
+                duration: self.total_duration,
+                end,
+            });
+        }
+    }
+
+    /// Ends the listen to the current track before its end. `replaced` tells whether another
+    /// track takes its place.
+    fn leave_listen(&mut self, replaced: bool) {
+        if self.listen.is_none() {
+            return;
+        }
+        let position = self.elapsed();
+        let end = listen::left_at(
+            position,
+            self.total_duration,
+            self.play_threshold,
+            replaced,
+            self.is_paused(),
+        );
+        self.end_listen(position, end);
+    }
+
+    /// Returns `true` if the current track resumes where it was left.
+    fn is_resumable(&self) -> bool {
+        !self.chapters.is_empty()
+            || self
+                .total_duration
+                .map_or(false, |duration| duration >= RESUME_MIN_DURATION)
+    }
+
+    /// Records the position in the current track in `positions`, if it resumes where it was
+    /// left. Positions near either end are forgotten instead.
+    fn record_position(&self, positions: &mut HashMap<PathBuf, Duration>) {
+        let path = match &self.current_path {
+            Some(path) if !self.is_stopped && self.is_resumable() => path,
+            _ => return,
+        };
+        let elapsed = self.elapsed();
+        let near_end = self
+            .total_duration
+            .map_or(false, |duration| elapsed + RESUME_MARGIN >= duration);
+        if elapsed < RESUME_MARGIN || near_end {
+            positions.remove(path);
+        } else {
+            positions.insert(path.clone(), elapsed);
+        }
+    }
+
+    fn remember_position(&mut self) {
+        let mut positions = std::mem::take(&mut self.resume_positions);
+        self.record_position(&mut positions);
+        self.resume_positions = positions;
+    }
+
+    /// Returns the position to start `path` from.
+    fn resume_position(&self, path: &Path) -> Duration {
+        self.resume_positions.get(path).copied().unwrap_or_default()
+    }
+
+    /// Returns the position to resume each long track from, including the current one.
+    pub fn resume_positions(&self) -> HashMap<PathBuf, Duration> {
+        let mut positions = self.resume_positions.clone();
+        self.record_position(&mut positions);
+        positions
+    }
+
+    /// Restores positions saved from a previous run.
+    pub fn set_resume_positions(&mut self, positions: HashMap<PathBuf, Duration>) {
+        self.resume_positions = positions;
+    }
+
+    /// Seeks to `time` in the current track.
+    pub fn seek_to(&mut self, time: Duration) {
+        // The seek cuts the fade out of the sleep timer short, so it starts again from the new
+        // position.
+        self.sleep_fading = false;
+        if self.pending_position.is_some() {
+            self.pending_position = Some(time);
+        }
+        self.sink.seek(time);
+    }
+    /// Returns the position in the current track, from 0.0 to 1.0.
+    pub fn percentage(&self) -> f64 {
+        self.duration().map_or(0.0, |duration| {
+            let elapsed = self.elapsed();
+            elapsed.as_secs_f64() / duration
+        })
+    }
+    /// Sets the volume, from 0 to `MAX_VOLUME`. Steps of the volume are perceived as even
+    /// changes of loudness.
+    pub fn set_volume(&mut self, volume: u16) {
+        self.volume = volume.min(MAX_VOLUME);
+        self.sink.set_volume(self.effective_volume());
+        self.update_dither();
+    }
+
+    /// Raises the volume by `step`, unmuting. Returns the new volume.
+    pub fn volume_up(&mut self, step: u16) -> u16 {
+        self.muted = false;
+        self.set_volume(self.volume.saturating_add(step));
+        self.volume
+    }
+
+    /// Lowers the volume by `step`. Returns the new volume.
+    pub fn volume_down(&mut self, step: u16) -> u16 {
+        self.set_volume(self.volume.saturating_sub(step));
+        self.volume
+    }
+
+    /// Silences the output, keeping the volume to restore with `unmute`.
+    pub fn mute(&mut self) {
+        self.muted = true;
+        self.sink.set_volume(self.effective_volume());
+        self.update_dither();
+    }
+
+    /// Restores the volume after `mute`.
+    pub fn unmute(&mut self) {
+        self.muted = false;
+        self.sink.set_volume(self.effective_volume());
+        self.update_dither();
+    }
+
+    /// Returns whether the player is muted.
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Sets the playback speed, 1.0 being the normal speed.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+        self.sink.set_speed(self.effective_speed());
+        self.update_dither();
+    }
+
+    // Volume and speed are bypassed in bit-perfect mode, though muting still silences it.
+    fn effective_volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else if self.bit_perfect {
+            1.0
+        } else {
+            volume_gain(self.volume)
+        }
+    }
+
+    fn effective_speed(&self) -> f32 {
+        if self.bit_perfect {
+            1.0
+        } else {
+            self.speed
+        }
+    }
+
+    /// Returns the position in percent, the position and the duration in seconds.
+    pub fn get_progress(&mut self) -> Result<(f64, i64, i64), PlayerError> {
+        if self.is_stopped {
+            return Err(PlayerError::StoppedError);
+        }
+
+        let position = self.elapsed().as_secs() as i64;
+        let duration = self.duration().unwrap() as i64;
+        let mut percent = self.percentage() * 100.0;
+        if percent > 100.0 {
+            percent = 100.0;
+        }
+        Ok((percent, position, duration))
+    }
+}
+
+/// Errors of the player.
+#[derive(Debug, Serialize)]
+pub enum PlayerError {
+    /// Nothing is playing.
+    StoppedError,
+    /// The output device failed.
+    DeviceError(String),
+}
+
+impl From<StreamError> for PlayerError {
+    fn from(err: StreamError) -> Self {
+        Self::DeviceError(err.to_string())
+    }
+}
@@ -0,0 +1,236 @@
+//! The list of tracks played one after the other by the `Player`.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::shuffle::{self, ShuffleMode, TrackKey};
+
+/// What happens when a track ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatMode {
+    /// Playback stops after the last track.
+    Off,
+    /// The current track is played again.
+    One,
+    /// The playlist starts over after the last track.
+    All,
+}
+
+impl Default for RepeatMode {
+    fn default() -> Self {
+        Self::Off
+    }
+}
+
+/// Tracks queued for playback, and the one being played.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Playlist {
+    tracks: Vec<PathBuf>,
+    current: Option<usize>,
+    repeat: RepeatMode,
+    shuffle: ShuffleMode,
+    /// Seed of the shuffled order.
+    seed: u64,
+    /// Indices of the tracks in the order they are played.
+    order: Vec<usize>,
+}
+
+impl Playlist {
+    /// Builds a playlist of `tracks`, positioned on `current` if it is a valid index.
+    pub fn new(tracks: Vec<PathBuf>, current: Option<usize>) -> Self {
+        let current = current.filter(|&index| index < tracks.len());
+        let order = (0..tracks.len()).collect();
+        Self {
+            tracks,
+            current,
+            order,
+            ..Self::default()
+        }
+    }
+
+    /// Replaces the tracks, keeping the repeat and shuffle modes.
+    ///
+    /// The tracks are left in playlist order until `shuffle` is called again.
+    pub fn set_tracks(&mut self, tracks: Vec<PathBuf>, current: Option<usize>) {
+        *self = Self {
+            repeat: self.repeat,
+            shuffle: self.shuffle,
+            seed: self.seed,
+            ..Self::new(tracks, current)
+        };
+    }
+
+    /// Returns the tracks, in playlist order.
+    pub fn tracks(&self) -> &[PathBuf] {
+        &self.tracks
+    }
+
+    /// Returns the number of tracks.
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Returns whether there are no tracks.
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    /// Returns the index of the current track.
+    pub fn current_index(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Returns the path of the current track.
+    pub fn current(&self) -> Option<&Path> {
+        self.current.map(|index| self.tracks[index].as_path())
+    }
+
+    /// Returns the index of `path` in the playlist, if it is in it.
+    pub fn position(&self, path: &Path) -> Option<usize> {
+        self.tracks.iter().position(|track| track == path)
+    }
+
+    /// Makes the track at `index` the current one. Returns `false` if there is no such track.
+    pub fn select(&mut self, index: usize) -> bool {
+        if index < self.tracks.len() {
+            self.current = Some(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the repeat mode.
+    pub fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    /// Sets the repeat mode.
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+
+    /// Returns the shuffle mode.
+    pub fn shuffle_mode(&self) -> ShuffleMode {
+        self.shuffle
+    }
+
+    /// Returns the seed of the current shuffle order.
+    pub fn shuffle_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the indices of the tracks in the order they are played.
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    /// Shuffles the tracks with `mode`, starting from the current track.
+    ///
+    /// `keys` holds the tags of the tracks, and is only needed by the modes for which
+    /// `ShuffleMode::uses_tags` is `true`.
+    pub fn shuffle(&mut self, mode: ShuffleMode, seed: u64, keys: &[TrackKey]) {
+        self.shuffle = mode;
+        self.seed = seed;
+        self.order = shuffle::order(mode, seed, self.tracks.len(), keys, self.current);
+    }
+
+    /// Restores an order saved by `shuffle`. Returns `false`, leaving the order unchanged, if
+    /// `order` isn't an order of the tracks of the playlist.
+    pub fn restore_shuffle(&mut self, mode: ShuffleMode, seed: u64, order: Vec<usize>) -> bool {
+        if order.len() != self.tracks.len() {
+            return false;
+        }
+        let mut seen = vec![false; order.len()];
+        for &index in &order {
+            match seen.get_mut(index) {
+                Some(seen) if !*seen => *seen = true,
+                _ => return false,
+            }
+        }
+
+        self.shuffle = mode;
+        self.seed = seed;
+        self.order = order;
+        true
+    }
+
+    /// Returns the position of the current track in the play order.
+    fn order_position(&self) -> Option<usize> {
+        self.current
+            .and_then(|current| self.order.iter().position(|&index| index == current))
+    }
+
+    /// Returns the index of the track after the current one, going back to the first track
+    /// after the last one unless repeat is off.
+    pub fn next_index(&self) -> Option<usize> {
+        let wrap = self.repeat != RepeatMode::Off;
+        match self.order_position() {
+            Some(position) if position + 1 < self.order.len() => Some(self.order[position + 1]),
+            Some(_) if wrap => self.order.first().copied(),
+            Some(_) => None,
+            None => self.order.first().copied(),
+        }
+    }
+
+    /// Returns the index of the track before the current one, going to the last track before
+    /// the first one unless repeat is off.
+    pub fn previous_index(&self) -> Option<usize> {
+        let wrap = self.repeat != RepeatMode::Off;
+        match self.order_position() {
+            Some(position) if position > 0 => Some(self.order[position - 1]),
+            Some(_) if wrap => self.order.last().copied(),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of tracks after the current one in the play order, ignoring the
+    /// repeat mode.
+    pub fn tracks_after_current(&self) -> usize {
+        self.order_position()
+            .map_or(self.order.len(), |position| self.order.len() - position - 1)
+    }
+
+    /// Returns the index of the track to play once the current one has ended.
+    pub fn following_index(&self) -> Option<usize> {
+        match self.repeat {
+            RepeatMode::One if self.current.is_some() => self.current,
+            _ => self.next_index(),
+        }
+    }
+
+    /// Adds `path` at the end of the playlist, and of the play order.
+    pub fn push(&mut self, path: PathBuf) {
+        self.order.push(self.tracks.len());
+        self.tracks.push(path);
+    }
+
+    /// Removes the track at `index`. Removing the current track leaves no current track.
+    pub fn remove(&mut self, index: usize) -> Option<PathBuf> {
+        if index >= self.tracks.len() {
+            return None;
+        }
+
+        self.current = match self.current {
+            Some(current) if current == index => None,
+            Some(current) if current > index => Some(current - 1),
+            current => current,
+        };
+        self.order.retain(|&track| track != index);
+        for track in &mut self.order {
+            if *track > index {
+                *track -= 1;
+            }
+        }
+        Some(self.tracks.remove(index))
+    }
+
+    /// Removes every track.
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+        self.order.clear();
+        self.current = None;
+    }
+}
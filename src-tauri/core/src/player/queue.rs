@@ -9,7 +9,7 @@ use std::{
 };
 
 use super::source::{Empty, Source, Zero};
-use super::Sample;
+use super::{ChannelLayout, Sample};
 
 /// Builds a new queue. It consists of an input and an output.
 ///
@@ -36,6 +36,7 @@ where
 
     let output = SourcesQueueOutput {
         current: Box::new(Empty::<S>::new()) as Box<_>,
+        current_is_silence: true,
         signal_after_end: None,
         input: input.clone(),
         sample_cache: VecDeque::new(),
@@ -103,6 +104,10 @@ pub struct SourcesQueueOutput<S> {
     // The current iterator that produces samples.
     current: Box<dyn Source<Item = S> + Send>,
 
+    // Whether `current` is the empty source the queue starts with or a silence played while the
+    // queue is empty, rather than a sound.
+    current_is_silence: bool,
+
     // Signal this sender before picking from `next`.
     signal_after_end: Option<Sender<()>>,
 
@@ -131,33 +136,40 @@ where
         // constant.
         const THRESHOLD: usize = 512;
 
-        // Try the current `current_frame_len`.
-        if let Some(val) = self.current.current_frame_len() {
-            if val != 0 {
-                return Some(val);
+        self.with_format_source(|source| {
+            // Try the current `current_frame_len`.
+            if let Some(val) = source.current_frame_len() {
+                if val != 0 {
+                    return Some(val);
+                }
             }
-        }
 
-        // Try the size hint.
-        let (lower_bound, _) = self.current.size_hint();
-        // The iterator default implementation just returns 0.
-        // That's a problematic value, so skip it.
-        if lower_bound > 0 {
-            return Some(lower_bound);
-        }
+            // Try the size hint.
+            let (lower_bound, _) = source.size_hint();
+            // The iterator default implementation just returns 0.
+            // That's a problematic value, so skip it.
+            if lower_bound > 0 {
+                return Some(lower_bound);
+            }
 
-        // Otherwise we use the constant value.
-        Some(THRESHOLD)
+            // Otherwise we use the constant value.
+            Some(THRESHOLD)
+        })
     }
 
     #[inline]
     fn channels(&self) -> u16 {
-        self.current.channels()
+        self.with_format_source(|source| source.channels())
+    }
+
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.with_format_source(|source| source.channel_layout())
     }
 
     #[inline]
     fn sample_rate(&self) -> u32 {
-        self.current.sample_rate()
+        self.with_format_source(|source| source.sample_rate())
     }
 
     #[inline]
@@ -210,6 +222,18 @@ impl<S> SourcesQueueOutput<S>
 where
     S: Sample + Send + 'static,
 {
+    // Calls `f` with the source whose format the queue has. While only silence played, that is
+    // the next sound if there is one, so that whatever converts the queue is set up for the
+    // sound rather than for the silence before it.
+    fn with_format_source<R>(&self, f: impl Fn(&dyn Source<Item = S>) -> R) -> R {
+        if self.current_is_silence {
+            if let Some((next, _)) = self.input.next_sounds.lock().unwrap().first() {
+                return f(next.as_ref());
+            }
+        }
+        f(self.current.as_ref())
+    }
+
     // Called when `current` is empty and we must jump to the next element.
     // Returns `Ok` if the sound should continue playing, or an error if it should stop.
     //
@@ -221,6 +245,7 @@ where
 
         let (next, signal_after_end) = {
             let mut next = self.input.next_sounds.lock().unwrap();
+            self.current_is_silence = next.is_empty();
 
             if next.len() == 0 {
                 if self.input.keep_alive_if_empty.load(Ordering::Acquire) {
@@ -264,3 +289,84 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::buffer::SamplesBuffer;
+
+    #[test]
+    fn gapless_playback_trims_leading_silence() {
+        let (input, mut output) = queue(false, true);
+        input.append(SamplesBuffer::new(
+            2,
+            44_100,
+            vec![0i16, 0, 0, 0, 5, 0, 6, 7],
+        ));
+        input.append(SamplesBuffer::new(2, 44_100, vec![0i16, 0, 0, 8, 0, 0]));
+
+        assert_eq!(output.channels(), 2);
+        assert_eq!(
+            output.by_ref().collect::<Vec<_>>(),
+            vec![5, 0, 6, 7, 0, 8, 0, 0]
+        );
+    }
+
+    #[test]
+    fn plays_silence_as_is_without_gapless_playback() {
+        let (input, output) = queue(false, false);
+        input.append(SamplesBuffer::new(2, 44_100, vec![0i16, 0, 1, 2]));
+        input.append(SamplesBuffer::new(2, 44_100, vec![0i16, 0, 3, 4]));
+
+        assert_eq!(output.collect::<Vec<_>>(), vec![0, 0, 1, 2, 0, 0, 3, 4]);
+    }
+
+    #[test]
+    fn signals_the_end_of_each_sound() {
+        let (input, mut output) = queue(false, true);
+        let first = input.append_with_signal(SamplesBuffer::new(1, 44_100, vec![1i16, 2]));
+        let second = input.append_with_signal(SamplesBuffer::new(1, 44_100, vec![3i16, 4]));
+
+        assert_eq!(output.next(), Some(1));
+        assert_eq!(output.next(), Some(2));
+        assert!(first.try_recv().is_err());
+        assert_eq!(output.next(), Some(3));
+        assert!(first.try_recv().is_ok());
+        assert_eq!(output.by_ref().count(), 1);
+        assert!(second.try_recv().is_ok());
+    }
+
+    #[test]
+    fn plays_silence_while_kept_alive() {
+        let (input, mut output) = queue::<i16>(true, true);
+        assert_eq!(
+            output
+                .by_ref()
+                .take(1_000)
+                .filter(|&sample| sample != 0)
+                .count(),
+            0
+        );
+
+        input.append(SamplesBuffer::new(1, 48_000, vec![9i16]));
+        assert!(output.by_ref().take(1_000).any(|sample| sample == 9));
+
+        // The silence being played is finished first.
+        input.set_keep_alive_if_empty(false);
+        assert!(output.by_ref().take(1_000).all(|sample| sample == 0));
+        assert_eq!(output.next(), None);
+    }
+
+    #[test]
+    fn has_the_format_of_the_first_sound_before_it_starts() {
+        let (input, mut output) = queue(true, true);
+        input.append(SamplesBuffer::new(2, 48_000, vec![1i16; 8]));
+        assert_eq!(output.channels(), 2);
+        assert_eq!(output.sample_rate(), 48_000);
+        assert_eq!(output.current_frame_len(), Some(8));
+
+        assert_eq!(output.next(), Some(1));
+        assert_eq!(output.channels(), 2);
+        assert_eq!(output.sample_rate(), 48_000);
+    }
+}
@@ -0,0 +1,389 @@
+//! Offline rendering of the output of a player, to a WAV file or to memory.
+//!
+//! A player made with `Player::new_offline` goes through the same sinks, mixer and conversions as
+//! one playing on a device, but its samples are produced as fast as they are asked for. The
+//! result is sample-exact and the same on every run.
+
+use std::convert::TryFrom;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+use super::Player;
+
+/// Number of frames rendered at a time.
+const BLOCK_FRAMES: usize = 4096;
+/// Size of the header written by `WavWriter`, up to the samples.
+const HEADER_LEN: u32 = 58;
+
+/// Sample format of a WAV file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WavFormat {
+    /// 16-bit integer samples.
+    Int16,
+    /// 24-bit integer samples.
+    Int24,
+    /// 32-bit float samples, which keep the output exactly as rendered.
+    Float32,
+}
+
+impl WavFormat {
+    fn bytes_per_sample(self) -> u16 {
+        match self {
+            Self::Int16 => 2,
+            Self::Int24 => 3,
+            Self::Float32 => 4,
+        }
+    }
+}
+
+/// Writes interleaved samples to a WAV file. The sizes in the header are filled in by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    format: WavFormat,
+    channels: u16,
+    /// Number of bytes of samples written so far.
+    data_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Writes the header of a WAV file with `channels` channels at `sample_rate` to `writer`.
+    pub fn new(
+        mut writer: W,
+        channels: u16,
+        sample_rate: u32,
+        format: WavFormat,
+    ) -> io::Result<Self> {
+        let bytes_per_sample = format.bytes_per_sample();
+        let block_align = channels * bytes_per_sample;
+        let format_tag: u16 = match format {
+            WavFormat::Int16 | WavFormat::Int24 => 1,
+            WavFormat::Float32 => 3,
+        };
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(HEADER_LEN - 8).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&18_u32.to_le_bytes())?;
+        writer.write_all(&format_tag.to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * u32::from(block_align)).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&(bytes_per_sample * 8).to_le_bytes())?;
+        // No extra format information.
+        writer.write_all(&0_u16.to_le_bytes())?;
+        // Number of frames, which formats other than integer PCM must give.
+        writer.write_all(b"fact")?;
+        writer.write_all(&4_u32.to_le_bytes())?;
+        writer.write_all(&0_u32.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0_u32.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            format,
+            channels,
+            data_len: 0,
+        })
+    }
+
+    /// Appends interleaved samples, from -1.0 to 1.0.
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let len = u32::try_from(samples.len() * usize::from(self.format.bytes_per_sample()))
+            .ok()
+            .and_then(|len| len.checked_add(self.data_len))
+            .filter(|len| len.checked_add(HEADER_LEN).is_some())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "WAV files are limited to 4 GiB",
+                )
+            })?;
+
+        let mut bytes = Vec::with_capacity(len as usize - self.data_len as usize);
+        for sample in samples {
+            match self.format {
+                WavFormat::Int16 => bytes.extend_from_slice(&to_i16(*sample).to_le_bytes()),
+                WavFormat::Int24 => bytes.extend_from_slice(&to_i24(*sample).to_le_bytes()[..3]),
+                WavFormat::Float32 => bytes.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+        self.writer.write_all(&bytes)?;
+        self.data_len = len;
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let block_align = u32::from(self.channels * self.format.bytes_per_sample());
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(46))?;
+        self.writer
+            .write_all(&(self.data_len / block_align).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(54))?;
+        self.writer.write_all(&self.data_len.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Converts `sample` to 16 bits with the scale `Sample::to_f32` uses the other way, so that
+/// 16-bit samples are written back unchanged.
+pub(crate) fn to_i16(sample: f32) -> i16 {
+    let sample = sample.clamp(-1.0, 1.0);
+    if sample >= 0.0 {
+        (sample * f32::from(i16::MAX)).round() as i16
+    } else {
+        (sample * -f32::from(i16::MIN)).round() as i16
+    }
+}
+
+/// Converts `sample` to 24 bits, in the low bytes of an `i32`, with the same asymmetric scale.
+fn to_i24(sample: f32) -> i32 {
+    const MAX: f64 = 8_388_607.0;
+    let sample = f64::from(sample.clamp(-1.0, 1.0));
+    if sample >= 0.0 {
+        (sample * MAX).round() as i32
+    } else {
+        (sample * (MAX + 1.0)).round() as i32
+    }
+}
+
+/// Renders the output of an offline player to memory, as interleaved samples, until playback
+/// stops or `limit` is reached.
+///
+/// Without a limit, a player that repeats or is paused renders forever.
+pub fn render_to_vec(player: &mut Player, limit: Option<Duration>) -> io::Result<Vec<f32>> {
+    let mut samples = Vec::new();
+    render_blocks(player, limit, |block| {
+        samples.extend_from_slice(block);
+        Ok(())
+    })?;
+    Ok(samples)
+}
+
+/// Renders the output of an offline player to a WAV file written to `writer`, until playback
+/// stops or `limit` is reached. Returns the writer and the duration rendered.
+///
+/// Without a limit, a player that repeats or is paused renders forever.
+pub fn render_to_wav<W: Write + Seek>(
+    player: &mut Player,
+    writer: W,
+    format: WavFormat,
+    limit: Option<Duration>,
+) -> io::Result<(W, Duration)> {
+    let (channels, sample_rate) = output_format(player)?;
+    let mut wav = WavWriter::new(writer, channels, sample_rate, format)?;
+    let duration = render_blocks(player, limit, |block| wav.write(block))?;
+    Ok((wav.finish()?, duration))
+}
+
+/// Renders blocks of samples and passes them to `write`. Returns the duration rendered.
+fn render_blocks<F>(
+    player: &mut Player,
+    limit: Option<Duration>,
+    mut write: F,
+) -> io::Result<Duration>
+where
+    F: FnMut(&[f32]) -> io::Result<()>,
+{
+    let (channels, sample_rate) = output_format(player)?;
+    let channels = usize::from(channels);
+    let limit_frames =
+        limit.map(|limit| (limit.as_secs_f64() * f64::from(sample_rate)).round() as u64);
+
+    let mut buffer = vec![0.0; BLOCK_FRAMES * channels];
+    let mut frames = 0_u64;
+    loop {
+        let len = match limit_frames {
+            Some(limit) if limit - frames < BLOCK_FRAMES as u64 => {
+                (limit - frames) as usize * channels
+            }
+            _ => buffer.len(),
+        };
+        if len == 0 {
+            break;
+        }
+        let written = player.render(&mut buffer[..len]);
+        write(&buffer[..written])?;
+        frames += (written / channels) as u64;
+        if written < len {
+            break;
+        }
+    }
+    Ok(Duration::from_secs_f64(
+        frames as f64 / f64::from(sample_rate),
+    ))
+}
+
+/// Returns the number of channels and the sample rate of an offline player.
+fn output_format(player: &Player) -> io::Result<(u16, u32)> {
+    let output = player.output_path();
+    match (output.output_channels, output.output_sample_rate) {
+        (Some(channels), Some(sample_rate)) if player.is_offline() => Ok((channels, sample_rate)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the player plays on a device",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::fixtures::WavFixture;
+    use crate::player::{BitPerfectLimit, MAX_VOLUME};
+    use cpal::Sample as CpalSample;
+    use std::io::{Cursor, Read};
+
+    const RATE: u32 = 44_100;
+    /// Frames at the start of each track that the fade in of `Sink::append` silences, and that
+    /// the gapless queue then skips.
+    const SKIPPED_FRAMES: usize = 2;
+    /// Frames after those that are still fading in.
+    const FADE_FRAMES: usize = 3;
+
+    /// Returns stereo samples that never are both zero, which the gapless queue would skip.
+    fn tone(frames: usize, step: i16) -> Vec<i16> {
+        (0..frames * 2)
+            .map(|i| ((i as i16 / 2).wrapping_mul(step) % 20_000).abs() + 100)
+            .collect()
+    }
+
+    fn write_fixture(samples: &[i16]) -> WavFixture {
+        let samples: Vec<f32> = samples.iter().map(CpalSample::to_f32).collect();
+        WavFixture::new(&samples, 2, RATE, WavFormat::Int16)
+    }
+
+    fn offline_player() -> Player {
+        let mut player = Player::new_offline(2, RATE);
+        player.set_volume(MAX_VOLUME);
+        player
+    }
+
+    /// Checks that `rendered` is the track `expected`, from where its fade in ends.
+    fn assert_track(rendered: &[f32], expected: &[i16]) {
+        let expected = &expected[SKIPPED_FRAMES * 2..];
+        assert_eq!(rendered.len(), expected.len());
+        let start = FADE_FRAMES * 2;
+        for (index, (rendered, expected)) in rendered.iter().zip(expected).enumerate().skip(start) {
+            assert_eq!(*rendered, expected.to_f32(), "sample {}", index);
+        }
+    }
+
+    #[test]
+    fn renders_a_track_sample_exactly() {
+        let samples = tone(RATE as usize / 2, 37);
+        let fixture = write_fixture(&samples);
+        let mut player = offline_player();
+        player.set_playlist(vec![fixture.path().to_path_buf()], Some(0));
+
+        let rendered = render_to_vec(&mut player, None).unwrap();
+        assert!(player.is_stopped());
+        assert_track(&rendered, &samples);
+    }
+
+    #[test]
+    fn reports_what_keeps_a_track_from_bit_perfect_output() {
+        let samples: Vec<f32> = tone(RATE as usize / 10, 7)
+            .iter()
+            .map(CpalSample::to_f32)
+            .collect();
+        let deep = WavFixture::new(&samples, 2, RATE, WavFormat::Int24);
+        let mut player = offline_player();
+        player.set_bit_perfect(true).unwrap();
+        player.set_playlist(vec![deep.path().to_path_buf()], Some(0));
+        let output = player.output_path();
+        assert!(!output.is_bit_perfect);
+        assert_eq!(
+            output.bit_perfect_limit,
+            Some(BitPerfectLimit::SourceBitDepth)
+        );
+
+        let fixture = write_fixture(&tone(RATE as usize / 10, 7));
+        player.set_playlist(vec![fixture.path().to_path_buf()], Some(0));
+        let output = player.output_path();
+        assert!(output.is_bit_perfect, "{:?}", output.bit_perfect_limit);
+    }
+
+    #[test]
+    fn joins_tracks_without_a_gap() {
+        let first = tone(10_000, 11);
+        let second = tone(7_000, 53);
+        let first_fixture = write_fixture(&first);
+        let second_fixture = write_fixture(&second);
+        let mut player = offline_player();
+        player.set_playlist(
+            vec![
+                first_fixture.path().to_path_buf(),
+                second_fixture.path().to_path_buf(),
+            ],
+            Some(0),
+        );
+
+        let rendered = render_to_vec(&mut player, None).unwrap();
+        let (start, end) = rendered.split_at(first.len() - SKIPPED_FRAMES * 2);
+        assert_track(start, &first);
+        assert_track(end, &second);
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let fixture = write_fixture(&tone(RATE as usize, 5));
+        let mut player = offline_player();
+        player.set_playlist(vec![fixture.path().to_path_buf()], Some(0));
+
+        let rendered = render_to_vec(&mut player, Some(Duration::from_millis(100))).unwrap();
+        assert_eq!(rendered.len(), RATE as usize / 10 * 2);
+        assert!(!player.is_stopped());
+    }
+
+    #[test]
+    fn writes_the_output_to_a_wav_file() {
+        let samples = tone(3_000, 23);
+        let fixture = write_fixture(&samples);
+        let mut player = offline_player();
+        player.set_playlist(vec![fixture.path().to_path_buf()], Some(0));
+
+        let (cursor, duration) = render_to_wav(
+            &mut player,
+            Cursor::new(Vec::new()),
+            WavFormat::Float32,
+            None,
+        )
+        .unwrap();
+        let frames = 3_000 - SKIPPED_FRAMES;
+        assert_eq!(
+            duration,
+            Duration::from_secs_f64(frames as f64 / f64::from(RATE))
+        );
+        let bytes = cursor.into_inner();
+        let field = |offset: usize| {
+            let mut field = [0; 4];
+            (&bytes[offset..offset + 4]).read_exact(&mut field).unwrap();
+            u32::from_le_bytes(field)
+        };
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(field(4) as usize, bytes.len() - 8);
+        assert_eq!(field(46) as usize, frames);
+        assert_eq!(field(54) as usize, frames * 2 * 4);
+
+        let rendered: Vec<f32> = bytes[HEADER_LEN as usize..]
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        assert_track(&rendered, &samples);
+    }
+
+    #[test]
+    fn renders_nothing_while_stopped() {
+        let mut player = offline_player();
+        assert!(player.is_offline());
+        assert_eq!(player.render(&mut [0.0; 4]), 0);
+        assert_eq!(render_to_vec(&mut player, None).unwrap(), Vec::<f32>::new());
+    }
+}
@@ -0,0 +1,372 @@
+//! Shuffled playback orders of a playlist.
+//!
+//! Orders are computed from a seed rather than drawn on the fly, so that the same seed always
+//! gives the same order: going back to the previous track retraces the order instead of drawing
+//! a new one, and it survives a restart of the app.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::track;
+
+/// How the playlist is shuffled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShuffleMode {
+    /// Tracks are played in the order of the playlist.
+    Off,
+    /// Every order is equally likely.
+    Uniform,
+    /// Random order that avoids playing two tracks of the same artist back-to-back whenever the
+    /// playlist allows it.
+    ArtistSpread,
+    /// Albums are played in random order, and the tracks of each album in playlist order.
+    Album,
+}
+
+impl Default for ShuffleMode {
+    fn default() -> Self {
+        Self::Off
+    }
+}
+
+impl ShuffleMode {
+    /// Returns `true` if the order depends on the tags of the tracks.
+    pub fn uses_tags(self) -> bool {
+        matches!(self, Self::ArtistSpread | Self::Album)
+    }
+}
+
+/// Tags of a track that the shuffle modes group tracks by.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrackKey {
+    /// Album artist, or artist, of the track.
+    pub artist: Option<String>,
+    /// Album of the track.
+    pub album: Option<String>,
+}
+
+impl TrackKey {
+    /// Reads the tags of the file at `path`. Tracks whose tags can't be read are not grouped with
+    /// any other.
+    pub fn read(path: &Path) -> Self {
+        Self::from_fields(&track::tag_fields(path))
+    }
+
+    /// Returns the key of a track with the tags `fields`, as read by `track::tag_fields`. Tags
+    /// that are missing or blank are left unknown.
+    pub fn from_fields(fields: &HashMap<String, String>) -> Self {
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        Self {
+            artist: field("albumartist").or_else(|| field("artist")),
+            album: field("album"),
+        }
+    }
+}
+
+/// Returns the order in which to play `len` tracks, as indices into the playlist.
+///
+/// `keys` holds the tags of the tracks for the modes that need them; missing keys are treated
+/// as unknown tags. The order starts with `first`, or for `ShuffleMode::Album` with the album
+/// of `first`, so that enabling shuffle doesn't interrupt the current track.
+pub fn order(
+    mode: ShuffleMode,
+    seed: u64,
+    len: usize,
+    keys: &[TrackKey],
+    first: Option<usize>,
+) -> Vec<usize> {
+    let mut rng = SplitMix64(seed);
+    let key = |index: usize| keys.get(index);
+
+    let mut order = match mode {
+        ShuffleMode::Off => return (0..len).collect(),
+        ShuffleMode::Uniform => {
+            let mut order: Vec<usize> = (0..len).collect();
+            rng.shuffle(&mut order);
+            order
+        }
+        ShuffleMode::ArtistSpread => {
+            let groups = group_by(len, |index| {
+                key(index).and_then(|key| key.artist.as_deref())
+            });
+            // Starting the spread with `first` keeps the order valid, which moving `first` to
+            // the front afterwards wouldn't.
+            return spread(groups, &mut rng, first);
+        }
+        ShuffleMode::Album => {
+            let mut groups = group_by(len, |index| key(index).and_then(|key| key.album.as_deref()));
+            rng.shuffle(&mut groups);
+            if let Some(first) = first {
+                if let Some(group) = groups.iter().position(|group| group.contains(&first)) {
+                    groups[..=group].rotate_right(1);
+                }
+            }
+            return groups.concat();
+        }
+    };
+
+    if let Some(position) = first.and_then(|first| order.iter().position(|&index| index == first)) {
+        order.rotate_left(position);
+    }
+    order
+}
+
+/// Groups the indices of `len` tracks by `key`, keeping the playlist order within each group.
+/// Tracks without a key each get a group of their own.
+fn group_by<'a, F>(len: usize, key: F) -> Vec<Vec<usize>>
+where
+    F: Fn(usize) -> Option<&'a str>,
+{
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut by_key: HashMap<&str, usize> = HashMap::new();
+    for index in 0..len {
+        match key(index) {
+            Some(key) => {
+                let group = *by_key.entry(key).or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+                groups[group].push(index);
+            }
+            None => groups.push(vec![index]),
+        }
+    }
+    groups
+}
+
+/// Interleaves the shuffled `groups` so that no two tracks of a group follow each other, unless
+/// a group holds more than half of the tracks. The order starts with `first`, if given.
+fn spread(mut groups: Vec<Vec<usize>>, rng: &mut SplitMix64, first: Option<usize>) -> Vec<usize> {
+    for group in &mut groups {
+        rng.shuffle(group);
+    }
+
+    let mut remaining: usize = groups.iter().map(Vec::len).sum();
+    let mut order = Vec::with_capacity(remaining);
+    let mut last: Option<usize> = None;
+    if let Some(first) = first {
+        for (group, tracks) in groups.iter_mut().enumerate() {
+            if let Some(position) = tracks.iter().position(|&index| index == first) {
+                order.push(tracks.remove(position));
+                remaining -= 1;
+                last = Some(group);
+                break;
+            }
+        }
+    }
+    while remaining > 0 {
+        let candidates = || {
+            groups
+                .iter()
+                .enumerate()
+                .filter(|&(group, tracks)| !tracks.is_empty() && Some(group) != last)
+        };
+
+        // A group that must take every other slot from now on is picked right away, otherwise
+        // the end of the order would have to put two of its tracks side by side.
+        let largest = candidates().max_by_key(|(_, tracks)| tracks.len());
+        let group = match largest {
+            None => last.unwrap_or_default(),
+            Some((group, tracks)) if 2 * tracks.len() >= remaining => group,
+            Some(_) => {
+                // Picks a group with a probability proportional to its remaining tracks.
+                let total: usize = candidates().map(|(_, tracks)| tracks.len()).sum();
+                let mut pick = rng.below(total);
+                candidates()
+                    .find(|(_, tracks)| {
+                        if pick < tracks.len() {
+                            true
+                        } else {
+                            pick -= tracks.len();
+                            false
+                        }
+                    })
+                    .map(|(group, _)| group)
+                    .unwrap_or_default()
+            }
+        };
+
+        if let Some(index) = groups[group].pop() {
+            order.push(index);
+        }
+        remaining -= 1;
+        last = Some(group);
+    }
+    order
+}
+
+/// Small and fast generator that is good enough for shuffling, and stable across versions
+/// unlike the generators of the standard library.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value in `0..bound`.
+    #[allow(clippy::cast_possible_truncation)]
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    /// Fisher-Yates shuffle.
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(tags: &[(&str, &str)]) -> Vec<TrackKey> {
+        tags.iter()
+            .map(|&(artist, album)| TrackKey {
+                artist: Some(artist.to_string()),
+                album: Some(album.to_string()),
+            })
+            .collect()
+    }
+
+    fn is_permutation(order: &[usize], len: usize) -> bool {
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        sorted == (0..len).collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn off_keeps_playlist_order() {
+        assert_eq!(
+            order(ShuffleMode::Off, 7, 4, &[], Some(2)),
+            vec![0, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn same_seed_gives_same_order() {
+        let first = order(ShuffleMode::Uniform, 42, 50, &[], None);
+        let second = order(ShuffleMode::Uniform, 42, 50, &[], None);
+        let other = order(ShuffleMode::Uniform, 43, 50, &[], None);
+        assert!(is_permutation(&first, 50));
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn starts_with_first_track() {
+        for seed in 0..20 {
+            let order = order(ShuffleMode::Uniform, seed, 10, &[], Some(6));
+            assert_eq!(order[0], 6);
+            assert!(is_permutation(&order, 10));
+        }
+    }
+
+    #[test]
+    fn artist_spread_avoids_same_artist_back_to_back() {
+        let keys = keys(&[
+            ("a", "1"),
+            ("a", "1"),
+            ("a", "1"),
+            ("a", "1"),
+            ("b", "2"),
+            ("b", "2"),
+            ("b", "2"),
+            ("c", "3"),
+            ("c", "3"),
+        ]);
+        for seed in 0..100 {
+            let order = order(ShuffleMode::ArtistSpread, seed, keys.len(), &keys, None);
+            assert!(is_permutation(&order, keys.len()));
+            for pair in order.windows(2) {
+                assert_ne!(keys[pair[0]].artist, keys[pair[1]].artist, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn artist_spread_starts_with_first_track() {
+        let keys = keys(&[
+            ("a", "1"),
+            ("a", "1"),
+            ("a", "1"),
+            ("b", "2"),
+            ("b", "2"),
+            ("b", "2"),
+            ("c", "3"),
+            ("c", "3"),
+        ]);
+        for seed in 0..100 {
+            for first in 0..keys.len() {
+                let order = order(
+                    ShuffleMode::ArtistSpread,
+                    seed,
+                    keys.len(),
+                    &keys,
+                    Some(first),
+                );
+                assert_eq!(order[0], first);
+                assert!(is_permutation(&order, keys.len()));
+                for pair in order.windows(2) {
+                    assert_ne!(
+                        keys[pair[0]].artist, keys[pair[1]].artist,
+                        "seed {}, first {}",
+                        seed, first
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keys_use_album_artist_and_leave_missing_tags_unknown() {
+        let fields: HashMap<String, String> = [("artist", "Artist"), ("albumartist", "Various")]
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let key = TrackKey::from_fields(&fields);
+        assert_eq!(key.artist.as_deref(), Some("Various"));
+        assert_eq!(key.album, None);
+        assert_eq!(TrackKey::from_fields(&HashMap::new()), TrackKey::default());
+    }
+
+    #[test]
+    fn album_keeps_albums_intact() {
+        let keys = keys(&[
+            ("a", "1"),
+            ("a", "1"),
+            ("b", "2"),
+            ("b", "2"),
+            ("b", "2"),
+            ("a", "3"),
+            ("a", "3"),
+        ]);
+        for seed in 0..20 {
+            let order = order(ShuffleMode::Album, seed, keys.len(), &keys, Some(3));
+            assert!(is_permutation(&order, keys.len()));
+            // The album of the first track comes first, in playlist order.
+            assert_eq!(&order[..3], &[2, 3, 4]);
+            for pair in order.windows(2) {
+                if keys[pair[0]].album == keys[pair[1]].album {
+                    assert_eq!(pair[0] + 1, pair[1]);
+                }
+            }
+        }
+    }
+}
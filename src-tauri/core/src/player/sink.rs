@@ -7,9 +7,59 @@ use std::time::Duration;
 // };
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-use super::{queue, source::Done, Sample, Source};
+use super::{
+    queue,
+    source::{Amplify, Done, Pausable, Speed, Stoppable, Tap, TapBuffer},
+    Sample, Source,
+};
 use super::{OutputStreamHandle, PlayError};
 
+/// The filters `append` wraps around each sound, from the outermost in.
+///
+/// The controls reach each filter through the accessors below. The periodic access of `append`
+/// takes this exact type, so a filter added to the chain without updating them doesn't compile.
+type Controlled<S> = Stoppable<Amplify<Pausable<Speed<Tap<S>>>>>;
+
+/// Returns the volume filter of a controlled sound.
+#[inline]
+fn amplify_mut<S>(src: &mut Controlled<S>) -> &mut Amplify<Pausable<Speed<Tap<S>>>>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    src.inner_mut()
+}
+
+/// Returns the pause filter of a controlled sound.
+#[inline]
+fn pausable_mut<S>(src: &mut Controlled<S>) -> &mut Pausable<Speed<Tap<S>>>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    amplify_mut(src).inner_mut()
+}
+
+/// Returns the speed filter of a controlled sound.
+#[inline]
+fn speed_mut<S>(src: &mut Controlled<S>) -> &mut Speed<Tap<S>>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    pausable_mut(src).inner_mut()
+}
+
+/// Returns the sound itself, under the tap, which fades and loops it.
+#[inline]
+fn sound_mut<S>(src: &mut Controlled<S>) -> &mut S
+where
+    S: Source,
+    S::Item: Sample,
+{
+    speed_mut(src).inner_mut().inner_mut()
+}
+
 /// Handle to an device that outputs sounds.
 ///
 /// Dropping the `Sink` stops all sounds. You can use `detach` if you want the sounds to continue
@@ -25,12 +75,17 @@ pub struct Sink {
     detached: bool,
 
     elapsed: Arc<RwLock<Duration>>,
+
+    /// Buffer receiving a copy of the samples, if anything reads them.
+    tap: Option<Arc<TapBuffer>>,
 }
 
 struct Controls {
     pause: AtomicBool,
     volume: Mutex<f32>,
     seek: Mutex<Option<Duration>>,
+    loop_region: Mutex<Option<(Duration, Duration)>>,
+    fade_out: Mutex<Option<Duration>>,
     stopped: AtomicBool,
     speed: Mutex<f32>,
 }
@@ -60,11 +115,14 @@ impl Sink {
                 volume: Mutex::new(1.0),
                 stopped: AtomicBool::new(false),
                 seek: Mutex::new(None),
+                loop_region: Mutex::new(None),
+                fade_out: Mutex::new(None),
                 speed: Mutex::new(1.0),
             }),
             sound_count: Arc::new(AtomicUsize::new(0)),
             detached: false,
             elapsed: Arc::new(RwLock::new(Duration::from_secs(0))),
+            tap: None,
         };
         (sink, queue_rx)
     }
@@ -77,33 +135,35 @@ impl Sink {
         S::Item: Sample + Send,
         // S::Item: Send,
     {
-        println!("append now!!!");
         source.fade_in_from_now(Duration::from_micros(100));
 
         let controls = self.controls.clone();
+        // Starts at the current volume, so that the sound doesn't ramp from full volume.
+        let volume = *controls.volume.lock().unwrap();
 
         let elapsed = self.elapsed.clone();
         let source = source
+            .tap(self.tap.clone())
             .speed(1.0)
             .pausable(false)
-            .amplify(1.0)
+            .amplify(volume)
             .stoppable()
-            .periodic_access(Duration::from_millis(50), move |src| {
+            .periodic_access(Duration::from_millis(50), move |src: &mut Controlled<S>| {
                 if controls.stopped.load(Ordering::SeqCst) {
                     src.stop();
                 } else {
+                    // Before the seek, whose own short fade must win.
+                    if let Some(duration) = controls.fade_out.lock().unwrap().take() {
+                        sound_mut(src).fade_out_from_now(duration);
+                    }
                     if let Some(seek_time) = controls.seek.lock().unwrap().take() {
                         src.seek(seek_time).unwrap();
                     }
+                    sound_mut(src).set_loop_region(*controls.loop_region.lock().unwrap());
                     *elapsed.write().unwrap() = src.elapsed();
-                    src.inner_mut().set_factor(*controls.volume.lock().unwrap());
-                    src.inner_mut()
-                        .inner_mut()
-                        .set_paused(controls.pause.load(Ordering::SeqCst));
-                    src.inner_mut()
-                        .inner_mut()
-                        .inner_mut()
-                        .set_factor(*controls.speed.lock().unwrap());
+                    amplify_mut(src).set_factor(*controls.volume.lock().unwrap());
+                    pausable_mut(src).set_paused(controls.pause.load(Ordering::SeqCst));
+                    speed_mut(src).set_factor(*controls.speed.lock().unwrap());
                 }
             })
             .convert_samples();
@@ -116,6 +176,12 @@ impl Sink {
         *self.sleep_until_end.lock().unwrap() = Some(self.queue_tx.append_with_signal(source));
     }
 
+    /// Sets the buffer that receives a copy of the samples of the sounds appended from now on.
+    #[inline]
+    pub fn set_tap(&mut self, tap: Arc<TapBuffer>) {
+        self.tap = Some(tap);
+    }
+
     /// Gets the volume of the sound.
     ///
     /// The value `1.0` is the "normal" volume (unfiltered input). Any value other than 1.0 will
@@ -160,11 +226,23 @@ impl Sink {
         }
     }
 
+    /// Seeks the current sound to `seek_time`.
     pub fn seek(&self, seek_time: Duration) {
-        println!("sink seek - seek_time: {:?}", seek_time);
         *self.controls.seek.lock().unwrap() = Some(seek_time);
     }
 
+    /// Makes the sound jump back to the start of `region` whenever it reaches its end, or stops
+    /// looping if `None`.
+    pub fn set_loop_region(&self, region: Option<(Duration, Duration)>) {
+        *self.controls.loop_region.lock().unwrap() = region;
+    }
+
+    /// Fades the sound out over `duration`, as done by `Source::fade_out_from_now`. A zero
+    /// duration cancels a fade in progress.
+    pub fn fade_out(&self, duration: Duration) {
+        *self.controls.fade_out.lock().unwrap() = Some(duration);
+    }
+
     /// Gets if a sink is paused
     ///
     /// Sinks can be paused and resumed using `pause()` and `play()`. This returns `true` if the
@@ -209,6 +287,7 @@ impl Sink {
         self.sound_count.load(Ordering::Relaxed)
     }
 
+    /// Returns the position in the current sound.
     #[inline]
     pub fn elapsed(&self) -> Duration {
         *self.elapsed.read().unwrap()
@@ -0,0 +1,27 @@
+//! Sleep timer, which fades out and stops playback after a while.
+
+use serde::{Deserialize, Serialize};
+
+/// When the sleep timer stops playback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SleepMode {
+    /// After the given number of minutes.
+    Minutes(u64),
+    /// At the end of the current track.
+    EndOfTrack,
+    /// At the end of the last track of the playlist, in play order.
+    EndOfQueue,
+}
+
+/// State of the sleep timer, as reported to the UI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct SleepStatus {
+    /// When the timer stops playback.
+    pub mode: SleepMode,
+    /// Time left before playback stops, in milliseconds. `None` while it isn't known yet, such
+    /// as before the last track of the playlist.
+    pub remaining_ms: Option<u64>,
+    /// Number of tracks to play after the current one, for `SleepMode::EndOfQueue`.
+    pub tracks_left: Option<usize>,
+}
@@ -1,6 +1,9 @@
 use std::time::Duration;
 
-use super::{Sample, Source};
+use super::{ChannelLayout, Sample, Source};
+
+/// Duration in milliseconds over which a change of the factor is spread, to avoid zipper noise.
+const RAMP_MILLIS: u32 = 5;
 
 /// Internal function that builds a `Amplify` object.
 pub fn amplify<I>(input: I, factor: f32) -> Amplify<I>
@@ -8,22 +11,51 @@ where
     I: Source,
     I::Item: Sample,
 {
-    Amplify { input, factor }
+    Amplify {
+        input,
+        factor,
+        target: factor,
+        step: 0.0,
+        ramp_remaining: 0,
+    }
 }
 
 /// Filter that modifies each sample by a given value.
 #[derive(Clone, Debug)]
 pub struct Amplify<I> {
     input: I,
+    /// Factor applied to the next sample.
     factor: f32,
+    /// Factor reached at the end of the current ramp.
+    target: f32,
+    /// Change of the factor from one sample to the next during a ramp.
+    step: f32,
+    /// Number of samples until the end of the current ramp.
+    ramp_remaining: u32,
 }
 
 #[allow(clippy::missing_const_for_fn, unused)]
-impl<I> Amplify<I> {
+impl<I> Amplify<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
     /// Modifies the amplification factor.
+    ///
+    /// The factor moves linearly to the new value over a few milliseconds.
     #[inline]
+    #[allow(clippy::float_cmp, clippy::cast_precision_loss)]
     pub fn set_factor(&mut self, factor: f32) {
-        self.factor = factor;
+        if factor == self.target {
+            return;
+        }
+
+        let samples = (RAMP_MILLIS * self.input.sample_rate() / 1000
+            * u32::from(self.input.channels()))
+        .max(1);
+        self.target = factor;
+        self.step = (factor - self.factor) / samples as f32;
+        self.ramp_remaining = samples;
     }
 
     /// Returns a reference to the inner source.
@@ -54,7 +86,16 @@ where
 
     #[inline]
     fn next(&mut self) -> Option<I::Item> {
-        self.input.next().map(|value| value.amplify(self.factor))
+        let value = self.input.next()?;
+        if self.ramp_remaining > 0 {
+            self.ramp_remaining -= 1;
+            self.factor = if self.ramp_remaining == 0 {
+                self.target
+            } else {
+                self.factor + self.step
+            };
+        }
+        Some(value.amplify(self.factor))
     }
 
     #[inline]
@@ -85,6 +126,11 @@ where
         self.input.channels()
     }
 
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.input.channel_layout()
+    }
+
     #[inline]
     fn sample_rate(&self) -> u32 {
         self.input.sample_rate()
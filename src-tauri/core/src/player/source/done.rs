@@ -2,7 +2,7 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use super::{Sample, Source};
+use super::{ChannelLayout, Sample, Source};
 
 /// When the inner source is empty this decrements an `AtomicUsize`.
 #[derive(Debug, Clone)]
@@ -14,6 +14,7 @@ pub struct Done<I> {
 
 #[allow(clippy::use_self, clippy::missing_const_for_fn, unused)]
 impl<I> Done<I> {
+    /// Wraps `input`, decrementing `signal` when it ends.
     #[inline]
     pub fn new(input: I, signal: Arc<AtomicUsize>) -> Done<I> {
         Done {
@@ -79,6 +80,11 @@ where
         self.input.channels()
     }
 
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.input.channel_layout()
+    }
+
     #[inline]
     fn sample_rate(&self) -> u32 {
         self.input.sample_rate()
@@ -16,6 +16,7 @@ impl<S> Default for Empty<S> {
 
 #[allow(clippy::use_self)]
 impl<S> Empty<S> {
+    /// Returns an empty source.
     #[inline]
     pub const fn new() -> Empty<S> {
         Empty(PhantomData)
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use super::{Sample, Source};
+use super::{ChannelLayout, Sample, Source};
 
 /// Internal function that builds a `FadeIn` object.
 #[allow(unused, clippy::cast_precision_loss)]
@@ -99,6 +99,11 @@ where
         self.input.channels()
     }
 
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.input.channel_layout()
+    }
+
     #[inline]
     fn sample_rate(&self) -> u32 {
         self.input.sample_rate()
@@ -1,7 +1,9 @@
 //! Sources of sound and various filters.
 
+use std::sync::Arc;
 use std::time::Duration;
 
+use super::conversions::ChannelLayout;
 use super::Sample;
 
 pub use self::amplify::Amplify;
@@ -14,6 +16,8 @@ pub use self::samples_converter::SamplesConverter;
 pub use self::speed::Speed;
 pub use self::stoppable::Stoppable;
 pub use self::take::TakeDuration;
+pub use self::tap::{Tap, TapBuffer};
+pub use self::trim_silence::TrimSilence;
 pub use self::uniform::UniformSourceIterator;
 pub use self::zero::Zero;
 
@@ -27,6 +31,8 @@ mod samples_converter;
 mod speed;
 mod stoppable;
 mod take;
+mod tap;
+mod trim_silence;
 mod uniform;
 mod zero;
 
@@ -112,6 +118,15 @@ where
     /// Returns the number of channels. Channels are always interleaved.
     fn channels(&self) -> u16;
 
+    /// Returns the speaker position of each channel, if known.
+    ///
+    /// `None` means the usual layout for the number of channels, as given by
+    /// `ChannelLayout::default_for`. Like `channels()`, this can only change between frames.
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        None
+    }
+
     /// Returns the rate at which the source should be played. In number of samples per second.
     fn sample_rate(&self) -> u32;
 
@@ -120,18 +135,37 @@ where
     /// `None` indicates at the same time "infinite" or "unknown".
     fn total_duration(&self) -> Option<Duration>;
 
+    /// Seeks to `time`. Returns the position reached, if the source can seek.
     fn seek(&mut self, time: Duration) -> Option<Duration>;
 
+    /// Returns the position in the source.
     fn elapsed(&mut self) -> Duration;
 
+    /// Fades the source in over `duration`, from the current position.
     fn fade_in_from_now(&mut self, duration: Duration) {
         unimplemented!()
     }
 
+    /// Fades the source out over `duration`, from the current position.
     fn fade_out_from_now(&mut self, duration: Duration) {
         unimplemented!()
     }
 
+    /// Makes the source jump back to the start of `region` whenever it reaches its end, or stops
+    /// looping if `None`.
+    ///
+    /// Sources that can't seek ignore it.
+    fn set_loop_region(&mut self, _region: Option<(Duration, Duration)>) {}
+
+    /// Removes the digital silence at the start and at the end of the sound, if `enabled`.
+    #[inline]
+    fn trim_silence(self, enabled: bool) -> TrimSilence<Self>
+    where
+        Self: Sized,
+    {
+        trim_silence::trim_silence(self, enabled)
+    }
+
     /// Takes a certain duration of this source and then stops.
     #[inline]
     fn take_duration(self, duration: Duration) -> TakeDuration<Self>
@@ -219,4 +253,13 @@ where
     {
         speed::speed(self, ratio)
     }
+
+    /// Copies every sample of the sound into the given `TapBuffer`, if any, without blocking.
+    #[inline]
+    fn tap(self, buffer: Option<Arc<TapBuffer>>) -> Tap<Self>
+    where
+        Self: Sized,
+    {
+        tap::tap(self, buffer)
+    }
 }
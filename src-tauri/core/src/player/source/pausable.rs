@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use super::{Sample, Source};
+use super::{ChannelLayout, Sample, Source};
 
 /// Internal function that builds a `Pausable` object.
 pub fn pausable<I>(source: I, paused: bool) -> Pausable<I>
@@ -20,6 +20,7 @@ where
     }
 }
 
+/// Source that can be paused.
 #[derive(Clone, Debug)]
 pub struct Pausable<I> {
     input: I,
@@ -107,6 +108,11 @@ where
         self.input.channels()
     }
 
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.input.channel_layout()
+    }
+
     #[inline]
     fn sample_rate(&self) -> u32 {
         self.input.sample_rate()
@@ -124,3 +130,37 @@ where
         self.input.seek(time)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::buffer::SamplesBuffer;
+
+    #[test]
+    fn plays_whole_frames_of_silence_while_paused() {
+        let mut source = pausable(SamplesBuffer::new(2, 44_100, vec![1i16, 2, 3, 4]), false);
+        assert_eq!(source.next(), Some(1));
+
+        source.set_paused(true);
+        let silence: Vec<i16> = source.by_ref().take(3).collect();
+        assert_eq!(silence, vec![0, 0, 0]);
+        // The frame of silence is finished before the sound resumes where it was.
+        source.set_paused(false);
+        assert_eq!(source.collect::<Vec<_>>(), vec![0, 2, 3, 4]);
+    }
+
+    #[test]
+    fn starts_paused() {
+        let mut source = pausable(SamplesBuffer::new(1, 44_100, vec![5i16, 6]), true);
+        assert_eq!(
+            source
+                .by_ref()
+                .take(100)
+                .filter(|&sample| sample != 0)
+                .count(),
+            0
+        );
+        source.set_paused(false);
+        assert_eq!(source.collect::<Vec<_>>(), vec![5, 6]);
+    }
+}
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use super::{Sample, Source};
+use super::{ChannelLayout, Sample, Source};
 
 /// Internal function that builds a `PeriodicAccess` object.
 #[allow(clippy::cast_possible_truncation)]
@@ -106,6 +106,11 @@ where
         self.input.channels()
     }
 
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.input.channel_layout()
+    }
+
     #[inline]
     fn sample_rate(&self) -> u32 {
         self.input.sample_rate()
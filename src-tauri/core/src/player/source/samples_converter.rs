@@ -1,7 +1,7 @@
 use std::marker::PhantomData;
 use std::time::Duration;
 
-use super::{Sample, Source};
+use super::{ChannelLayout, Sample, Source};
 use cpal::Sample as CpalSample;
 
 /// An iterator that reads from a `Source` and converts the samples to a specific rate and
@@ -17,6 +17,7 @@ pub struct SamplesConverter<I, D> {
 
 #[allow(clippy::use_self, unused, clippy::missing_const_for_fn)]
 impl<I, D> SamplesConverter<I, D> {
+    /// Wraps `input`, converting its samples to `D`.
     #[inline]
     pub fn new(input: I) -> SamplesConverter<I, D> {
         SamplesConverter {
@@ -79,6 +80,11 @@ where
         self.inner.channels()
     }
 
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.inner.channel_layout()
+    }
+
     #[inline]
     fn sample_rate(&self) -> u32 {
         self.inner.sample_rate()
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use super::{Sample, Source};
+use super::{ChannelLayout, Sample, Source};
 
 /// Internal function that builds a `Speed` object.
 pub const fn speed<I>(input: I, factor: f32) -> Speed<I> {
@@ -90,6 +90,11 @@ where
         self.input.channels()
     }
 
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.input.channel_layout()
+    }
+
     #[inline]
     fn sample_rate(&self) -> u32 {
         (self.input.sample_rate() as f32 * self.factor) as u32
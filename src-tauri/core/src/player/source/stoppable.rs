@@ -1,4 +1,4 @@
-use super::{Sample, Source};
+use super::{ChannelLayout, Sample, Source};
 use std::time::Duration;
 
 /// Internal function that builds a `Stoppable` object.
@@ -9,6 +9,7 @@ pub const fn stoppable<I>(source: I) -> Stoppable<I> {
     }
 }
 
+/// Source that can be stopped.
 #[derive(Clone, Debug)]
 pub struct Stoppable<I> {
     input: I,
@@ -79,6 +80,11 @@ where
         self.input.channels()
     }
 
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.input.channel_layout()
+    }
+
     #[inline]
     fn sample_rate(&self) -> u32 {
         self.input.sample_rate()
@@ -99,3 +105,19 @@ where
         self.input.elapsed()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::buffer::SamplesBuffer;
+
+    #[test]
+    fn ends_once_stopped() {
+        let mut source = stoppable(SamplesBuffer::new(1, 44_100, vec![1i16, 2, 3]));
+        assert_eq!(source.next(), Some(1));
+        source.stop();
+        assert_eq!(source.next(), None);
+        assert_eq!(source.next(), None);
+        assert_eq!(source.into_inner().collect::<Vec<_>>(), vec![2, 3]);
+    }
+}
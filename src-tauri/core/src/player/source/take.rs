@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use super::{Sample, Source};
+use super::{ChannelLayout, Sample, Source};
 
 /// Internal function that builds a `TakeDuration` object.
 #[allow(clippy::module_name_repetitions)]
@@ -95,10 +95,12 @@ where
         self.input
     }
 
+    /// Fades the source out over the taken duration.
     pub fn set_filter_fadeout(&mut self) {
         self.filter = Some(DurationFilter::FadeOut);
     }
 
+    /// Removes the fade out.
     pub fn clear_filter(&mut self) {
         self.filter = None;
     }
@@ -122,7 +124,7 @@ where
             }
         }
 
-        if self.remaining_duration <= self.duration_per_sample {
+        if self.remaining_duration < self.duration_per_sample {
             None
         } else if let Some(sample) = self.input.next() {
             let sample = match &self.filter {
@@ -166,6 +168,11 @@ where
         self.input.channels()
     }
 
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.input.channel_layout()
+    }
+
     #[inline]
     fn sample_rate(&self) -> u32 {
         self.input.sample_rate()
@@ -189,3 +196,37 @@ where
         self.input.seek(time)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::buffer::SamplesBuffer;
+
+    #[test]
+    fn takes_whole_frames_of_the_duration() {
+        let source = SamplesBuffer::new(2, 1_000, vec![1i16; 100]);
+        assert_eq!(take_duration(source, Duration::from_millis(10)).count(), 20);
+
+        let source = SamplesBuffer::new(2, 44_100, vec![1i16; 2_000]);
+        assert_eq!(
+            take_duration(source, Duration::from_millis(10)).count(),
+            882
+        );
+    }
+
+    #[test]
+    fn ends_with_the_source() {
+        let source = SamplesBuffer::new(1, 1_000, vec![1i16, 2, 3]);
+        let taken: Vec<i16> = take_duration(source, Duration::from_secs(1)).collect();
+        assert_eq!(taken, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn fades_out_to_the_end() {
+        let source = SamplesBuffer::new(1, 1_000, vec![1.0f32; 100]);
+        let mut taken = take_duration(source, Duration::from_millis(10));
+        taken.set_filter_fadeout();
+        let expected: Vec<f32> = (1..=10).rev().map(|step| step as f32 / 10.0).collect();
+        assert_eq!(taken.collect::<Vec<_>>(), expected);
+    }
+}
@@ -0,0 +1,219 @@
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{ChannelLayout, Sample, Source};
+use cpal::Sample as CpalSample;
+
+/// Fallback number of samples between two format checks when the source doesn't know its frame
+/// length.
+const FORMAT_CHECK_INTERVAL: usize = 4096;
+
+/// Internal function that builds a `Tap` object.
+pub fn tap<I>(input: I, buffer: Option<Arc<TapBuffer>>) -> Tap<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    Tap {
+        input,
+        buffer,
+        remaining_frame_len: 0,
+    }
+}
+
+/// Ring buffer shared between a `Tap` and the code that reads the captured samples.
+///
+/// Writing never blocks: the oldest samples are overwritten once the buffer is full. Samples are
+/// stored as `f32` bit patterns in atomics, so a reader may observe a torn window while a writer
+/// is going around the ring. This is fine for visualization purposes.
+#[allow(clippy::module_name_repetitions)]
+pub struct TapBuffer {
+    samples: Box<[AtomicU32]>,
+    written: AtomicUsize,
+    channels: AtomicU16,
+    sample_rate: AtomicU32,
+}
+
+impl TapBuffer {
+    /// Builds a new buffer able to hold `capacity` interleaved samples.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+
+        Self {
+            samples: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            channels: AtomicU16::new(2),
+            sample_rate: AtomicU32::new(44_100),
+        }
+    }
+
+    /// Returns the number of samples that can be stored before the oldest ones are overwritten.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns the total number of samples written since the buffer was created.
+    ///
+    /// This value wraps around on overflow and is only meant to detect new data.
+    #[inline]
+    pub fn written(&self) -> usize {
+        self.written.load(Ordering::Acquire)
+    }
+
+    /// Returns the channel count of the most recently written samples.
+    #[inline]
+    pub fn channels(&self) -> u16 {
+        self.channels.load(Ordering::Relaxed)
+    }
+
+    /// Returns the sample rate of the most recently written samples.
+    #[inline]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn set_format(&self, channels: u16, sample_rate: u32) {
+        self.channels.store(channels, Ordering::Relaxed);
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn push(&self, value: f32) {
+        let pos = self.written.fetch_add(1, Ordering::AcqRel);
+        self.samples[pos % self.samples.len()].store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Copies the latest `len` interleaved samples into `out`, oldest first.
+    ///
+    /// The copy is aligned on a frame boundary of the current channel count. Returns the number
+    /// of samples copied, which is smaller than `len` if not enough samples were written yet.
+    pub fn copy_latest(&self, out: &mut Vec<f32>, len: usize) -> usize {
+        let channels = usize::from(self.channels().max(1));
+        let written = self.written();
+        let end = written - written % channels;
+        let len = len.min(self.capacity()).min(end);
+        let len = len - len % channels;
+
+        out.clear();
+        out.extend((end - len..end).map(|pos| {
+            f32::from_bits(self.samples[pos % self.capacity()].load(Ordering::Relaxed))
+        }));
+        len
+    }
+}
+
+/// Filter that copies every sample going through it into a `TapBuffer`, if it has one.
+#[derive(Clone)]
+pub struct Tap<I> {
+    input: I,
+    buffer: Option<Arc<TapBuffer>>,
+    // Remaining samples before the channels and sample rate are checked again.
+    remaining_frame_len: usize,
+}
+
+#[allow(unused)]
+impl<I> Tap<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    /// Returns a reference to the inner source.
+    #[inline]
+    pub fn inner(&self) -> &I {
+        &self.input
+    }
+
+    /// Returns a mutable reference to the inner source.
+    #[inline]
+    pub fn inner_mut(&mut self) -> &mut I {
+        &mut self.input
+    }
+
+    /// Returns the inner source.
+    #[inline]
+    pub fn into_inner(self) -> I {
+        self.input
+    }
+}
+
+impl<I> Iterator for Tap<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    type Item = I::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<I::Item> {
+        let buffer = match &self.buffer {
+            Some(buffer) => buffer,
+            None => return self.input.next(),
+        };
+        if self.remaining_frame_len == 0 {
+            // Sample rate or channels might have changed
+            buffer.set_format(self.input.channels(), self.input.sample_rate());
+            self.remaining_frame_len = self
+                .input
+                .current_frame_len()
+                .filter(|len| *len > 0)
+                .unwrap_or(FORMAT_CHECK_INTERVAL);
+        }
+        self.remaining_frame_len -= 1;
+
+        let sample = self.input.next()?;
+        buffer.push(sample.to_f32());
+        Some(sample)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<I> Source for Tap<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.input.channel_layout()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    #[inline]
+    fn elapsed(&mut self) -> Duration {
+        self.input.elapsed()
+    }
+
+    fn seek(&mut self, time: Duration) -> Option<Duration> {
+        self.input.seek(time)
+    }
+}
@@ -0,0 +1,261 @@
+use super::{ChannelLayout, Sample, Source};
+use std::time::Duration;
+
+/// Most silent frames read ahead in a call to `next`. Reading a long silence at once would hold
+/// up the audio callback, so a frame of the held back silence is played every so many frames
+/// instead. Trailing silence is still cut to a small fraction of its length.
+const MAX_SILENT_FRAMES_PER_CALL: usize = 32;
+
+/// Internal function that builds a `TrimSilence` object.
+pub fn trim_silence<I>(input: I, enabled: bool) -> TrimSilence<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    TrimSilence {
+        input,
+        enabled,
+        leading: true,
+        frame: Vec::new(),
+        frame_offset: 0,
+        pending_silence: 0,
+        silence_to_play: 0,
+    }
+}
+
+/// Filter that removes the digital silence at the start and at the end of a sound.
+///
+/// Silence is detected a frame at a time, so that the channels stay aligned. Silent frames in
+/// the middle of the sound are only counted, and played back once a frame with sound follows
+/// them, which lets silence of any length be dropped at the end without buffering it. A long
+/// silence is read ahead a little at a time, to keep each call short.
+#[derive(Clone, Debug)]
+pub struct TrimSilence<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    input: I,
+    enabled: bool,
+    /// Whether no frame with sound has been read yet.
+    leading: bool,
+    /// Frame being played.
+    frame: Vec<I::Item>,
+    /// Position of the next sample in `frame`.
+    frame_offset: usize,
+    /// Number of silent samples read and held back.
+    pending_silence: usize,
+    /// Number of held back silent samples to play before `frame`.
+    silence_to_play: usize,
+}
+
+#[allow(unused, clippy::missing_const_for_fn)]
+impl<I> TrimSilence<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    /// Returns a reference to the inner source.
+    #[inline]
+    pub fn inner(&self) -> &I {
+        &self.input
+    }
+
+    /// Returns a mutable reference to the inner source.
+    #[inline]
+    pub fn inner_mut(&mut self) -> &mut I {
+        &mut self.input
+    }
+
+    /// Returns the inner source.
+    #[inline]
+    pub fn into_inner(self) -> I {
+        self.input
+    }
+
+    /// Reads the next frame of the input into `frame`. Returns `false` at the end of the input.
+    fn read_frame(&mut self) -> bool {
+        self.frame.clear();
+        self.frame_offset = 0;
+        for _ in 0..self.input.channels().max(1) {
+            match self.input.next() {
+                Some(sample) => self.frame.push(sample),
+                None => break,
+            }
+        }
+        !self.frame.is_empty()
+    }
+
+    #[allow(clippy::float_cmp)]
+    fn is_frame_silent(&self) -> bool {
+        self.frame
+            .iter()
+            .all(|sample| cpal::Sample::to_f32(sample) == 0.0)
+    }
+}
+
+impl<I> Iterator for TrimSilence<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    type Item = I::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<I::Item> {
+        if !self.enabled {
+            return self.input.next();
+        }
+
+        let mut silent_frames = 0;
+        loop {
+            if self.silence_to_play > 0 {
+                self.silence_to_play -= 1;
+                return Some(I::Item::zero_value());
+            }
+            if let Some(&sample) = self.frame.get(self.frame_offset) {
+                self.frame_offset += 1;
+                return Some(sample);
+            }
+
+            // Silence held back when the input ends is trailing silence, and is dropped.
+            if !self.read_frame() {
+                return None;
+            }
+            if self.is_frame_silent() {
+                let len = self.frame.len();
+                if !self.leading {
+                    self.pending_silence += len;
+                }
+                self.frame.clear();
+                silent_frames += 1;
+                if silent_frames >= MAX_SILENT_FRAMES_PER_CALL {
+                    // Played from the held back silence, so that the length of a silence in
+                    // the middle of the sound is kept.
+                    self.silence_to_play = len;
+                    self.pending_silence = self.pending_silence.saturating_sub(len);
+                }
+            } else {
+                self.leading = false;
+                self.silence_to_play = self.pending_silence;
+                self.pending_silence = 0;
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let buffered = self.frame.len() - self.frame_offset + self.silence_to_play;
+        let (_, upper) = self.input.size_hint();
+        (
+            buffered,
+            upper.map(|upper| upper + buffered + self.pending_silence),
+        )
+    }
+}
+
+impl<I> Source for TrimSilence<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    #[inline]
+    fn channel_layout(&self) -> Option<ChannelLayout> {
+        self.input.channel_layout()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    #[inline]
+    fn seek(&mut self, time: Duration) -> Option<Duration> {
+        // Silence held back, and the rest of the frame being played, belong to the previous
+        // position.
+        self.frame.clear();
+        self.frame_offset = 0;
+        self.pending_silence = 0;
+        self.silence_to_play = 0;
+        self.leading = time == Duration::from_secs(0);
+        self.input.seek(time)
+    }
+
+    #[inline]
+    fn elapsed(&mut self) -> Duration {
+        self.input.elapsed()
+    }
+
+    #[inline]
+    fn fade_in_from_now(&mut self, duration: Duration) {
+        self.input.fade_in_from_now(duration);
+    }
+
+    #[inline]
+    fn fade_out_from_now(&mut self, duration: Duration) {
+        self.input.fade_out_from_now(duration);
+    }
+
+    #[inline]
+    fn set_loop_region(&mut self, region: Option<(Duration, Duration)>) {
+        self.input.set_loop_region(region);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::buffer::SamplesBuffer;
+
+    fn trimmed(channels: u16, samples: Vec<i16>) -> Vec<i16> {
+        trim_silence(SamplesBuffer::new(channels, 1_000, samples), true).collect()
+    }
+
+    #[test]
+    fn drops_silence_at_both_ends_only() {
+        let samples = vec![0, 0, 0, 0, 1, 2, 0, 0, 3, 4, 0, 0, 0, 0];
+        assert_eq!(trimmed(2, samples), vec![1, 2, 0, 0, 3, 4]);
+    }
+
+    #[test]
+    fn keeps_long_silences_in_the_middle() {
+        let mut samples = vec![1, 1];
+        samples.extend(vec![0; 2 * 10 * MAX_SILENT_FRAMES_PER_CALL]);
+        samples.extend(vec![2, 2]);
+        let output = trimmed(2, samples.clone());
+        assert_eq!(output, samples);
+    }
+
+    #[test]
+    fn cuts_most_of_a_long_trailing_silence() {
+        let mut samples = vec![1, 1];
+        samples.extend(vec![0; 2 * 10 * MAX_SILENT_FRAMES_PER_CALL]);
+        let output = trimmed(2, samples);
+        assert_eq!(output.len(), 2 + 2 * 10);
+        assert!(output[2..].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn forgets_the_frame_being_played_on_seek() {
+        let mut source = trim_silence(SamplesBuffer::new(2, 1_000, vec![1i16, 2, 3, 4]), true);
+        assert_eq!(source.next(), Some(1));
+        // The buffer seeks from where it is, after the first frame.
+        source.seek(Duration::from_secs(0));
+        assert_eq!(source.collect::<Vec<_>>(), vec![3, 4]);
+    }
+}
@@ -1,7 +1,10 @@
 use std::cmp;
 use std::time::Duration;
 
-use super::super::conversions::{ChannelCountConverter, DataConverter, SampleRateConverter};
+use super::super::conversions::{
+    ChannelCountConverter, ChannelLayout, ChannelOptions, DataConverter, ResamplerQuality,
+    SampleRateConverter,
+};
 use super::{Sample, Source};
 
 /// An iterator that reads from a `Source` and converts the samples to a specific rate and
@@ -20,6 +23,8 @@ where
     inner: Option<DataConverter<ChannelCountConverter<SampleRateConverter<Take<I>>>, D>>,
     target_channels: u16,
     target_sample_rate: u32,
+    quality: ResamplerQuality,
+    channel_options: ChannelOptions,
     total_duration: Option<Duration>,
 }
 
@@ -29,20 +34,31 @@ where
     I::Item: Sample,
     D: Sample,
 {
+    /// Wraps `input`, converting it to `target_channels` and `target_sample_rate`.
     #[inline]
     #[allow(clippy::use_self)]
     pub fn new(
         input: I,
         target_channels: u16,
         target_sample_rate: u32,
+        quality: ResamplerQuality,
+        channel_options: ChannelOptions,
     ) -> UniformSourceIterator<I, D> {
         let total_duration = input.total_duration();
-        let input = UniformSourceIterator::bootstrap(input, target_channels, target_sample_rate);
+        let input = UniformSourceIterator::bootstrap(
+            input,
+            target_channels,
+            target_sample_rate,
+            quality,
+            channel_options,
+        );
 
         UniformSourceIterator {
             inner: Some(input),
             target_channels,
             target_sample_rate,
+            quality,
+            channel_options,
             total_duration,
         }
     }
@@ -52,24 +68,36 @@ where
         input: I,
         target_channels: u16,
         target_sample_rate: u32,
+        quality: ResamplerQuality,
+        channel_options: ChannelOptions,
     ) -> DataConverter<ChannelCountConverter<SampleRateConverter<Take<I>>>, D> {
-        // Limit the frame length to something reasonable
-        let frame_len = input.current_frame_len().map(|x| x.min(32768));
-
         let from_channels = input.channels();
         let from_sample_rate = input.sample_rate();
+        let from_layout = input.channel_layout();
 
         let input = Take {
+            n: frame_len(&input),
             iter: input,
-            n: frame_len,
+            channels: from_channels,
+            sample_rate: from_sample_rate,
+            channel_layout: from_layout,
         };
         let input = SampleRateConverter::new(
             input,
             cpal::SampleRate(from_sample_rate),
             cpal::SampleRate(target_sample_rate),
             from_channels,
+            quality,
+        );
+        let from_layout = from_layout
+            .filter(|layout| layout.channels() == from_channels)
+            .unwrap_or_else(|| ChannelLayout::default_for(from_channels));
+        let input = ChannelCountConverter::with_layouts(
+            input,
+            from_layout,
+            ChannelLayout::default_for(target_channels),
+            channel_options,
         );
-        let input = ChannelCountConverter::new(input, from_channels, target_channels);
 
         DataConverter::new(input)
     }
@@ -98,7 +126,13 @@ where
             .into_inner()
             .iter;
 
-        let mut input = Self::bootstrap(input, self.target_channels, self.target_sample_rate);
+        let mut input = Self::bootstrap(
+            input,
+            self.target_channels,
+            self.target_sample_rate,
+            self.quality,
+            self.channel_options,
+        );
 
         let value = input.next();
         self.inner = Some(input);
@@ -150,37 +184,68 @@ where
             .into_inner()
             .iter;
         let ret = input.seek(time);
-        let input = Self::bootstrap(input, self.target_channels, self.target_sample_rate);
+        let input = Self::bootstrap(
+            input,
+            self.target_channels,
+            self.target_sample_rate,
+            self.quality,
+            self.channel_options,
+        );
 
         self.inner = Some(input);
         ret
     }
 }
 
+/// Returns the length of the current frame of `input`, limited to something reasonable.
+#[inline]
+fn frame_len<I: Source>(input: &I) -> Option<usize>
+where
+    I::Item: Sample,
+{
+    input.current_frame_len().map(|x| x.min(32768))
+}
+
+/// Iterator over the samples of a source up to the next change of format.
+///
+/// Consecutive frames with the same channel count and sample rate are read as one, so that the
+/// sample rate converter keeps its history across them instead of starting over at each frame.
 #[derive(Clone, Debug)]
 struct Take<I> {
     iter: I,
+    /// Samples left in the current frame, or `None` if it lasts until the end of `iter`.
     n: Option<usize>,
+    channels: u16,
+    sample_rate: u32,
+    channel_layout: Option<ChannelLayout>,
 }
 
 impl<I> Iterator for Take<I>
 where
-    I: Iterator,
+    I: Source,
+    I::Item: Sample,
 {
     type Item = <I as Iterator>::Item;
 
     #[inline]
     fn next(&mut self) -> Option<<I as Iterator>::Item> {
-        if let Some(n) = &mut self.n {
-            if *n == 0 {
-                None
-            } else {
-                *n -= 1;
-                self.iter.next()
+        if self.n == Some(0) {
+            if self.iter.channels() != self.channels
+                || self.iter.sample_rate() != self.sample_rate
+                || self.iter.channel_layout() != self.channel_layout
+            {
+                return None;
+            }
+            self.n = frame_len(&self.iter);
+            if self.n == Some(0) {
+                return None;
             }
-        } else {
-            self.iter.next()
         }
+
+        if let Some(n) = &mut self.n {
+            *n -= 1;
+        }
+        self.iter.next()
     }
 
     #[inline]
@@ -188,16 +253,9 @@ where
         self.n.map_or_else(
             || self.iter.size_hint(),
             |n| {
+                // Following frames may have the same format, so only the lower bound is known.
                 let (lower, upper) = self.iter.size_hint();
-
-                let lower = cmp::min(lower, n);
-
-                let upper = match upper {
-                    Some(x) if x < n => Some(x),
-                    _ => Some(n),
-                };
-
-                (lower, upper)
+                (cmp::min(lower, n), upper)
             },
         )
     }
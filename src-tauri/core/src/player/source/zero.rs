@@ -12,6 +12,7 @@ pub struct Zero<S> {
 }
 
 impl<S> Zero<S> {
+    /// Returns an infinite source of silence.
     #[inline]
     pub const fn new(channels: u16, sample_rate: u32) -> Self {
         Self {
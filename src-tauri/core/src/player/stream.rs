@@ -0,0 +1,481 @@
+// use std::io::{Read, Seek};
+// use std::marker::Sync;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::{error, fmt};
+
+use super::conversions::{ChannelOptions, ResamplerQuality};
+use super::decoder;
+use super::dither::{DitherConfig, Ditherer};
+use super::dynamic_mixer::{self, DynamicMixer, DynamicMixerController};
+// use super::sink::Sink;
+use super::source::Source;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::Sample;
+
+/// `cpal::Stream` container. Also see the more useful `OutputStreamHandle`.
+///
+/// If this is dropped playback will end & attached `OutputStreamHandle`s will no longer work.
+#[allow(clippy::module_name_repetitions)]
+pub struct OutputStream {
+    mixer: Arc<DynamicMixerController<f32>>,
+    backend: Backend,
+    failed: Arc<AtomicBool>,
+    dither: Arc<DitherConfig>,
+    device_name: Option<String>,
+    channels: u16,
+    sample_rate: u32,
+    sample_format: cpal::SampleFormat,
+}
+
+/// Where the output of the mixer goes.
+enum Backend {
+    /// The stream is only kept so that it goes on playing.
+    #[allow(dead_code)]
+    Device(cpal::Stream),
+    /// Nothing plays the mixer. Its samples are pulled with `OutputStream::render`, as fast as
+    /// they can be produced.
+    Offline(DynamicMixer<f32>),
+}
+
+/// More flexible handle to a `OutputStream` that provides playback.
+#[derive(Clone)]
+pub struct OutputStreamHandle {
+    mixer: Weak<DynamicMixerController<f32>>,
+    dither: Weak<DitherConfig>,
+}
+
+impl OutputStream {
+    /// Returns a new stream & handle using the given output device.
+    pub fn try_from_device(
+        device: &cpal::Device,
+    ) -> Result<(Self, OutputStreamHandle), StreamError> {
+        let failed = Arc::new(AtomicBool::new(false));
+        let dither = Arc::new(DitherConfig::default());
+        let (mixer, stream, config) =
+            device.try_new_output_stream(failed.clone(), dither.clone())?;
+        Self::start(device, mixer, stream, config, failed, dither)
+    }
+
+    /// Returns a new stream & handle using the given output device and format, without falling
+    /// back to other formats.
+    pub fn try_from_device_with_format(
+        device: &cpal::Device,
+        config: cpal::SupportedStreamConfig,
+    ) -> Result<(Self, OutputStreamHandle), StreamError> {
+        let failed = Arc::new(AtomicBool::new(false));
+        let dither = Arc::new(DitherConfig::default());
+        let (mixer, stream) =
+            device.new_output_stream_with_format(config.clone(), failed.clone(), dither.clone())?;
+        Self::start(device, mixer, stream, config, failed, dither)
+    }
+
+    fn start(
+        device: &cpal::Device,
+        mixer: Arc<DynamicMixerController<f32>>,
+        stream: cpal::Stream,
+        config: cpal::SupportedStreamConfig,
+        failed: Arc<AtomicBool>,
+        dither: Arc<DitherConfig>,
+    ) -> Result<(Self, OutputStreamHandle), StreamError> {
+        stream.play()?;
+        let out = Self {
+            mixer,
+            backend: Backend::Device(stream),
+            failed,
+            dither,
+            device_name: device.name().ok(),
+            channels: config.channels(),
+            sample_rate: config.sample_rate().0,
+            sample_format: config.sample_format(),
+        };
+        let handle = out.handle();
+        Ok((out, handle))
+    }
+
+    /// Returns a new stream & handle that don't play on a device. The output, in `f32` samples,
+    /// is pulled with `render` instead.
+    pub fn offline(channels: u16, sample_rate: u32) -> (Self, OutputStreamHandle) {
+        let (mixer, output) = dynamic_mixer::mixer::<f32>(channels, sample_rate);
+        let out = Self {
+            mixer,
+            backend: Backend::Offline(output),
+            failed: Arc::new(AtomicBool::new(false)),
+            dither: Arc::new(DitherConfig::default()),
+            device_name: None,
+            channels,
+            sample_rate,
+            sample_format: cpal::SampleFormat::F32,
+        };
+        let handle = out.handle();
+        (out, handle)
+    }
+
+    fn handle(&self) -> OutputStreamHandle {
+        OutputStreamHandle {
+            mixer: Arc::downgrade(&self.mixer),
+            dither: Arc::downgrade(&self.dither),
+        }
+    }
+
+    /// Return a new stream & handle using the default output device.
+    ///
+    /// On failure will fallback to trying any non-default output devices.
+    pub fn try_default() -> Result<(Self, OutputStreamHandle), StreamError> {
+        let default_device = cpal::default_host()
+            .default_output_device()
+            .ok_or(StreamError::NoDevice)?;
+
+        let default_stream = Self::try_from_device(&default_device);
+
+        default_stream.or_else(|original_err| {
+            // default device didn't work, try other ones
+            let mut devices = match cpal::default_host().output_devices() {
+                Ok(d) => d,
+                Err(_) => return Err(original_err),
+            };
+
+            devices
+                .find_map(|d| Self::try_from_device(&d).ok())
+                .ok_or(original_err)
+        })
+    }
+
+    /// Returns `true` once the stream reported an error, e.g. because its device was
+    /// disconnected. A failed stream doesn't play anything anymore and should be rebuilt.
+    pub fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Returns the name of the device this stream plays on.
+    pub fn device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
+
+    /// Returns the number of channels the device was opened with.
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Returns the sample rate the device was opened with, in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns the sample format the device was opened with.
+    pub fn sample_format(&self) -> cpal::SampleFormat {
+        self.sample_format
+    }
+
+    /// Returns `true` if the stream was created with `offline`.
+    pub fn is_offline(&self) -> bool {
+        matches!(self.backend, Backend::Offline(_))
+    }
+
+    /// Fills `buffer` with the next interleaved samples of an offline stream, with silence where
+    /// nothing plays. Returns `false`, leaving `buffer` untouched, if the stream plays on a
+    /// device.
+    pub fn render(&mut self, buffer: &mut [f32]) -> bool {
+        match &mut self.backend {
+            Backend::Offline(mixer) => {
+                for sample in buffer.iter_mut() {
+                    *sample = mixer.next().unwrap_or(0.0);
+                }
+                true
+            }
+            Backend::Device(_) => false,
+        }
+    }
+}
+
+#[allow(unused)]
+impl OutputStreamHandle {
+    /// Plays a source with a device until it ends.
+    pub fn play_raw<S>(&self, source: S) -> Result<(), PlayError>
+    where
+        S: Source<Item = f32> + Send + 'static,
+    {
+        let mixer = self.mixer.upgrade().ok_or(PlayError::NoDevice)?;
+        mixer.add(source);
+        Ok(())
+    }
+
+    /// Sets the quality of the sample rate conversion for the sources played from now on.
+    pub fn set_resampler_quality(&self, quality: ResamplerQuality) -> Result<(), PlayError> {
+        let mixer = self.mixer.upgrade().ok_or(PlayError::NoDevice)?;
+        mixer.set_resampler_quality(quality);
+        Ok(())
+    }
+
+    /// Sets how the channels of the sources played from now on are mapped to the output.
+    pub fn set_channel_options(&self, options: ChannelOptions) -> Result<(), PlayError> {
+        let mixer = self.mixer.upgrade().ok_or(PlayError::NoDevice)?;
+        mixer.set_channel_options(options);
+        Ok(())
+    }
+
+    /// Sets whether dither, optionally noise shaped, is applied when the output is converted to
+    /// a 16-bit format. Devices that take `f32` samples are not affected.
+    pub fn set_dither(&self, enabled: bool, noise_shaping: bool) -> Result<(), PlayError> {
+        let dither = self.dither.upgrade().ok_or(PlayError::NoDevice)?;
+        dither.set(enabled, noise_shaping);
+        Ok(())
+    }
+
+    // Plays a sound once. Returns a `Sink` that can be used to control the sound.
+    // pub fn play_once<R>(&self, input: R) -> Result<Sink, PlayError>
+    // where
+    //     R: Read + Seek + Send + Sync + 'static,
+    // {
+    //     let input = decoder::Decoder::new_decoder(input)?;
+    //     let sink = Sink::try_new(self)?;
+    //     sink.append(input);
+    //     Ok(sink)
+    // }
+}
+
+/// An error occurred while attemping to play a sound.
+#[derive(Debug)]
+pub enum PlayError {
+    /// Attempting to decode the audio failed.
+    DecoderError(decoder::SymphoniaDecoderError),
+    /// The output device was lost.
+    NoDevice,
+}
+
+impl From<decoder::SymphoniaDecoderError> for PlayError {
+    fn from(err: decoder::SymphoniaDecoderError) -> Self {
+        Self::DecoderError(err)
+    }
+}
+
+impl fmt::Display for PlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DecoderError(e) => e.fmt(f),
+            Self::NoDevice => write!(f, "NoDevice"),
+        }
+    }
+}
+
+impl error::Error for PlayError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::DecoderError(e) => Some(e),
+            Self::NoDevice => None,
+        }
+    }
+}
+
+/// Errors opening the output stream.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions, clippy::enum_variant_names)]
+pub enum StreamError {
+    /// The stream couldn't be started.
+    PlayStreamError(cpal::PlayStreamError),
+    /// The default configuration of the device couldn't be read.
+    DefaultStreamConfigError(cpal::DefaultStreamConfigError),
+    /// The stream couldn't be built.
+    BuildStreamError(cpal::BuildStreamError),
+    /// The configurations of the device couldn't be read.
+    SupportedStreamConfigsError(cpal::SupportedStreamConfigsError),
+    /// The devices couldn't be listed.
+    DevicesError(cpal::DevicesError),
+    /// There is no output device.
+    NoDevice,
+}
+
+impl From<cpal::DefaultStreamConfigError> for StreamError {
+    fn from(err: cpal::DefaultStreamConfigError) -> Self {
+        Self::DefaultStreamConfigError(err)
+    }
+}
+
+impl From<cpal::SupportedStreamConfigsError> for StreamError {
+    fn from(err: cpal::SupportedStreamConfigsError) -> Self {
+        Self::SupportedStreamConfigsError(err)
+    }
+}
+
+impl From<cpal::DevicesError> for StreamError {
+    fn from(err: cpal::DevicesError) -> Self {
+        Self::DevicesError(err)
+    }
+}
+
+impl From<cpal::BuildStreamError> for StreamError {
+    fn from(err: cpal::BuildStreamError) -> Self {
+        Self::BuildStreamError(err)
+    }
+}
+
+impl From<cpal::PlayStreamError> for StreamError {
+    fn from(err: cpal::PlayStreamError) -> Self {
+        Self::PlayStreamError(err)
+    }
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PlayStreamError(e) => e.fmt(f),
+            Self::BuildStreamError(e) => e.fmt(f),
+            Self::DefaultStreamConfigError(e) => e.fmt(f),
+            Self::SupportedStreamConfigsError(e) => e.fmt(f),
+            Self::DevicesError(e) => e.fmt(f),
+            Self::NoDevice => write!(f, "NoDevice"),
+        }
+    }
+}
+
+impl error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::PlayStreamError(e) => Some(e),
+            Self::BuildStreamError(e) => Some(e),
+            Self::DefaultStreamConfigError(e) => Some(e),
+            Self::SupportedStreamConfigsError(e) => Some(e),
+            Self::DevicesError(e) => Some(e),
+            Self::NoDevice => None,
+        }
+    }
+}
+
+/// Extensions to `cpal::Device`
+///
+/// `failed` is set when the device of the stream is lost. The error callback only stores to it,
+/// so it never blocks the audio thread. `dither` is read by the audio thread when converting to
+/// integer formats.
+pub trait CpalDeviceExt {
+    fn new_output_stream_with_format(
+        &self,
+        format: cpal::SupportedStreamConfig,
+        failed: Arc<AtomicBool>,
+        dither: Arc<DitherConfig>,
+    ) -> Result<(Arc<DynamicMixerController<f32>>, cpal::Stream), cpal::BuildStreamError>;
+
+    fn try_new_output_stream(
+        &self,
+        failed: Arc<AtomicBool>,
+        dither: Arc<DitherConfig>,
+    ) -> Result<
+        (
+            Arc<DynamicMixerController<f32>>,
+            cpal::Stream,
+            cpal::SupportedStreamConfig,
+        ),
+        StreamError,
+    >;
+}
+
+impl CpalDeviceExt for cpal::Device {
+    fn new_output_stream_with_format(
+        &self,
+        format: cpal::SupportedStreamConfig,
+        failed: Arc<AtomicBool>,
+        dither: Arc<DitherConfig>,
+    ) -> Result<(Arc<DynamicMixerController<f32>>, cpal::Stream), cpal::BuildStreamError> {
+        let (mixer_tx, mut mixer_rx) =
+            dynamic_mixer::mixer::<f32>(format.channels(), format.sample_rate().0);
+        let mut ditherer = Ditherer::new(format.channels());
+
+        let error_callback = move |err| {
+            // Other errors, such as the underruns some backends report, don't stop the stream.
+            // Nothing is printed, since writing to stderr takes a lock on the audio thread.
+            if let cpal::StreamError::DeviceNotAvailable = err {
+                failed.store(true, Ordering::Relaxed);
+            }
+        };
+
+        match format.sample_format() {
+            cpal::SampleFormat::F32 => self.build_output_stream::<f32, _, _>(
+                &format.config(),
+                move |data, _| {
+                    data.iter_mut()
+                        .for_each(|d| *d = mixer_rx.next().unwrap_or(0_f32));
+                },
+                error_callback,
+            ),
+            cpal::SampleFormat::I16 => self.build_output_stream::<i16, _, _>(
+                &format.config(),
+                move |data, _| {
+                    let (enabled, noise_shaping) = (dither.enabled(), dither.noise_shaping());
+                    for d in data.iter_mut() {
+                        *d = mixer_rx
+                            .next()
+                            .map_or(0_i16, |s| ditherer.convert(s, enabled, noise_shaping));
+                    }
+                },
+                error_callback,
+            ),
+            cpal::SampleFormat::U16 => self.build_output_stream::<u16, _, _>(
+                &format.config(),
+                move |data, _| {
+                    let (enabled, noise_shaping) = (dither.enabled(), dither.noise_shaping());
+                    for d in data.iter_mut() {
+                        *d = mixer_rx.next().map_or(u16::max_value() / 2, |s| {
+                            ditherer.convert(s, enabled, noise_shaping).to_u16()
+                        });
+                    }
+                },
+                error_callback,
+            ),
+        }
+        .map(|stream| (mixer_tx, stream))
+    }
+
+    fn try_new_output_stream(
+        &self,
+        failed: Arc<AtomicBool>,
+        dither: Arc<DitherConfig>,
+    ) -> Result<
+        (
+            Arc<DynamicMixerController<f32>>,
+            cpal::Stream,
+            cpal::SupportedStreamConfig,
+        ),
+        StreamError,
+    > {
+        // Determine the format to use for the new stream.
+        let default_format = self.default_output_config()?;
+
+        self.new_output_stream_with_format(default_format.clone(), failed.clone(), dither.clone())
+            .map(|(mixer, stream)| (mixer, stream, default_format))
+            .or_else(|err| {
+                // look through all supported formats to see if another works
+                supported_output_formats(self)?
+                    .find_map(|format| {
+                        self.new_output_stream_with_format(
+                            format.clone(),
+                            failed.clone(),
+                            dither.clone(),
+                        )
+                        .ok()
+                        .map(|(mixer, stream)| (mixer, stream, format))
+                    })
+                    // return original error if nothing works
+                    .ok_or(StreamError::BuildStreamError(err))
+            })
+    }
+}
+
+/// All the supported output formats with sample rates
+fn supported_output_formats(
+    device: &cpal::Device,
+) -> Result<impl Iterator<Item = cpal::SupportedStreamConfig>, StreamError> {
+    const HZ_44100: cpal::SampleRate = cpal::SampleRate(44_100);
+
+    let mut supported: Vec<_> = device.supported_output_configs()?.collect();
+    supported.sort_by(|a, b| b.cmp_default_heuristics(a));
+
+    Ok(supported.into_iter().flat_map(|sf| {
+        let max_rate = sf.max_sample_rate();
+        let min_rate = sf.min_sample_rate();
+        let mut formats = vec![sf.clone().with_max_sample_rate()];
+        if HZ_44100 < max_rate && HZ_44100 > min_rate {
+            formats.push(sf.clone().with_sample_rate(HZ_44100));
+        }
+        formats.push(sf.with_sample_rate(min_rate));
+        formats
+    }))
+}
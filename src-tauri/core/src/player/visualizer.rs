@@ -0,0 +1,211 @@
+//! Spectrum and level analysis of the samples captured by a `Tap`.
+//!
+//! The analysis runs on its own thread and only reads the `TapBuffer`, so the audio callback is
+//! never blocked by it.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::Serialize;
+
+use super::source::TapBuffer;
+
+/// Number of frames analyzed by each FFT.
+const FFT_SIZE: usize = 2048;
+/// Lowest frequency of the first band, in Hz.
+const MIN_FREQUENCY: f32 = 20.0;
+/// Highest frequency of the last band, in Hz. Clamped to the Nyquist frequency.
+const MAX_FREQUENCY: f32 = 20_000.0;
+/// Magnitudes below this level are reported as 0.
+const FLOOR_DB: f32 = -90.0;
+
+/// Frames per second of the spectrum by default.
+pub const DEFAULT_FPS: u32 = 30;
+/// Number of bands of the spectrum by default.
+pub const DEFAULT_BAND_COUNT: usize = 32;
+const MAX_FPS: u32 = 120;
+
+/// One analysis result, sent to the UI.
+#[derive(Clone, Debug, Serialize)]
+pub struct VisualizerFrame {
+    /// Magnitude of each log-spaced band, from 0.0 (silence) to 1.0 (full scale).
+    pub bands: Vec<f32>,
+    /// RMS level of each channel, from 0.0 to 1.0.
+    pub rms: Vec<f32>,
+    /// Peak level of each channel, from 0.0 to 1.0.
+    pub peak: Vec<f32>,
+}
+
+/// Handle to the background analysis thread. The thread stops when this is dropped.
+pub struct Visualizer {
+    fps: Arc<AtomicU32>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Visualizer {
+    /// Spawns the analysis thread.
+    ///
+    /// `emit` is called `fps` times per second with the analysis of the latest samples of
+    /// `buffer`, as long as new samples keep being written to it.
+    pub fn spawn<F>(buffer: Arc<TapBuffer>, fps: u32, band_count: usize, mut emit: F) -> Self
+    where
+        F: FnMut(VisualizerFrame) + Send + 'static,
+    {
+        let fps = Arc::new(AtomicU32::new(fps.clamp(1, MAX_FPS)));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread = {
+            let fps = fps.clone();
+            let running = running.clone();
+            thread::spawn(move || {
+                let mut analyzer = Analyzer::new(band_count);
+                let mut samples = Vec::with_capacity(FFT_SIZE * 8);
+                let mut last_written = buffer.written();
+
+                while running.load(Ordering::Relaxed) {
+                    let fps = fps.load(Ordering::Relaxed);
+                    thread::sleep(Duration::from_secs(1) / fps);
+
+                    let written = buffer.written();
+                    if written == last_written {
+                        continue;
+                    }
+                    last_written = written;
+
+                    let channels = buffer.channels().max(1);
+                    let sample_rate = buffer.sample_rate();
+                    buffer.copy_latest(&mut samples, FFT_SIZE * usize::from(channels));
+                    emit(analyzer.analyze(&samples, channels, sample_rate));
+                }
+            })
+        };
+
+        Self {
+            fps,
+            running,
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns the number of frames emitted per second.
+    pub fn fps(&self) -> u32 {
+        self.fps.load(Ordering::Relaxed)
+    }
+
+    /// Changes the number of frames emitted per second.
+    pub fn set_fps(&self, fps: u32) {
+        self.fps.store(fps.clamp(1, MAX_FPS), Ordering::Relaxed);
+    }
+}
+
+impl Drop for Visualizer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Analyzer {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    band_count: usize,
+}
+
+#[allow(clippy::cast_precision_loss)]
+impl Analyzer {
+    fn new(band_count: usize) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+        // Hann window
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / (FFT_SIZE - 1) as f32).cos()
+            })
+            .collect();
+
+        Self {
+            fft,
+            window,
+            spectrum: vec![Complex::default(); FFT_SIZE],
+            band_count: band_count.max(1),
+        }
+    }
+
+    fn analyze(&mut self, samples: &[f32], channels: u16, sample_rate: u32) -> VisualizerFrame {
+        let channels = usize::from(channels);
+        let frames = samples.len() / channels;
+
+        let mut sum_squares = vec![0.0_f32; channels];
+        let mut peak = vec![0.0_f32; channels];
+        for frame in samples.chunks_exact(channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                sum_squares[channel] += sample * sample;
+                peak[channel] = peak[channel].max(sample.abs());
+            }
+        }
+        let rms = sum_squares
+            .iter()
+            .map(|sum| {
+                if frames == 0 {
+                    0.0
+                } else {
+                    (sum / frames as f32).sqrt()
+                }
+            })
+            .collect();
+
+        // The spectrum is computed on the mono downmix, zero-padded at the front if not enough
+        // samples were captured yet.
+        let padding = FFT_SIZE - frames.min(FFT_SIZE);
+        for (i, value) in self.spectrum.iter_mut().enumerate() {
+            let mono = if i < padding {
+                0.0
+            } else {
+                let frame = &samples[(i - padding) * channels..(i - padding + 1) * channels];
+                frame.iter().sum::<f32>() / channels as f32
+            };
+            *value = Complex::new(mono * self.window[i], 0.0);
+        }
+        self.fft.process(&mut self.spectrum);
+
+        VisualizerFrame {
+            bands: self.bands(sample_rate),
+            rms,
+            peak,
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn bands(&self, sample_rate: u32) -> Vec<f32> {
+        let bin_width = sample_rate as f32 / FFT_SIZE as f32;
+        let max_frequency = MAX_FREQUENCY.min(sample_rate as f32 / 2.0);
+        let ratio = (max_frequency / MIN_FREQUENCY).powf(1.0 / self.band_count as f32);
+        // Sum of the Hann window, halved because only the positive frequencies are kept.
+        let scale = 4.0 / FFT_SIZE as f32;
+
+        (0..self.band_count)
+            .map(|band| {
+                let low = MIN_FREQUENCY * ratio.powi(band as i32);
+                let high = low * ratio;
+                let first = ((low / bin_width) as usize).max(1);
+                let last = ((high / bin_width).ceil() as usize)
+                    .max(first + 1)
+                    .min(FFT_SIZE / 2);
+
+                let magnitude = self.spectrum[first.min(last - 1)..last]
+                    .iter()
+                    .map(|bin| bin.norm() * scale)
+                    .fold(0.0_f32, f32::max);
+                let db = 20.0 * magnitude.max(f32::MIN_POSITIVE).log10();
+                ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0)
+            })
+            .collect()
+    }
+}
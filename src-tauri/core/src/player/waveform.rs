@@ -0,0 +1,299 @@
+//! Waveform overview of a whole file, for drawing the seek bar.
+//!
+//! A file is decoded once into fixed-size blocks of `BLOCK_FRAMES` frames, which are cached on
+//! disk together with the modification time of the file. Any number of buckets can then be
+//! computed from the blocks without decoding again.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{error, fmt};
+
+use serde::Serialize;
+
+use super::decoder::{Symphonia, SymphoniaDecoderError};
+use super::Source;
+
+/// Number of frames summarized by each cached block.
+const BLOCK_FRAMES: usize = 256;
+const CACHE_MAGIC: &[u8; 4] = b"PMPW";
+const CACHE_VERSION: u32 = 1;
+/// Number of bytes of each block in the cache.
+const CACHE_BLOCK_LEN: u64 = 12;
+
+/// Levels of one bucket of the waveform, from -1.0 to 1.0.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct WaveformBucket {
+    /// Lowest sample.
+    pub min: f32,
+    /// Highest sample.
+    pub max: f32,
+    /// Root mean square of the samples.
+    pub rms: f32,
+}
+
+/// Returns `buckets` evenly spaced summaries of the file at `path`.
+///
+/// If `cache_dir` is given, the decoded blocks are read from it when the file has not been
+/// modified since they were written, and written to it otherwise.
+pub fn get_waveform(
+    path: &Path,
+    buckets: usize,
+    cache_dir: Option<&Path>,
+) -> Result<Vec<WaveformBucket>, WaveformError> {
+    let modified = fs::metadata(path)?.modified()?;
+    let cache_path = cache_dir.map(|dir| cache_file(dir, path));
+
+    let blocks = match cache_path
+        .as_ref()
+        .and_then(|p| read_cache(p, path, modified).ok())
+    {
+        Some(blocks) => blocks,
+        None => {
+            let blocks = decode_blocks(path)?;
+            if let Some(cache_path) = &cache_path {
+                // A failure to write the cache only means the file will be decoded again.
+                let _ = write_cache(cache_path, path, modified, &blocks);
+            }
+            blocks
+        }
+    };
+
+    Ok(to_buckets(&blocks, buckets))
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn decode_blocks(path: &Path) -> Result<Vec<WaveformBucket>, WaveformError> {
+    let decoder = Symphonia::new(File::open(path)?, false)?;
+    let channels = usize::from(decoder.channels().max(1));
+    let block_len = BLOCK_FRAMES * channels;
+
+    let mut blocks = Vec::new();
+    let mut block = WaveformBucket::default();
+    let mut sum_squares = 0.0_f32;
+    let mut count = 0;
+    for sample in decoder {
+        let value = f32::from(sample) / 32768.0;
+        block.min = block.min.min(value);
+        block.max = block.max.max(value);
+        sum_squares += value * value;
+        count += 1;
+
+        if count == block_len {
+            block.rms = (sum_squares / count as f32).sqrt();
+            blocks.push(block);
+            block = WaveformBucket::default();
+            sum_squares = 0.0;
+            count = 0;
+        }
+    }
+    if count > 0 {
+        block.rms = (sum_squares / count as f32).sqrt();
+        blocks.push(block);
+    }
+
+    Ok(blocks)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn to_buckets(blocks: &[WaveformBucket], buckets: usize) -> Vec<WaveformBucket> {
+    if blocks.is_empty() {
+        return vec![WaveformBucket::default(); buckets];
+    }
+
+    (0..buckets)
+        .map(|bucket| {
+            let start = bucket * blocks.len() / buckets;
+            let end = ((bucket + 1) * blocks.len() / buckets).max(start + 1);
+            let range = &blocks[start.min(blocks.len() - 1)..end.min(blocks.len())];
+
+            let mean_square = range.iter().map(|b| b.rms * b.rms).sum::<f32>() / range.len() as f32;
+            WaveformBucket {
+                min: range.iter().map(|b| b.min).fold(0.0, f32::min),
+                max: range.iter().map(|b| b.max).fold(0.0, f32::max),
+                rms: mean_square.sqrt(),
+            }
+        })
+        .collect()
+}
+
+fn cache_file(cache_dir: &Path, path: &Path) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    cache_dir.join(format!("{:016x}.bin", hasher.finish()))
+}
+
+fn modified_key(modified: SystemTime) -> (u64, u32) {
+    modified
+        .duration_since(UNIX_EPOCH)
+        .map_or((0, 0), |d| (d.as_secs(), d.subsec_nanos()))
+}
+
+fn read_cache(
+    cache_path: &Path,
+    path: &Path,
+    modified: SystemTime,
+) -> io::Result<Vec<WaveformBucket>> {
+    let file = File::open(cache_path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "stale waveform cache");
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != CACHE_MAGIC || read_u32(&mut reader)? != CACHE_VERSION {
+        return Err(invalid());
+    }
+
+    let (secs, nanos) = modified_key(modified);
+    if read_u64(&mut reader)? != secs || read_u32(&mut reader)? != nanos {
+        return Err(invalid());
+    }
+
+    // Lengths are checked against the size of the file before anything is allocated, as the
+    // cache may be truncated or corrupt. The header and the two lengths take 28 bytes.
+    let path_len = u64::from(read_u32(&mut reader)?);
+    let remaining = file_len.saturating_sub(28);
+    if path_len > remaining {
+        return Err(invalid());
+    }
+
+    // The file name is only a hash of the path, so the path itself is checked as well.
+    let mut cached_path = vec![0; path_len as usize];
+    reader.read_exact(&mut cached_path)?;
+    if cached_path != path.to_string_lossy().as_bytes() {
+        return Err(invalid());
+    }
+
+    let count = read_u32(&mut reader)?;
+    if u64::from(count) * CACHE_BLOCK_LEN != remaining - path_len {
+        return Err(invalid());
+    }
+    let count = count as usize;
+    let mut blocks = Vec::with_capacity(count);
+    for _ in 0..count {
+        blocks.push(WaveformBucket {
+            min: read_f32(&mut reader)?,
+            max: read_f32(&mut reader)?,
+            rms: read_f32(&mut reader)?,
+        });
+    }
+    Ok(blocks)
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn write_cache(
+    cache_path: &Path,
+    path: &Path,
+    modified: SystemTime,
+    blocks: &[WaveformBucket],
+) -> io::Result<()> {
+    if let Some(dir) = cache_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // Written next to the final file first, so a reader never sees a partial cache.
+    let tmp_path = cache_path.with_extension("tmp");
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        let (secs, nanos) = modified_key(modified);
+        let path = path.to_string_lossy();
+
+        writer.write_all(CACHE_MAGIC)?;
+        writer.write_all(&CACHE_VERSION.to_le_bytes())?;
+        writer.write_all(&secs.to_le_bytes())?;
+        writer.write_all(&nanos.to_le_bytes())?;
+        writer.write_all(&(path.len() as u32).to_le_bytes())?;
+        writer.write_all(path.as_bytes())?;
+        writer.write_all(&(blocks.len() as u32).to_le_bytes())?;
+        for block in blocks {
+            writer.write_all(&block.min.to_le_bytes())?;
+            writer.write_all(&block.max.to_le_bytes())?;
+            writer.write_all(&block.rms.to_le_bytes())?;
+        }
+        writer.flush()?;
+    }
+    fs::rename(tmp_path, cache_path)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+/// Error that can happen when computing a waveform.
+#[derive(Debug, Serialize)]
+pub enum WaveformError {
+    /// The file could not be read.
+    IoError(String),
+    /// The file could not be decoded.
+    DecoderError(String),
+}
+
+impl From<io::Error> for WaveformError {
+    fn from(err: io::Error) -> Self {
+        Self::IoError(err.to_string())
+    }
+}
+
+impl From<SymphoniaDecoderError> for WaveformError {
+    fn from(err: SymphoniaDecoderError) -> Self {
+        Self::DecoderError(err.to_string())
+    }
+}
+
+impl fmt::Display for WaveformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IoError(msg) | Self::DecoderError(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl error::Error for WaveformError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::fixtures::TempFile;
+    use std::time::Duration;
+
+    #[test]
+    fn treats_corrupt_caches_as_misses() {
+        let cache = TempFile::new("bin");
+        let path = Path::new("/music/track.flac");
+        let modified = UNIX_EPOCH + Duration::from_secs(1000);
+        let blocks = vec![WaveformBucket::default(); 3];
+        write_cache(cache.path(), path, modified, &blocks).unwrap();
+        assert_eq!(read_cache(cache.path(), path, modified).unwrap().len(), 3);
+
+        let mut contents = fs::read(cache.path()).unwrap();
+        // A huge count of blocks.
+        let count_at = contents.len() - 3 * 12 - 4;
+        contents[count_at..count_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(cache.path(), &contents).unwrap();
+        assert!(read_cache(cache.path(), path, modified).is_err());
+
+        // A huge path length, in a truncated file.
+        contents[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+        contents.truncate(30);
+        fs::write(cache.path(), &contents).unwrap();
+        assert!(read_cache(cache.path(), path, modified).is_err());
+    }
+}
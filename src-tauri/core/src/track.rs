@@ -3,10 +3,11 @@ use anyhow::{bail, Result};
 // use id3::frame::Lyrics;
 use lofty::id3::v2::{Frame, FrameFlags, FrameValue, ID3v2Tag, LanguageFrame, TextEncoding};
 use lofty::{
-    mp3::Mp3File, Accessor, AudioFile, FileType, ItemKey, ItemValue, Picture, PictureType, TagExt,
-    TagItem,
+    mp3::Mp3File, Accessor, AudioFile, FileType, ItemKey, ItemValue, Picture, PictureType, Tag,
+    TagExt, TagItem, TagType,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::From;
 use std::ffi::OsStr;
 use std::fs::rename;
@@ -20,8 +21,8 @@ use std::time::Duration;
 pub struct Track {
     /// Artist of the song
     artist: Option<String>,
-    // Album of the song
-    // album: Option<String>,
+    /// Album of the song
+    album: Option<String>,
     /// Title of the song
     title: Option<String>,
     /// File path to the song
@@ -76,7 +77,7 @@ impl Track {
                 // }
 
                 song.artist = tag.artist().map(str::to_string);
-                // song.album = tag.album().map(str::to_string);
+                song.album = tag.album().map(str::to_string);
                 song.title = tag.title().map(str::to_string);
                 // song.genre = tag.get_string(&ItemKey::Genre).map(str::to_string);
 
@@ -148,7 +149,7 @@ impl Track {
         // let directory = Some(p.parent().unwrap().to_string_lossy().into_owned());
         // let ext = p.extension().and_then(OsStr::to_str).map(String::from);
         let artist = Some(String::from("Unsupported?"));
-        // let album = Some(String::from("Unsupported?"));
+        let album = Some(String::from("Unsupported?"));
         let title = p.file_stem().and_then(OsStr::to_str).map(String::from);
         let file = Some(p.to_string_lossy().into_owned());
         // let duration = Duration::from_secs(0);
@@ -166,7 +167,7 @@ impl Track {
             // ext,
             // file_type: None,
             artist,
-            // album,
+            album,
             title,
             file,
             // directory,
@@ -295,17 +296,13 @@ impl Track {
 
     /// Optionally return the song's album
     /// If `None` failed to read the tags
-    // pub fn album(&self) -> Option<&str> {
-    //     self.album.as_deref()
-    //     // match self.album.as_ref() {
-    //     //     Some(album) => Some(album),
-    //     //     None => None,
-    //     // }
-    // }
+    pub fn album(&self) -> Option<&str> {
+        self.album.as_deref()
+    }
 
-    // pub fn set_album(&mut self, album: &str) {
-    //     self.album = Some(album.to_string());
-    // }
+    pub fn set_album(&mut self, album: &str) {
+        self.album = Some(album.to_string());
+    }
 
     // pub fn genre(&self) -> Option<&str> {
     //     self.genre.as_deref()
@@ -547,6 +544,115 @@ impl Track {
     // }
 }
 
+/// Tag fields that paths can be named after, and the items they are read from, in order.
+const FIELDS: &[(&str, &[ItemKey])] = &[
+    ("artist", &[ItemKey::TrackArtist]),
+    ("albumartist", &[ItemKey::AlbumArtist]),
+    ("album", &[ItemKey::AlbumTitle]),
+    ("title", &[ItemKey::TrackTitle]),
+    ("track", &[ItemKey::TrackNumber]),
+    ("disc", &[ItemKey::DiscNumber]),
+    ("year", &[ItemKey::Year, ItemKey::RecordingDate]),
+    ("genre", &[ItemKey::Genre]),
+];
+
+/// Returns the fields `artist`, `albumartist`, `album`, `title`, `track`, `disc`, `year` and
+/// `genre` of the primary tag of the file at `path`, as written in the tag. Missing fields, and
+/// every field of a file whose tags can't be read, are left out.
+pub fn tag_fields(path: &Path) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+    let tagged_file = match lofty::read_from_path(path, false) {
+        Ok(tagged_file) => tagged_file,
+        Err(_) => return fields,
+    };
+    if let Some(tag) = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())
+    {
+        for (name, keys) in FIELDS {
+            if let Some(value) = keys.iter().find_map(|key| tag.get_string(key)) {
+                fields.insert((*name).to_string(), value.to_string());
+            }
+        }
+    }
+    fields
+}
+
+/// Copies the tag of the file at `source`, with its pictures, to the file at `target`, in the
+/// tag format of `target`. Items that format has no place for are dropped.
+pub fn copy_tags(source: &Path, target: &Path) -> Result<()> {
+    let source_file = lofty::read_from_path(source, false)?;
+    let source_tag = match source_file
+        .primary_tag()
+        .or_else(|| source_file.first_tag())
+    {
+        Some(tag) => tag,
+        None => return Ok(()),
+    };
+
+    let target_file = lofty::read_from_path(target, false)?;
+    let mut tag = Tag::new(target_file.primary_tag_type());
+    for item in source_tag.items() {
+        tag.insert_item(item.clone());
+    }
+    for picture in source_tag.pictures() {
+        tag.push_picture(picture.clone());
+    }
+    tag.save_to_path(target)?;
+    Ok(())
+}
+
+/// Email of the POPM frames written with the ratings, which names the player that wrote them.
+const POPM_EMAIL: &str = "Plain Music Player";
+
+/// Values of the rating byte of POPM frames for each half star, as most players read them.
+const POPM_RATINGS: [u8; 11] = [0, 13, 1, 54, 64, 118, 128, 186, 196, 242, 255];
+
+/// Writes `rating`, in half stars from 0 to 10, to the tag of the file at `path`, or removes it
+/// if `None`.
+///
+/// ID3v2 tags get a POPM frame, replacing those of other players, and other tags a
+/// `FMPS_Rating` item from 0.0 to 1.0, which is what other players read.
+pub fn write_rating(path: &Path, rating: Option<u8>) -> Result<()> {
+    let rating = rating.map(|rating| rating.min(10));
+    let mut tagged_file = lofty::read_from_path(path, false)?;
+    let tag_type = tagged_file.primary_tag_type();
+    let (key, value) = match tag_type {
+        TagType::Id3v2 => {
+            let value = rating.map(|rating| {
+                let mut frame = POPM_EMAIL.as_bytes().to_vec();
+                frame.push(0);
+                frame.push(POPM_RATINGS[usize::from(rating)]);
+                ItemValue::Binary(frame)
+            });
+            (ItemKey::Unknown(String::from("POPM")), value)
+        }
+        _ => {
+            let key = match tag_type {
+                TagType::Mp4Ilst => "----:com.apple.iTunes:FMPS_Rating",
+                _ => "FMPS_RATING",
+            };
+            let value =
+                rating.map(|rating| ItemValue::Text(format!("{:.2}", f64::from(rating) / 10.0)));
+            (ItemKey::Unknown(key.to_string()), value)
+        }
+    };
+
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = match tagged_file.primary_tag_mut() {
+        Some(tag) => tag,
+        None => bail!("no tag for {}", path.display()),
+    };
+    tag.remove_key(&key);
+    if let Some(value) = value {
+        tag.insert_item_unchecked(TagItem::new(key, value));
+    }
+    tag.save_to_path(path)?;
+    Ok(())
+}
+
 // fn create_lyrics(tag: &mut lofty::Tag, lyric_frames: &mut Vec<Lyrics>) {
 //     let lyrics = tag.take(&ItemKey::Lyrics);
 //     for lyric in lyrics {
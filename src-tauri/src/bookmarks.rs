@@ -0,0 +1,95 @@
+use crate::storage::{self, Stored};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the bookmarks file in the app data directory.
+const BOOKMARKS_FILE: &str = "bookmarks.json";
+/// Version of the layout of the bookmarks file.
+const BOOKMARKS_VERSION: u32 = 1;
+
+/// A named position in a track.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    /// Position in the track, in milliseconds.
+    pub position_ms: u64,
+}
+
+/// Bookmarks of every track, by path.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bookmarks {
+    /// Bookmarks of each track, sorted by position. Paths are stored as strings so that they
+    /// can be keys of the JSON object.
+    files: BTreeMap<String, Vec<Bookmark>>,
+}
+
+impl Bookmarks {
+    /// Returns the path of the bookmarks file in `app_dir`.
+    pub fn path(app_dir: &Path) -> PathBuf {
+        app_dir.join(BOOKMARKS_FILE)
+    }
+
+    /// Reads the bookmarks from `path`, falling back to no bookmarks if the file is missing or
+    /// can't be parsed.
+    pub fn load(path: &Path) -> Self {
+        storage::load(path, BOOKMARKS_VERSION, Self::migrate).unwrap_or_default()
+    }
+
+    /// Upgrades bookmarks written by an older version.
+    fn migrate(_version: u32, _value: &mut Value) {}
+
+    /// Returns the bookmarks of `file`, sorted by position.
+    pub fn list(&self, file: &Path) -> &[Bookmark] {
+        self.files
+            .get(file.to_string_lossy().as_ref())
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the bookmark of `file` named `name`.
+    pub fn find(&self, file: &Path, name: &str) -> Option<&Bookmark> {
+        self.list(file)
+            .iter()
+            .find(|bookmark| bookmark.name == name)
+    }
+
+    /// Adds a bookmark to `file`, replacing the one with the same name.
+    pub fn add(&mut self, file: &Path, bookmark: Bookmark) {
+        let bookmarks = self
+            .files
+            .entry(file.to_string_lossy().into_owned())
+            .or_default();
+        bookmarks.retain(|other| other.name != bookmark.name);
+        let index = bookmarks.partition_point(|other| other.position_ms <= bookmark.position_ms);
+        bookmarks.insert(index, bookmark);
+    }
+
+    /// Removes the bookmark of `file` named `name`. Returns `false` if there is none.
+    pub fn remove(&mut self, file: &Path, name: &str) -> bool {
+        let key = file.to_string_lossy();
+        let bookmarks = match self.files.get_mut(key.as_ref()) {
+            Some(bookmarks) => bookmarks,
+            None => return false,
+        };
+
+        let len = bookmarks.len();
+        bookmarks.retain(|bookmark| bookmark.name != name);
+        let removed = bookmarks.len() != len;
+        if bookmarks.is_empty() {
+            self.files.remove(key.as_ref());
+        }
+        removed
+    }
+}
+
+impl Stored for Bookmarks {
+    const NAME: &'static str = "bookmarks";
+
+    /// Writes the bookmarks to `path`, creating the parent directory if needed.
+    fn save(&self, path: &Path) -> Result<()> {
+        storage::save(path, BOOKMARKS_VERSION, self)
+    }
+}
@@ -0,0 +1,591 @@
+use crate::player::{Listen, ListenEnd};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Name of the history file in the app data directory. The file is a log with an entry per line,
+/// as JSON, so that recording a playback never rewrites the entries before it.
+const HISTORY_FILE: &str = "history.jsonl";
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+/// Columns of the history exported as CSV.
+const CSV_HEADER: &str =
+    "started,artist,album_artist,album,title,genre,listened_ms,duration_ms,end,path";
+
+/// A playback of a track, with the tags the track had then.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    /// When the track started, in seconds since the Unix epoch.
+    pub started: u64,
+    /// Time of the track that was heard, in milliseconds.
+    pub listened_ms: u64,
+    /// Duration of the track in milliseconds, if known.
+    pub duration_ms: Option<u64>,
+    pub end: ListenEnd,
+}
+
+impl HistoryEntry {
+    /// Returns the entry of `listen`, whose track has the tags `fields`, as read by
+    /// `track::tag_fields`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(listen: &Listen, fields: &HashMap<String, String>) -> Self {
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        Self {
+            path: listen.path.clone(),
+            title: field("title"),
+            artist: field("artist"),
+            album_artist: field("albumartist"),
+            album: field("album"),
+            genre: field("genre"),
+            started: listen
+                .started
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |started| started.as_secs()),
+            listened_ms: listen.listened.as_millis() as u64,
+            duration_ms: listen.duration.map(|duration| duration.as_millis() as u64),
+            end: listen.end,
+        }
+    }
+
+    /// Returns the title, or the name of the file if the track has none.
+    fn title_or_file_name(&self) -> String {
+        self.title.clone().unwrap_or_else(|| {
+            self.path
+                .file_stem()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+        })
+    }
+}
+
+/// Plays and time listened of the entries of a report.
+#[derive(Clone, Copy, Default)]
+struct Totals {
+    plays: u32,
+    listened_ms: u64,
+}
+
+impl Totals {
+    fn add(&mut self, entry: &HistoryEntry) {
+        self.plays += u32::from(entry.end == ListenEnd::Played);
+        self.listened_ms += entry.listened_ms;
+    }
+}
+
+/// Times from `from` included to `to` excluded, in seconds since the Unix epoch. Missing bounds
+/// leave the range open.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TimeRange {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+/// What the top entries of the history are grouped by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TopGroup {
+    Artist,
+    Album,
+    Track,
+}
+
+/// An artist, album or track among the most listened to.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TopEntry {
+    /// Name of the artist or album, or title of the track.
+    pub name: String,
+    /// Artist of the album or track.
+    pub artist: Option<String>,
+    /// Path of the track.
+    pub path: Option<PathBuf>,
+    pub plays: u32,
+    /// Time listened, in milliseconds.
+    pub listened_ms: u64,
+}
+
+/// Listening of one day.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DayTotal {
+    /// Day, as `YYYY-MM-DD`.
+    pub date: String,
+    pub plays: u32,
+    /// Time listened, in milliseconds.
+    pub listened_ms: u64,
+}
+
+/// Listening of one genre.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GenreShare {
+    /// Genre, or `None` for the tracks without one.
+    pub genre: Option<String>,
+    pub plays: u32,
+    /// Time listened, in milliseconds.
+    pub listened_ms: u64,
+    /// Fraction of the time listened to every genre, from 0.0 to 1.0.
+    pub share: f64,
+}
+
+/// Every playback, oldest first.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// Returns the path of the history file in `app_dir`.
+    pub fn path(app_dir: &Path) -> PathBuf {
+        app_dir.join(HISTORY_FILE)
+    }
+
+    /// Reads the history from the log at `path`, falling back to an empty history if the file is
+    /// missing. Lines that can't be parsed, such as one cut short by a crash, are skipped.
+    pub fn load(path: &Path) -> Self {
+        let text = fs::read_to_string(path).unwrap_or_default();
+        let mut entries: Vec<HistoryEntry> = text
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        entries.sort_by_key(|entry| entry.started);
+        Self { entries }
+    }
+
+    /// Appends `entries` to the log at `path`, creating the file and its parent directory if
+    /// needed.
+    pub fn append(path: &Path, entries: &[HistoryEntry]) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let mut lines = String::new();
+        // A line cut short by a crash is ended first, so that it doesn't swallow the next entry.
+        if file.seek(SeekFrom::End(0))? > 0 {
+            let mut last = [0];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                lines.push('\n');
+            }
+        }
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        file.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+
+    /// Adds `entry`, in the order of the start times.
+    pub fn record(&mut self, entry: HistoryEntry) {
+        let index = self
+            .entries
+            .partition_point(|other| other.started <= entry.started);
+        self.entries.insert(index, entry);
+    }
+
+    /// Returns the entries that started in `range`, oldest first.
+    pub fn entries(&self, range: TimeRange) -> &[HistoryEntry] {
+        let start = range.from.map_or(0, |from| {
+            self.entries.partition_point(|entry| entry.started < from)
+        });
+        let end = range.to.map_or(self.entries.len(), |to| {
+            self.entries.partition_point(|entry| entry.started < to)
+        });
+        &self.entries[start..end.max(start)]
+    }
+
+    /// Returns the `limit` artists, albums or tracks played most in `range`, then listened to
+    /// longest. Entries without an artist or album are left out of those groups.
+    pub fn top(&self, range: TimeRange, group: TopGroup, limit: usize) -> Vec<TopEntry> {
+        // Keyed by artist, name and path.
+        let mut groups: HashMap<_, Totals> = HashMap::new();
+        for entry in self.entries(range) {
+            let key = match group {
+                TopGroup::Artist => match &entry.artist {
+                    Some(artist) => (None, artist.clone(), None),
+                    None => continue,
+                },
+                TopGroup::Album => match &entry.album {
+                    Some(album) => {
+                        let artist = entry.album_artist.as_ref().or(entry.artist.as_ref());
+                        (artist.cloned(), album.clone(), None)
+                    }
+                    None => continue,
+                },
+                TopGroup::Track => (
+                    entry.artist.clone(),
+                    entry.title_or_file_name(),
+                    Some(entry.path.clone()),
+                ),
+            };
+            groups.entry(key).or_default().add(entry);
+        }
+
+        let mut top: Vec<TopEntry> = groups
+            .into_iter()
+            .map(|((artist, name, path), totals)| TopEntry {
+                name,
+                artist,
+                path,
+                plays: totals.plays,
+                listened_ms: totals.listened_ms,
+            })
+            .collect();
+        top.sort_by(|a, b| {
+            b.plays
+                .cmp(&a.plays)
+                .then(b.listened_ms.cmp(&a.listened_ms))
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.artist.cmp(&b.artist))
+        });
+        top.truncate(limit);
+        top
+    }
+
+    /// Returns the listening of each day from the first to the last entry in `range`, days
+    /// without any included. Days are in the time zone `utc_offset_minutes` ahead of UTC, and
+    /// each entry counts on the day it started.
+    pub fn daily(&self, range: TimeRange, utc_offset_minutes: i32) -> Vec<DayTotal> {
+        let offset = i64::from(utc_offset_minutes) * 60;
+        let mut days: BTreeMap<i64, Totals> = BTreeMap::new();
+        for entry in self.entries(range) {
+            #[allow(clippy::cast_possible_wrap)]
+            let day = (entry.started as i64 + offset).div_euclid(SECONDS_PER_DAY);
+            days.entry(day).or_default().add(entry);
+        }
+
+        let (first, last) = match (days.keys().next(), days.keys().next_back()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return Vec::new(),
+        };
+        (first..=last)
+            .map(|day| {
+                let totals = days.get(&day).copied().unwrap_or_default();
+                let (year, month, day) = civil_date(day);
+                DayTotal {
+                    date: format!("{:04}-{:02}-{:02}", year, month, day),
+                    plays: totals.plays,
+                    listened_ms: totals.listened_ms,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the listening of each genre in `range`, listened to longest first.
+    pub fn genres(&self, range: TimeRange) -> Vec<GenreShare> {
+        let mut genres: HashMap<Option<String>, Totals> = HashMap::new();
+        for entry in self.entries(range) {
+            genres.entry(entry.genre.clone()).or_default().add(entry);
+        }
+
+        let total: u64 = genres.values().map(|totals| totals.listened_ms).sum();
+        #[allow(clippy::cast_precision_loss)]
+        let mut shares: Vec<GenreShare> = genres
+            .into_iter()
+            .map(|(genre, totals)| GenreShare {
+                genre,
+                plays: totals.plays,
+                listened_ms: totals.listened_ms,
+                share: if total == 0 {
+                    0.0
+                } else {
+                    totals.listened_ms as f64 / total as f64
+                },
+            })
+            .collect();
+        shares.sort_by(|a, b| {
+            b.listened_ms
+                .cmp(&a.listened_ms)
+                .then(b.plays.cmp(&a.plays))
+                .then_with(|| a.genre.cmp(&b.genre))
+        });
+        shares
+    }
+}
+
+/// Returns `entries` as CSV, with a header line. Start times are written in UTC.
+pub fn to_csv(entries: &[HistoryEntry]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push_str("\r\n");
+    for entry in entries {
+        let optional = |value: &Option<String>| csv_field(value.as_deref().unwrap_or_default());
+        let end = match entry.end {
+            ListenEnd::Played => "played",
+            ListenEnd::Skipped => "skipped",
+            ListenEnd::Stopped => "stopped",
+        };
+        let fields = [
+            format_utc(entry.started),
+            optional(&entry.artist),
+            optional(&entry.album_artist),
+            optional(&entry.album),
+            optional(&entry.title),
+            optional(&entry.genre),
+            entry.listened_ms.to_string(),
+            entry
+                .duration_ms
+                .map_or_else(String::new, |duration| duration.to_string()),
+            end.to_string(),
+            csv_field(&entry.path.to_string_lossy()),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Quotes `value` if it holds a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\r', '\n'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Returns `time`, in seconds since the Unix epoch, as `YYYY-MM-DDTHH:MM:SSZ`.
+#[allow(clippy::cast_possible_wrap)]
+fn format_utc(time: u64) -> String {
+    let time = time as i64;
+    let (year, month, day) = civil_date(time.div_euclid(SECONDS_PER_DAY));
+    let seconds = time.rem_euclid(SECONDS_PER_DAY);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Returns the year, month and day of `days` days after 1970-01-01, in the proleptic Gregorian
+/// calendar.
+fn civil_date(days: i64) -> (i64, i64, i64) {
+    // Counts from 0000-03-01, so that leap days end the years, in eras of 400 years.
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempFile;
+
+    /// 2022-06-01T00:00:00Z.
+    const JUNE_1: u64 = 1_654_041_600;
+    const HOUR: u64 = 60 * 60;
+
+    fn entry(
+        started: u64,
+        artist: &str,
+        album: &str,
+        title: &str,
+        genre: Option<&str>,
+        end: ListenEnd,
+    ) -> HistoryEntry {
+        HistoryEntry {
+            path: PathBuf::from(format!("/music/{}/{}/{}.flac", artist, album, title)),
+            title: Some(title.to_string()),
+            artist: Some(artist.to_string()),
+            album_artist: None,
+            album: Some(album.to_string()),
+            genre: genre.map(str::to_string),
+            started,
+            listened_ms: 60_000,
+            duration_ms: Some(60_000),
+            end,
+        }
+    }
+
+    fn history() -> History {
+        let mut history = History::default();
+        let played = ListenEnd::Played;
+        history.record(entry(JUNE_1 + 3 * HOUR, "B", "Y", "b1", None, played));
+        history.record(entry(JUNE_1, "A", "X", "a1", Some("Jazz"), played));
+        history.record(entry(JUNE_1 + HOUR, "A", "X", "a2", Some("Jazz"), played));
+        history.record(entry(
+            JUNE_1 + 2 * 24 * HOUR,
+            "B",
+            "Y",
+            "b1",
+            Some("Rock"),
+            ListenEnd::Skipped,
+        ));
+        history
+    }
+
+    #[test]
+    fn keeps_entries_in_order_of_start() {
+        let history = history();
+        let starts: Vec<u64> = history
+            .entries(TimeRange::default())
+            .iter()
+            .map(|entry| entry.started - JUNE_1)
+            .collect();
+        assert_eq!(starts, [0, HOUR, 3 * HOUR, 48 * HOUR]);
+
+        let range = TimeRange {
+            from: Some(JUNE_1 + HOUR),
+            to: Some(JUNE_1 + 3 * HOUR),
+        };
+        assert_eq!(history.entries(range).len(), 1);
+    }
+
+    #[test]
+    fn ranks_artists_albums_and_tracks() {
+        let history = history();
+        let names = |group| -> Vec<(String, u32)> {
+            history
+                .top(TimeRange::default(), group, 10)
+                .into_iter()
+                .map(|top| (top.name, top.plays))
+                .collect()
+        };
+        assert_eq!(
+            names(TopGroup::Artist),
+            [("A".to_string(), 2), ("B".to_string(), 1)]
+        );
+        assert_eq!(
+            names(TopGroup::Album),
+            [("X".to_string(), 2), ("Y".to_string(), 1)]
+        );
+
+        let tracks = history.top(TimeRange::default(), TopGroup::Track, 1);
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].name, "b1");
+        assert_eq!(tracks[0].artist.as_deref(), Some("B"));
+        assert_eq!(tracks[0].plays, 1);
+        assert_eq!(tracks[0].listened_ms, 120_000);
+    }
+
+    #[test]
+    fn totals_each_day_in_local_time() {
+        let history = history();
+        let days = history.daily(TimeRange::default(), 0);
+        let totals: Vec<(&str, u32, u64)> = days
+            .iter()
+            .map(|day| (day.date.as_str(), day.plays, day.listened_ms))
+            .collect();
+        assert_eq!(
+            totals,
+            [
+                ("2022-06-01", 3, 180_000),
+                ("2022-06-02", 0, 0),
+                ("2022-06-03", 0, 60_000)
+            ]
+        );
+
+        // Two hours behind UTC, the first two tracks played on the evening before.
+        let days = history.daily(TimeRange::default(), -120);
+        assert_eq!(days[0].date, "2022-05-31");
+        assert_eq!(days[0].plays, 2);
+    }
+
+    #[test]
+    fn breaks_listening_down_by_genre() {
+        let shares = history().genres(TimeRange::default());
+        let genres: Vec<(Option<&str>, f64)> = shares
+            .iter()
+            .map(|share| (share.genre.as_deref(), share.share))
+            .collect();
+        assert_eq!(
+            genres,
+            [(Some("Jazz"), 0.5), (None, 0.25), (Some("Rock"), 0.25)]
+        );
+    }
+
+    #[test]
+    fn exports_csv() {
+        let mut entry = entry(
+            JUNE_1 + 3723,
+            "A, B",
+            "X",
+            "Say \"hi\"",
+            None,
+            ListenEnd::Played,
+        );
+        entry.duration_ms = None;
+        let csv = to_csv(&[entry]);
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            "2022-06-01T01:02:03Z,\"A, B\",,X,\"Say \"\"hi\"\"\",,60000,,played,\
+             \"/music/A, B/X/Say \"\"hi\"\".flac\""
+        );
+        assert_eq!(lines[2], "");
+    }
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(19_144), (2022, 6, 1));
+    }
+
+    #[test]
+    fn appends_to_the_log_and_loads_it() {
+        let file = TempFile::new("jsonl");
+        assert!(History::load(file.path()).entries.is_empty());
+
+        let played = ListenEnd::Played;
+        let entries = [
+            entry(JUNE_1 + HOUR, "A", "X", "a1", None, played),
+            entry(JUNE_1, "B", "Y", "b1", Some("Rock"), ListenEnd::Skipped),
+            entry(JUNE_1 + 2 * HOUR, "C", "Z", "c1", None, played),
+        ];
+        let titles = || -> Vec<String> {
+            let history = History::load(file.path());
+            history
+                .entries
+                .into_iter()
+                .filter_map(|entry| entry.title)
+                .collect()
+        };
+        History::append(file.path(), &entries[..1]).unwrap();
+        History::append(file.path(), &entries[1..2]).unwrap();
+        assert_eq!(fs::read_to_string(file.path()).unwrap().lines().count(), 2);
+        assert_eq!(titles(), ["b1", "a1"]);
+
+        // A line cut short by a crash loses only its own entry.
+        let mut contents = fs::read_to_string(file.path()).unwrap();
+        contents.push_str("{\"path\":\"/music/C");
+        fs::write(file.path(), contents).unwrap();
+        History::append(file.path(), &entries[2..]).unwrap();
+        assert_eq!(titles(), ["b1", "a1", "c1"]);
+    }
+}
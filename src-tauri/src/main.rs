@@ -3,24 +3,225 @@
     windows_subsystem = "windows"
 )]
 
-mod player;
-mod track;
+mod bookmarks;
+mod history;
+mod mpd;
+#[cfg(target_os = "linux")]
+mod mpris;
+mod remote;
+mod scrobble;
+mod session;
+mod settings;
+mod stats;
+mod storage;
+#[cfg(test)]
+mod test_support;
 
-use crate::player::PlayerError;
+use crate::bookmarks::{Bookmark, Bookmarks};
+use crate::history::{DayTotal, GenreShare, History, HistoryEntry, TimeRange, TopEntry, TopGroup};
+use crate::library::{Library, LibraryTrack};
+use crate::mpd::Mpd;
+use crate::player::device::{self, OutputDeviceInfo};
+use crate::player::export::{ExportError, ExportEvent, ExportJob, ExportOptions};
+use crate::player::visualizer::{self, Visualizer};
+use crate::player::waveform::{self, WaveformBucket, WaveformError};
+use crate::player::{
+    ChannelOptions, Chapter, DitherMode, Listen, ListenEnd, OutputPath, PlayerError, Playlist,
+    RepeatMode, ResamplerQuality, ShuffleMode, SleepMode, SleepStatus, VOLUME_STEP,
+};
+use crate::remote::{Events, Remote};
+use crate::scrobble::{QueueStatus, Scrobble, Scrobbler, Service, ServiceInfo};
+use crate::session::Session;
+use crate::settings::Settings;
+use crate::stats::{Stats, TrackStats, MAX_RATING};
+use crate::storage::Persisted;
 use crate::track::Track;
 use anyhow::Result;
 use cocoa::appkit::{NSWindow, NSWindowStyleMask, NSWindowTitleVisibility};
+use plain_music_player::{library, player, track};
 use player::Player;
-use std::fs::File;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
 use std::io::BufReader;
-use std::path::Path;
-use std::sync::Mutex;
-use std::thread::sleep;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tauri::State;
-use tauri::{Manager, Runtime, Window};
+use tauri::{AppHandle, Manager, RunEvent, Runtime, Window};
 
-struct PlayerState(Mutex<Player>);
+/// The player, shared with the remote control interfaces.
+struct PlayerState(Arc<Mutex<Player>>);
+
+/// How often the output stream is checked for errors and default device changes.
+const OUTPUT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the end of the current track is checked for, to move on to the next one.
+const TRACK_END_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the session is saved while the app runs, in case it doesn't exit cleanly.
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+struct VisualizerState(Visualizer);
+
+#[cfg(target_os = "linux")]
+struct MprisState(mpris::Mpris);
+
+/// The library, shared with the remote control API.
+struct LibraryState(Arc<RwLock<Library>>);
+
+struct RemoteState {
+    events: Arc<Events>,
+    server: Mutex<Option<Remote>>,
+}
+
+struct MpdState(Mutex<Option<Mpd>>);
+
+/// The export running in the background, if any.
+struct ExportState(Mutex<Option<ExportJob>>);
+
+/// Settings of the remote control API, as reported to the UI.
+#[derive(Debug, Serialize)]
+struct RemoteControl {
+    enabled: bool,
+    /// Address the server listens on, or would listen on once enabled.
+    address: String,
+    token: Option<String>,
+}
+
+/// Sends a state event to the UI and to the remote control clients.
+fn emit<S: Serialize + Clone>(handle: &AppHandle, event: &str, payload: S) {
+    handle
+        .state::<RemoteState>()
+        .events
+        .publish(event, &payload);
+    let _ = handle.emit_all(event, payload);
+}
+
+/// Counts `listens` in the statistics, adds them to the history, and scrobbles them if enabled.
+fn record_listens(handle: &AppHandle, listens: &[Listen]) {
+    // Stopped listens count as neither plays nor skips.
+    let counted: Vec<&Listen> = listens
+        .iter()
+        .filter(|listen| listen.end != ListenEnd::Stopped)
+        .collect();
+    if !counted.is_empty() {
+        handle.state::<StatsState>().update(|stats| {
+            for listen in &counted {
+                stats.record(listen);
+            }
+        });
+        let paths: Vec<&Path> = counted.iter().map(|listen| listen.path.as_path()).collect();
+        emit(handle, "stats-changed", paths);
+    }
+
+    let scrobbling = handle.state::<SettingsState>().lock().scrobbling.is_some();
+    let mut entries = Vec::new();
+    // Tracks that were only cued, and never heard, aren't part of the history.
+    for listen in listens
+        .iter()
+        .filter(|listen| listen.listened > Duration::from_secs(0))
+    {
+        let fields = track::tag_fields(&listen.path);
+        if scrobbling && scrobble::is_scrobbled(listen) {
+            if let Some(scrobble) = Scrobble::new(&fields, listen.duration, listen.started) {
+                handle.state::<ScrobblerState>().0.scrobble(scrobble);
+            }
+        }
+        entries.push(HistoryEntry::new(listen, &fields));
+    }
+    if !entries.is_empty() {
+        handle.state::<HistoryState>().record(entries);
+        emit(handle, "history-changed", ());
+    }
+}
+
+/// Indexes the library folders in the background.
+fn scan_library(handle: AppHandle, folders: Vec<PathBuf>) {
+    thread::spawn(move || {
+        let library = Library::scan(&folders);
+        let count = library.tracks().len();
+        *handle.state::<LibraryState>().0.write().unwrap() = library;
+        if let Some(mpd) = &*handle.state::<MpdState>().0.lock().unwrap() {
+            mpd.library_changed();
+        }
+        emit(&handle, "library-changed", count);
+    });
+}
+
+/// Starts or stops the remote control API to match `settings`.
+fn apply_remote_control(handle: &AppHandle, settings: &Settings) -> Result<(), String> {
+    let state = handle.state::<RemoteState>();
+    let mut server = state.server.lock().unwrap();
+    // The old server must let go of its address before a new one binds to it.
+    *server = None;
+    let token = match (&settings.remote_token, settings.remote_enabled) {
+        (Some(token), true) => token.clone(),
+        _ => return Ok(()),
+    };
+
+    let address = settings
+        .remote_address
+        .as_deref()
+        .unwrap_or(remote::DEFAULT_ADDRESS);
+    let context = remote::Context {
+        player: handle.state::<PlayerState>().0.clone(),
+        library: handle.state::<LibraryState>().0.clone(),
+        events: state.events.clone(),
+        on_command: {
+            let handle = handle.clone();
+            Box::new(move || emit(&handle, "player-changed", ()))
+        },
+    };
+    *server = Some(Remote::start(address, token, context)?);
+    Ok(())
+}
+
+type SettingsState = Persisted<Settings>;
+type BookmarksState = Persisted<Bookmarks>;
+type StatsState = Persisted<Stats>;
+
+/// The thread submitting scrobbles.
+struct ScrobblerState(Scrobbler);
+
+/// The history, and the log it is appended to.
+struct HistoryState {
+    history: Mutex<History>,
+    path: Option<PathBuf>,
+}
+
+impl HistoryState {
+    fn lock(&self) -> MutexGuard<'_, History> {
+        self.history.lock().unwrap()
+    }
+
+    /// Adds `entries` to the history, and appends them to its log.
+    fn record(&self, entries: Vec<HistoryEntry>) {
+        let mut history = self.lock();
+        if let Some(path) = &self.path {
+            if let Err(err) = History::append(path, &entries) {
+                eprintln!("failed to save history: {}", err);
+            }
+        }
+        for entry in entries {
+            history.record(entry);
+        }
+    }
+}
+
+struct SessionState {
+    path: Option<PathBuf>,
+}
+
+impl SessionState {
+    /// Writes `session` to disk. Writing waits for the disk, so it is best done without holding
+    /// the player.
+    fn save(&self, session: &Session) {
+        if let Some(path) = &self.path {
+            if let Err(err) = session.save(path) {
+                eprintln!("failed to save session: {}", err);
+            }
+        }
+    }
+}
 
 pub trait WindowExt {
     #[cfg(target_os = "macos")]
@@ -94,21 +295,926 @@ fn seek_to(time: u64, player: State<PlayerState>) {
     player.0.lock().unwrap().seek_to(Duration::from_secs(time));
 }
 
+/// Sets the volume, from 0 to 100 on a perceptual scale.
+#[tauri::command]
+fn set_volume(volume: u16, player: State<PlayerState>) {
+    player.0.lock().unwrap().set_volume(volume);
+}
+
+#[tauri::command]
+fn volume_up(step: Option<u16>, player: State<PlayerState>) -> u16 {
+    player
+        .0
+        .lock()
+        .unwrap()
+        .volume_up(step.unwrap_or(VOLUME_STEP))
+}
+
+#[tauri::command]
+fn volume_down(step: Option<u16>, player: State<PlayerState>) -> u16 {
+    player
+        .0
+        .lock()
+        .unwrap()
+        .volume_down(step.unwrap_or(VOLUME_STEP))
+}
+
+#[tauri::command]
+fn mute(player: State<PlayerState>) {
+    player.0.lock().unwrap().mute();
+}
+
+#[tauri::command]
+fn unmute(player: State<PlayerState>) {
+    player.0.lock().unwrap().unmute();
+}
+
+/// Seeks `delta_ms` milliseconds forward, or backward if negative.
+#[tauri::command]
+fn seek_relative(delta_ms: i64, player: State<PlayerState>) {
+    player.0.lock().unwrap().seek_relative(delta_ms);
+}
+
+#[tauri::command]
+fn seek_forward(player: State<PlayerState>) {
+    player.0.lock().unwrap().seek_fw();
+}
+
+#[tauri::command]
+fn seek_backward(player: State<PlayerState>) {
+    player.0.lock().unwrap().seek_bw();
+}
+
+#[tauri::command]
+fn set_seek_step(step_ms: u64, player: State<PlayerState>, settings: State<SettingsState>) {
+    player
+        .0
+        .lock()
+        .unwrap()
+        .set_seek_step(Duration::from_millis(step_ms));
+    settings.update(|settings| settings.seek_step_ms = Some(step_ms));
+}
+
+#[tauri::command]
+fn set_skip_silence(enabled: bool, player: State<PlayerState>, settings: State<SettingsState>) {
+    player.0.lock().unwrap().set_skip_silence(enabled);
+    settings.update(|settings| settings.skip_silence = enabled);
+}
+
+/// Loops the current track between `a_ms` and `b_ms`, in milliseconds.
+#[tauri::command]
+fn set_loop(a_ms: u64, b_ms: u64, player: State<PlayerState>) -> bool {
+    player
+        .0
+        .lock()
+        .unwrap()
+        .set_loop(Duration::from_millis(a_ms), Duration::from_millis(b_ms))
+}
+
+#[tauri::command]
+fn clear_loop(player: State<PlayerState>) {
+    player.0.lock().unwrap().clear_loop();
+}
+
+/// Bookmarks the current position of the current track as `name`, and returns the bookmarks of
+/// the track.
+#[tauri::command]
+#[allow(clippy::cast_possible_truncation)]
+fn add_bookmark(
+    name: String,
+    player: State<PlayerState>,
+    bookmarks: State<BookmarksState>,
+) -> Vec<Bookmark> {
+    let player = player.0.lock().unwrap();
+    let path = match player.current_path() {
+        Some(path) => path,
+        None => return Vec::new(),
+    };
+    let bookmark = Bookmark {
+        name,
+        position_ms: player.elapsed().as_millis() as u64,
+    };
+    bookmarks.update(|bookmarks| {
+        bookmarks.add(path, bookmark);
+        bookmarks.list(path).to_vec()
+    })
+}
+
+/// Returns the path of `path`, or of the current track.
+fn track_path(path: Option<String>, player: &PlayerState) -> Option<PathBuf> {
+    path.map(PathBuf::from).or_else(|| {
+        player
+            .0
+            .lock()
+            .unwrap()
+            .current_path()
+            .map(Path::to_path_buf)
+    })
+}
+
+/// Returns the bookmarks of the track at `path`, or of the current track.
+#[tauri::command]
+fn get_bookmarks(
+    path: Option<String>,
+    player: State<PlayerState>,
+    bookmarks: State<BookmarksState>,
+) -> Vec<Bookmark> {
+    track_path(path, &player).map_or_else(Vec::new, |path| bookmarks.lock().list(&path).to_vec())
+}
+
+#[tauri::command]
+fn remove_bookmark(path: String, name: String, bookmarks: State<BookmarksState>) -> bool {
+    bookmarks.update(|bookmarks| bookmarks.remove(Path::new(&path), &name))
+}
+
+/// Seeks to the bookmark of the current track named `name`.
+#[tauri::command]
+fn go_to_bookmark(
+    name: String,
+    player: State<PlayerState>,
+    bookmarks: State<BookmarksState>,
+) -> bool {
+    let mut player = player.0.lock().unwrap();
+    let position = match player.current_path() {
+        Some(path) => bookmarks
+            .lock()
+            .find(path, &name)
+            .map(|bookmark| Duration::from_millis(bookmark.position_ms)),
+        None => None,
+    };
+    match position {
+        Some(position) => {
+            player.seek_to(position);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns the play and skip counts and the rating of the track at `path`, or of the current
+/// track.
+#[tauri::command]
+fn get_track_stats(
+    path: Option<String>,
+    player: State<PlayerState>,
+    stats: State<StatsState>,
+) -> TrackStats {
+    track_path(path, &player).map_or_else(TrackStats::default, |path| stats.lock().get(&path))
+}
+
+/// Rates the track at `path`, or the current track, in half stars from 0 to 10, or removes its
+/// rating if `None`. The rating is also written to the tags of the track if enabled in the
+/// settings.
+#[tauri::command]
+fn set_rating(
+    path: Option<String>,
+    rating: Option<u8>,
+    player: State<PlayerState>,
+    settings: State<SettingsState>,
+    stats: State<StatsState>,
+) -> Result<TrackStats, String> {
+    let path = track_path(path, &player).ok_or("no track is playing")?;
+    if rating.map_or(false, |rating| rating > MAX_RATING) {
+        return Err(format!("ratings range from 0 to {}", MAX_RATING));
+    }
+    let track_stats = stats.update(|stats| stats.set_rating(&path, rating));
+    if settings.lock().write_ratings_to_tags {
+        track::write_rating(&path, rating).map_err(|err| err.to_string())?;
+    }
+    Ok(track_stats)
+}
+
+/// Sets the fraction of a track, from 0.0 to 1.0, past which leaving it counts as a play.
+#[tauri::command]
+fn set_play_threshold(threshold: f64, player: State<PlayerState>, settings: State<SettingsState>) {
+    let mut player = player.0.lock().unwrap();
+    player.set_play_threshold(threshold);
+    let threshold = player.play_threshold();
+    settings.update(|settings| settings.play_threshold = Some(threshold));
+}
+
+#[tauri::command]
+fn set_write_ratings_to_tags(enabled: bool, settings: State<SettingsState>) {
+    settings.update(|settings| settings.write_ratings_to_tags = enabled);
+}
+
+/// Scrobbling settings and the state of the queue, as reported to the UI.
+#[derive(Debug, Serialize)]
+struct Scrobbling {
+    /// The service, without its secrets.
+    service: Option<ServiceInfo>,
+    #[serde(flatten)]
+    status: QueueStatus,
+}
+
+#[tauri::command]
+fn get_scrobbling(settings: State<SettingsState>, scrobbler: State<ScrobblerState>) -> Scrobbling {
+    Scrobbling {
+        service: settings.lock().scrobbling.as_ref().map(Service::info),
+        status: scrobbler.0.status(),
+    }
+}
+
+/// Scrobbles listens to `service` from now on, or stops scrobbling if `None`. Scrobbles still
+/// queued are submitted to the new service.
+#[tauri::command]
+fn set_scrobbling(
+    service: Option<Service>,
+    settings: State<SettingsState>,
+    scrobbler: State<ScrobblerState>,
+) {
+    scrobbler.0.set_service(service.clone());
+    settings.update(|settings| settings.scrobbling = service);
+}
+
+/// Logs in to the Last.fm compatible service set in the settings, and keeps the session to
+/// scrobble with.
+#[tauri::command]
+async fn login_last_fm(
+    username: String,
+    password: String,
+    settings: State<'_, SettingsState>,
+    scrobbler: State<'_, ScrobblerState>,
+) -> Result<(), String> {
+    let service = settings
+        .lock()
+        .scrobbling
+        .clone()
+        .ok_or("scrobbling is disabled")?;
+    let service = tauri::async_runtime::spawn_blocking(move || service.login(&username, &password))
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| err.to_string())?;
+    scrobbler.0.set_service(Some(service.clone()));
+    settings.update(|settings| settings.scrobbling = Some(service));
+    Ok(())
+}
+
+/// Submits the queued scrobbles now, without waiting for the next retry.
+#[tauri::command]
+fn submit_scrobbles(scrobbler: State<ScrobblerState>) {
+    scrobbler.0.submit();
+}
+
+/// Format of an export of the history.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum HistoryFormat {
+    Csv,
+    Json,
+}
+
+/// Returns the playbacks that started in `range`, latest first, at most `limit` of them.
+#[tauri::command]
+fn get_history(
+    range: Option<TimeRange>,
+    limit: Option<usize>,
+    history: State<HistoryState>,
+) -> Vec<HistoryEntry> {
+    let history = history.lock();
+    let entries = history.entries(range.unwrap_or_default());
+    entries
+        .iter()
+        .rev()
+        .take(limit.unwrap_or(entries.len()))
+        .cloned()
+        .collect()
+}
+
+/// Returns the `limit` artists, albums or tracks played most in `range`.
+#[tauri::command]
+fn get_top_listened(
+    group: TopGroup,
+    range: Option<TimeRange>,
+    limit: usize,
+    history: State<HistoryState>,
+) -> Vec<TopEntry> {
+    let history = history.lock();
+    history.top(range.unwrap_or_default(), group, limit)
+}
+
+/// Returns the time listened each day in `range`, in the time zone `utc_offset_minutes` ahead of
+/// UTC.
+#[tauri::command]
+fn get_daily_listening(
+    range: Option<TimeRange>,
+    utc_offset_minutes: i32,
+    history: State<HistoryState>,
+) -> Vec<DayTotal> {
+    let history = history.lock();
+    history.daily(range.unwrap_or_default(), utc_offset_minutes)
+}
+
+/// Returns the time listened to each genre in `range`.
+#[tauri::command]
+fn get_genre_breakdown(range: Option<TimeRange>, history: State<HistoryState>) -> Vec<GenreShare> {
+    let history = history.lock();
+    history.genres(range.unwrap_or_default())
+}
+
+/// Writes the playbacks that started in `range` to `path`, oldest first. Returns how many were
+/// written.
+#[tauri::command]
+fn export_history(
+    path: String,
+    format: HistoryFormat,
+    range: Option<TimeRange>,
+    history: State<HistoryState>,
+) -> Result<usize, String> {
+    let history = history.lock();
+    let entries = history.entries(range.unwrap_or_default());
+    let contents = match format {
+        HistoryFormat::Csv => history::to_csv(entries),
+        HistoryFormat::Json => {
+            serde_json::to_string_pretty(entries).map_err(|err| err.to_string())?
+        }
+    };
+    fs::write(&path, contents).map_err(|err| err.to_string())?;
+    Ok(entries.len())
+}
+
+/// Returns the chapters of the current track.
+#[tauri::command]
+fn get_chapters(player: State<PlayerState>) -> Vec<Chapter> {
+    player.0.lock().unwrap().chapters().to_vec()
+}
+
+#[tauri::command]
+fn next_chapter(player: State<PlayerState>) -> bool {
+    player.0.lock().unwrap().next_chapter()
+}
+
+#[tauri::command]
+fn previous_chapter(player: State<PlayerState>) -> bool {
+    player.0.lock().unwrap().previous_chapter()
+}
+
+/// Starts the sleep timer, which fades out and stops playback after a number of minutes, or at
+/// the end of the current track or of the playlist.
+#[tauri::command]
+fn set_sleep_timer(mode: SleepMode, player: State<PlayerState>) -> Option<SleepStatus> {
+    let mut player = player.0.lock().unwrap();
+    player.set_sleep_timer(mode);
+    player.sleep_timer()
+}
+
+#[tauri::command]
+fn cancel_sleep_timer(player: State<PlayerState>) {
+    player.0.lock().unwrap().cancel_sleep_timer();
+}
+
+#[tauri::command]
+fn get_sleep_timer(player: State<PlayerState>) -> Option<SleepStatus> {
+    player.0.lock().unwrap().sleep_timer()
+}
+
+/// Sets the duration of the fade out before the sleep timer stops playback, in milliseconds.
+#[tauri::command]
+fn set_sleep_fade(fade_ms: u64, player: State<PlayerState>, settings: State<SettingsState>) {
+    player
+        .0
+        .lock()
+        .unwrap()
+        .set_sleep_fade(Duration::from_millis(fade_ms));
+    settings.update(|settings| settings.sleep_fade_ms = Some(fade_ms));
+}
+
+#[tauri::command]
+fn get_playlist(player: State<PlayerState>) -> Playlist {
+    player.0.lock().unwrap().playlist().clone()
+}
+
+#[tauri::command]
+fn set_playlist(paths: Vec<String>, index: Option<usize>, player: State<PlayerState>) {
+    let tracks = paths.into_iter().map(PathBuf::from).collect();
+    player.0.lock().unwrap().set_playlist(tracks, index);
+}
+
+#[tauri::command]
+fn enqueue(path: String, player: State<PlayerState>) {
+    player.0.lock().unwrap().enqueue(PathBuf::from(path));
+}
+
+#[tauri::command]
+fn dequeue(index: usize, player: State<PlayerState>) {
+    player.0.lock().unwrap().dequeue(index);
+}
+
+#[tauri::command]
+fn play_index(index: usize, player: State<PlayerState>) -> bool {
+    player.0.lock().unwrap().play_index(index)
+}
+
+#[tauri::command]
+fn next_track(player: State<PlayerState>) -> bool {
+    player.0.lock().unwrap().next()
+}
+
+#[tauri::command]
+fn previous_track(player: State<PlayerState>) -> bool {
+    player.0.lock().unwrap().previous()
+}
+
+#[tauri::command]
+fn set_repeat(mode: RepeatMode, player: State<PlayerState>) {
+    player.0.lock().unwrap().set_repeat(mode);
+}
+
+/// Shuffles the playlist with `mode`. Without a `seed`, a new order is drawn.
+#[tauri::command]
+fn set_shuffle(mode: ShuffleMode, seed: Option<u64>, player: State<PlayerState>) -> Playlist {
+    let seed = seed.unwrap_or_else(new_shuffle_seed);
+    let mut player = player.0.lock().unwrap();
+    player.set_shuffle(mode, seed);
+    player.playlist().clone()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn new_shuffle_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64)
+}
+
 #[tauri::command]
 fn get_progress(player: State<PlayerState>) -> Result<(f64, i64, i64), PlayerError> {
     player.0.lock().unwrap().get_progress()
 }
 
+#[tauri::command]
+fn set_visualizer_fps(fps: u32, visualizer: State<VisualizerState>) {
+    visualizer.0.set_fps(fps);
+}
+
+#[tauri::command]
+async fn get_waveform(
+    path: String,
+    buckets: usize,
+    app: AppHandle,
+) -> Result<Vec<WaveformBucket>, WaveformError> {
+    let cache_dir = app
+        .path_resolver()
+        .app_dir()
+        .map(|dir| dir.join("waveforms"));
+    // Decoding a whole file takes a while, so it is kept away from the command threads.
+    tauri::async_runtime::spawn_blocking(move || {
+        waveform::get_waveform(Path::new(&path), buckets, cache_dir.as_deref())
+    })
+    .await
+    .map_err(|err| WaveformError::IoError(err.to_string()))?
+}
+
+#[tauri::command]
+fn list_output_devices() -> Result<Vec<OutputDeviceInfo>, PlayerError> {
+    Ok(device::list_output_devices()?)
+}
+
+#[tauri::command]
+fn get_output_device(player: State<PlayerState>) -> Option<String> {
+    player.0.lock().unwrap().output_device().map(str::to_string)
+}
+
+#[tauri::command]
+fn has_output_device(player: State<PlayerState>) -> bool {
+    player.0.lock().unwrap().has_output_device()
+}
+
+#[tauri::command]
+fn set_output_device(
+    name: Option<String>,
+    player: State<PlayerState>,
+    settings: State<SettingsState>,
+) -> Result<(), PlayerError> {
+    player
+        .0
+        .lock()
+        .unwrap()
+        .set_output_device(name.as_deref())?;
+    settings.update(|settings| settings.output_device = name);
+    Ok(())
+}
+
+/// Enables or disables bit-perfect playback. Returns how the current track reaches the device,
+/// including what keeps it from reaching it unaltered, if anything.
+#[tauri::command]
+fn set_bit_perfect(
+    enabled: bool,
+    player: State<PlayerState>,
+    settings: State<SettingsState>,
+) -> Result<OutputPath, PlayerError> {
+    let mut player = player.0.lock().unwrap();
+    player.set_bit_perfect(enabled)?;
+    settings.update(|settings| settings.bit_perfect = enabled);
+    Ok(player.output_path())
+}
+
+#[tauri::command]
+fn set_resampler_quality(
+    quality: ResamplerQuality,
+    player: State<PlayerState>,
+    settings: State<SettingsState>,
+) {
+    player.0.lock().unwrap().set_resampler_quality(quality);
+    settings.update(|settings| settings.resampler_quality = quality);
+}
+
+#[tauri::command]
+fn set_mono_output(enabled: bool, player: State<PlayerState>, settings: State<SettingsState>) {
+    let mut player = player.0.lock().unwrap();
+    let options = player.channel_options();
+    player.set_channel_options(ChannelOptions {
+        mono: enabled,
+        ..options
+    });
+    settings.update(|settings| settings.mono_output = enabled);
+}
+
+#[tauri::command]
+fn set_swap_channels(enabled: bool, player: State<PlayerState>, settings: State<SettingsState>) {
+    let mut player = player.0.lock().unwrap();
+    let options = player.channel_options();
+    player.set_channel_options(ChannelOptions {
+        swap_left_right: enabled,
+        ..options
+    });
+    settings.update(|settings| settings.swap_channels = enabled);
+}
+
+#[tauri::command]
+fn set_dither(
+    mode: DitherMode,
+    noise_shaping: bool,
+    player: State<PlayerState>,
+    settings: State<SettingsState>,
+) {
+    player.0.lock().unwrap().set_dither(mode, noise_shaping);
+    settings.update(|settings| {
+        settings.dither = mode;
+        settings.noise_shaping = noise_shaping;
+    });
+}
+
+#[tauri::command]
+fn get_output_path(player: State<PlayerState>) -> OutputPath {
+    player.0.lock().unwrap().output_path()
+}
+
+/// Sets the folders indexed in the library, and indexes them again.
+#[tauri::command]
+fn set_library_folders(folders: Vec<String>, handle: AppHandle, settings: State<SettingsState>) {
+    let folders: Vec<PathBuf> = folders.into_iter().map(PathBuf::from).collect();
+    settings.update(|settings| settings.library_folders = folders.clone());
+    scan_library(handle, folders);
+}
+
+/// Exports `paths`, or the playlist if none are given, to a folder. The progress is sent as
+/// `export-progress` events. An export still running is cancelled.
+///
+/// FLAC is encoded by the player itself, but MP3, Ogg Vorbis and Opus need `lame`, `oggenc` and
+/// `opusenc` on the `PATH`. The export fails with `EncoderNotFound` when one is missing.
+#[tauri::command]
+fn export_tracks(
+    paths: Option<Vec<String>>,
+    options: ExportOptions,
+    handle: AppHandle,
+    player: State<PlayerState>,
+    export: State<ExportState>,
+) -> Result<(), ExportError> {
+    let tracks = match paths {
+        Some(paths) => paths.into_iter().map(PathBuf::from).collect(),
+        None => player.0.lock().unwrap().playlist().tracks().to_vec(),
+    };
+    let mut job = export.0.lock().unwrap();
+    if let Some(job) = job.take() {
+        job.cancel();
+    }
+    let on_event = Box::new(move |event: ExportEvent| emit(&handle, "export-progress", event));
+    *job = Some(ExportJob::start(tracks, options, on_event)?);
+    Ok(())
+}
+
+#[tauri::command]
+fn cancel_export(export: State<ExportState>) {
+    if let Some(job) = export.0.lock().unwrap().take() {
+        job.cancel();
+    }
+}
+
+/// Returns the tracks of the library whose tags or file name contain every word of `query`.
+#[tauri::command]
+fn search_library(query: String, library: State<LibraryState>) -> Vec<LibraryTrack> {
+    library
+        .0
+        .read()
+        .unwrap()
+        .search(&query)
+        .into_iter()
+        .cloned()
+        .collect()
+}
+
+#[tauri::command]
+fn get_remote_control(settings: State<SettingsState>) -> RemoteControl {
+    remote_control(&settings.lock())
+}
+
+fn remote_control(settings: &Settings) -> RemoteControl {
+    RemoteControl {
+        enabled: settings.remote_enabled,
+        address: settings
+            .remote_address
+            .clone()
+            .unwrap_or_else(|| remote::DEFAULT_ADDRESS.to_string()),
+        token: settings.remote_token.clone(),
+    }
+}
+
+/// Enables or disables the remote control API, listening on `address` or on the default
+/// address. A token is created the first time it is enabled.
+#[tauri::command]
+fn set_remote_control(
+    enabled: bool,
+    address: Option<String>,
+    handle: AppHandle,
+    settings: State<SettingsState>,
+) -> Result<RemoteControl, String> {
+    let token = remote::new_token()?;
+    let mut updated = None;
+    settings.update(|settings| {
+        settings.remote_enabled = enabled;
+        settings.remote_address = address;
+        if enabled && settings.remote_token.is_none() {
+            settings.remote_token = Some(token);
+        }
+        updated = Some(settings.clone());
+    });
+    let settings = updated.unwrap_or_default();
+    apply_remote_control(&handle, &settings)?;
+    Ok(remote_control(&settings))
+}
+
+/// Replaces the token of the remote control API, so that the clients given the old one are
+/// locked out.
+#[tauri::command]
+fn reset_remote_token(
+    handle: AppHandle,
+    settings: State<SettingsState>,
+) -> Result<RemoteControl, String> {
+    let token = remote::new_token()?;
+    let mut updated = None;
+    settings.update(|settings| {
+        settings.remote_token = Some(token);
+        updated = Some(settings.clone());
+    });
+    let settings = updated.unwrap_or_default();
+    apply_remote_control(&handle, &settings)?;
+    Ok(remote_control(&settings))
+}
+
+/// Settings of the MPD protocol server, as reported to the UI.
+#[derive(Debug, Serialize)]
+struct MpdServer {
+    enabled: bool,
+    /// Address the server listens on, or would listen on once enabled.
+    address: String,
+    /// Whether clients must give a password. The password itself is never sent to the UI.
+    has_password: bool,
+}
+
+/// Starts or stops the MPD protocol server to match `settings`.
+fn apply_mpd_server(handle: &AppHandle, settings: &Settings) -> Result<(), String> {
+    let state = handle.state::<MpdState>();
+    let mut server = state.0.lock().unwrap();
+    // The old server must let go of its address before a new one binds to it.
+    *server = None;
+    if !settings.mpd_enabled {
+        return Ok(());
+    }
+
+    let address = settings
+        .mpd_address
+        .as_deref()
+        .unwrap_or(mpd::DEFAULT_ADDRESS);
+    let context = mpd::Context {
+        player: handle.state::<PlayerState>().0.clone(),
+        library: handle.state::<LibraryState>().0.clone(),
+        password: settings.mpd_password.clone(),
+        on_command: {
+            let handle = handle.clone();
+            Box::new(move || emit(&handle, "player-changed", ()))
+        },
+    };
+    *server = Some(Mpd::start(address, context)?);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_mpd_server(settings: State<SettingsState>) -> MpdServer {
+    mpd_server(&settings.lock())
+}
+
+fn mpd_server(settings: &Settings) -> MpdServer {
+    MpdServer {
+        enabled: settings.mpd_enabled,
+        address: settings
+            .mpd_address
+            .clone()
+            .unwrap_or_else(|| mpd::DEFAULT_ADDRESS.to_string()),
+        has_password: settings.mpd_password.is_some(),
+    }
+}
+
+/// Enables or disables the MPD protocol server, listening on `address` or on the default
+/// address. The password is kept if `password` is `None`, and removed if it is empty. Without a
+/// password, every client that can connect is let in.
+#[tauri::command]
+fn set_mpd_server(
+    enabled: bool,
+    address: Option<String>,
+    password: Option<String>,
+    handle: AppHandle,
+    settings: State<SettingsState>,
+) -> Result<MpdServer, String> {
+    let mut updated = None;
+    settings.update(|settings| {
+        settings.mpd_enabled = enabled;
+        settings.mpd_address = address;
+        if let Some(password) = password {
+            settings.mpd_password = Some(password).filter(|password| !password.is_empty());
+        }
+        updated = Some(settings.clone());
+    });
+    let settings = updated.unwrap_or_default();
+    apply_mpd_server(&handle, &settings)?;
+    Ok(mpd_server(&settings))
+}
+
 #[tauri::command]
 fn read_track_from_path(path: String) -> Track {
     Track::read_from_path(path).unwrap()
 }
 
 fn main() {
+    let context = tauri::generate_context!();
+    let app_dir = tauri::api::path::app_dir(context.config());
+    let settings_path = app_dir.as_deref().map(Settings::path);
+    let session_path = app_dir.as_deref().map(Session::path);
+    let bookmarks_path = app_dir.as_deref().map(Bookmarks::path);
+    let stats_path = app_dir.as_deref().map(Stats::path);
+    let history_path = app_dir.as_deref().map(History::path);
+    let scrobble_queue_path = app_dir.as_deref().map(scrobble::Queue::path);
+    let settings = settings_path
+        .as_deref()
+        .map(Settings::load)
+        .unwrap_or_default();
+
+    let mut player = Player::new();
+    if let Some(name) = settings.output_device.as_deref() {
+        // The device may have been unplugged since the last run.
+        if let Err(err) = player.set_output_device(Some(name)) {
+            eprintln!("failed to open output device {}: {}", name, err);
+        }
+    }
+    if let Err(err) = player.set_bit_perfect(settings.bit_perfect) {
+        eprintln!("failed to enable bit-perfect playback: {}", err);
+    }
+    player.set_resampler_quality(settings.resampler_quality);
+    player.set_channel_options(ChannelOptions {
+        mono: settings.mono_output,
+        swap_left_right: settings.swap_channels,
+    });
+    player.set_dither(settings.dither, settings.noise_shaping);
+    if let Some(step_ms) = settings.seek_step_ms {
+        player.set_seek_step(Duration::from_millis(step_ms));
+    }
+    player.set_skip_silence(settings.skip_silence);
+    if let Some(fade_ms) = settings.sleep_fade_ms {
+        player.set_sleep_fade(Duration::from_millis(fade_ms));
+    }
+    if let Some(threshold) = settings.play_threshold {
+        player.set_play_threshold(threshold);
+    }
+    if let Some(path) = session_path.as_deref() {
+        Session::load(path).restore(&mut player);
+    }
+    let scrobbler = Scrobbler::spawn(settings.scrobbling.clone(), scrobble_queue_path);
+
     tauri::Builder::default()
         .setup(|app| {
             let main_window = app.get_window("main").unwrap();
             main_window.set_transparent_titlebar(true, false);
+
+            let handle = app.handle();
+            let tap = app.state::<PlayerState>().0.lock().unwrap().tap_buffer();
+            let visualizer = Visualizer::spawn(
+                tap,
+                visualizer::DEFAULT_FPS,
+                visualizer::DEFAULT_BAND_COUNT,
+                move |frame| {
+                    let _ = handle.emit_all("visualizer", frame);
+                },
+            );
+            app.manage(VisualizerState(visualizer));
+
+            let settings = app.state::<SettingsState>().lock().clone();
+            scan_library(app.handle(), settings.library_folders.clone());
+            if let Err(err) = apply_remote_control(&app.handle(), &settings) {
+                eprintln!("failed to start remote control API: {}", err);
+            }
+            if let Err(err) = apply_mpd_server(&app.handle(), &settings) {
+                eprintln!("failed to start MPD server: {}", err);
+            }
+
+            #[cfg(target_os = "linux")]
+            {
+                let handle = app.handle();
+                let player = app.state::<PlayerState>().0.clone();
+                // Media keys and desktop widgets are optional, so the app runs without a bus.
+                match mpris::Mpris::spawn(player, move || {
+                    emit(&handle, "player-changed", ());
+                }) {
+                    Ok(mpris) => {
+                        app.manage(MprisState(mpris));
+                    }
+                    Err(err) => eprintln!("failed to start MPRIS interface: {}", err),
+                }
+            }
+
+            let handle = app.handle();
+            thread::spawn(move || loop {
+                sleep(OUTPUT_CHECK_INTERVAL);
+                let state = handle.state::<PlayerState>();
+                let mut player = state.0.lock().unwrap();
+                if player.check_output() {
+                    emit(&handle, "output-changed", player.has_output_device());
+                }
+            });
+
+            let handle = app.handle();
+            thread::spawn(move || {
+                let mut last_save = Instant::now();
+                let mut last_sleep_report = None;
+                // Start of the listen last announced as playing now.
+                let mut last_now_playing = None;
+                loop {
+                    sleep(TRACK_END_CHECK_INTERVAL);
+                    let state = handle.state::<PlayerState>();
+                    let mut player = state.0.lock().unwrap();
+                    let changed = player.poll_track_end();
+                    if changed {
+                        emit(&handle, "track-changed", player.playlist().current_index());
+                    }
+                    if player.poll_sleep_timer() {
+                        emit(&handle, "sleep-timer-expired", ());
+                    }
+                    let listens = player.poll_listens();
+                    // Listens are announced once they are heard, not while paused.
+                    let listen_started = player.listen_started();
+                    let mut now_playing = None;
+                    if listen_started != last_now_playing && !player.is_paused() {
+                        last_now_playing = listen_started;
+                        if let (Some(started), Some(path)) = (listen_started, player.current_path())
+                        {
+                            let duration = player.duration().map(Duration::from_secs_f64);
+                            now_playing = Some((path.to_path_buf(), duration, started));
+                        }
+                    }
+                    // The time left is reported once per second, rounded up.
+                    let status = player.sleep_timer();
+                    let report = status.map(|status| {
+                        (
+                            status.mode,
+                            status.remaining_ms.map(|ms| (ms + 999) / 1000),
+                            status.tracks_left,
+                        )
+                    });
+                    if report != last_sleep_report {
+                        emit(&handle, "sleep-timer", status);
+                        last_sleep_report = report;
+                    }
+                    let session = if changed || last_save.elapsed() >= SESSION_SAVE_INTERVAL {
+                        last_save = Instant::now();
+                        Some(Session::capture(&player))
+                    } else {
+                        None
+                    };
+                    // The session is written and the tags of the tracks are read without
+                    // holding up the player.
+                    drop(player);
+                    if let Some(session) = session {
+                        handle.state::<SessionState>().save(&session);
+                    }
+                    if !listens.is_empty() {
+                        record_listens(&handle, &listens);
+                    }
+                    if let Some((path, duration, started)) = now_playing {
+                        let fields = track::tag_fields(&path);
+                        if let Some(scrobble) = Scrobble::new(&fields, duration, started) {
+                            handle.state::<ScrobblerState>().0.now_playing(scrobble);
+                        }
+                    }
+                }
+            });
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -118,10 +1224,113 @@ fn main() {
             is_paused,
             stop,
             seek_to,
+            seek_relative,
+            seek_forward,
+            seek_backward,
+            set_seek_step,
+            set_skip_silence,
             get_progress,
+            set_loop,
+            clear_loop,
+            add_bookmark,
+            get_bookmarks,
+            remove_bookmark,
+            go_to_bookmark,
+            get_track_stats,
+            set_rating,
+            set_play_threshold,
+            set_write_ratings_to_tags,
+            get_scrobbling,
+            set_scrobbling,
+            login_last_fm,
+            submit_scrobbles,
+            get_history,
+            get_top_listened,
+            get_daily_listening,
+            get_genre_breakdown,
+            export_history,
+            get_chapters,
+            next_chapter,
+            previous_chapter,
+            set_sleep_timer,
+            cancel_sleep_timer,
+            get_sleep_timer,
+            set_sleep_fade,
+            set_volume,
+            volume_up,
+            volume_down,
+            mute,
+            unmute,
+            get_playlist,
+            set_playlist,
+            enqueue,
+            dequeue,
+            play_index,
+            next_track,
+            previous_track,
+            set_repeat,
+            set_shuffle,
+            set_visualizer_fps,
+            get_waveform,
+            list_output_devices,
+            get_output_device,
+            has_output_device,
+            set_output_device,
+            set_bit_perfect,
+            set_resampler_quality,
+            set_mono_output,
+            set_swap_channels,
+            set_dither,
+            get_output_path,
+            set_library_folders,
+            search_library,
+            export_tracks,
+            cancel_export,
+            get_remote_control,
+            set_remote_control,
+            reset_remote_token,
+            get_mpd_server,
+            set_mpd_server,
             read_track_from_path
         ])
-        .manage(PlayerState(Mutex::new(Player::new())))
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .manage(PlayerState(Arc::new(Mutex::new(player))))
+        .manage(SettingsState::new(settings, settings_path))
+        .manage(SessionState { path: session_path })
+        .manage(LibraryState(Arc::new(RwLock::new(Library::default()))))
+        .manage(RemoteState {
+            events: Arc::new(Events::default()),
+            server: Mutex::new(None),
+        })
+        .manage(MpdState(Mutex::new(None)))
+        .manage(ExportState(Mutex::new(None)))
+        .manage(BookmarksState::new(
+            bookmarks_path
+                .as_deref()
+                .map(Bookmarks::load)
+                .unwrap_or_default(),
+            bookmarks_path,
+        ))
+        .manage(StatsState::new(
+            stats_path.as_deref().map(Stats::load).unwrap_or_default(),
+            stats_path,
+        ))
+        .manage(HistoryState {
+            history: Mutex::new(
+                history_path
+                    .as_deref()
+                    .map(History::load)
+                    .unwrap_or_default(),
+            ),
+            path: history_path,
+        })
+        .manage(ScrobblerState(scrobbler))
+        .build(context)
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let RunEvent::Exit = event {
+                let player = app.state::<PlayerState>();
+                let session = Session::capture(&player.0.lock().unwrap());
+                app.state::<SessionState>().save(&session);
+            }
+        });
 }
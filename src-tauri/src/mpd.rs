@@ -0,0 +1,1868 @@
+//! Server speaking a subset of the MPD protocol, so that MPD clients such as ncmpcpp or
+//! M.A.L.P. can drive the player.
+//!
+//! The playlist of the player is the MPD queue. Its entries have no ids of their own, so the id
+//! of a song is its position. URIs are paths relative to the library folders, or absolute paths
+//! for files outside them. Changes, whether made through MPD or in the app, are noticed by
+//! polling the player, and reported to the clients waiting in `idle`.
+
+use crate::library::{Library, LibraryTrack};
+use crate::player::{Player, RepeatMode, ShuffleMode, MAX_VOLUME};
+use std::collections::BTreeSet;
+use std::fmt::{Display, Write as _};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Address the server binds to unless another is configured: the port MPD uses, on this machine
+/// only.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:6600";
+/// Version of the protocol announced to clients. Commands of later versions are answered as
+/// unknown, which clients handle.
+const PROTOCOL_VERSION: &str = "0.21.0";
+/// How often the player is checked for changes, and the listener for new connections.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Smallest jump of the position taken for a seek rather than for the jitter of polling.
+const SEEK_TOLERANCE: Duration = Duration::from_secs(1);
+/// Longest command line read. Longer lines close the connection.
+const MAX_LINE_LENGTH: u64 = 64 * 1024;
+/// Deepest nesting of parentheses in a filter expression, which keeps a single line from
+/// overflowing the stack of the parser.
+const MAX_FILTER_DEPTH: usize = 32;
+
+const ACK_ERROR_ARG: u32 = 2;
+const ACK_ERROR_PASSWORD: u32 = 3;
+const ACK_ERROR_PERMISSION: u32 = 4;
+const ACK_ERROR_UNKNOWN: u32 = 5;
+const ACK_ERROR_NO_EXIST: u32 = 50;
+const ACK_ERROR_PLAYER_SYNC: u32 = 55;
+
+/// Commands answered by `commands`.
+const COMMANDS: &[&str] = &[
+    "add",
+    "addid",
+    "clear",
+    "close",
+    "command_list_begin",
+    "command_list_end",
+    "command_list_ok_begin",
+    "commands",
+    "consume",
+    "currentsong",
+    "decoders",
+    "delete",
+    "deleteid",
+    "find",
+    "findadd",
+    "getvol",
+    "idle",
+    "list",
+    "lsinfo",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "password",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistid",
+    "playlistinfo",
+    "plchanges",
+    "plchangesposid",
+    "previous",
+    "random",
+    "repeat",
+    "search",
+    "searchadd",
+    "seek",
+    "seekcur",
+    "seekid",
+    "setvol",
+    "single",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "urlhandlers",
+    "volume",
+];
+
+/// Commands allowed before the password was sent.
+const UNPROTECTED_COMMANDS: &[&str] = &["close", "commands", "notcommands", "password", "ping"];
+
+/// Everything the clients work with.
+pub struct Context {
+    pub player: Arc<Mutex<Player>>,
+    pub library: Arc<RwLock<Library>>,
+    /// Password the clients must send before other commands. `None` lets every client in.
+    pub password: Option<String>,
+    /// Called after each command that changed the player, so that the UI can catch up.
+    pub on_command: Box<dyn Fn() + Send + Sync>,
+}
+
+/// Handle to the running server. The server stops, and closes its connections, when this is
+/// dropped.
+pub struct Mpd {
+    shared: Arc<Shared>,
+    address: SocketAddr,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Mpd {
+    /// Starts serving the MPD protocol on `address`.
+    pub fn start(address: &str, context: Context) -> Result<Self, String> {
+        let listener = TcpListener::bind(address).map_err(|err| err.to_string())?;
+        let address = listener.local_addr().map_err(|err| err.to_string())?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|err| err.to_string())?;
+
+        let snapshot = Snapshot::of(&context.player.lock().unwrap());
+        let shared = Arc::new(Shared {
+            context,
+            clients: Mutex::new(Vec::new()),
+            watched: Mutex::new(Watched {
+                snapshot,
+                checked_at: Instant::now(),
+                playlist_version: 1,
+            }),
+            started: Instant::now(),
+        });
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread = {
+            let shared = shared.clone();
+            let running = running.clone();
+            thread::spawn(move || listen(&listener, &shared, &running))
+        };
+        Ok(Self {
+            shared,
+            address,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the address the server listens on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Tells the clients that the library was indexed again.
+    pub fn library_changed(&self) {
+        self.shared.notify(Subsystem::Database);
+    }
+}
+
+impl Drop for Mpd {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        for client in self.shared.clients.lock().unwrap().drain(..) {
+            let _ = client.send(Input::Closed);
+        }
+    }
+}
+
+/// Parts of the state whose changes clients can wait for with `idle`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Subsystem {
+    Database,
+    Playlist,
+    Player,
+    Mixer,
+    Options,
+}
+
+impl Subsystem {
+    const ALL: [Self; 5] = [
+        Self::Database,
+        Self::Playlist,
+        Self::Player,
+        Self::Mixer,
+        Self::Options,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Database => "database",
+            Self::Playlist => "playlist",
+            Self::Player => "player",
+            Self::Mixer => "mixer",
+            Self::Options => "options",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|subsystem| subsystem.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// State of the player as MPD reports it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Play,
+    Pause,
+    Stop,
+}
+
+impl State {
+    fn of(player: &Player) -> Self {
+        if player.is_stopped() {
+            Self::Stop
+        } else if player.is_paused() {
+            Self::Pause
+        } else {
+            Self::Play
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Play => "play",
+            Self::Pause => "pause",
+            Self::Stop => "stop",
+        }
+    }
+}
+
+/// What the clients are told about when it changes.
+#[derive(Clone, Debug, PartialEq)]
+struct Snapshot {
+    state: State,
+    index: Option<usize>,
+    position: Duration,
+    speed: f32,
+    volume: u16,
+    muted: bool,
+    repeat: RepeatMode,
+    shuffle: ShuffleMode,
+    playlist: Vec<PathBuf>,
+}
+
+impl Snapshot {
+    fn of(player: &Player) -> Self {
+        Self {
+            state: State::of(player),
+            index: player.playlist().current_index(),
+            position: player.elapsed(),
+            speed: player.speed,
+            volume: player.volume,
+            muted: player.is_muted(),
+            repeat: player.repeat(),
+            shuffle: player.shuffle_mode(),
+            playlist: player.playlist().tracks().to_vec(),
+        }
+    }
+
+    /// Returns the subsystems that changed since `last`, which was taken `elapsed` ago.
+    fn changes(&self, last: &Self, elapsed: Duration) -> Vec<Subsystem> {
+        let mut changes = Vec::new();
+        if self.playlist != last.playlist {
+            changes.push(Subsystem::Playlist);
+        }
+        if self.state != last.state || self.index != last.index || self.is_seek(last, elapsed) {
+            changes.push(Subsystem::Player);
+        }
+        if self.volume != last.volume || self.muted != last.muted {
+            changes.push(Subsystem::Mixer);
+        }
+        if self.repeat != last.repeat || self.shuffle != last.shuffle {
+            changes.push(Subsystem::Options);
+        }
+        changes
+    }
+
+    /// Returns `true` if the position jumped since `last`, which was taken `elapsed` ago.
+    fn is_seek(&self, last: &Self, elapsed: Duration) -> bool {
+        let distance = |a: Duration, b: Duration| if a > b { a - b } else { b - a };
+        let expected = if self.state == State::Play {
+            last.position + elapsed.mul_f32(self.speed)
+        } else {
+            last.position
+        };
+        distance(self.position, expected) > SEEK_TOLERANCE
+            && distance(self.position, last.position) > SEEK_TOLERANCE
+    }
+}
+
+struct Watched {
+    snapshot: Snapshot,
+    checked_at: Instant,
+    /// Version of the playlist reported to clients, bumped on each change.
+    playlist_version: u32,
+}
+
+/// State shared by the listener and the connections.
+struct Shared {
+    context: Context,
+    clients: Mutex<Vec<Sender<Input>>>,
+    watched: Mutex<Watched>,
+    started: Instant,
+}
+
+impl Shared {
+    /// Compares the player with its last snapshot, and tells the clients what changed. Returns
+    /// `true` if anything did.
+    fn check(&self) -> bool {
+        let snapshot = Snapshot::of(&self.context.player.lock().unwrap());
+        let changes = {
+            let mut watched = self.watched.lock().unwrap();
+            let now = Instant::now();
+            let changes = snapshot.changes(&watched.snapshot, now - watched.checked_at);
+            if changes.contains(&Subsystem::Playlist) {
+                watched.playlist_version = watched.playlist_version.wrapping_add(1);
+            }
+            watched.snapshot = snapshot;
+            watched.checked_at = now;
+            changes
+        };
+        for &subsystem in &changes {
+            self.notify(subsystem);
+        }
+        !changes.is_empty()
+    }
+
+    fn notify(&self, subsystem: Subsystem) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.send(Input::Changed(subsystem)).is_ok());
+    }
+
+    fn playlist_version(&self) -> u32 {
+        self.watched.lock().unwrap().playlist_version
+    }
+}
+
+/// Accepts connections and watches the player until `running` is cleared.
+fn listen(listener: &TcpListener, shared: &Arc<Shared>, running: &AtomicBool) {
+    while running.load(Ordering::Relaxed) {
+        // Fails once there is no pending connection left.
+        while let Ok((stream, _)) = listener.accept() {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                // Fails only if the client went away.
+                let _ = Client::serve(stream, shared);
+            });
+        }
+        shared.check();
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// What a connection reacts to.
+enum Input {
+    Line(String),
+    Changed(Subsystem),
+    Closed,
+}
+
+/// Error answered to a command.
+#[derive(Debug)]
+struct Ack {
+    code: u32,
+    message: String,
+}
+
+impl Ack {
+    fn new(code: u32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn arg(message: impl Into<String>) -> Self {
+        Self::new(ACK_ERROR_ARG, message)
+    }
+
+    fn no_such_song() -> Self {
+        Self::new(ACK_ERROR_NO_EXIST, "No such song")
+    }
+
+    /// Formats the error for the command `command`, at `index` in a command list.
+    fn render(&self, index: usize, command: &str) -> String {
+        format!(
+            "ACK [{}@{}] {{{}}} {}\n",
+            self.code, index, command, self.message
+        )
+    }
+}
+
+/// Commands collected between `command_list_begin` and `command_list_end`.
+struct CommandList {
+    /// Whether each successful command is followed by `list_OK`.
+    ok: bool,
+    lines: Vec<String>,
+}
+
+/// A connected client.
+struct Client {
+    stream: TcpStream,
+    shared: Arc<Shared>,
+    inputs: Receiver<Input>,
+    /// Subsystems that changed since the last `idle` reported them.
+    pending: BTreeSet<Subsystem>,
+    authorized: bool,
+}
+
+impl Client {
+    fn serve(stream: TcpStream, shared: Arc<Shared>) -> io::Result<()> {
+        // Accepted sockets inherit the non-blocking mode of the listener on some systems.
+        stream.set_nonblocking(false)?;
+        let (sender, inputs) = mpsc::channel();
+        {
+            let reader = stream.try_clone()?;
+            let sender = sender.clone();
+            thread::spawn(move || read_lines(reader, &sender));
+        }
+        shared.clients.lock().unwrap().push(sender);
+
+        let authorized = shared.context.password.is_none();
+        let mut client = Self {
+            stream,
+            shared,
+            inputs,
+            pending: BTreeSet::new(),
+            authorized,
+        };
+        let result = client.run();
+        // Ends the thread reading the lines.
+        let _ = client.stream.shutdown(Shutdown::Both);
+        result
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        self.write(&format!("OK MPD {}\n", PROTOCOL_VERSION))?;
+        let mut list: Option<CommandList> = None;
+        while let Some(line) = self.next_line() {
+            match (&mut list, line.as_str()) {
+                (None, "command_list_begin") | (None, "command_list_ok_begin") => {
+                    list = Some(CommandList {
+                        ok: line == "command_list_ok_begin",
+                        lines: Vec::new(),
+                    });
+                }
+                (Some(_), "command_list_end") => {
+                    if let Some(list) = list.take() {
+                        self.run_list(&list)?;
+                    }
+                }
+                (Some(list), _) => list.lines.push(line),
+                (None, _) => {
+                    if !self.run_line(&line)? {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the next command line, keeping track of the changes in the meantime. Returns
+    /// `None` once the connection is closed.
+    fn next_line(&mut self) -> Option<String> {
+        loop {
+            match self.inputs.recv() {
+                Ok(Input::Line(line)) => return Some(line),
+                Ok(Input::Changed(subsystem)) => {
+                    self.pending.insert(subsystem);
+                }
+                Ok(Input::Closed) | Err(_) => return None,
+            }
+        }
+    }
+
+    /// Runs a command outside of a command list. Returns `false` if the connection must be
+    /// closed.
+    fn run_line(&mut self, line: &str) -> io::Result<bool> {
+        let args = match tokenize(line) {
+            Ok(args) => args,
+            Err(ack) => {
+                self.write(&ack.render(0, ""))?;
+                return Ok(true);
+            }
+        };
+        let name = args.first().map_or("", String::as_str);
+        match name {
+            "close" => return Ok(false),
+            // Only meaningful while idle.
+            "noidle" => return Ok(true),
+            "idle" => {
+                return match self.check_permission(name) {
+                    Ok(()) => self.idle(&args[1..]),
+                    Err(ack) => self.write(&ack.render(0, name)).map(|_| true),
+                };
+            }
+            _ => {}
+        }
+
+        let response = match self.execute(&args) {
+            Ok(mut response) => {
+                response.push_str("OK\n");
+                response
+            }
+            Err(ack) => ack.render(0, name),
+        };
+        self.after_command();
+        self.write(&response)?;
+        Ok(true)
+    }
+
+    /// Runs the commands of `list`, stopping at the first that fails.
+    fn run_list(&mut self, list: &CommandList) -> io::Result<()> {
+        let mut response = String::new();
+        let mut failed = false;
+        for (index, line) in list.lines.iter().enumerate() {
+            let result = tokenize(line).and_then(|args| match args.first().map(String::as_str) {
+                Some("idle") | Some("noidle") | Some("close") => {
+                    Err(Ack::arg("not allowed in a command list"))
+                }
+                _ => self.execute(&args),
+            });
+            match result {
+                Ok(output) => {
+                    response.push_str(&output);
+                    if list.ok {
+                        response.push_str("list_OK\n");
+                    }
+                }
+                Err(ack) => {
+                    let name = line.split_whitespace().next().unwrap_or("");
+                    response.push_str(&ack.render(index, name));
+                    failed = true;
+                    break;
+                }
+            }
+        }
+        if !failed {
+            response.push_str("OK\n");
+        }
+        self.after_command();
+        self.write(&response)
+    }
+
+    /// Reports the changes made by a command to the other clients and to the app.
+    fn after_command(&self) {
+        if self.shared.check() {
+            (self.shared.context.on_command)();
+        }
+    }
+
+    /// Waits for a change of one of the subsystems named in `args`, or of any subsystem if
+    /// there is none, and reports it. `noidle` stops waiting. Returns `false` if the connection
+    /// must be closed.
+    fn idle(&mut self, args: &[String]) -> io::Result<bool> {
+        let mut wanted = Vec::new();
+        for name in args {
+            match Subsystem::parse(name) {
+                Some(subsystem) => wanted.push(subsystem),
+                None => {
+                    let ack = Ack::arg(format!("Unrecognized idle event: {}", name));
+                    self.write(&ack.render(0, "idle"))?;
+                    return Ok(true);
+                }
+            }
+        }
+        if wanted.is_empty() {
+            wanted = Subsystem::ALL.to_vec();
+        }
+
+        loop {
+            let changed: Vec<Subsystem> = wanted
+                .iter()
+                .copied()
+                .filter(|subsystem| self.pending.contains(subsystem))
+                .collect();
+            if !changed.is_empty() {
+                let mut response = String::new();
+                for subsystem in changed {
+                    self.pending.remove(&subsystem);
+                    line(&mut response, "changed", subsystem.name());
+                }
+                response.push_str("OK\n");
+                self.write(&response)?;
+                return Ok(true);
+            }
+
+            match self.inputs.recv() {
+                Ok(Input::Changed(subsystem)) => {
+                    self.pending.insert(subsystem);
+                }
+                Ok(Input::Line(line)) if line.trim() == "noidle" => {
+                    self.write("OK\n")?;
+                    return Ok(true);
+                }
+                // MPD closes the connection of clients sending other commands while idle.
+                Ok(Input::Line(_)) | Ok(Input::Closed) | Err(_) => return Ok(false),
+            }
+        }
+    }
+
+    fn check_permission(&self, name: &str) -> Result<(), Ack> {
+        if self.authorized || UNPROTECTED_COMMANDS.contains(&name) {
+            Ok(())
+        } else {
+            Err(Ack::new(
+                ACK_ERROR_PERMISSION,
+                format!("you don't have permission for \"{}\"", name),
+            ))
+        }
+    }
+
+    fn write(&mut self, response: &str) -> io::Result<()> {
+        self.stream.write_all(response.as_bytes())
+    }
+
+    /// Runs the command `args`, and returns its response without the final `OK`.
+    fn execute(&mut self, args: &[String]) -> Result<String, Ack> {
+        let (name, args) = match args.split_first() {
+            Some((name, args)) => (name.as_str(), args),
+            None => return Err(Ack::new(ACK_ERROR_UNKNOWN, "No command given")),
+        };
+        self.check_permission(name)?;
+
+        let shared = self.shared.clone();
+        let context = &shared.context;
+        let mut out = String::new();
+        match name {
+            "ping" => {}
+            "password" => {
+                if context.password.as_deref() != Some(single_arg(args)?) {
+                    return Err(Ack::new(ACK_ERROR_PASSWORD, "incorrect password"));
+                }
+                self.authorized = true;
+            }
+            "commands" => {
+                for command in COMMANDS {
+                    line(&mut out, "command", command);
+                }
+            }
+            "notcommands" | "urlhandlers" | "decoders" => {}
+            "tagtypes" => {
+                // Subcommands choosing the tags sent are accepted, but all tags are always sent.
+                if args.is_empty() {
+                    for tag in Tag::LISTED {
+                        line(&mut out, "tagtype", tag.name());
+                    }
+                }
+            }
+            "outputs" => {
+                line(&mut out, "outputid", 0);
+                line(&mut out, "outputname", "Default");
+                line(&mut out, "outputenabled", 1);
+            }
+            "status" => {
+                let version = shared.playlist_version();
+                write_status(&mut out, &context.player.lock().unwrap(), version);
+            }
+            "currentsong" => {
+                let current = {
+                    let player = context.player.lock().unwrap();
+                    let current = player
+                        .playlist()
+                        .current_index()
+                        .zip(player.playlist().current());
+                    match current {
+                        Some((index, path)) if !player.is_stopped() => {
+                            Some((index, path.to_path_buf(), player.duration()))
+                        }
+                        _ => None,
+                    }
+                };
+                if let Some((index, path, duration)) = current {
+                    let library = context.library.read().unwrap();
+                    write_song(&mut out, &library, &path, Some(index), duration);
+                }
+            }
+            "stats" => {
+                let library = context.library.read().unwrap();
+                let artists: BTreeSet<&str> = library
+                    .tracks()
+                    .iter()
+                    .filter_map(|track| track.artist.as_deref())
+                    .collect();
+                let albums: BTreeSet<&str> = library
+                    .tracks()
+                    .iter()
+                    .filter_map(|track| track.album.as_deref())
+                    .collect();
+                line(&mut out, "artists", artists.len());
+                line(&mut out, "albums", albums.len());
+                line(&mut out, "songs", library.tracks().len());
+                line(&mut out, "uptime", shared.started.elapsed().as_secs());
+                line(&mut out, "playtime", 0);
+                line(&mut out, "db_playtime", 0);
+            }
+            "play" | "playid" => {
+                let index = optional_arg(args)?.map(parse_number::<usize>).transpose()?;
+                let mut player = context.player.lock().unwrap();
+                match index {
+                    Some(index) => {
+                        if !player.play_index(index) {
+                            return Err(Ack::no_such_song());
+                        }
+                    }
+                    None if player.is_stopped() => {
+                        if !player.playlist().is_empty() {
+                            let index = player.playlist().current_index().unwrap_or(0);
+                            player.play_index(index);
+                        }
+                    }
+                    None => player.resume(),
+                }
+            }
+            "pause" => {
+                let pause = optional_arg(args)?.map(parse_bool).transpose()?;
+                let mut player = context.player.lock().unwrap();
+                if pause.unwrap_or_else(|| !player.is_paused()) {
+                    player.pause();
+                } else {
+                    player.resume();
+                }
+            }
+            "stop" => context.player.lock().unwrap().stop(),
+            "next" => {
+                context.player.lock().unwrap().next();
+            }
+            "previous" => {
+                context.player.lock().unwrap().previous();
+            }
+            "seek" | "seekid" => {
+                let (index, time) = two_args(args)?;
+                let index: usize = parse_number(index)?;
+                let time = parse_seconds(time)?;
+                let mut player = context.player.lock().unwrap();
+                let is_current =
+                    !player.is_stopped() && player.playlist().current_index() == Some(index);
+                if !is_current && !player.play_index(index) {
+                    return Err(Ack::no_such_song());
+                }
+                player.seek_to(time);
+            }
+            "seekcur" => {
+                let time = single_arg(args)?;
+                let mut player = context.player.lock().unwrap();
+                if player.is_stopped() {
+                    return Err(Ack::new(ACK_ERROR_PLAYER_SYNC, "Not playing"));
+                }
+                match time.strip_prefix('+') {
+                    Some(delta) => player.seek_relative(millis(parse_seconds(delta)?)),
+                    None => match time.strip_prefix('-') {
+                        Some(delta) => player.seek_relative(-millis(parse_seconds(delta)?)),
+                        None => player.seek_to(parse_seconds(time)?),
+                    },
+                }
+            }
+            "setvol" => {
+                let volume: u32 = parse_number(single_arg(args)?)?;
+                if volume > 100 {
+                    return Err(Ack::arg("Invalid volume value"));
+                }
+                set_volume_percent(&mut context.player.lock().unwrap(), volume);
+            }
+            "volume" => {
+                let change: i64 = parse_number(single_arg(args)?)?;
+                let mut player = context.player.lock().unwrap();
+                let volume = (i64::from(volume_percent(&player)) + change).clamp(0, 100);
+                set_volume_percent(&mut player, volume as u32);
+            }
+            "getvol" => {
+                line(
+                    &mut out,
+                    "volume",
+                    volume_percent(&context.player.lock().unwrap()),
+                );
+            }
+            "repeat" => {
+                let repeat = parse_bool(single_arg(args)?)?;
+                let mut player = context.player.lock().unwrap();
+                let mode = match (repeat, player.repeat()) {
+                    (false, _) => RepeatMode::Off,
+                    (true, RepeatMode::One) => RepeatMode::One,
+                    (true, _) => RepeatMode::All,
+                };
+                player.set_repeat(mode);
+            }
+            "single" => {
+                // The player has no mode stopping after the current track, so single mode always
+                // repeats it.
+                let single = parse_bool(single_arg(args)?)?;
+                let mut player = context.player.lock().unwrap();
+                if single {
+                    player.set_repeat(RepeatMode::One);
+                } else if player.repeat() == RepeatMode::One {
+                    player.set_repeat(RepeatMode::All);
+                }
+            }
+            "random" => {
+                let random = parse_bool(single_arg(args)?)?;
+                let mut player = context.player.lock().unwrap();
+                if random && player.shuffle_mode() == ShuffleMode::Off {
+                    player.set_shuffle(ShuffleMode::Uniform, crate::new_shuffle_seed());
+                } else if !random {
+                    player.set_shuffle(ShuffleMode::Off, 0);
+                }
+            }
+            "consume" => {
+                if parse_bool(single_arg(args)?)? {
+                    return Err(Ack::arg("Consume mode is not supported"));
+                }
+            }
+            "playlistinfo" | "playlistid" => {
+                let tracks = context.player.lock().unwrap().playlist().tracks().to_vec();
+                let range = match optional_arg(args)? {
+                    Some(range) if name == "playlistinfo" => parse_range(range, tracks.len())?,
+                    Some(id) => {
+                        let index: usize = parse_number(id)?;
+                        if index >= tracks.len() {
+                            return Err(Ack::no_such_song());
+                        }
+                        index..index + 1
+                    }
+                    None => 0..tracks.len(),
+                };
+                let library = context.library.read().unwrap();
+                for index in range {
+                    write_song(&mut out, &library, &tracks[index], Some(index), None);
+                }
+            }
+            "plchanges" | "plchangesposid" => {
+                // Without a history of the playlist, any older version gets the whole playlist.
+                let version: u32 = parse_number(single_arg(args)?)?;
+                let current_version = shared.playlist_version();
+                let tracks = context.player.lock().unwrap().playlist().tracks().to_vec();
+                if version != current_version {
+                    let library = context.library.read().unwrap();
+                    for (index, path) in tracks.iter().enumerate() {
+                        if name == "plchanges" {
+                            write_song(&mut out, &library, path, Some(index), None);
+                        } else {
+                            line(&mut out, "cpos", index);
+                            line(&mut out, "Id", index);
+                        }
+                    }
+                }
+            }
+            "add" => {
+                let paths = resolve(&context.library.read().unwrap(), single_arg(args)?);
+                if paths.is_empty() {
+                    return Err(Ack::new(ACK_ERROR_NO_EXIST, "No such directory"));
+                }
+                let mut player = context.player.lock().unwrap();
+                for path in paths {
+                    player.enqueue(path);
+                }
+            }
+            "addid" => {
+                let (uri, position) = match args {
+                    [uri] => (uri, None),
+                    [uri, position] => (uri, Some(parse_number::<usize>(position)?)),
+                    _ => return Err(wrong_argument_count()),
+                };
+                let mut paths = resolve(&context.library.read().unwrap(), uri);
+                if paths.len() != 1 {
+                    return Err(Ack::no_such_song());
+                }
+                let mut player = context.player.lock().unwrap();
+                let id = player.playlist().len();
+                if position.map_or(false, |position| position != id) {
+                    return Err(Ack::arg("Inserting before the end is not supported"));
+                }
+                player.enqueue(paths.remove(0));
+                line(&mut out, "Id", id);
+            }
+            "delete" | "deleteid" => {
+                let mut player = context.player.lock().unwrap();
+                let len = player.playlist().len();
+                let range = if name == "delete" {
+                    parse_range(single_arg(args)?, len)?
+                } else {
+                    let index: usize = parse_number(single_arg(args)?)?;
+                    index..index + 1
+                };
+                if range.end > len {
+                    return Err(Ack::no_such_song());
+                }
+                for index in range.rev() {
+                    player.dequeue(index);
+                }
+            }
+            "clear" => context
+                .player
+                .lock()
+                .unwrap()
+                .set_playlist(Vec::new(), None),
+            "list" => {
+                let (tag, args) = match args.split_first() {
+                    Some((tag, args)) => (tag, args),
+                    None => return Err(wrong_argument_count()),
+                };
+                let tag = match Tag::parse(tag) {
+                    Some(Tag::Any) | None => {
+                        return Err(Ack::arg(format!("Unknown tag type: {}", tag)))
+                    }
+                    Some(tag) => tag,
+                };
+                // Grouping is accepted, but the values are listed without their groups.
+                let filters = match args
+                    .iter()
+                    .position(|arg| arg.eq_ignore_ascii_case("group"))
+                {
+                    Some(group) => &args[..group],
+                    None => args,
+                };
+                let filter = match filters {
+                    // Before filters, `list album` took an artist as its only argument.
+                    [artist] if tag == Tag::Album && !artist.starts_with('(') => Filter {
+                        conditions: vec![Condition {
+                            tag: Tag::Artist,
+                            operator: Operator::Equals,
+                            value: artist.clone(),
+                        }],
+                        ignore_case: false,
+                    },
+                    _ => Filter::parse(filters, false)?,
+                };
+
+                let library = context.library.read().unwrap();
+                let mut values = BTreeSet::new();
+                for track in library.tracks() {
+                    let uri = uri(library.folders(), &track.path);
+                    if filter.matches(track, &uri) {
+                        values.extend(tag.values(track, &uri).into_iter().map(str::to_string));
+                    }
+                }
+                for value in values {
+                    line(&mut out, tag.name(), value);
+                }
+            }
+            "find" | "search" | "findadd" | "searchadd" => {
+                let filter = Filter::parse(args, name.starts_with("search"))?;
+                let library = context.library.read().unwrap();
+                let tracks: Vec<&LibraryTrack> = library
+                    .tracks()
+                    .iter()
+                    .filter(|track| filter.matches(track, &uri(library.folders(), &track.path)))
+                    .collect();
+                if name.ends_with("add") {
+                    let mut player = context.player.lock().unwrap();
+                    for track in tracks {
+                        player.enqueue(track.path.clone());
+                    }
+                } else {
+                    for track in tracks {
+                        write_song(&mut out, &library, &track.path, None, None);
+                    }
+                }
+            }
+            "lsinfo" => {
+                let uri = optional_arg(args)?.unwrap_or("");
+                write_directory(&mut out, &context.library.read().unwrap(), uri)?;
+            }
+            _ => {
+                return Err(Ack::new(
+                    ACK_ERROR_UNKNOWN,
+                    format!("unknown command \"{}\"", name),
+                ))
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Sends the lines read from `stream` to its connection, then `Input::Closed`.
+fn read_lines(stream: TcpStream, sender: &Sender<Input>) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        match (&mut reader).take(MAX_LINE_LENGTH).read_line(&mut line) {
+            // A line without its newline is either too long or cut by the end of the stream.
+            Ok(_) if line.ends_with('\n') => {
+                if sender
+                    .send(Input::Line(line.trim_end().to_string()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            _ => break,
+        }
+    }
+    let _ = sender.send(Input::Closed);
+}
+
+/// Splits a command line into its arguments, which may be quoted.
+fn tokenize(line: &str) -> Result<Vec<String>, Ack> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut arg = String::new();
+        match chars.peek() {
+            None => return Ok(args),
+            Some('"') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => arg.push(c),
+                            None => return Err(Ack::arg("Missing closing '\"'")),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(Ack::arg("Missing closing '\"'")),
+                    }
+                }
+            }
+            Some(_) => {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    arg.push(c);
+                    chars.next();
+                }
+            }
+        }
+        args.push(arg);
+    }
+}
+
+fn wrong_argument_count() -> Ack {
+    Ack::arg("wrong number of arguments")
+}
+
+fn single_arg(args: &[String]) -> Result<&str, Ack> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(wrong_argument_count()),
+    }
+}
+
+fn optional_arg(args: &[String]) -> Result<Option<&str>, Ack> {
+    match args {
+        [] => Ok(None),
+        [arg] => Ok(Some(arg)),
+        _ => Err(wrong_argument_count()),
+    }
+}
+
+fn two_args(args: &[String]) -> Result<(&str, &str), Ack> {
+    match args {
+        [first, second] => Ok((first, second)),
+        _ => Err(wrong_argument_count()),
+    }
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, Ack> {
+    value
+        .parse()
+        .map_err(|_| Ack::arg(format!("Number expected: {}", value)))
+}
+
+fn parse_bool(value: &str) -> Result<bool, Ack> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(Ack::arg(format!("Boolean (0/1) expected: {}", value))),
+    }
+}
+
+/// Parses a time in seconds, which may have a fractional part.
+fn parse_seconds(value: &str) -> Result<Duration, Ack> {
+    let seconds: f64 = parse_number(value)?;
+    if seconds.is_finite() && seconds >= 0.0 {
+        Ok(Duration::from_secs_f64(seconds))
+    } else {
+        Err(Ack::arg(format!("Invalid time: {}", value)))
+    }
+}
+
+/// Parses a position `N` or a range `START:END`, whose end may be left out, in a playlist of
+/// `len` tracks.
+fn parse_range(value: &str, len: usize) -> Result<Range<usize>, Ack> {
+    let range = match value.split_once(':') {
+        Some((start, "")) => parse_number(start)?..len,
+        Some((start, end)) => parse_number(start)?..parse_number(end)?,
+        None => {
+            let index: usize = parse_number(value)?;
+            index..index + 1
+        }
+    };
+    if range.start > range.end || range.end > len {
+        return Err(Ack::arg("Bad song index"));
+    }
+    Ok(range)
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn millis(duration: Duration) -> i64 {
+    duration.as_millis() as i64
+}
+
+/// Returns the volume from 0 to 100, which is 0 while muted.
+fn volume_percent(player: &Player) -> u32 {
+    if player.is_muted() {
+        0
+    } else {
+        u32::from(player.volume) * 100 / u32::from(MAX_VOLUME)
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn set_volume_percent(player: &mut Player, volume: u32) {
+    player.unmute();
+    player.set_volume((volume * u32::from(MAX_VOLUME) / 100) as u16);
+}
+
+/// Appends the line `key: value` to `out`.
+fn line(out: &mut String, key: &str, value: impl Display) {
+    // Writing to a `String` can't fail.
+    let _ = writeln!(out, "{}: {}", key, value);
+}
+
+fn write_status(out: &mut String, player: &Player, playlist_version: u32) {
+    let repeat = player.repeat();
+    let state = State::of(player);
+    line(out, "volume", volume_percent(player));
+    line(out, "repeat", u8::from(repeat != RepeatMode::Off));
+    line(
+        out,
+        "random",
+        u8::from(player.shuffle_mode() != ShuffleMode::Off),
+    );
+    line(out, "single", u8::from(repeat == RepeatMode::One));
+    line(out, "consume", 0);
+    line(out, "playlist", playlist_version);
+    line(out, "playlistlength", player.playlist().len());
+    line(out, "state", state.name());
+    if let Some(index) = player.playlist().current_index() {
+        line(out, "song", index);
+        line(out, "songid", index);
+    }
+    if let Some(index) = player.playlist().next_index() {
+        line(out, "nextsong", index);
+        line(out, "nextsongid", index);
+    }
+    if state != State::Stop {
+        let elapsed = player.elapsed().as_secs_f64();
+        line(out, "elapsed", format!("{:.3}", elapsed));
+        match player.duration() {
+            Some(duration) => {
+                line(out, "time", format!("{:.0}:{:.0}", elapsed, duration));
+                line(out, "duration", format!("{:.3}", duration));
+            }
+            None => line(out, "time", format!("{:.0}:0", elapsed)),
+        }
+    }
+}
+
+/// Appends the description of the song at `path`, at `position` in the playlist if it is
+/// queued. Songs that aren't in the library have their tags read from the file.
+fn write_song(
+    out: &mut String,
+    library: &Library,
+    path: &Path,
+    position: Option<usize>,
+    duration: Option<f64>,
+) {
+    let read;
+    let track = match library.track(path) {
+        Some(track) => track,
+        None => {
+            read = LibraryTrack::read(path.to_path_buf());
+            &read
+        }
+    };
+    line(out, "file", uri(library.folders(), path));
+    for (key, value) in [
+        ("Artist", &track.artist),
+        ("Album", &track.album),
+        ("Title", &track.title),
+    ] {
+        if let Some(value) = value {
+            line(out, key, value);
+        }
+    }
+    if let Some(duration) = duration {
+        line(out, "Time", format!("{:.0}", duration));
+        line(out, "duration", format!("{:.3}", duration));
+    }
+    if let Some(position) = position {
+        line(out, "Pos", position);
+        line(out, "Id", position);
+    }
+}
+
+/// Appends the folders and songs directly in the library folder `uri`, or the song `uri`.
+fn write_directory(out: &mut String, library: &Library, uri: &str) -> Result<(), Ack> {
+    let directory = uri.trim_matches('/');
+    let mut directories = BTreeSet::new();
+    let mut files = Vec::new();
+    for track in library.tracks() {
+        let track_uri = self::uri(library.folders(), &track.path);
+        if !directory.is_empty() && track_uri == directory {
+            write_song(out, library, &track.path, None, None);
+            return Ok(());
+        }
+        let rest = if directory.is_empty() {
+            Some(track_uri.as_str())
+        } else {
+            track_uri
+                .strip_prefix(directory)
+                .and_then(|rest| rest.strip_prefix('/'))
+        };
+        match rest.map(|rest| rest.split_once('/')) {
+            Some(Some((child, _))) if directory.is_empty() => {
+                directories.insert(child.to_string());
+            }
+            Some(Some((child, _))) => {
+                directories.insert(format!("{}/{}", directory, child));
+            }
+            Some(None) => files.push(track),
+            None => {}
+        }
+    }
+    if directories.is_empty() && files.is_empty() && !directory.is_empty() {
+        return Err(Ack::new(ACK_ERROR_NO_EXIST, "No such directory"));
+    }
+
+    for directory in directories {
+        line(out, "directory", directory);
+    }
+    for track in files {
+        write_song(out, library, &track.path, None, None);
+    }
+    Ok(())
+}
+
+/// Returns the URI of `path`: its path in the library folder it is in, or the absolute path of
+/// files outside the library.
+fn uri(folders: &[PathBuf], path: &Path) -> String {
+    match folders
+        .iter()
+        .find_map(|folder| path.strip_prefix(folder).ok())
+    {
+        Some(relative) => relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        None => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// Returns the files `uri` names: a single file, or the library tracks in a folder.
+fn resolve(library: &Library, uri: &str) -> Vec<PathBuf> {
+    let path = Path::new(uri);
+    if path.is_absolute() {
+        if path.is_file() {
+            return vec![path.to_path_buf()];
+        }
+        return library
+            .tracks()
+            .iter()
+            .filter(|track| track.path.starts_with(path))
+            .map(|track| track.path.clone())
+            .collect();
+    }
+
+    let directory = uri.trim_matches('/');
+    library
+        .tracks()
+        .iter()
+        .filter(|track| {
+            let track_uri = self::uri(library.folders(), &track.path);
+            directory.is_empty()
+                || track_uri == directory
+                || track_uri
+                    .strip_prefix(directory)
+                    .map_or(false, |rest| rest.starts_with('/'))
+        })
+        .map(|track| track.path.clone())
+        .collect()
+}
+
+/// Tag a filter or `list` can refer to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Tag {
+    Artist,
+    /// Same as the artist, as album artists aren't indexed.
+    AlbumArtist,
+    Album,
+    Title,
+    File,
+    /// Any of the tags or the URI.
+    Any,
+}
+
+impl Tag {
+    /// Tags listed by `tagtypes`.
+    const LISTED: [Self; 4] = [Self::Artist, Self::AlbumArtist, Self::Album, Self::Title];
+
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "artist" | "artistsort" => Some(Self::Artist),
+            "albumartist" | "albumartistsort" => Some(Self::AlbumArtist),
+            "album" | "albumsort" => Some(Self::Album),
+            "title" => Some(Self::Title),
+            "file" => Some(Self::File),
+            "any" => Some(Self::Any),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Artist => "Artist",
+            Self::AlbumArtist => "AlbumArtist",
+            Self::Album => "Album",
+            Self::Title => "Title",
+            Self::File => "file",
+            Self::Any => "any",
+        }
+    }
+
+    /// Returns the values of the tag for `track`, whose URI is `uri`.
+    fn values<'a>(self, track: &'a LibraryTrack, uri: &'a str) -> Vec<&'a str> {
+        match self {
+            Self::Artist | Self::AlbumArtist => track.artist.as_deref().into_iter().collect(),
+            Self::Album => track.album.as_deref().into_iter().collect(),
+            Self::Title => track.title.as_deref().into_iter().collect(),
+            Self::File => vec![uri],
+            Self::Any => [&track.artist, &track.album, &track.title]
+                .iter()
+                .filter_map(|value| value.as_deref())
+                .chain(Some(uri))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Equals,
+    NotEquals,
+    Contains,
+}
+
+#[derive(Debug)]
+struct Condition {
+    tag: Tag,
+    operator: Operator,
+    value: String,
+}
+
+/// Conditions a track must all meet, given either as an expression such as
+/// `((artist == 'X') AND (album contains 'Y'))` or as pairs of tags and values.
+#[derive(Debug)]
+struct Filter {
+    conditions: Vec<Condition>,
+    /// Whether values are compared ignoring case, as `search` does.
+    ignore_case: bool,
+}
+
+impl Filter {
+    fn parse(args: &[String], ignore_case: bool) -> Result<Self, Ack> {
+        let mut conditions = Vec::new();
+        match args {
+            [expression] if expression.starts_with('(') => {
+                let mut parser = Expression { rest: expression };
+                parser.parse(&mut conditions, 1)?;
+                if !parser.rest.trim().is_empty() {
+                    return Err(Ack::arg("Unparsed garbage after expression"));
+                }
+            }
+            _ if args.len() % 2 == 1 => {
+                return Err(Ack::arg("Incorrect number of filter arguments"));
+            }
+            _ => {
+                for pair in args.chunks(2) {
+                    let tag = Tag::parse(&pair[0])
+                        .ok_or_else(|| Ack::arg(format!("Unknown tag type: {}", pair[0])))?;
+                    // Legacy `search` arguments match parts of values.
+                    let operator = if ignore_case {
+                        Operator::Contains
+                    } else {
+                        Operator::Equals
+                    };
+                    conditions.push(Condition {
+                        tag,
+                        operator,
+                        value: pair[1].clone(),
+                    });
+                }
+            }
+        }
+        Ok(Self {
+            conditions,
+            ignore_case,
+        })
+    }
+
+    fn matches(&self, track: &LibraryTrack, uri: &str) -> bool {
+        let fold = |value: &str| {
+            if self.ignore_case {
+                value.to_lowercase()
+            } else {
+                value.to_string()
+            }
+        };
+        self.conditions.iter().all(|condition| {
+            let expected = fold(&condition.value);
+            let mut values = condition.tag.values(track, uri).into_iter().map(&fold);
+            match condition.operator {
+                Operator::Equals => values.any(|value| value == expected),
+                Operator::NotEquals => values.all(|value| value != expected),
+                Operator::Contains => values.any(|value| value.contains(&expected)),
+            }
+        })
+    }
+}
+
+/// Parser of filter expressions.
+struct Expression<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Expression<'a> {
+    /// Parses `(TAG OPERATOR 'VALUE')` or `(EXPRESSION AND EXPRESSION ...)`, nested `depth`
+    /// parentheses deep.
+    fn parse(&mut self, conditions: &mut Vec<Condition>, depth: usize) -> Result<(), Ack> {
+        if depth > MAX_FILTER_DEPTH {
+            return Err(Ack::arg("Filter expression nested too deeply"));
+        }
+        self.expect("(")?;
+        if self.rest.trim_start().starts_with('(') {
+            self.parse(conditions, depth + 1)?;
+            while self.eat("AND") {
+                self.parse(conditions, depth + 1)?;
+            }
+        } else {
+            let name = self.word();
+            let tag =
+                Tag::parse(name).ok_or_else(|| Ack::arg(format!("Unknown tag type: {}", name)))?;
+            let operator = if self.eat("==") {
+                Operator::Equals
+            } else if self.eat("!=") {
+                Operator::NotEquals
+            } else if self.eat("contains") {
+                Operator::Contains
+            } else {
+                return Err(Ack::arg("Unknown filter operator"));
+            };
+            let value = self.quoted()?;
+            conditions.push(Condition {
+                tag,
+                operator,
+                value,
+            });
+        }
+        self.expect(")")
+    }
+
+    /// Skips `token`, after whitespace, and returns `true` if it comes next.
+    fn eat(&mut self, token: &str) -> bool {
+        match self.rest.trim_start().strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), Ack> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(Ack::arg(format!("'{}' expected", token)))
+        }
+    }
+
+    fn word(&mut self) -> &'a str {
+        let rest = self.rest.trim_start();
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+            .unwrap_or(rest.len());
+        self.rest = &rest[end..];
+        &rest[..end]
+    }
+
+    /// Parses a value in single or double quotes, in which backslashes escape characters.
+    fn quoted(&mut self) -> Result<String, Ack> {
+        let rest = self.rest.trim_start();
+        let mut chars = rest.char_indices();
+        let quote = match chars.next() {
+            Some((_, quote)) if quote == '\'' || quote == '"' => quote,
+            _ => return Err(Ack::arg("Quoted value expected")),
+        };
+        let mut value = String::new();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, c)) => value.push(c),
+                    None => break,
+                },
+                c if c == quote => {
+                    self.rest = &rest[index + 1..];
+                    return Ok(value);
+                }
+                c => value.push(c),
+            }
+        }
+        Err(Ack::arg("Closing quote not found"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Client speaking to the server through a real connection.
+    struct TestClient {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl TestClient {
+        fn connect(mpd: &Mpd) -> Self {
+            let stream = TcpStream::connect(mpd.address()).unwrap();
+            stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+            let mut client = Self {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+            };
+            assert_eq!(client.read_line(), format!("OK MPD {}", PROTOCOL_VERSION));
+            client
+        }
+
+        fn read_line(&mut self) -> String {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            line.trim_end().to_string()
+        }
+
+        fn send(&mut self, command: &str) {
+            writeln!(self.writer, "{}", command).unwrap();
+        }
+
+        /// Sends `command`, and returns the lines of its response up to `OK` or `ACK`, included.
+        fn command(&mut self, command: &str) -> Vec<String> {
+            self.send(command);
+            self.response()
+        }
+
+        fn response(&mut self) -> Vec<String> {
+            let mut lines = Vec::new();
+            loop {
+                let line = self.read_line();
+                let done = line == "OK" || line.starts_with("ACK ") || line.is_empty();
+                lines.push(line);
+                if done {
+                    return lines;
+                }
+            }
+        }
+    }
+
+    fn value<'a>(response: &'a [String], key: &str) -> Option<&'a str> {
+        response
+            .iter()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(": "))
+    }
+
+    fn values<'a>(response: &'a [String], key: &str) -> Vec<&'a str> {
+        response
+            .iter()
+            .filter_map(|line| line.strip_prefix(key)?.strip_prefix(": "))
+            .collect()
+    }
+
+    fn track(path: &str, artist: &str, album: &str, title: &str) -> LibraryTrack {
+        LibraryTrack {
+            path: PathBuf::from(path),
+            artist: Some(artist.to_string()),
+            album: Some(album.to_string()),
+            title: Some(title.to_string()),
+        }
+    }
+
+    fn library() -> Library {
+        Library::from_tracks(
+            vec![PathBuf::from("/music")],
+            vec![
+                track(
+                    "/music/Abba/Arrival/01.mp3",
+                    "ABBA",
+                    "Arrival",
+                    "When I Kissed",
+                ),
+                track(
+                    "/music/Abba/Arrival/02.mp3",
+                    "ABBA",
+                    "Arrival",
+                    "Dancing Queen",
+                ),
+                track("/music/Bach/Mass.flac", "Bach", "Mass in B minor", "Kyrie"),
+            ],
+        )
+    }
+
+    fn serve(password: Option<&str>) -> (Arc<Mutex<Player>>, Mpd) {
+        let player = Arc::new(Mutex::new(Player::new()));
+        let context = Context {
+            player: player.clone(),
+            library: Arc::new(RwLock::new(library())),
+            password: password.map(str::to_string),
+            on_command: Box::new(|| {}),
+        };
+        let mpd = Mpd::start("127.0.0.1:0", context).unwrap();
+        (player, mpd)
+    }
+
+    #[test]
+    fn reports_status_of_stopped_player() {
+        let (_player, mpd) = serve(None);
+        let mut client = TestClient::connect(&mpd);
+
+        assert_eq!(client.command("ping"), ["OK"]);
+        let status = client.command("status");
+        assert_eq!(value(&status, "state"), Some("stop"));
+        assert_eq!(value(&status, "playlistlength"), Some("0"));
+        assert_eq!(value(&status, "repeat"), Some("0"));
+        assert_eq!(status.last().map(String::as_str), Some("OK"));
+        assert_eq!(client.command("currentsong"), ["OK"]);
+    }
+
+    #[test]
+    fn sets_volume_and_options() {
+        let (player, mpd) = serve(None);
+        let mut client = TestClient::connect(&mpd);
+
+        assert_eq!(client.command("setvol 30"), ["OK"]);
+        assert_eq!(client.command("repeat 1"), ["OK"]);
+        assert_eq!(client.command("volume +5"), ["OK"]);
+        {
+            let player = player.lock().unwrap();
+            assert_eq!(player.volume, MAX_VOLUME * 35 / 100);
+            assert_eq!(player.repeat(), RepeatMode::All);
+        }
+
+        assert_eq!(client.command("single 1"), ["OK"]);
+        assert_eq!(player.lock().unwrap().repeat(), RepeatMode::One);
+        let status = client.command("status");
+        assert_eq!(value(&status, "volume"), Some("35"));
+        assert_eq!(value(&status, "single"), Some("1"));
+        assert_eq!(
+            client.command("setvol 101"),
+            ["ACK [2@0] {setvol} Invalid volume value"]
+        );
+    }
+
+    #[test]
+    fn edits_the_playlist() {
+        let (player, mpd) = serve(None);
+        let mut client = TestClient::connect(&mpd);
+
+        let version = client.command("status");
+        let version = value(&version, "playlist").unwrap().to_string();
+        assert_eq!(client.command("add Abba"), ["OK"]);
+        assert_eq!(client.command("addid Bach/Mass.flac"), ["Id: 2", "OK"]);
+        assert_eq!(
+            player.lock().unwrap().playlist().tracks(),
+            [
+                PathBuf::from("/music/Abba/Arrival/01.mp3"),
+                PathBuf::from("/music/Abba/Arrival/02.mp3"),
+                PathBuf::from("/music/Bach/Mass.flac"),
+            ]
+        );
+
+        let songs = client.command("playlistinfo");
+        assert_eq!(
+            values(&songs, "file"),
+            [
+                "Abba/Arrival/01.mp3",
+                "Abba/Arrival/02.mp3",
+                "Bach/Mass.flac"
+            ]
+        );
+        assert_eq!(values(&songs, "Title")[1], "Dancing Queen");
+        assert_eq!(values(&songs, "Pos"), ["0", "1", "2"]);
+        assert_eq!(
+            values(&client.command("playlistinfo 1:"), "Pos"),
+            ["1", "2"]
+        );
+
+        let status = client.command("status");
+        assert_ne!(value(&status, "playlist"), Some(version.as_str()));
+        let changes = client.command(&format!("plchangesposid {}", version));
+        assert_eq!(values(&changes, "cpos").len(), 3);
+
+        assert_eq!(client.command("delete 0:2"), ["OK"]);
+        assert_eq!(
+            values(&client.command("playlistinfo"), "file"),
+            ["Bach/Mass.flac"]
+        );
+        assert_eq!(
+            client.command("deleteid 5"),
+            ["ACK [50@0] {deleteid} No such song"]
+        );
+        assert_eq!(client.command("clear"), ["OK"]);
+        assert!(player.lock().unwrap().playlist().is_empty());
+    }
+
+    #[test]
+    fn lists_and_searches_the_library() {
+        let (_player, mpd) = serve(None);
+        let mut client = TestClient::connect(&mpd);
+
+        assert_eq!(
+            client.command("list artist"),
+            ["Artist: ABBA", "Artist: Bach", "OK"]
+        );
+        assert_eq!(
+            client.command("list album \"(Artist == 'Bach')\""),
+            ["Album: Mass in B minor", "OK"]
+        );
+        assert_eq!(client.command("list album ABBA"), ["Album: Arrival", "OK"]);
+
+        let found = client.command("find \"((artist == 'ABBA') AND (title != 'Kyrie'))\"");
+        assert_eq!(values(&found, "file").len(), 2);
+        assert!(client.command("find artist abba").len() == 1);
+        let nested = |depth: usize| {
+            format!(
+                "find \"{}(artist == 'ABBA'){}\"",
+                "(".repeat(depth - 1),
+                ")".repeat(depth - 1)
+            )
+        };
+        let found = client.command(&nested(MAX_FILTER_DEPTH));
+        assert_eq!(values(&found, "file").len(), 2);
+        assert_eq!(
+            client.command(&nested(MAX_FILTER_DEPTH + 1)),
+            ["ACK [2@0] {find} Filter expression nested too deeply"]
+        );
+        // Far deeper than the stack would take, within the longest line.
+        assert_eq!(
+            client.command(&nested(30_000)),
+            ["ACK [2@0] {find} Filter expression nested too deeply"]
+        );
+        let found = client.command("search title queen");
+        assert_eq!(values(&found, "file"), ["Abba/Arrival/02.mp3"]);
+        let found = client.command("search any \"b minor\"");
+        assert_eq!(values(&found, "Title"), ["Kyrie"]);
+
+        let listed = client.command("lsinfo");
+        assert_eq!(values(&listed, "directory"), ["Abba", "Bach"]);
+        let listed = client.command("lsinfo Bach");
+        assert_eq!(values(&listed, "file"), ["Bach/Mass.flac"]);
+        assert_eq!(
+            client.command("lsinfo Mozart"),
+            ["ACK [50@0] {lsinfo} No such directory"]
+        );
+    }
+
+    #[test]
+    fn answers_errors_and_command_lists() {
+        let (player, mpd) = serve(None);
+        let mut client = TestClient::connect(&mpd);
+
+        assert_eq!(
+            client.command("frobnicate"),
+            ["ACK [5@0] {frobnicate} unknown command \"frobnicate\""]
+        );
+        assert_eq!(
+            client.command("setvol"),
+            ["ACK [2@0] {setvol} wrong number of arguments"]
+        );
+
+        client.send("command_list_ok_begin");
+        client.send("setvol 20");
+        client.send("getvol");
+        client.send("command_list_end");
+        assert_eq!(
+            client.response(),
+            ["list_OK", "volume: 20", "list_OK", "OK"]
+        );
+
+        client.send("command_list_begin");
+        client.send("setvol 40");
+        client.send("play 7");
+        client.send("setvol 60");
+        client.send("command_list_end");
+        assert_eq!(client.response(), ["ACK [50@1] {play} No such song"]);
+        assert_eq!(player.lock().unwrap().volume, MAX_VOLUME * 40 / 100);
+    }
+
+    #[test]
+    fn requires_the_password() {
+        let (_player, mpd) = serve(Some("secret"));
+        let mut client = TestClient::connect(&mpd);
+
+        assert_eq!(
+            client.command("status"),
+            ["ACK [4@0] {status} you don't have permission for \"status\""]
+        );
+        assert_eq!(
+            client.command("password wrong"),
+            ["ACK [3@0] {password} incorrect password"]
+        );
+        assert_eq!(client.command("password secret"), ["OK"]);
+        assert_eq!(client.command("status").last().unwrap(), "OK");
+    }
+
+    #[test]
+    fn idle_reports_changes_made_elsewhere() {
+        let (player, mpd) = serve(None);
+        let mut client = TestClient::connect(&mpd);
+        let mut other = TestClient::connect(&mpd);
+
+        client.send("idle mixer options");
+        player.lock().unwrap().set_volume(MAX_VOLUME / 4);
+        assert_eq!(client.response(), ["changed: mixer", "OK"]);
+
+        client.send("idle playlist");
+        assert_eq!(other.command("add Bach"), ["OK"]);
+        assert_eq!(client.response(), ["changed: playlist", "OK"]);
+
+        client.send("idle database");
+        mpd.library_changed();
+        assert_eq!(client.response(), ["changed: database", "OK"]);
+
+        client.send("idle player");
+        thread::sleep(POLL_INTERVAL);
+        client.send("noidle");
+        assert_eq!(client.response(), ["OK"]);
+    }
+
+    #[test]
+    fn splits_quoted_arguments() {
+        assert_eq!(
+            tokenize(r#"find "artist" "Guns N' \"Roses\"" album  x"#).unwrap(),
+            ["find", "artist", "Guns N' \"Roses\"", "album", "x"]
+        );
+        assert!(tokenize("find \"artist").is_err());
+    }
+}
@@ -0,0 +1,853 @@
+//! MPRIS2 D-Bus interface, through which media keys, desktop widgets and `playerctl` control the
+//! player on Linux.
+//!
+//! The `org.mpris.MediaPlayer2` and `org.mpris.MediaPlayer2.Player` interfaces are served on a
+//! thread of their own. The player changes from the app as well as over D-Bus, so the properties
+//! are compared at regular intervals, and the differences announced with `PropertiesChanged` and
+//! `Seeked` signals.
+
+use crate::player::{Player, RepeatMode, ShuffleMode, MAX_VOLUME};
+use crate::track::Track;
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::{
+    PropertiesPropertiesChanged, RequestNameReply,
+};
+use dbus::blocking::Connection;
+use dbus::channel::{Channel, Sender};
+use dbus::message::SignalArgs;
+use dbus::Path as ObjectPath;
+use dbus_crossroads::{Crossroads, IfaceToken, MethodErr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Name owned on the bus. MPRIS clients find players by the prefix.
+pub const BUS_NAME: &str = "org.mpris.MediaPlayer2.plain_music_player";
+pub const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+pub const ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+pub const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+/// Track id reported when nothing is playing.
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+/// Prefix of the track ids, which end with the index of the track in the playlist.
+const TRACK_ID_PREFIX: &str = "/org/plain_music_player/track/";
+const IDENTITY: &str = "Plain Music Player";
+const DESKTOP_ENTRY: &str = "plain-music-player";
+const MIN_RATE: f64 = 0.5;
+const MAX_RATE: f64 = 2.0;
+/// How often the properties are compared to find changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// A position further than this from where playback should be is reported as a seek.
+const SEEK_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// Handle to the thread serving the interface. The thread stops when this is dropped.
+pub struct Mpris {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Mpris {
+    /// Serves `player` on the session bus.
+    ///
+    /// `on_command` is called after each change requested over D-Bus, so that the UI can catch
+    /// up.
+    pub fn spawn<F>(player: Arc<Mutex<Player>>, on_command: F) -> Result<Self, dbus::Error>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        Self::spawn_on(None, player, on_command)
+    }
+
+    /// Serves `player` on the bus at `address`, or on the session bus if `None`.
+    pub fn spawn_on<F>(
+        address: Option<&str>,
+        player: Arc<Mutex<Player>>,
+        on_command: F,
+    ) -> Result<Self, dbus::Error>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let address = address.map(str::to_string);
+        let running = Arc::new(AtomicBool::new(true));
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let thread = {
+            let running = running.clone();
+            thread::spawn(move || {
+                // The connection can't leave the thread that opened it.
+                let connection = match connect(address.as_deref()) {
+                    Ok(connection) => connection,
+                    Err(err) => {
+                        let _ = ready_tx.send(Err(err));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+                let state = State {
+                    player,
+                    tags: None,
+                    on_command: Box::new(on_command),
+                };
+                serve(&connection, state, &running);
+            })
+        };
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(Self {
+                running,
+                thread: Some(thread),
+            }),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(dbus::Error::new_failed("MPRIS thread exited")),
+        }
+    }
+}
+
+impl Drop for Mpris {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Connects to the bus and takes `BUS_NAME`.
+fn connect(address: Option<&str>) -> Result<Connection, dbus::Error> {
+    let connection = match address {
+        Some(address) => {
+            let mut channel = Channel::open_private(address)?;
+            channel.register()?;
+            Connection::from(channel)
+        }
+        None => Connection::new_session()?,
+    };
+    match connection.request_name(BUS_NAME, false, true, true)? {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => Ok(connection),
+        _ => Err(dbus::Error::new_failed("MPRIS bus name is taken")),
+    }
+}
+
+/// Handles calls and announces changes until `running` is cleared or the bus goes away.
+fn serve(connection: &Connection, state: State, running: &AtomicBool) {
+    let mut crossroads = Crossroads::new();
+    let root = register_root(&mut crossroads);
+    let player = register_player(&mut crossroads);
+    let path = ObjectPath::from(OBJECT_PATH);
+    crossroads.insert(OBJECT_PATH, &[root, player], state);
+
+    let mut last = None;
+    let mut last_position: Option<(String, Duration, Instant)> = None;
+    while running.load(Ordering::Relaxed) {
+        if connection
+            .channel()
+            .read_write(Some(POLL_INTERVAL))
+            .is_err()
+        {
+            break;
+        }
+        while let Some(message) = connection.channel().pop_message() {
+            // Fails for messages that aren't method calls, which need no answer.
+            let _ = crossroads.handle_message(message, connection);
+        }
+
+        let state = match crossroads.data_mut::<State>(&path) {
+            Some(state) => state,
+            None => break,
+        };
+        let snapshot = state.snapshot();
+        if let Some(last) = &last {
+            let changed = snapshot.changed_properties(last);
+            if !changed.is_empty() {
+                let signal = PropertiesPropertiesChanged {
+                    interface_name: PLAYER_INTERFACE.to_string(),
+                    changed_properties: changed,
+                    invalidated_properties: Vec::new(),
+                };
+                let _ = connection.send(signal.to_emit_message(&path));
+            }
+        }
+
+        let now = Instant::now();
+        let track_id = snapshot.track.id();
+        if let Some((last_id, position, at)) = &last_position {
+            if *last_id == track_id && is_seek(&snapshot, *position, now - *at) {
+                let seeked = Seeked {
+                    position: micros(snapshot.position),
+                };
+                let _ = connection.send(seeked.to_emit_message(&path));
+            }
+        }
+        last_position = Some((track_id, snapshot.position, now));
+        last = Some(snapshot);
+        connection.channel().flush();
+    }
+}
+
+/// Returns `true` if the position in `snapshot` jumped from `position`, which it had `elapsed`
+/// ago.
+#[allow(clippy::cast_possible_truncation)]
+fn is_seek(snapshot: &Snapshot, position: Duration, elapsed: Duration) -> bool {
+    let distance = |a: Duration, b: Duration| if a > b { a - b } else { b - a };
+    let expected = if snapshot.playback_status == "Playing" {
+        position + elapsed.mul_f64(snapshot.rate)
+    } else {
+        position
+    };
+    // Playback that didn't move at all, such as while waiting for an output device, isn't a seek.
+    distance(snapshot.position, expected) > SEEK_TOLERANCE
+        && distance(snapshot.position, position) > SEEK_TOLERANCE
+}
+
+/// `Seeked` signal of the `Player` interface.
+#[derive(Debug)]
+struct Seeked {
+    position: i64,
+}
+
+impl dbus::arg::AppendAll for Seeked {
+    fn append(&self, i: &mut dbus::arg::IterAppend) {
+        dbus::arg::RefArg::append(&self.position, i);
+    }
+}
+
+impl dbus::arg::ReadAll for Seeked {
+    fn read(i: &mut dbus::arg::Iter) -> Result<Self, dbus::arg::TypeMismatchError> {
+        Ok(Self {
+            position: i.read()?,
+        })
+    }
+}
+
+impl SignalArgs for Seeked {
+    const NAME: &'static str = "Seeked";
+    const INTERFACE: &'static str = PLAYER_INTERFACE;
+}
+
+/// Data of the object served on the bus.
+struct State {
+    player: Arc<Mutex<Player>>,
+    /// Tags of the last track read, so that they aren't read again at every poll.
+    tags: Option<(PathBuf, Tags)>,
+    on_command: Box<dyn FnMut() + Send>,
+}
+
+impl State {
+    fn player(&self) -> MutexGuard<Player> {
+        self.player.lock().unwrap()
+    }
+
+    /// Applies a change requested over D-Bus to the player.
+    fn command<T, F: FnOnce(&mut Player) -> T>(&mut self, command: F) -> T {
+        let result = command(&mut self.player());
+        (self.on_command)();
+        result
+    }
+
+    fn snapshot(&mut self) -> Snapshot {
+        let player = self.player.lock().unwrap();
+        let playlist = player.playlist();
+        let stopped = player.is_stopped();
+        let path = player.current_path().filter(|_| !stopped);
+
+        let tags = match (path, &self.tags) {
+            (Some(path), Some((tagged, tags))) if path == tagged => tags.clone(),
+            (Some(path), _) => {
+                let tags = Tags::read(path);
+                self.tags = Some((path.to_path_buf(), tags.clone()));
+                tags
+            }
+            (None, _) => Tags::default(),
+        };
+
+        Snapshot {
+            playback_status: if stopped {
+                "Stopped"
+            } else if player.is_paused() {
+                "Paused"
+            } else {
+                "Playing"
+            },
+            loop_status: match player.repeat() {
+                RepeatMode::Off => "None",
+                RepeatMode::One => "Track",
+                RepeatMode::All => "Playlist",
+            },
+            rate: f64::from(player.speed),
+            shuffle: player.shuffle_mode() != ShuffleMode::Off,
+            volume: if player.is_muted() {
+                0.0
+            } else {
+                f64::from(player.volume) / f64::from(MAX_VOLUME)
+            },
+            position: if stopped {
+                Duration::from_secs(0)
+            } else {
+                player.elapsed()
+            },
+            can_go_next: playlist.next_index().is_some(),
+            // Going back restarts the current track when there is none before it.
+            can_go_previous: playlist.previous_index().is_some() || !stopped,
+            can_play: player.current_path().is_some() || !playlist.is_empty(),
+            can_pause: !stopped,
+            can_seek: !stopped && player.duration().is_some(),
+            track: TrackInfo {
+                index: path.and(playlist.current_index()),
+                path: path.map(Path::to_path_buf),
+                length: path
+                    .and_then(|_| player.duration())
+                    .map(|secs| Duration::from_secs_f64(secs.max(0.0))),
+                tags,
+            },
+        }
+    }
+}
+
+/// Tags of a track reported in `Metadata`.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Tags {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+}
+
+impl Tags {
+    fn read(path: &Path) -> Self {
+        match Track::read_from_path(path) {
+            Ok(track) => Self {
+                title: track.title().map(str::to_string),
+                artist: track.artist().map(str::to_string),
+                album: track.album().map(str::to_string),
+            },
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+/// The track being played.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct TrackInfo {
+    /// Position in the playlist.
+    index: Option<usize>,
+    path: Option<PathBuf>,
+    length: Option<Duration>,
+    tags: Tags,
+}
+
+impl TrackInfo {
+    fn id(&self) -> String {
+        match (self.index, &self.path) {
+            (Some(index), Some(_)) => format!("{}{}", TRACK_ID_PREFIX, index),
+            _ => NO_TRACK.to_string(),
+        }
+    }
+
+    fn metadata(&self) -> PropMap {
+        let mut metadata = PropMap::new();
+        let mut insert = |key: &str, value: Box<dyn RefArg>| {
+            metadata.insert(key.to_string(), Variant(value));
+        };
+        insert("mpris:trackid", Box::new(ObjectPath::from(self.id())));
+        if self.path.is_none() {
+            return metadata;
+        }
+
+        if let Some(length) = self.length {
+            insert("mpris:length", Box::new(micros(length)));
+        }
+        if let Some(path) = &self.path {
+            insert("xesam:url", Box::new(file_url(path)));
+        }
+        // Tracks without a title tag are named after their file.
+        let title = self.tags.title.clone().or_else(|| {
+            self.path
+                .as_deref()
+                .and_then(Path::file_stem)
+                .map(|stem| stem.to_string_lossy().into_owned())
+        });
+        if let Some(title) = title {
+            insert("xesam:title", Box::new(title));
+        }
+        if let Some(artist) = &self.tags.artist {
+            insert("xesam:artist", Box::new(vec![artist.clone()]));
+        }
+        if let Some(album) = &self.tags.album {
+            insert("xesam:album", Box::new(album.clone()));
+        }
+        metadata
+    }
+}
+
+/// Values of the properties of the `Player` interface.
+#[derive(Clone, Debug, PartialEq)]
+struct Snapshot {
+    playback_status: &'static str,
+    loop_status: &'static str,
+    rate: f64,
+    shuffle: bool,
+    volume: f64,
+    position: Duration,
+    can_go_next: bool,
+    can_go_previous: bool,
+    can_play: bool,
+    can_pause: bool,
+    can_seek: bool,
+    track: TrackInfo,
+}
+
+impl Snapshot {
+    /// Returns the properties that differ from `last`. `Position` changes all the time, and is
+    /// announced by `Seeked` instead.
+    #[allow(clippy::float_cmp)]
+    fn changed_properties(&self, last: &Self) -> PropMap {
+        let mut changed = PropMap::new();
+        let mut insert = |name: &str, value: Box<dyn RefArg>| {
+            changed.insert(name.to_string(), Variant(value));
+        };
+        if self.playback_status != last.playback_status {
+            insert("PlaybackStatus", Box::new(self.playback_status.to_string()));
+        }
+        if self.loop_status != last.loop_status {
+            insert("LoopStatus", Box::new(self.loop_status.to_string()));
+        }
+        if self.rate != last.rate {
+            insert("Rate", Box::new(self.rate));
+        }
+        if self.shuffle != last.shuffle {
+            insert("Shuffle", Box::new(self.shuffle));
+        }
+        if self.volume != last.volume {
+            insert("Volume", Box::new(self.volume));
+        }
+        if self.track != last.track {
+            insert("Metadata", Box::new(self.track.metadata()));
+        }
+        if self.can_go_next != last.can_go_next {
+            insert("CanGoNext", Box::new(self.can_go_next));
+        }
+        if self.can_go_previous != last.can_go_previous {
+            insert("CanGoPrevious", Box::new(self.can_go_previous));
+        }
+        if self.can_play != last.can_play {
+            insert("CanPlay", Box::new(self.can_play));
+        }
+        if self.can_pause != last.can_pause {
+            insert("CanPause", Box::new(self.can_pause));
+        }
+        if self.can_seek != last.can_seek {
+            insert("CanSeek", Box::new(self.can_seek));
+        }
+        changed
+    }
+}
+
+fn register_root(crossroads: &mut Crossroads) -> IfaceToken<State> {
+    crossroads.register(ROOT_INTERFACE, |b| {
+        // The window belongs to Tauri, so it can neither be raised nor closed from here.
+        b.method("Raise", (), (), |_, _: &mut State, _: ()| Ok(()));
+        b.method("Quit", (), (), |_, _: &mut State, _: ()| Ok(()));
+        b.property("CanQuit").get(|_, _| Ok(false));
+        b.property("CanRaise").get(|_, _| Ok(false));
+        b.property("HasTrackList").get(|_, _| Ok(false));
+        b.property("Identity").get(|_, _| Ok(IDENTITY.to_string()));
+        b.property("DesktopEntry")
+            .get(|_, _| Ok(DESKTOP_ENTRY.to_string()));
+        b.property("SupportedUriSchemes")
+            .get(|_, _| Ok(vec!["file".to_string()]));
+        b.property("SupportedMimeTypes").get(|_, _| {
+            Ok([
+                "audio/aac",
+                "audio/flac",
+                "audio/mp4",
+                "audio/mpeg",
+                "audio/ogg",
+                "audio/wav",
+                "audio/x-alac",
+            ]
+            .iter()
+            .map(|mime| mime.to_string())
+            .collect::<Vec<_>>())
+        });
+    })
+}
+
+#[allow(clippy::too_many_lines)]
+fn register_player(crossroads: &mut Crossroads) -> IfaceToken<State> {
+    crossroads.register(PLAYER_INTERFACE, |b| {
+        b.signal::<(i64,), _>("Seeked", ("Position",));
+
+        b.method("Next", (), (), |_, state: &mut State, _: ()| {
+            state.command(Player::next);
+            Ok(())
+        });
+        b.method("Previous", (), (), |_, state: &mut State, _: ()| {
+            state.command(Player::previous);
+            Ok(())
+        });
+        b.method("Pause", (), (), |_, state: &mut State, _: ()| {
+            state.command(Player::pause);
+            Ok(())
+        });
+        b.method("PlayPause", (), (), |_, state: &mut State, _: ()| {
+            state.command(|player| {
+                if player.is_stopped() || player.is_paused() {
+                    play(player);
+                } else {
+                    player.pause();
+                }
+            });
+            Ok(())
+        });
+        b.method("Stop", (), (), |_, state: &mut State, _: ()| {
+            state.command(Player::stop);
+            Ok(())
+        });
+        b.method("Play", (), (), |_, state: &mut State, _: ()| {
+            state.command(play);
+            Ok(())
+        });
+        b.method(
+            "Seek",
+            ("Offset",),
+            (),
+            |_, state: &mut State, (offset,): (i64,)| {
+                state.command(|player| seek(player, offset));
+                Ok(())
+            },
+        );
+        b.method(
+            "SetPosition",
+            ("TrackId", "Position"),
+            (),
+            |_, state: &mut State, (track_id, position): (ObjectPath<'static>, i64)| {
+                let current = state.snapshot().track;
+                let length = current.length.unwrap_or_default();
+                #[allow(clippy::cast_sign_loss)]
+                let position = Duration::from_micros(position.max(0) as u64);
+                // Requests for another track, or past its end, are ignored as the
+                // specification asks.
+                if *track_id == *current.id() && current.path.is_some() && position <= length {
+                    state.command(|player| player.seek_to(position));
+                }
+                Ok(())
+            },
+        );
+        b.method(
+            "OpenUri",
+            ("Uri",),
+            (),
+            |_, state: &mut State, (uri,): (String,)| {
+                let path = file_path(&uri)
+                    .ok_or_else(|| MethodErr::invalid_arg(&"only file:// URIs are supported"))?;
+                state.command(|player| player.play(&path));
+                Ok(())
+            },
+        );
+
+        b.property("PlaybackStatus")
+            .get(|_, state: &mut State| Ok(state.snapshot().playback_status.to_string()));
+        b.property("LoopStatus")
+            .get(|_, state: &mut State| Ok(state.snapshot().loop_status.to_string()))
+            .set(|_, state: &mut State, status: String| {
+                let repeat = match status.as_str() {
+                    "None" => RepeatMode::Off,
+                    "Track" => RepeatMode::One,
+                    "Playlist" => RepeatMode::All,
+                    _ => return Err(MethodErr::invalid_arg(&status)),
+                };
+                state.command(|player| player.set_repeat(repeat));
+                // The change is announced by the next poll.
+                Ok(None)
+            });
+        b.property("Rate")
+            .get(|_, state: &mut State| Ok(state.snapshot().rate))
+            .set(|_, state: &mut State, rate: f64| {
+                // A rate of zero means pause, as the specification allows.
+                if rate <= 0.0 {
+                    state.command(Player::pause);
+                } else {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let speed = rate.clamp(MIN_RATE, MAX_RATE) as f32;
+                    state.command(|player| player.set_speed(speed));
+                }
+                Ok(None)
+            });
+        b.property("Shuffle")
+            .get(|_, state: &mut State| Ok(state.snapshot().shuffle))
+            .set(|_, state: &mut State, shuffle: bool| {
+                state.command(|player| {
+                    if shuffle && player.shuffle_mode() == ShuffleMode::Off {
+                        player.set_shuffle(ShuffleMode::Uniform, crate::new_shuffle_seed());
+                    } else if !shuffle {
+                        player.set_shuffle(ShuffleMode::Off, 0);
+                    }
+                });
+                Ok(None)
+            });
+        b.property("Metadata")
+            .get(|_, state: &mut State| Ok(state.snapshot().track.metadata()));
+        b.property("Volume")
+            .get(|_, state: &mut State| Ok(state.snapshot().volume))
+            .set(|_, state: &mut State, volume: f64| {
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let volume = (volume.clamp(0.0, 1.0) * f64::from(MAX_VOLUME)).round() as u16;
+                state.command(|player| {
+                    player.unmute();
+                    player.set_volume(volume);
+                });
+                Ok(None)
+            });
+        b.property("Position")
+            .get(|_, state: &mut State| Ok(micros(state.snapshot().position)))
+            .emits_changed_false();
+        b.property("MinimumRate")
+            .get(|_, _| Ok(MIN_RATE))
+            .emits_changed_const();
+        b.property("MaximumRate")
+            .get(|_, _| Ok(MAX_RATE))
+            .emits_changed_const();
+        b.property("CanGoNext")
+            .get(|_, state: &mut State| Ok(state.snapshot().can_go_next));
+        b.property("CanGoPrevious")
+            .get(|_, state: &mut State| Ok(state.snapshot().can_go_previous));
+        b.property("CanPlay")
+            .get(|_, state: &mut State| Ok(state.snapshot().can_play));
+        b.property("CanPause")
+            .get(|_, state: &mut State| Ok(state.snapshot().can_pause));
+        b.property("CanSeek")
+            .get(|_, state: &mut State| Ok(state.snapshot().can_seek));
+        b.property("CanControl")
+            .get(|_, _| Ok(true))
+            .emits_changed_const();
+    })
+}
+
+/// Resumes playback, or starts the current track of the playlist when stopped.
+fn play(player: &mut Player) {
+    if !player.is_stopped() {
+        player.resume();
+        return;
+    }
+    let index = player.playlist().current_index().unwrap_or(0);
+    player.play_index(index);
+}
+
+/// Seeks `offset` microseconds from the current position. Seeking past the end moves to the
+/// next track.
+fn seek(player: &mut Player, offset: i64) {
+    if player.is_stopped() {
+        return;
+    }
+    let offset_duration = Duration::from_micros(offset.unsigned_abs());
+    let position = if offset >= 0 {
+        player.elapsed() + offset_duration
+    } else {
+        player.elapsed().saturating_sub(offset_duration)
+    };
+    let past_end = player
+        .duration()
+        .map_or(false, |secs| position.as_secs_f64() > secs);
+    if past_end {
+        player.next();
+    } else {
+        player.seek_to(position);
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+fn micros(duration: Duration) -> i64 {
+    duration.as_micros() as i64
+}
+
+/// Returns the `file://` URL of `path`, escaping the bytes that can't appear in a URL.
+fn file_url(path: &Path) -> String {
+    let mut url = String::from("file://");
+    for &byte in path.to_string_lossy().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            url.push(char::from(byte));
+        } else {
+            url.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    url
+}
+
+/// Returns the path of a `file://` URL.
+fn file_path(url: &str) -> Option<PathBuf> {
+    let escaped = url.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut i = 0;
+    while i < escaped.len() {
+        if escaped[i] == b'%' {
+            let hex = std::str::from_utf8(escaped.get(i + 1..i + 3)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(escaped[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::Proxy;
+    use dbus::Message;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Private session bus, stopped when dropped.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("dbus-daemon must be installed to run the MPRIS tests");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+
+        fn connect(&self) -> Connection {
+            let mut channel = Channel::open_private(&self.address).unwrap();
+            channel.register().unwrap();
+            Connection::from(channel)
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    fn serve(bus: &Bus) -> (Arc<Mutex<Player>>, Mpris) {
+        let player = Arc::new(Mutex::new(Player::new()));
+        let mpris = Mpris::spawn_on(Some(&bus.address), player.clone(), || {}).unwrap();
+        (player, mpris)
+    }
+
+    fn proxy(connection: &Connection) -> Proxy<'_, &Connection> {
+        connection.with_proxy(BUS_NAME, OBJECT_PATH, TIMEOUT)
+    }
+
+    #[test]
+    fn exposes_root_properties() {
+        let bus = Bus::start();
+        let (_player, _mpris) = serve(&bus);
+        let client = bus.connect();
+        let proxy = proxy(&client);
+
+        let identity: String = proxy.get(ROOT_INTERFACE, "Identity").unwrap();
+        assert_eq!(identity, IDENTITY);
+        let can_quit: bool = proxy.get(ROOT_INTERFACE, "CanQuit").unwrap();
+        assert!(!can_quit);
+    }
+
+    #[test]
+    fn reports_stopped_player() {
+        let bus = Bus::start();
+        let (_player, _mpris) = serve(&bus);
+        let client = bus.connect();
+        let proxy = proxy(&client);
+
+        let status: String = proxy.get(PLAYER_INTERFACE, "PlaybackStatus").unwrap();
+        assert_eq!(status, "Stopped");
+        let position: i64 = proxy.get(PLAYER_INTERFACE, "Position").unwrap();
+        assert_eq!(position, 0);
+        let metadata: PropMap = proxy.get(PLAYER_INTERFACE, "Metadata").unwrap();
+        let track_id = metadata["mpris:trackid"].0.as_str().unwrap().to_string();
+        assert_eq!(track_id, NO_TRACK);
+    }
+
+    #[test]
+    fn sets_volume_rate_and_loop_status() {
+        let bus = Bus::start();
+        let (player, _mpris) = serve(&bus);
+        let client = bus.connect();
+        let proxy = proxy(&client);
+
+        proxy.set(PLAYER_INTERFACE, "Volume", 0.5).unwrap();
+        proxy.set(PLAYER_INTERFACE, "Rate", 1.5).unwrap();
+        proxy
+            .set(PLAYER_INTERFACE, "LoopStatus", "Playlist".to_string())
+            .unwrap();
+
+        let player = player.lock().unwrap();
+        assert_eq!(player.volume, MAX_VOLUME / 2);
+        assert!((player.speed - 1.5).abs() < f32::EPSILON);
+        assert_eq!(player.repeat(), RepeatMode::All);
+    }
+
+    #[test]
+    fn rejects_unknown_loop_status() {
+        let bus = Bus::start();
+        let (_player, _mpris) = serve(&bus);
+        let client = bus.connect();
+        let proxy = proxy(&client);
+
+        assert!(proxy
+            .set(PLAYER_INTERFACE, "LoopStatus", "Forever".to_string())
+            .is_err());
+    }
+
+    #[test]
+    fn announces_changes_made_by_the_app() {
+        let bus = Bus::start();
+        let (player, _mpris) = serve(&bus);
+        let client = bus.connect();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        {
+            let changes = changes.clone();
+            proxy(&client)
+                .match_signal(
+                    move |signal: PropertiesPropertiesChanged, _: &Connection, _: &Message| {
+                        changes.lock().unwrap().push(signal);
+                        true
+                    },
+                )
+                .unwrap();
+        }
+
+        // Lets a poll record the initial state first.
+        thread::sleep(POLL_INTERVAL * 2);
+        player.lock().unwrap().set_volume(MAX_VOLUME / 4);
+
+        let deadline = Instant::now() + TIMEOUT;
+        while changes.lock().unwrap().is_empty() && Instant::now() < deadline {
+            client.process(POLL_INTERVAL).unwrap();
+        }
+        let changes = changes.lock().unwrap();
+        let change = changes.first().expect("no PropertiesChanged signal");
+        assert_eq!(change.interface_name, PLAYER_INTERFACE);
+        let volume = change.changed_properties["Volume"].0.as_f64().unwrap();
+        assert!((volume - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn file_urls_round_trip() {
+        let path = Path::new("/music/Ça va/01 #1 100%.flac");
+        let url = file_url(path);
+        assert_eq!(url, "file:///music/%C3%87a%20va/01%20%231%20100%25.flac");
+        assert_eq!(file_path(&url).as_deref(), Some(path));
+        assert_eq!(file_path("http://example.com/a.mp3"), None);
+    }
+}
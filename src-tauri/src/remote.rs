@@ -0,0 +1,731 @@
+//! Remote control over HTTP, for phones and scripts on the local network.
+//!
+//! The REST endpoints under `/api` drive the same player as the Tauri commands, and
+//! `/api/events` is a WebSocket streaming the state events the UI receives. Every request must
+//! carry the access token, as an `Authorization: Bearer` header or, for browsers that can't set
+//! headers on a WebSocket, as a `token` query parameter.
+
+use crate::library::{Library, LibraryTrack};
+use crate::player::{Player, RepeatMode, ShuffleMode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+/// Address the server binds to unless another is configured. Only this machine can connect to
+/// it, so reaching it from the network is an explicit choice.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+/// Largest request body read, which is plenty for the JSON the endpoints take.
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+/// How often idle WebSockets are pinged, to notice clients that went away.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// Number of results of a library search.
+const MAX_SEARCH_RESULTS: usize = 200;
+/// Number of random bytes in an access token.
+const TOKEN_LEN: usize = 16;
+
+/// Fans the state events out to the connected WebSockets.
+#[derive(Default)]
+pub struct Events {
+    subscribers: Mutex<Vec<Sender<String>>>,
+}
+
+impl Events {
+    /// Sends `event` with its `payload` to every WebSocket.
+    pub fn publish<S: Serialize>(&self, event: &str, payload: &S) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let message = json!({ "event": event, "payload": payload }).to_string();
+        subscribers.retain(|subscriber| subscriber.send(message.clone()).is_ok());
+    }
+
+    fn subscribe(&self) -> Receiver<String> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+}
+
+/// Everything the request handlers work with.
+pub struct Context {
+    pub player: Arc<Mutex<Player>>,
+    pub library: Arc<RwLock<Library>>,
+    pub events: Arc<Events>,
+    /// Called after each change made through the API, so that the UI can catch up.
+    pub on_command: Box<dyn Fn() + Send + Sync>,
+}
+
+/// Handle to the running server. The server stops when this is dropped.
+pub struct Remote {
+    server: Arc<Server>,
+    address: SocketAddr,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Remote {
+    /// Starts serving the API on `address`, accepting requests that carry `token`.
+    pub fn start(address: &str, token: String, context: Context) -> Result<Self, String> {
+        let server = Arc::new(Server::http(address).map_err(|err| err.to_string())?);
+        let address = server
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| "not an IP address".to_string())?;
+        let context = Arc::new(context);
+
+        let thread = {
+            let server = server.clone();
+            thread::spawn(move || {
+                // Ends once the server is unblocked.
+                for request in server.incoming_requests() {
+                    // Checked before starting a thread, so that clients without the token can't
+                    // start any.
+                    if !is_authorized(&request, &token) {
+                        respond(request, Err(ApiError::new(401, "missing or wrong token")));
+                        continue;
+                    }
+                    // Each request gets a thread, so that a slow client or a long search doesn't
+                    // hold up the others.
+                    let context = context.clone();
+                    thread::spawn(move || handle(request, &context));
+                }
+            })
+        };
+        Ok(Self {
+            server,
+            address,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the address the server listens on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for Remote {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Returns a new random access token, read from the random number generator of the operating
+/// system.
+pub fn new_token() -> Result<String, String> {
+    let mut bytes = [0; TOKEN_LEN];
+    getrandom::getrandom(&mut bytes).map_err(|err| err.to_string())?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// State of the player reported by `GET /api/status`.
+#[derive(Debug, Serialize)]
+struct Status {
+    stopped: bool,
+    paused: bool,
+    path: Option<PathBuf>,
+    index: Option<usize>,
+    position_ms: u64,
+    duration_ms: Option<u64>,
+    volume: u16,
+    muted: bool,
+    speed: f32,
+    repeat: RepeatMode,
+    shuffle: ShuffleMode,
+}
+
+impl Status {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn of(player: &Player) -> Self {
+        let stopped = player.is_stopped();
+        Self {
+            stopped,
+            paused: player.is_paused(),
+            path: player
+                .current_path()
+                .filter(|_| !stopped)
+                .map(Path::to_path_buf),
+            index: player.playlist().current_index(),
+            position_ms: player.elapsed().as_millis() as u64,
+            duration_ms: player
+                .duration()
+                .filter(|_| !stopped)
+                .map(|secs| (secs.max(0.0) * 1000.0) as u64),
+            volume: player.volume,
+            muted: player.is_muted(),
+            speed: player.speed,
+            repeat: player.repeat(),
+            shuffle: player.shuffle_mode(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PlayRequest {
+    /// Index in the playlist of the track to play.
+    index: Option<usize>,
+    /// File to play, added to the playlist if it isn't in it.
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SeekRequest {
+    position_ms: Option<u64>,
+    delta_ms: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct VolumeRequest {
+    volume: Option<u16>,
+    muted: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EnqueueRequest {
+    path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplaceQueueRequest {
+    paths: Vec<PathBuf>,
+    #[serde(default)]
+    index: Option<usize>,
+}
+
+/// Error answered to a request.
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn not_found() -> Self {
+        Self::new(404, "not found")
+    }
+}
+
+type ApiResult = Result<Value, ApiError>;
+
+/// Answers `request`, which carries the token.
+fn handle(mut request: Request, context: &Context) {
+    let (path, query) = split_url(request.url());
+    if path == "/api/events" {
+        if *request.method() == Method::Get {
+            stream_events(request, &context.events);
+        } else {
+            respond(request, Err(ApiError::new(405, "method not allowed")));
+        }
+        return;
+    }
+
+    let body = match read_body(&mut request) {
+        Ok(body) => body,
+        Err(err) => {
+            respond(request, Err(err));
+            return;
+        }
+    };
+    let result = route(request.method(), &path, &query, &body, context);
+    respond(request, result);
+}
+
+/// Runs the endpoint at `path`.
+fn route(method: &Method, path: &str, query: &str, body: &[u8], context: &Context) -> ApiResult {
+    let segments: Vec<&str> = path
+        .trim_start_matches("/api")
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    match (method, segments.as_slice()) {
+        (Method::Get, ["status"]) => Ok(json!(Status::of(&context.player.lock().unwrap()))),
+        (Method::Get, ["queue"]) => Ok(json!(context.player.lock().unwrap().playlist())),
+        (Method::Get, ["search"]) => {
+            let query = query_param(query, "q").unwrap_or_default();
+            let library = context.library.read().unwrap();
+            let tracks: Vec<&LibraryTrack> = library
+                .search(&query)
+                .into_iter()
+                .take(MAX_SEARCH_RESULTS)
+                .collect();
+            Ok(json!(tracks))
+        }
+        (Method::Post, [action]) => {
+            let result = command(action, body, &mut context.player.lock().unwrap())?;
+            (context.on_command)();
+            Ok(result)
+        }
+        (Method::Put, ["queue"]) => {
+            let request: ReplaceQueueRequest = parse(body)?;
+            let mut player = context.player.lock().unwrap();
+            player.set_playlist(request.paths, request.index);
+            let playlist = json!(player.playlist());
+            drop(player);
+            (context.on_command)();
+            Ok(playlist)
+        }
+        (Method::Delete, ["queue", index]) => {
+            let index: usize = index
+                .parse()
+                .map_err(|_| ApiError::new(400, "invalid index"))?;
+            let mut player = context.player.lock().unwrap();
+            if index >= player.playlist().len() {
+                return Err(ApiError::not_found());
+            }
+            player.dequeue(index);
+            let playlist = json!(player.playlist());
+            drop(player);
+            (context.on_command)();
+            Ok(playlist)
+        }
+        _ => Err(ApiError::not_found()),
+    }
+}
+
+/// Runs the `POST` endpoint named `action`, and returns the new status of the player.
+fn command(action: &str, body: &[u8], player: &mut Player) -> ApiResult {
+    match action {
+        "play" => {
+            let request: PlayRequest = parse_or_default(body)?;
+            let played = match (request.index, request.path) {
+                (Some(index), _) => player.play_index(index),
+                (None, Some(path)) => {
+                    player.play(&path);
+                    true
+                }
+                (None, None) if player.is_stopped() => {
+                    let index = player.playlist().current_index().unwrap_or(0);
+                    player.play_index(index)
+                }
+                (None, None) => {
+                    player.resume();
+                    true
+                }
+            };
+            if !played {
+                return Err(ApiError::new(404, "no such track"));
+            }
+        }
+        "pause" => player.pause(),
+        "toggle" => {
+            if player.is_paused() {
+                player.resume();
+            } else {
+                player.pause();
+            }
+        }
+        "stop" => player.stop(),
+        "next" => {
+            player.next();
+        }
+        "previous" => {
+            player.previous();
+        }
+        "seek" => {
+            let request: SeekRequest = parse(body)?;
+            match (request.position_ms, request.delta_ms) {
+                (Some(position_ms), _) => player.seek_to(Duration::from_millis(position_ms)),
+                (None, Some(delta_ms)) => player.seek_relative(delta_ms),
+                (None, None) => {
+                    return Err(ApiError::new(400, "expected position_ms or delta_ms"));
+                }
+            }
+        }
+        "volume" => {
+            let request: VolumeRequest = parse(body)?;
+            if let Some(volume) = request.volume {
+                player.set_volume(volume);
+            }
+            match request.muted {
+                Some(true) => player.mute(),
+                Some(false) => player.unmute(),
+                None => {}
+            }
+        }
+        "queue" => {
+            let request: EnqueueRequest = parse(body)?;
+            player.enqueue(request.path);
+            return Ok(json!(player.playlist()));
+        }
+        _ => return Err(ApiError::not_found()),
+    }
+    Ok(json!(Status::of(player)))
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(body: &[u8]) -> Result<T, ApiError> {
+    serde_json::from_slice(body).map_err(|err| ApiError::new(400, err.to_string()))
+}
+
+/// Parses `body`, which may be left out.
+fn parse_or_default<T: for<'de> Deserialize<'de> + Default>(body: &[u8]) -> Result<T, ApiError> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        Ok(T::default())
+    } else {
+        parse(body)
+    }
+}
+
+fn read_body(request: &mut Request) -> Result<Vec<u8>, ApiError> {
+    if request
+        .body_length()
+        .map_or(false, |len| len as u64 > MAX_BODY_SIZE)
+    {
+        return Err(ApiError::new(413, "request body too large"));
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_SIZE)
+        .read_to_end(&mut body)
+        .map_err(|err| ApiError::new(400, err.to_string()))?;
+    Ok(body)
+}
+
+fn respond(request: Request, result: ApiResult) {
+    let (status, body) = match result {
+        Ok(body) => (200, body),
+        Err(err) => (err.status, json!({ "error": err.message })),
+    };
+    let response = Response::from_string(body.to_string())
+        .with_status_code(StatusCode(status))
+        .with_header(header("Content-Type", "application/json"));
+    // Fails only if the client went away.
+    let _ = request.respond(response);
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn header_value<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Returns the path and the query string of `url`.
+fn split_url(url: &str) -> (String, String) {
+    match url.split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (url.to_string(), String::new()),
+    }
+}
+
+fn is_authorized(request: &Request, token: &str) -> bool {
+    let (_, query) = split_url(request.url());
+    let bearer = header_value(request, "Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+    bearer
+        .or_else(|| query_param(&query, "token"))
+        .map_or(false, |given| {
+            constant_time_eq(given.as_bytes(), token.as_bytes())
+        })
+}
+
+/// Compares in a time that doesn't depend on where the first difference is, so that the token
+/// can't be guessed a byte at a time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Returns the decoded value of the parameter `name` of a query string.
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes[i] {
+            b'%' => bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match (escaped, bytes[i]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (None, b'+') => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (None, byte) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Upgrades `request` to a WebSocket and sends it the events on a thread of its own.
+fn stream_events(request: Request, events: &Events) {
+    let key = match header_value(&request, "Sec-WebSocket-Key") {
+        Some(key) => key.to_string(),
+        None => {
+            respond(request, Err(ApiError::new(400, "expected a WebSocket")));
+            return;
+        }
+    };
+    let accept = tungstenite::handshake::derive_accept_key(key.as_bytes());
+    let response = Response::empty(101).with_header(header("Sec-WebSocket-Accept", &accept));
+
+    let receiver = events.subscribe();
+    let stream = request.upgrade("websocket", response);
+    thread::spawn(move || {
+        // Messages from the client are never read: the stream only goes one way.
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        loop {
+            let message = match receiver.recv_timeout(PING_INTERVAL) {
+                Ok(event) => Message::Text(event),
+                Err(RecvTimeoutError::Timeout) => Message::Ping(Vec::new()),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            if socket.write_message(message).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const TOKEN: &str = "secret";
+
+    struct TestServer {
+        player: Arc<Mutex<Player>>,
+        events: Arc<Events>,
+        commands: Arc<AtomicUsize>,
+        remote: Remote,
+    }
+
+    fn serve() -> TestServer {
+        let player = Arc::new(Mutex::new(Player::new_offline(2, 44_100)));
+        let events = Arc::new(Events::default());
+        let commands = Arc::new(AtomicUsize::new(0));
+        let context = Context {
+            player: player.clone(),
+            library: Arc::new(RwLock::new(Library::default())),
+            events: events.clone(),
+            on_command: {
+                let commands = commands.clone();
+                Box::new(move || {
+                    commands.fetch_add(1, Ordering::SeqCst);
+                })
+            },
+        };
+        let remote = Remote::start("127.0.0.1:0", TOKEN.to_string(), context).unwrap();
+        TestServer {
+            player,
+            events,
+            commands,
+            remote,
+        }
+    }
+
+    /// Sends a request through a real connection, and returns the status and body of the
+    /// response.
+    fn request(
+        server: &TestServer,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+        body: &str,
+    ) -> (u16, Value) {
+        let mut stream = TcpStream::connect(server.remote.address()).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let authorization = token
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            authorization,
+            body.len(),
+            body
+        )
+        .unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).unwrap();
+        let status = status_line.split(' ').nth(1).unwrap().parse().unwrap();
+        let mut response = String::new();
+        reader.read_to_string(&mut response).unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn makes_random_tokens() {
+        let token = new_token().unwrap();
+        assert_eq!(token.len(), TOKEN_LEN * 2);
+        assert!(token.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_ne!(token, new_token().unwrap());
+    }
+
+    #[test]
+    fn rejects_requests_without_the_token() {
+        let server = serve();
+        let unauthorized = json!({ "error": "missing or wrong token" });
+
+        assert_eq!(
+            request(&server, "GET", "/api/status", None, ""),
+            (401, unauthorized.clone())
+        );
+        assert_eq!(
+            request(&server, "GET", "/api/status", Some("wrong"), ""),
+            (401, unauthorized.clone())
+        );
+        assert_eq!(
+            request(&server, "GET", "/api/status?token=secre", None, ""),
+            (401, unauthorized.clone())
+        );
+        assert_eq!(
+            request(&server, "POST", "/api/stop", Some("wrong"), ""),
+            (401, unauthorized)
+        );
+        assert_eq!(server.commands.load(Ordering::SeqCst), 0);
+
+        assert_eq!(
+            request(&server, "GET", "/api/status", Some(TOKEN), "").0,
+            200
+        );
+        assert_eq!(
+            request(&server, "GET", "/api/status?token=secret", None, "").0,
+            200
+        );
+    }
+
+    #[test]
+    fn routes_commands_to_the_player() {
+        let server = serve();
+
+        let (status, body) = request(
+            &server,
+            "POST",
+            "/api/volume",
+            Some(TOKEN),
+            r#"{"volume": 30, "muted": true}"#,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body["volume"], 30);
+        assert_eq!(body["muted"], true);
+        assert_eq!(server.player.lock().unwrap().volume, 30);
+        assert_eq!(server.commands.load(Ordering::SeqCst), 1);
+
+        let (status, body) = request(&server, "GET", "/api/status", Some(TOKEN), "");
+        assert_eq!(status, 200);
+        assert_eq!(body["volume"], 30);
+        assert_eq!(body["stopped"], true);
+
+        let (status, body) = request(
+            &server,
+            "POST",
+            "/api/queue",
+            Some(TOKEN),
+            r#"{"path": "/music/a.flac"}"#,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(server.player.lock().unwrap().playlist().len(), 1);
+        assert_eq!(body, json!(server.player.lock().unwrap().playlist()));
+
+        assert_eq!(
+            request(&server, "DELETE", "/api/queue/3", Some(TOKEN), "").0,
+            404
+        );
+        assert_eq!(
+            request(&server, "DELETE", "/api/queue/0", Some(TOKEN), "").0,
+            200
+        );
+        assert_eq!(server.player.lock().unwrap().playlist().len(), 0);
+
+        assert_eq!(
+            request(&server, "POST", "/api/seek", Some(TOKEN), "{}").0,
+            400
+        );
+        assert_eq!(
+            request(&server, "POST", "/api/rewind", Some(TOKEN), "").0,
+            404
+        );
+        assert_eq!(server.commands.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn streams_events_over_a_websocket() {
+        let server = serve();
+
+        assert_eq!(
+            request(&server, "GET", "/api/events", Some(TOKEN), "").0,
+            400
+        );
+        assert_eq!(
+            request(&server, "POST", "/api/events", Some(TOKEN), "").0,
+            405
+        );
+
+        let url = format!(
+            "ws://{}/api/events?token={}",
+            server.remote.address(),
+            TOKEN
+        );
+        let (mut socket, response) = tungstenite::connect(url).unwrap();
+        assert_eq!(response.status(), 101);
+
+        server.events.publish("volume", &json!({ "volume": 30 }));
+        let message = socket.read_message().unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(message.to_text().unwrap()).unwrap(),
+            json!({ "event": "volume", "payload": { "volume": 30 } })
+        );
+
+        let url = format!("ws://{}/api/events?token=wrong", server.remote.address());
+        assert!(tungstenite::connect(url).is_err());
+    }
+}
@@ -0,0 +1,1054 @@
+//! Scrobbling to Last.fm, ListenBrainz, and the services that speak either protocol, such as
+//! Libre.fm or a self-hosted server.
+//!
+//! A track is scrobbled once half of it, or 4 minutes of it, was heard, as both services ask.
+//! Scrobbles are queued on disk first, and submitted in batches by a background thread, so that
+//! the ones made offline are submitted once the service can be reached again. "Now playing"
+//! notices only matter while the track plays, so they aren't queued.
+
+use crate::player::Listen;
+use crate::storage;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Base URL of the Last.fm API.
+pub const LASTFM_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+/// Base URL of the ListenBrainz API.
+pub const LISTENBRAINZ_URL: &str = "https://api.listenbrainz.org";
+/// Name of the scrobble queue file in the app data directory.
+const QUEUE_FILE: &str = "scrobbles.json";
+/// Version of the layout of the scrobble queue file.
+const QUEUE_VERSION: u32 = 1;
+/// Name the player gives the services.
+const CLIENT_NAME: &str = "Plain Music Player";
+/// Tracks this short or shorter aren't scrobbled.
+const MIN_DURATION: Duration = Duration::from_secs(30);
+/// Hearing this much of a track scrobbles it, even if it is less than half of it.
+const MAX_LISTEN_REQUIRED: Duration = Duration::from_secs(4 * 60);
+/// Most scrobbles submitted in a request, the limit of Last.fm.
+const BATCH_SIZE: usize = 50;
+/// How long to wait before submitting again after the service couldn't be reached.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// How long a request may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A scrobbling service, and the credentials of the account.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "protocol")]
+pub enum Service {
+    #[serde(rename = "listenbrainz")]
+    ListenBrainz {
+        /// Base URL of the API. `None` uses ListenBrainz.
+        base_url: Option<String>,
+        /// Token of the user.
+        token: String,
+    },
+    #[serde(rename = "lastfm")]
+    LastFm {
+        /// Base URL of the API. `None` uses Last.fm.
+        base_url: Option<String>,
+        api_key: String,
+        api_secret: String,
+        /// Key of the session opened by `login`. Nothing is submitted until then.
+        session_key: Option<String>,
+    },
+}
+
+/// What the UI is shown of a `Service`: everything but the secrets.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "protocol")]
+pub enum ServiceInfo {
+    #[serde(rename = "listenbrainz")]
+    ListenBrainz { base_url: Option<String> },
+    #[serde(rename = "lastfm")]
+    LastFm {
+        base_url: Option<String>,
+        api_key: String,
+        /// Whether `login` opened a session.
+        logged_in: bool,
+    },
+}
+
+impl Service {
+    /// Returns the settings of the service, without the token, the API secret and the session
+    /// key.
+    pub fn info(&self) -> ServiceInfo {
+        match self {
+            Self::ListenBrainz { base_url, .. } => ServiceInfo::ListenBrainz {
+                base_url: base_url.clone(),
+            },
+            Self::LastFm {
+                base_url,
+                api_key,
+                session_key,
+                ..
+            } => ServiceInfo::LastFm {
+                base_url: base_url.clone(),
+                api_key: api_key.clone(),
+                logged_in: session_key.is_some(),
+            },
+        }
+    }
+
+    /// Tells the service that the track of `scrobble` started playing.
+    pub fn now_playing(&self, scrobble: &Scrobble) -> Result<(), ScrobbleError> {
+        let scrobbles = slice::from_ref(scrobble);
+        match self {
+            Self::ListenBrainz { base_url, token } => submit_listens(
+                base_url.as_deref().unwrap_or(LISTENBRAINZ_URL),
+                token,
+                "playing_now",
+                scrobbles,
+            ),
+            Self::LastFm { .. } => self
+                .call_last_fm("track.updateNowPlaying", last_fm_tracks(scrobbles, false))
+                .map(drop),
+        }
+    }
+
+    /// Submits `scrobbles`, at most `BATCH_SIZE` of them.
+    pub fn submit(&self, scrobbles: &[Scrobble]) -> Result<(), ScrobbleError> {
+        match self {
+            Self::ListenBrainz { base_url, token } => {
+                let listen_type = if scrobbles.len() == 1 {
+                    "single"
+                } else {
+                    "import"
+                };
+                submit_listens(
+                    base_url.as_deref().unwrap_or(LISTENBRAINZ_URL),
+                    token,
+                    listen_type,
+                    scrobbles,
+                )
+            }
+            Self::LastFm { .. } => self
+                .call_last_fm("track.scrobble", last_fm_tracks(scrobbles, true))
+                .map(drop),
+        }
+    }
+
+    /// Opens a Last.fm session for `username`, and returns the service with its key.
+    pub fn login(&self, username: &str, password: &str) -> Result<Self, ScrobbleError> {
+        let (base_url, api_key, api_secret) = match self {
+            Self::LastFm {
+                base_url,
+                api_key,
+                api_secret,
+                ..
+            } => (base_url, api_key, api_secret),
+            Self::ListenBrainz { .. } => {
+                return Err(ScrobbleError::Rejected(
+                    "ListenBrainz takes a token instead".to_string(),
+                ))
+            }
+        };
+
+        let mut params = BTreeMap::new();
+        params.insert("username".to_string(), username.to_string());
+        params.insert("password".to_string(), password.to_string());
+        let response = call_last_fm(
+            base_url.as_deref().unwrap_or(LASTFM_URL),
+            api_key,
+            api_secret,
+            "auth.getMobileSession",
+            params,
+        )?;
+        let session_key = response
+            .pointer("/session/key")
+            .and_then(Value::as_str)
+            .ok_or_else(|| ScrobbleError::Rejected("no session in the response".to_string()))?;
+        Ok(Self::LastFm {
+            base_url: base_url.clone(),
+            api_key: api_key.clone(),
+            api_secret: api_secret.clone(),
+            session_key: Some(session_key.to_string()),
+        })
+    }
+
+    /// Calls `method` of the Last.fm API in the session of the user.
+    fn call_last_fm(
+        &self,
+        method: &str,
+        mut params: BTreeMap<String, String>,
+    ) -> Result<Value, ScrobbleError> {
+        match self {
+            Self::LastFm {
+                base_url,
+                api_key,
+                api_secret,
+                session_key: Some(session_key),
+            } => {
+                params.insert("sk".to_string(), session_key.clone());
+                call_last_fm(
+                    base_url.as_deref().unwrap_or(LASTFM_URL),
+                    api_key,
+                    api_secret,
+                    method,
+                    params,
+                )
+            }
+            _ => Err(ScrobbleError::Unauthorized(
+                "not logged in to Last.fm".to_string(),
+            )),
+        }
+    }
+}
+
+/// A listen to a track, as submitted to the services.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scrobble {
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    /// Duration of the track in seconds, if known.
+    pub duration: Option<u64>,
+    /// When the track started, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl Scrobble {
+    /// Returns the scrobble of a track with the tags `fields`, as read by `track::tag_fields`,
+    /// that lasts `duration` and started at `started`. Returns `None` if the track has no artist
+    /// or title, which the services require.
+    pub fn new(
+        fields: &HashMap<String, String>,
+        duration: Option<Duration>,
+        started: SystemTime,
+    ) -> Option<Self> {
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        Some(Self {
+            artist: field("artist")?,
+            title: field("title")?,
+            album: field("album"),
+            album_artist: field("albumartist"),
+            // The number of a track may be followed by the total, as in `3/12`.
+            track_number: field("track")
+                .and_then(|track| track.split('/').next()?.trim().parse().ok()),
+            duration: duration.map(|duration| duration.as_secs()),
+            timestamp: started
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |started| started.as_secs()),
+        })
+    }
+}
+
+/// Returns `true` if `listen` is scrobbled: its track is longer than 30 seconds, and half of it
+/// or 4 minutes of it were heard. Tracks of unknown duration take the 4 minutes.
+pub fn is_scrobbled(listen: &Listen) -> bool {
+    match listen.duration {
+        Some(duration) => {
+            duration > MIN_DURATION && listen.listened >= (duration / 2).min(MAX_LISTEN_REQUIRED)
+        }
+        None => listen.listened >= MAX_LISTEN_REQUIRED,
+    }
+}
+
+/// Why a request to a service failed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScrobbleError {
+    /// The service couldn't be reached, or is unavailable for now. Scrobbles are submitted again
+    /// later.
+    Unavailable(String),
+    /// The service refused the credentials. Scrobbles are submitted again later, which succeeds
+    /// once the credentials are fixed.
+    Unauthorized(String),
+    /// The service refused the request itself. Scrobbles it refused are dropped.
+    Rejected(String),
+}
+
+impl fmt::Display for ScrobbleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable(message) => write!(f, "service unavailable: {}", message),
+            Self::Unauthorized(message) => write!(f, "not authorized: {}", message),
+            Self::Rejected(message) => write!(f, "rejected: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ScrobbleError {}
+
+impl From<attohttpc::Error> for ScrobbleError {
+    fn from(err: attohttpc::Error) -> Self {
+        Self::Unavailable(err.to_string())
+    }
+}
+
+/// Submits `scrobbles` to the ListenBrainz API at `base_url`, as listens of `listen_type`.
+fn submit_listens(
+    base_url: &str,
+    token: &str,
+    listen_type: &str,
+    scrobbles: &[Scrobble],
+) -> Result<(), ScrobbleError> {
+    let payload: Vec<Value> = scrobbles
+        .iter()
+        .map(|scrobble| {
+            let mut listen = json!({ "track_metadata": listenbrainz_metadata(scrobble) });
+            // Tracks playing now haven't been listened to yet.
+            if listen_type != "playing_now" {
+                listen["listened_at"] = json!(scrobble.timestamp);
+            }
+            listen
+        })
+        .collect();
+    let body = json!({ "listen_type": listen_type, "payload": payload });
+
+    let url = format!("{}/1/submit-listens", base_url.trim_end_matches('/'));
+    let response = attohttpc::post(url)
+        .timeout(TIMEOUT)
+        .header("Authorization", format!("Token {}", token))
+        .json(&body)?
+        .send()?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let message = response.text().unwrap_or_default();
+    Err(match status.as_u16() {
+        401 | 403 => ScrobbleError::Unauthorized(message),
+        429 | 500..=599 => ScrobbleError::Unavailable(message),
+        _ => ScrobbleError::Rejected(message),
+    })
+}
+
+fn listenbrainz_metadata(scrobble: &Scrobble) -> Value {
+    let mut info = json!({
+        "media_player": CLIENT_NAME,
+        "submission_client": CLIENT_NAME,
+        "submission_client_version": env!("CARGO_PKG_VERSION"),
+    });
+    if let Some(duration) = scrobble.duration {
+        info["duration"] = json!(duration);
+    }
+    if let Some(track_number) = scrobble.track_number {
+        info["tracknumber"] = json!(track_number);
+    }
+    let mut metadata = json!({
+        "artist_name": scrobble.artist,
+        "track_name": scrobble.title,
+        "additional_info": info,
+    });
+    if let Some(album) = &scrobble.album {
+        metadata["release_name"] = json!(album);
+    }
+    metadata
+}
+
+/// Returns the parameters of the Last.fm API describing `scrobbles`. Scrobbles are `indexed`
+/// and carry their timestamps when submitted, but not in "now playing" notices.
+fn last_fm_tracks(scrobbles: &[Scrobble], indexed: bool) -> BTreeMap<String, String> {
+    let mut params = BTreeMap::new();
+    for (index, scrobble) in scrobbles.iter().enumerate() {
+        let mut add = |name: &str, value: String| {
+            let name = if indexed {
+                format!("{}[{}]", name, index)
+            } else {
+                name.to_string()
+            };
+            params.insert(name, value);
+        };
+        add("artist", scrobble.artist.clone());
+        add("track", scrobble.title.clone());
+        if indexed {
+            add("timestamp", scrobble.timestamp.to_string());
+        }
+        if let Some(album) = &scrobble.album {
+            add("album", album.clone());
+        }
+        if let Some(album_artist) = &scrobble.album_artist {
+            add("albumArtist", album_artist.clone());
+        }
+        if let Some(track_number) = scrobble.track_number {
+            add("trackNumber", track_number.to_string());
+        }
+        if let Some(duration) = scrobble.duration {
+            add("duration", duration.to_string());
+        }
+    }
+    params
+}
+
+/// Calls `method` of the Last.fm API at `base_url` with `params`, signed with `api_secret`, and
+/// returns its response.
+fn call_last_fm(
+    base_url: &str,
+    api_key: &str,
+    api_secret: &str,
+    method: &str,
+    mut params: BTreeMap<String, String>,
+) -> Result<Value, ScrobbleError> {
+    params.insert("method".to_string(), method.to_string());
+    params.insert("api_key".to_string(), api_key.to_string());
+    let signature = last_fm_signature(&params, api_secret);
+    params.insert("api_sig".to_string(), signature);
+    params.insert("format".to_string(), "json".to_string());
+
+    let response = attohttpc::post(base_url)
+        .timeout(TIMEOUT)
+        .form(&params)?
+        .send()?;
+    let status = response.status();
+    let text = response.text()?;
+    let value: Value = serde_json::from_str(&text).unwrap_or(Value::Null);
+    if let Some(code) = value.get("error").and_then(Value::as_u64) {
+        let message = value
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        return Err(match code {
+            // Authentication failed, invalid API key or session, or suspended API key.
+            4 | 9 | 10 | 26 => ScrobbleError::Unauthorized(message),
+            // Operation failed, service offline or temporarily unavailable, or rate limit.
+            8 | 11 | 16 | 29 => ScrobbleError::Unavailable(message),
+            _ => ScrobbleError::Rejected(message),
+        });
+    }
+    if status.is_success() {
+        Ok(value)
+    } else if status.is_server_error() {
+        Err(ScrobbleError::Unavailable(text))
+    } else {
+        Err(ScrobbleError::Rejected(text))
+    }
+}
+
+/// Returns the signature of `params`: the MD5 of their names and values, sorted by name, then
+/// of `api_secret`.
+fn last_fm_signature(params: &BTreeMap<String, String>, api_secret: &str) -> String {
+    let mut text = String::new();
+    for (name, value) in params {
+        text.push_str(name);
+        text.push_str(value);
+    }
+    text.push_str(api_secret);
+    format!("{:x}", md5::compute(text))
+}
+
+/// Scrobbles waiting to be submitted, oldest first.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Queue {
+    scrobbles: Vec<Scrobble>,
+}
+
+impl Queue {
+    /// Returns the path of the scrobble queue file in `app_dir`.
+    pub fn path(app_dir: &Path) -> PathBuf {
+        app_dir.join(QUEUE_FILE)
+    }
+
+    /// Reads the queue from `path`, falling back to an empty queue if the file is missing or
+    /// can't be parsed.
+    pub fn load(path: &Path) -> Self {
+        storage::load(path, QUEUE_VERSION, Self::migrate).unwrap_or_default()
+    }
+
+    /// Writes the queue to `path`, creating the parent directory if needed.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        storage::save(path, QUEUE_VERSION, self)
+    }
+
+    /// Upgrades a queue written by an older version.
+    fn migrate(_version: u32, _value: &mut Value) {}
+
+    pub fn scrobbles(&self) -> &[Scrobble] {
+        &self.scrobbles
+    }
+}
+
+/// State of the queue, as reported to the UI.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct QueueStatus {
+    /// Number of scrobbles waiting to be submitted.
+    pub queued: usize,
+    /// Why the last submission failed, until one succeeds.
+    pub error: Option<String>,
+}
+
+enum Message {
+    NowPlaying(Scrobble),
+    Scrobble(Scrobble),
+    SetService(Option<Service>),
+    Submit,
+}
+
+/// Handle to the thread submitting the scrobbles. The thread ends when this is dropped.
+pub struct Scrobbler {
+    sender: Mutex<Sender<Message>>,
+    status: Arc<Mutex<QueueStatus>>,
+}
+
+impl Scrobbler {
+    /// Starts submitting scrobbles to `service`, beginning with those left in the queue file at
+    /// `queue_path`. Without a service, scrobbles are queued until one is set.
+    pub fn spawn(service: Option<Service>, queue_path: Option<PathBuf>) -> Self {
+        let queue = queue_path.as_deref().map(Queue::load).unwrap_or_default();
+        let status = Arc::new(Mutex::new(QueueStatus {
+            queued: queue.scrobbles.len(),
+            error: None,
+        }));
+        let worker = Worker {
+            service,
+            queue,
+            queue_path,
+            status: status.clone(),
+            // Scrobbles left from the last run are submitted right away.
+            retry_at: Some(Instant::now()),
+        };
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || worker.run(&receiver));
+        Self {
+            sender: Mutex::new(sender),
+            status,
+        }
+    }
+
+    /// Sends a "now playing" notice for `scrobble`.
+    pub fn now_playing(&self, scrobble: Scrobble) {
+        self.send(Message::NowPlaying(scrobble));
+    }
+
+    /// Queues `scrobble`, and submits the queue unless the service was unavailable moments ago.
+    pub fn scrobble(&self, scrobble: Scrobble) {
+        self.send(Message::Scrobble(scrobble));
+    }
+
+    /// Submits the queue to `service` from now on, or stops submitting if `None`.
+    pub fn set_service(&self, service: Option<Service>) {
+        self.send(Message::SetService(service));
+    }
+
+    /// Submits the queue now, without waiting for the next retry.
+    pub fn submit(&self) {
+        self.send(Message::Submit);
+    }
+
+    /// Returns the state of the queue.
+    pub fn status(&self) -> QueueStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn send(&self, message: Message) {
+        // The thread only ends once the handle is dropped.
+        let _ = self.sender.lock().unwrap().send(message);
+    }
+}
+
+/// The thread submitting the scrobbles.
+struct Worker {
+    service: Option<Service>,
+    queue: Queue,
+    queue_path: Option<PathBuf>,
+    status: Arc<Mutex<QueueStatus>>,
+    /// When to submit again after the service couldn't be reached.
+    retry_at: Option<Instant>,
+}
+
+impl Worker {
+    fn run(mut self, receiver: &Receiver<Message>) {
+        loop {
+            let message = match self.retry_at {
+                Some(retry_at) => {
+                    match receiver.recv_timeout(retry_at.saturating_duration_since(Instant::now()))
+                    {
+                        Ok(message) => message,
+                        Err(RecvTimeoutError::Timeout) => Message::Submit,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                None => match receiver.recv() {
+                    Ok(message) => message,
+                    Err(_) => return,
+                },
+            };
+
+            match message {
+                Message::NowPlaying(scrobble) => {
+                    if let Some(service) = &self.service {
+                        if let Err(err) = service.now_playing(&scrobble) {
+                            eprintln!("failed to send now playing: {}", err);
+                        }
+                    }
+                }
+                Message::Scrobble(scrobble) => {
+                    self.queue.scrobbles.push(scrobble);
+                    self.save();
+                    // While the service is unavailable, scrobbles wait for the next retry.
+                    if self.retry_at.is_none() {
+                        self.submit();
+                    }
+                }
+                Message::SetService(service) => {
+                    self.service = service;
+                    self.submit();
+                }
+                Message::Submit => self.submit(),
+            }
+        }
+    }
+
+    /// Submits the queue in batches, until it is empty or the service fails.
+    fn submit(&mut self) {
+        self.retry_at = None;
+        let service = match &self.service {
+            Some(service) => service,
+            None => return,
+        };
+
+        let mut result = Ok(());
+        while !self.queue.scrobbles.is_empty() {
+            let count = self.queue.scrobbles.len().min(BATCH_SIZE);
+            let done = match service.submit(&self.queue.scrobbles[..count]) {
+                Ok(()) => count,
+                // The service may refuse a batch for a single bad scrobble, so the batch is
+                // submitted again a scrobble at a time to only drop the ones it refuses.
+                Err(ScrobbleError::Rejected(_)) if count > 1 => {
+                    let mut done = 0;
+                    for scrobble in &self.queue.scrobbles[..count] {
+                        match service.submit(slice::from_ref(scrobble)) {
+                            Ok(()) => {}
+                            Err(ScrobbleError::Rejected(message)) => {
+                                eprintln!("dropped a rejected scrobble: {}", message);
+                            }
+                            Err(err) => {
+                                result = Err(err);
+                                break;
+                            }
+                        }
+                        done += 1;
+                    }
+                    done
+                }
+                Err(ScrobbleError::Rejected(message)) => {
+                    eprintln!("dropped a rejected scrobble: {}", message);
+                    count
+                }
+                Err(err) => {
+                    result = Err(err);
+                    0
+                }
+            };
+            if done > 0 {
+                self.queue.scrobbles.drain(..done);
+                self.save();
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+
+        if result.is_err() {
+            self.retry_at = Some(Instant::now() + RETRY_INTERVAL);
+        }
+        self.status.lock().unwrap().error = result.err().map(|err| err.to_string());
+    }
+
+    /// Writes the queue to disk.
+    fn save(&self) {
+        if let Some(path) = &self.queue_path {
+            if let Err(err) = self.queue.save(path) {
+                eprintln!("failed to save scrobble queue: {}", err);
+            }
+        }
+        self.status.lock().unwrap().queued = self.queue.scrobbles.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempFile;
+    use std::sync::atomic::{AtomicU16, Ordering};
+    use tiny_http::{Response, Server};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// A request received by `MockService`.
+    struct Received {
+        url: String,
+        authorization: Option<String>,
+        body: String,
+    }
+
+    /// A local server standing in for a scrobbling service, which answers every request with
+    /// the same status and body, except the requests rejected with `reject`.
+    struct MockService {
+        server: Arc<Server>,
+        url: String,
+        status: Arc<AtomicU16>,
+        body: Arc<Mutex<String>>,
+        /// Text of the requests answered with a 400 error.
+        rejected: Arc<Mutex<Option<String>>>,
+        requests: Receiver<Received>,
+    }
+
+    impl MockService {
+        fn start(body: &str) -> Self {
+            let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+            let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+            let status = Arc::new(AtomicU16::new(200));
+            let body = Arc::new(Mutex::new(body.to_string()));
+            let rejected = Arc::new(Mutex::new(None::<String>));
+            let (sender, requests) = mpsc::channel();
+            {
+                let server = server.clone();
+                let status = status.clone();
+                let body = body.clone();
+                let rejected = rejected.clone();
+                thread::spawn(move || {
+                    for mut request in server.incoming_requests() {
+                        let authorization = request
+                            .headers()
+                            .iter()
+                            .find(|header| header.field.equiv("Authorization"))
+                            .map(|header| header.value.to_string());
+                        let mut received = String::new();
+                        request.as_reader().read_to_string(&mut received).unwrap();
+                        let is_rejected = rejected
+                            .lock()
+                            .unwrap()
+                            .as_ref()
+                            .map_or(false, |text| received.contains(text.as_str()));
+                        let _ = sender.send(Received {
+                            url: request.url().to_string(),
+                            authorization,
+                            body: received,
+                        });
+                        let response = if is_rejected {
+                            Response::from_string(r#"{"code":400,"error":"Invalid listen"}"#)
+                                .with_status_code(400)
+                        } else {
+                            Response::from_string(body.lock().unwrap().clone())
+                                .with_status_code(status.load(Ordering::SeqCst))
+                        };
+                        let _ = request.respond(response);
+                    }
+                });
+            }
+            Self {
+                server,
+                url,
+                status,
+                body,
+                rejected,
+                requests,
+            }
+        }
+
+        /// Answers the requests containing `text` with a 400 error.
+        fn reject(&self, text: &str) {
+            *self.rejected.lock().unwrap() = Some(text.to_string());
+        }
+
+        fn next_request(&self) -> Received {
+            self.requests.recv_timeout(WAIT).unwrap()
+        }
+
+        fn listenbrainz(&self) -> Service {
+            Service::ListenBrainz {
+                base_url: Some(self.url.clone()),
+                token: "token".to_string(),
+            }
+        }
+
+        fn last_fm(&self, session_key: Option<&str>) -> Service {
+            Service::LastFm {
+                base_url: Some(format!("{}/2.0/", self.url)),
+                api_key: "key".to_string(),
+                api_secret: "secret".to_string(),
+                session_key: session_key.map(str::to_string),
+            }
+        }
+    }
+
+    impl Drop for MockService {
+        fn drop(&mut self) {
+            self.server.unblock();
+        }
+    }
+
+    fn scrobble(index: u64) -> Scrobble {
+        Scrobble {
+            artist: "Artist".to_string(),
+            title: format!("Title {}", index),
+            album: Some("Album".to_string()),
+            album_artist: None,
+            track_number: Some(3),
+            duration: Some(200),
+            timestamp: 1_654_041_600 + index,
+        }
+    }
+
+    /// Returns the fields of a form, decoded.
+    fn form(body: &str) -> BTreeMap<String, String> {
+        let decode = |text: &str| {
+            let text = text.replace('+', " ");
+            let mut bytes = Vec::new();
+            let mut rest = text.as_bytes();
+            while let Some((&byte, tail)) = rest.split_first() {
+                if byte == b'%' && tail.len() >= 2 {
+                    let hex = std::str::from_utf8(&tail[..2]).unwrap();
+                    bytes.push(u8::from_str_radix(hex, 16).unwrap());
+                    rest = &tail[2..];
+                } else {
+                    bytes.push(byte);
+                    rest = tail;
+                }
+            }
+            String::from_utf8(bytes).unwrap()
+        };
+        body.split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(name, value)| (decode(name), decode(value)))
+            .collect()
+    }
+
+    fn wait_for(scrobbler: &Scrobbler, check: impl Fn(&QueueStatus) -> bool) {
+        let deadline = Instant::now() + WAIT;
+        while !check(&scrobbler.status()) {
+            assert!(Instant::now() < deadline, "status {:?}", scrobbler.status());
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn listen(listened: u64, duration: Option<u64>) -> Listen {
+        Listen {
+            path: PathBuf::from("/music/track.flac"),
+            started: SystemTime::now(),
+            position: Duration::from_secs(listened),
+            listened: Duration::from_secs(listened),
+            duration: duration.map(Duration::from_secs),
+            end: crate::player::ListenEnd::Played,
+        }
+    }
+
+    #[test]
+    fn scrobbles_after_half_of_the_track_or_four_minutes() {
+        assert!(is_scrobbled(&listen(100, Some(200))));
+        assert!(!is_scrobbled(&listen(99, Some(200))));
+        assert!(is_scrobbled(&listen(240, Some(1200))));
+        assert!(!is_scrobbled(&listen(239, Some(1200))));
+        assert!(!is_scrobbled(&listen(30, Some(30))));
+        assert!(is_scrobbled(&listen(240, None)));
+        assert!(!is_scrobbled(&listen(200, None)));
+    }
+
+    #[test]
+    fn reads_scrobbles_from_the_tags() {
+        let fields: HashMap<String, String> = [
+            ("artist", "Artist"),
+            ("title", "Title"),
+            ("track", "3/12"),
+            ("album", " "),
+        ]
+        .iter()
+        .map(|&(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        let started = UNIX_EPOCH + Duration::from_secs(1000);
+        let scrobble = Scrobble::new(&fields, Some(Duration::from_millis(200_500)), started);
+        assert_eq!(
+            scrobble,
+            Some(Scrobble {
+                artist: "Artist".to_string(),
+                title: "Title".to_string(),
+                album: None,
+                album_artist: None,
+                track_number: Some(3),
+                duration: Some(200),
+                timestamp: 1000,
+            })
+        );
+        assert_eq!(Scrobble::new(&HashMap::new(), None, started), None);
+    }
+
+    #[test]
+    fn submits_to_listenbrainz() {
+        let mock = MockService::start(r#"{"status":"ok"}"#);
+        let scrobbler = Scrobbler::spawn(Some(mock.listenbrainz()), None);
+
+        scrobbler.now_playing(scrobble(1));
+        let request = mock.next_request();
+        assert_eq!(request.url, "/1/submit-listens");
+        assert_eq!(request.authorization.as_deref(), Some("Token token"));
+        let body: Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["listen_type"], "playing_now");
+        let listen = &body["payload"][0];
+        assert_eq!(listen.get("listened_at"), None);
+        assert_eq!(listen["track_metadata"]["artist_name"], "Artist");
+        assert_eq!(listen["track_metadata"]["track_name"], "Title 1");
+        assert_eq!(listen["track_metadata"]["release_name"], "Album");
+        assert_eq!(listen["track_metadata"]["additional_info"]["duration"], 200);
+
+        scrobbler.scrobble(scrobble(1));
+        let body: Value = serde_json::from_str(&mock.next_request().body).unwrap();
+        assert_eq!(body["listen_type"], "single");
+        assert_eq!(body["payload"][0]["listened_at"], 1_654_041_601);
+        wait_for(&scrobbler, |status| status.queued == 0);
+    }
+
+    #[test]
+    fn signs_last_fm_requests() {
+        let mock = MockService::start(r#"{"session":{"name":"user","key":"session"}}"#);
+        let service = mock.last_fm(None).login("user", "pw").unwrap();
+        assert_eq!(service, mock.last_fm(Some("session")));
+        let request = mock.next_request();
+        assert_eq!(request.url, "/2.0/");
+        let params = form(&request.body);
+        assert_eq!(params["method"], "auth.getMobileSession");
+        assert_eq!(params["format"], "json");
+        // The MD5 of "api_keykeymethodauth.getMobileSessionpasswordpwusernameusersecret".
+        assert_eq!(params["api_sig"], "b00662f7e0a4e2c593868c3739b595b6");
+
+        *mock.body.lock().unwrap() = r#"{"scrobbles":{}}"#.to_string();
+        service.submit(&[scrobble(1), scrobble(2)]).unwrap();
+        let params = form(&mock.next_request().body);
+        assert_eq!(params["method"], "track.scrobble");
+        assert_eq!(params["sk"], "session");
+        assert_eq!(params["track[0]"], "Title 1");
+        assert_eq!(params["track[1]"], "Title 2");
+        assert_eq!(params["timestamp[1]"], "1654041602");
+        assert_eq!(params["trackNumber[0]"], "3");
+        let mut signed = params.clone();
+        signed.remove("api_sig");
+        signed.remove("format");
+        assert_eq!(params["api_sig"], last_fm_signature(&signed, "secret"));
+
+        *mock.body.lock().unwrap() = r#"{"error":9,"message":"Invalid session key"}"#.to_string();
+        mock.status.store(403, Ordering::SeqCst);
+        assert_eq!(
+            service.now_playing(&scrobble(1)),
+            Err(ScrobbleError::Unauthorized(
+                "Invalid session key".to_string()
+            ))
+        );
+        assert_eq!(
+            mock.last_fm(None).submit(&[scrobble(1)]),
+            Err(ScrobbleError::Unauthorized(
+                "not logged in to Last.fm".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn queues_scrobbles_while_the_service_is_unavailable() {
+        let mock = MockService::start("unavailable");
+        mock.status.store(503, Ordering::SeqCst);
+        let queue_file = TempFile::new("json");
+
+        let scrobbler = Scrobbler::spawn(
+            Some(mock.listenbrainz()),
+            Some(queue_file.path().to_path_buf()),
+        );
+        for index in 0..60 {
+            scrobbler.scrobble(scrobble(index));
+        }
+        wait_for(&scrobbler, |status| {
+            status.queued == 60 && status.error.is_some()
+        });
+        // Scrobbles made after the failure wait for the next retry.
+        mock.next_request();
+        assert!(mock.requests.try_recv().is_err());
+        drop(scrobbler);
+        assert_eq!(Queue::load(queue_file.path()).scrobbles().len(), 60);
+
+        // The next run submits the queue in batches.
+        mock.status.store(200, Ordering::SeqCst);
+        let scrobbler = Scrobbler::spawn(
+            Some(mock.listenbrainz()),
+            Some(queue_file.path().to_path_buf()),
+        );
+        let sizes: Vec<usize> = (0..2)
+            .map(|_| {
+                let body: Value = serde_json::from_str(&mock.next_request().body).unwrap();
+                assert_eq!(body["listen_type"], "import");
+                body["payload"].as_array().unwrap().len()
+            })
+            .collect();
+        assert_eq!(sizes, [BATCH_SIZE, 60 - BATCH_SIZE]);
+        wait_for(&scrobbler, |status| {
+            status.queued == 0 && status.error.is_none()
+        });
+        assert!(Queue::load(queue_file.path()).scrobbles().is_empty());
+    }
+
+    #[test]
+    fn drops_rejected_scrobbles() {
+        let mock = MockService::start(r#"{"code":400,"error":"Invalid listen"}"#);
+        mock.status.store(400, Ordering::SeqCst);
+        let scrobbler = Scrobbler::spawn(Some(mock.listenbrainz()), None);
+        scrobbler.scrobble(scrobble(1));
+        mock.next_request();
+        wait_for(&scrobbler, |status| status.queued == 0);
+        assert_eq!(scrobbler.status().error, None);
+    }
+
+    #[test]
+    fn drops_only_the_rejected_scrobbles_of_a_batch() {
+        let mock = MockService::start(r#"{"status":"ok"}"#);
+        mock.reject("Title 2");
+        let queue_file = TempFile::new("json");
+        let queue = Queue {
+            scrobbles: (1..=3).map(scrobble).collect(),
+        };
+        queue.save(queue_file.path()).unwrap();
+
+        let scrobbler = Scrobbler::spawn(
+            Some(mock.listenbrainz()),
+            Some(queue_file.path().to_path_buf()),
+        );
+        let body: Value = serde_json::from_str(&mock.next_request().body).unwrap();
+        assert_eq!(body["payload"].as_array().unwrap().len(), 3);
+        let titles: Vec<Value> = (0..3)
+            .map(|_| {
+                let body: Value = serde_json::from_str(&mock.next_request().body).unwrap();
+                assert_eq!(body["listen_type"], "single");
+                body["payload"][0]["track_metadata"]["track_name"].clone()
+            })
+            .collect();
+        assert_eq!(titles, ["Title 1", "Title 2", "Title 3"]);
+        wait_for(&scrobbler, |status| {
+            status.queued == 0 && status.error.is_none()
+        });
+        assert!(Queue::load(queue_file.path()).scrobbles().is_empty());
+    }
+
+    #[test]
+    fn hides_the_secrets_of_services() {
+        let mock = MockService::start("");
+        let info = serde_json::to_value(mock.last_fm(Some("session")).info()).unwrap();
+        assert_eq!(
+            info,
+            json!({
+                "protocol": "lastfm",
+                "base_url": format!("{}/2.0/", mock.url),
+                "api_key": "key",
+                "logged_in": true,
+            })
+        );
+        let info = serde_json::to_value(mock.listenbrainz().info()).unwrap();
+        assert_eq!(
+            info,
+            json!({ "protocol": "listenbrainz", "base_url": mock.url })
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::player::{Player, Playlist, RepeatMode, ShuffleMode, DEFAULT_SPEED, DEFAULT_VOLUME};
+use crate::storage;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Name of the session file in the app data directory.
+const SESSION_FILE: &str = "session.json";
+/// Version of the layout of the session file.
+const SESSION_VERSION: u32 = 2;
+
+/// State of the player saved when the app closes and restored on the next launch.
+///
+/// The output device and the DSP options are user preferences kept in `Settings`, which are
+/// restored along with the session.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub playlist: Vec<PathBuf>,
+    pub current_index: Option<usize>,
+    /// Position in the current track, in milliseconds.
+    pub position_ms: u64,
+    /// Volume on the perceptual scale of `Player::set_volume`.
+    pub volume: u16,
+    pub muted: bool,
+    pub speed: f32,
+    pub repeat: RepeatMode,
+    pub shuffle: ShuffleMode,
+    pub shuffle_seed: u64,
+    /// Play order of the playlist, so that a shuffled order survives changes to the tags.
+    pub shuffle_order: Vec<usize>,
+    /// Position to resume long tracks from, in milliseconds, by path.
+    pub resume_positions: BTreeMap<String, u64>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            playlist: Vec::new(),
+            current_index: None,
+            position_ms: 0,
+            volume: DEFAULT_VOLUME,
+            muted: false,
+            speed: DEFAULT_SPEED,
+            repeat: RepeatMode::default(),
+            shuffle: ShuffleMode::default(),
+            shuffle_seed: 0,
+            shuffle_order: Vec::new(),
+            resume_positions: BTreeMap::new(),
+        }
+    }
+}
+
+impl Session {
+    /// Returns the path of the session file in `app_dir`.
+    pub fn path(app_dir: &Path) -> PathBuf {
+        app_dir.join(SESSION_FILE)
+    }
+
+    /// Reads the session from `path`, falling back to an empty session if the file is missing or
+    /// can't be parsed.
+    pub fn load(path: &Path) -> Self {
+        storage::load(path, SESSION_VERSION, Self::migrate).unwrap_or_default()
+    }
+
+    /// Writes the session to `path`, creating the parent directory if needed.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        storage::save(path, SESSION_VERSION, self)
+    }
+
+    /// Upgrades a session written by an older version.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn migrate(version: u32, value: &mut Value) {
+        // Version 1 stored a linear volume, which the cubic curve maps back to the same gain.
+        if version < 2 {
+            if let Some(volume) = value.get_mut("volume") {
+                if let Some(linear) = volume.as_f64() {
+                    let level = (linear.clamp(0.0, 100.0) / 100.0).cbrt() * 100.0;
+                    *volume = Value::from(level.round() as u16);
+                }
+            }
+        }
+    }
+
+    /// Captures the current state of `player`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn capture(player: &Player) -> Self {
+        let playlist = player.playlist();
+        Self {
+            playlist: playlist.tracks().to_vec(),
+            current_index: playlist.current_index(),
+            position_ms: player.elapsed().as_millis() as u64,
+            volume: player.volume,
+            muted: player.is_muted(),
+            speed: player.speed,
+            repeat: playlist.repeat(),
+            shuffle: playlist.shuffle_mode(),
+            shuffle_seed: playlist.shuffle_seed(),
+            shuffle_order: playlist.order().to_vec(),
+            resume_positions: player
+                .resume_positions()
+                .into_iter()
+                .map(|(path, position)| {
+                    (
+                        path.to_string_lossy().into_owned(),
+                        position.as_millis() as u64,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Restores the session in `player`, paused at the saved position.
+    pub fn restore(self, player: &mut Player) {
+        player.set_volume(self.volume);
+        if self.muted {
+            player.mute();
+        }
+        player.set_speed(self.speed);
+        player.set_resume_positions(
+            self.resume_positions
+                .into_iter()
+                .map(|(path, position_ms)| {
+                    (PathBuf::from(path), Duration::from_millis(position_ms))
+                })
+                .collect(),
+        );
+        let mut playlist = Playlist::new(self.playlist, self.current_index);
+        playlist.set_repeat(self.repeat);
+        let restored =
+            playlist.restore_shuffle(self.shuffle, self.shuffle_seed, self.shuffle_order);
+        player.restore_playlist(playlist, Duration::from_millis(self.position_ms));
+        if !restored {
+            player.set_shuffle(self.shuffle, self.shuffle_seed);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::player::{DitherMode, ResamplerQuality};
+use crate::scrobble::Service;
+use crate::storage::{self, Stored};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Name of the settings file in the app data directory.
+const SETTINGS_FILE: &str = "settings.json";
+/// Version of the layout of the settings file.
+const SETTINGS_VERSION: u32 = 1;
+
+/// User preferences that persist across restarts.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Name of the chosen output device. `None` follows the system default.
+    pub output_device: Option<String>,
+    /// Whether bit-perfect playback is enabled.
+    pub bit_perfect: bool,
+    /// Quality of the sample rate conversion.
+    pub resampler_quality: ResamplerQuality,
+    /// Whether every track is downmixed to mono.
+    pub mono_output: bool,
+    /// Whether the left and right channels are swapped.
+    pub swap_channels: bool,
+    /// When dither is applied to 16-bit output.
+    pub dither: DitherMode,
+    /// Whether the dither is noise shaped.
+    pub noise_shaping: bool,
+    /// Jump of the seek forward and backward commands, in milliseconds. `None` uses the default
+    /// step of the player.
+    pub seek_step_ms: Option<u64>,
+    /// Whether the digital silence at the start and end of tracks is skipped.
+    pub skip_silence: bool,
+    /// Duration of the fade out before the sleep timer stops playback, in milliseconds. `None`
+    /// uses the default fade of the player.
+    pub sleep_fade_ms: Option<u64>,
+    /// Fraction of a track past which leaving it counts as a play. `None` uses the default
+    /// threshold of the player.
+    pub play_threshold: Option<f64>,
+    /// Whether ratings are also written to the tags of the tracks.
+    pub write_ratings_to_tags: bool,
+    /// Folders indexed in the library.
+    pub library_folders: Vec<PathBuf>,
+    /// Whether the HTTP remote control API is served.
+    pub remote_enabled: bool,
+    /// Address the remote control API binds to. `None` uses the default address, which only
+    /// accepts connections from this machine.
+    pub remote_address: Option<String>,
+    /// Token the remote control clients must send, created when the API is first enabled.
+    pub remote_token: Option<String>,
+    /// Whether the MPD protocol server is running.
+    pub mpd_enabled: bool,
+    /// Address the MPD protocol server binds to. `None` uses the default address, which only
+    /// accepts connections from this machine.
+    pub mpd_address: Option<String>,
+    /// Password MPD clients must send, if any.
+    pub mpd_password: Option<String>,
+    /// Service listens are scrobbled to, if any.
+    pub scrobbling: Option<Service>,
+}
+
+impl Settings {
+    /// Returns the path of the settings file in `app_dir`.
+    pub fn path(app_dir: &Path) -> PathBuf {
+        app_dir.join(SETTINGS_FILE)
+    }
+
+    /// Reads the settings from `path`, falling back to the defaults if the file is missing or
+    /// can't be parsed.
+    pub fn load(path: &Path) -> Self {
+        storage::load(path, SETTINGS_VERSION, Self::migrate).unwrap_or_default()
+    }
+
+    /// Upgrades settings written by an older version.
+    fn migrate(_version: u32, _value: &mut Value) {
+        // Version 0 files only lack the version field, missing fields get their defaults.
+    }
+}
+
+impl Stored for Settings {
+    const NAME: &'static str = "settings";
+
+    /// Writes the settings to `path`, creating the parent directory if needed.
+    fn save(&self, path: &Path) -> Result<()> {
+        storage::save(path, SETTINGS_VERSION, self)
+    }
+}
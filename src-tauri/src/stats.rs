@@ -0,0 +1,195 @@
+use crate::player::{Listen, ListenEnd};
+use crate::storage::{self, Stored};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Name of the statistics file in the app data directory.
+const STATS_FILE: &str = "stats.json";
+/// Version of the layout of the statistics file.
+const STATS_VERSION: u32 = 1;
+/// Highest rating, in half stars.
+pub const MAX_RATING: u8 = 10;
+
+/// How often a track was played and skipped, and its rating.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrackStats {
+    pub plays: u32,
+    pub skips: u32,
+    /// When the last play started, in seconds since the Unix epoch.
+    pub last_played: Option<u64>,
+    /// When the last skipped listen started, in seconds since the Unix epoch.
+    pub last_skipped: Option<u64>,
+    /// Rating, in half stars from 0 to `MAX_RATING`.
+    pub rating: Option<u8>,
+}
+
+impl TrackStats {
+    fn is_empty(&self) -> bool {
+        self.plays == 0 && self.skips == 0 && self.rating.is_none()
+    }
+}
+
+/// Statistics of every track, by path.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+    /// Paths are stored as strings so that they can be keys of the JSON object.
+    tracks: BTreeMap<String, TrackStats>,
+}
+
+impl Stats {
+    /// Returns the path of the statistics file in `app_dir`.
+    pub fn path(app_dir: &Path) -> PathBuf {
+        app_dir.join(STATS_FILE)
+    }
+
+    /// Reads the statistics from `path`, falling back to none if the file is missing or can't
+    /// be parsed.
+    pub fn load(path: &Path) -> Self {
+        storage::load(path, STATS_VERSION, Self::migrate).unwrap_or_default()
+    }
+
+    /// Upgrades statistics written by an older version.
+    fn migrate(_version: u32, _value: &mut Value) {}
+
+    /// Returns the statistics of `file`.
+    pub fn get(&self, file: &Path) -> TrackStats {
+        self.tracks
+            .get(file.to_string_lossy().as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Counts `listen` as a play or a skip of its track. Listens that were stopped count as
+    /// neither.
+    pub fn record(&mut self, listen: &Listen) {
+        if listen.end == ListenEnd::Stopped {
+            return;
+        }
+        let started = listen
+            .started
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |started| started.as_secs());
+        let stats = self
+            .tracks
+            .entry(listen.path.to_string_lossy().into_owned())
+            .or_default();
+        if listen.end == ListenEnd::Played {
+            stats.plays += 1;
+            stats.last_played = Some(started);
+        } else {
+            stats.skips += 1;
+            stats.last_skipped = Some(started);
+        }
+    }
+
+    /// Sets the rating of `file`, in half stars, or removes it if `None`. Returns the statistics
+    /// of `file`.
+    pub fn set_rating(&mut self, file: &Path, rating: Option<u8>) -> TrackStats {
+        let key = file.to_string_lossy().into_owned();
+        let stats = self.tracks.entry(key.clone()).or_default();
+        stats.rating = rating.map(|rating| rating.min(MAX_RATING));
+        let stats = stats.clone();
+        if stats.is_empty() {
+            self.tracks.remove(&key);
+        }
+        stats
+    }
+}
+
+impl Stored for Stats {
+    const NAME: &'static str = "statistics";
+
+    /// Writes the statistics to `path`, creating the parent directory if needed.
+    fn save(&self, path: &Path) -> Result<()> {
+        storage::save(path, STATS_VERSION, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempFile;
+    use std::time::Duration;
+
+    /// 2022-06-01T00:00:00Z.
+    const JUNE_1: u64 = 1_654_041_600;
+
+    fn listen(path: &str, started: u64, end: ListenEnd) -> Listen {
+        Listen {
+            path: PathBuf::from(path),
+            started: UNIX_EPOCH + Duration::from_secs(started),
+            position: Duration::from_secs(60),
+            listened: Duration::from_secs(60),
+            duration: Some(Duration::from_secs(100)),
+            end,
+        }
+    }
+
+    #[test]
+    fn counts_plays_and_skips() {
+        let mut stats = Stats::default();
+        stats.record(&listen("/music/a.flac", JUNE_1, ListenEnd::Played));
+        stats.record(&listen("/music/a.flac", JUNE_1 + 10, ListenEnd::Skipped));
+        stats.record(&listen("/music/a.flac", JUNE_1 + 20, ListenEnd::Played));
+        stats.record(&listen("/music/a.flac", JUNE_1 + 30, ListenEnd::Stopped));
+        stats.record(&listen("/music/b.flac", JUNE_1 + 40, ListenEnd::Stopped));
+
+        let a = stats.get(Path::new("/music/a.flac"));
+        assert_eq!((a.plays, a.skips), (2, 1));
+        assert_eq!(a.last_played, Some(JUNE_1 + 20));
+        assert_eq!(a.last_skipped, Some(JUNE_1 + 10));
+
+        // Stopped listens leave no trace.
+        let b = stats.get(Path::new("/music/b.flac"));
+        assert_eq!((b.plays, b.skips, b.last_played), (0, 0, None));
+        assert!(!stats.tracks.contains_key("/music/b.flac"));
+    }
+
+    #[test]
+    fn clamps_ratings_and_forgets_empty_tracks() {
+        let mut stats = Stats::default();
+        let file = Path::new("/music/a.flac");
+        assert_eq!(stats.set_rating(file, Some(7)).rating, Some(7));
+        assert_eq!(stats.set_rating(file, Some(MAX_RATING)).rating, Some(10));
+        assert_eq!(stats.set_rating(file, Some(200)).rating, Some(MAX_RATING));
+        assert_eq!(stats.set_rating(file, Some(0)).rating, Some(0));
+
+        assert_eq!(stats.set_rating(file, None).rating, None);
+        assert!(stats.tracks.is_empty());
+
+        stats.record(&listen("/music/a.flac", JUNE_1, ListenEnd::Played));
+        stats.set_rating(file, Some(4));
+        let a = stats.set_rating(file, None);
+        assert_eq!((a.plays, a.rating), (1, None));
+        assert_eq!(stats.tracks.len(), 1);
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let file = TempFile::new("json");
+        assert!(Stats::load(file.path()).tracks.is_empty());
+
+        let mut stats = Stats::default();
+        stats.record(&listen("/music/a.flac", JUNE_1, ListenEnd::Played));
+        stats.record(&listen("/music/b.flac", JUNE_1, ListenEnd::Skipped));
+        stats.set_rating(Path::new("/music/b.flac"), Some(9));
+        stats.save(file.path()).unwrap();
+
+        let loaded = Stats::load(file.path());
+        assert_eq!(loaded.tracks.len(), 2);
+        let a = loaded.get(Path::new("/music/a.flac"));
+        assert_eq!((a.plays, a.last_played), (1, Some(JUNE_1)));
+        let b = loaded.get(Path::new("/music/b.flac"));
+        assert_eq!((b.skips, b.rating), (1, Some(9)));
+
+        // A file that can't be parsed is no statistics.
+        std::fs::write(file.path(), "{").unwrap();
+        assert!(Stats::load(file.path()).tracks.is_empty());
+    }
+}
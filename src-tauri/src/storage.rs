@@ -0,0 +1,112 @@
+//! Reading and writing of the JSON files kept in the app data directory.
+//!
+//! Files carry a `version` field so that the layout of older files can be migrated when it
+//! changes, and are replaced atomically so that a crash while writing never leaves a partial
+//! file behind.
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+/// Name of the field holding the version of a file.
+const VERSION_FIELD: &str = "version";
+
+/// Reads the file at `path`, written by `save` with `version` or an older version.
+///
+/// `migrate` is called with the version of the file, which is 0 for files written before
+/// versions were recorded, and updates the JSON value in place to the current layout. Returns
+/// `None` if the file is missing or can't be parsed.
+pub fn load<T, F>(path: &Path, version: u32, migrate: F) -> Option<T>
+where
+    T: DeserializeOwned,
+    F: FnOnce(u32, &mut Value),
+{
+    let text = fs::read_to_string(path).ok()?;
+    let mut value: Value = serde_json::from_str(&text).ok()?;
+
+    #[allow(clippy::cast_possible_truncation)]
+    let file_version = value
+        .get(VERSION_FIELD)
+        .and_then(Value::as_u64)
+        .map_or(0, |version| version as u32);
+    if file_version < version {
+        migrate(file_version, &mut value);
+    }
+
+    serde_json::from_value(value).ok()
+}
+
+/// Writes `data` to `path` as JSON, tagged with `version`.
+pub fn save<T: Serialize>(path: &Path, version: u32, data: &T) -> Result<()> {
+    let mut value = serde_json::to_value(data)?;
+    if let Value::Object(map) = &mut value {
+        map.insert(VERSION_FIELD.to_string(), Value::from(version));
+    }
+    write_atomic(path, serde_json::to_string_pretty(&value)?.as_bytes())?;
+    Ok(())
+}
+
+/// Replaces the file at `path` with `contents`, creating the parent directory if needed.
+///
+/// The contents are written to a temporary file next to `path` first, then renamed over it.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    fs::rename(tmp_path, path)
+}
+
+/// Data kept in a file of the app data directory.
+pub trait Stored {
+    /// What the data is, in error messages.
+    const NAME: &'static str;
+
+    /// Writes the data to `path`, creating the parent directory if needed.
+    fn save(&self, path: &Path) -> Result<()>;
+}
+
+/// Data shared by the commands, written to its file whenever it is updated.
+pub struct Persisted<T> {
+    value: Mutex<T>,
+    /// Path of the file, or `None` if the data isn't kept across restarts.
+    path: Option<PathBuf>,
+}
+
+impl<T: Stored> Persisted<T> {
+    /// Shares `value`, kept in the file at `path` if any.
+    pub fn new(value: T, path: Option<PathBuf>) -> Self {
+        Self {
+            value: Mutex::new(value),
+            path,
+        }
+    }
+
+    /// Locks the data, to read it.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.value.lock().unwrap()
+    }
+
+    /// Applies `update` to the data and writes it to disk.
+    pub fn update<R, F: FnOnce(&mut T) -> R>(&self, update: F) -> R {
+        let mut value = self.lock();
+        let result = update(&mut value);
+        if let Some(path) = &self.path {
+            if let Err(err) = value.save(path) {
+                eprintln!("failed to save {}: {}", T::NAME, err);
+            }
+        }
+        result
+    }
+}
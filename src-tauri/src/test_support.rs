@@ -0,0 +1,44 @@
+//! Helpers shared by the tests of the app modules.
+//!
+//! `TempFile` works like the one of the player fixtures in the core crate, which the app can't
+//! reach since it only exists in the tests of that crate.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of the next temporary file, which keeps the files of tests running at once apart.
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// A file or folder in the temporary folder, removed when dropped.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Picks the path of a file with `extension` that no other test uses.
+    pub fn new(extension: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "plain-music-player-app-test-{}-{}.{}",
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed),
+            extension
+        ));
+        Self { path }
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if self.path.is_dir() {
+            let _ = fs::remove_dir_all(&self.path);
+        } else {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}